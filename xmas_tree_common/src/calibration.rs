@@ -0,0 +1,51 @@
+//! Per-LED color correction, so LEDs which came out of the factory brighter or bluer than their
+//! neighbours can be toned down to match.
+
+use std::{error::Error, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+/// One row of a calibration file: the factor to scale each channel of an LED by.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LedGain {
+    pub led: usize,
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+}
+
+/// A calibration file, stored as CSV with a `led,r,g,b` header. LEDs which aren't listed are
+/// left as they are.
+#[derive(Debug, Clone, Default)]
+pub struct Calibration {
+    pub gains: Vec<[f32; 3]>,
+}
+
+impl Calibration {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut reader = csv::Reader::from_path(path)
+            .map_err(|e| format!("Cannot read calibration {}: {}", path.display(), e))?;
+        let mut gains = Vec::new();
+        for row in reader.deserialize() {
+            let LedGain { led, r, g, b } = row?;
+            if gains.len() <= led {
+                gains.resize(led + 1, [1.0; 3]);
+            }
+            gains[led] = [r, g, b];
+        }
+        Ok(Self { gains })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut writer = csv::Writer::from_path(path)?;
+        for (led, &[r, g, b]) in self.gains.iter().enumerate() {
+            writer.serialize(LedGain { led, r, g, b })?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn gain(&self, led: usize) -> [f32; 3] {
+        self.gains.get(led).copied().unwrap_or([1.0; 3])
+    }
+}
@@ -0,0 +1,124 @@
+//! Which universe or controller port drives each LED, for trees wired to several outputs. It's
+//! saved as JSON listing each output's LEDs as ranges, so it can still be read and fixed up by
+//! hand, e.g. `{ "outputs": { "1": "0-99", "2": "100-199,250" } }`.
+
+use std::{collections::BTreeMap, error::Error, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelMap {
+    /// The universe or port each LED is on, if it has been assigned one.
+    pub assignments: Vec<Option<u16>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChannelMapFile {
+    outputs: BTreeMap<u16, String>,
+}
+
+/// Parses LED indices written as a comma separated list of indices and inclusive ranges, e.g.
+/// `0,49-51`.
+pub fn parse_indices(s: &str) -> Result<Vec<usize>, String> {
+    let mut indices = Vec::new();
+    for part in s.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+        let invalid = || format!("Invalid LED index or range: {}", part);
+        let (start, end) = part.split_once('-').unwrap_or((part, part));
+        let start: usize = start.trim().parse().map_err(|_| invalid())?;
+        let end: usize = end.trim().parse().map_err(|_| invalid())?;
+        if end < start {
+            return Err(invalid());
+        }
+        indices.extend(start..=end);
+    }
+    Ok(indices)
+}
+
+/// Writes sorted LED indices in the form [`parse_indices`] reads, with runs as ranges.
+pub fn format_indices(indices: &[usize]) -> String {
+    let mut parts = Vec::new();
+    let mut rest = indices;
+    while let Some(&start) = rest.first() {
+        let run = rest
+            .iter()
+            .enumerate()
+            .take_while(|&(i, &index)| index == start + i)
+            .count();
+        let end = rest[run - 1];
+        parts.push(if end == start {
+            start.to_string()
+        } else {
+            format!("{}-{}", start, end)
+        });
+        rest = &rest[run..];
+    }
+    parts.join(",")
+}
+
+impl ChannelMap {
+    /// A map with none of `led_count` LEDs assigned yet.
+    pub fn empty(led_count: usize) -> Self {
+        Self {
+            assignments: vec![None; led_count],
+        }
+    }
+
+    pub fn load(path: &Path, led_count: usize) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read channel map {}: {}", path.display(), e))?;
+        let file: ChannelMapFile = serde_json::from_str(&text)
+            .map_err(|e| format!("Cannot read channel map {}: {}", path.display(), e))?;
+        let mut map = Self::empty(led_count);
+        for (&output, leds) in &file.outputs {
+            for index in parse_indices(leds)? {
+                let assignment = map.assignments.get_mut(index).ok_or_else(|| {
+                    format!(
+                        "{} assigns LED {}, but the tree only has {}",
+                        path.display(),
+                        index,
+                        led_count
+                    )
+                })?;
+                if let Some(other) = assignment.replace(output) {
+                    return Err(format!(
+                        "{} assigns LED {} to both {} and {}",
+                        path.display(),
+                        index,
+                        other,
+                        output
+                    )
+                    .into());
+                }
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let file = ChannelMapFile {
+            outputs: self
+                .outputs()
+                .into_iter()
+                .map(|(output, leds)| (output, format_indices(&leds)))
+                .collect(),
+        };
+        fs::write(path, serde_json::to_string_pretty(&file)? + "\n")
+            .map_err(|e| format!("Cannot write {}: {}", path.display(), e))?;
+        Ok(())
+    }
+
+    /// The LEDs on each output, in order.
+    pub fn outputs(&self) -> BTreeMap<u16, Vec<usize>> {
+        let mut outputs: BTreeMap<u16, Vec<usize>> = BTreeMap::new();
+        for (index, output) in self.assignments.iter().enumerate() {
+            if let Some(output) = output {
+                outputs.entry(*output).or_default().push(index);
+            }
+        }
+        outputs
+    }
+
+    pub fn unassigned(&self) -> usize {
+        self.assignments.iter().filter(|a| a.is_none()).count()
+    }
+}
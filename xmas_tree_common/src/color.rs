@@ -0,0 +1,161 @@
+//! Floating point color helpers, with channels nominally in `0.0..=1.0`.
+
+use std::{f32::consts::PI, fmt, str::FromStr};
+
+pub type Color = (f32, f32, f32);
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a * (1.0 - t) + b * t
+}
+
+/// Interpolates between angles in radians the short way round, so turning from just below a
+/// full turn to just above zero doesn't sweep back through everything in between.
+pub fn lerp_angle(a: f32, b: f32, t: f32) -> f32 {
+    // Only move `b` when it is over half a turn away, so nearby angles lerp exactly as usual
+    let b = if (b - a).abs() > PI {
+        b - ((b - a) / (PI * 2.0)).round() * PI * 2.0
+    } else {
+        b
+    };
+    lerp(a, b, t)
+}
+
+/// Interpolates between hues (`0.0..1.0` is one turn) the short way round the color wheel.
+pub fn lerp_hue(a: f32, b: f32, t: f32) -> f32 {
+    let delta = (b - a + 0.5).rem_euclid(1.0) - 0.5;
+    (a + delta * t).rem_euclid(1.0)
+}
+
+/// Blends two colors through hue, saturation and value, taking the shorter way round the color
+/// wheel. Unlike mixing RGB, red to green passes through yellow rather than a muddy brown.
+pub fn mix_hue(a: Color, b: Color, t: f32) -> Color {
+    // Converting to HSV and back isn't exact, so leave the end points alone
+    if t <= 0.0 {
+        return a;
+    }
+    if t >= 1.0 {
+        return b;
+    }
+    let (mut hue_a, mut saturation_a, value_a) = rgb_to_hsv(a);
+    let (mut hue_b, mut saturation_b, value_b) = rgb_to_hsv(b);
+    // Greys have no hue of their own and black no saturation either, so fade in the other
+    // color's
+    if value_a <= 0.0 {
+        saturation_a = saturation_b;
+    }
+    if value_b <= 0.0 {
+        saturation_b = saturation_a;
+    }
+    if saturation_a <= 0.0 {
+        hue_a = hue_b;
+    }
+    if saturation_b <= 0.0 {
+        hue_b = hue_a;
+    }
+    hsv_to_rgb(
+        lerp_hue(hue_a, hue_b, t),
+        lerp(saturation_a, saturation_b, t),
+        lerp(value_a, value_b, t),
+    )
+}
+
+/// Converts RGB to hue (`0.0..1.0`), saturation and value.
+pub fn rgb_to_hsv((r, g, b): Color) -> (f32, f32, f32) {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    let hue = if delta <= 0.0 {
+        0.0
+    } else if max == r {
+        ((g - b) / delta).rem_euclid(6.0) / 6.0
+    } else if max == g {
+        ((b - r) / delta + 2.0) / 6.0
+    } else {
+        ((r - g) / delta + 4.0) / 6.0
+    };
+    let saturation = if max > 0.0 { delta / max } else { 0.0 };
+    (hue, saturation, max)
+}
+
+/// Converts hue (wrapping, `0.0..1.0` is one turn), saturation and value to RGB.
+pub fn hsv_to_rgb(hue: f32, saturation: f32, value: f32) -> Color {
+    let h = hue.rem_euclid(1.0) * 6.0;
+    let c = value * saturation;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = if h < 1.0 {
+        (c, x, 0.0)
+    } else if h < 2.0 {
+        (x, c, 0.0)
+    } else if h < 3.0 {
+        (0.0, c, x)
+    } else if h < 4.0 {
+        (0.0, x, c)
+    } else if h < 5.0 {
+        (x, 0.0, c)
+    } else {
+        (c, 0.0, x)
+    };
+    let m = value - c;
+    (r + m, g + m, b + m)
+}
+
+/// How colors brighter than an LED can show are brought back into range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipMode {
+    /// Clamp each channel on its own. Over-bright colors drift towards yellow, cyan, magenta or
+    /// white, e.g. a bright orange becomes yellow.
+    Channel,
+    /// Scale the whole color down until it fits, keeping its hue and saturation.
+    Hue,
+    /// As `hue`, but roll the brightness off gently from [`SOFT_KNEE`], so gradients which go
+    /// over the top stay visible rather than flattening out.
+    Soft,
+}
+
+/// Where `soft` clipping starts to compress brightness.
+pub const SOFT_KNEE: f32 = 0.8;
+
+impl FromStr for ClipMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "channel" => Ok(Self::Channel),
+            "hue" => Ok(Self::Hue),
+            "soft" => Ok(Self::Soft),
+            other => Err(format!("Unknown clip mode: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for ClipMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Channel => "channel",
+            Self::Hue => "hue",
+            Self::Soft => "soft",
+        })
+    }
+}
+
+/// Brings a color into `0.0..=1.0`. Negative channels are always clamped to zero. Scaling every
+/// channel by the same amount keeps the color's hue and chroma in proportion in perceptual
+/// spaces such as Oklab, so `hue` and `soft` only change how bright it is.
+pub fn clip(color: Color, mode: ClipMode) -> Color {
+    let (r, g, b) = (color.0.max(0.0), color.1.max(0.0), color.2.max(0.0));
+    let max = r.max(g).max(b);
+    let target = match mode {
+        ClipMode::Channel => return (r.min(1.0), g.min(1.0), b.min(1.0)),
+        ClipMode::Hue => max.min(1.0),
+        ClipMode::Soft if max > SOFT_KNEE => {
+            let range = 1.0 - SOFT_KNEE;
+            SOFT_KNEE + range * ((max - SOFT_KNEE) / range).tanh()
+        }
+        ClipMode::Soft => max,
+    };
+    if max <= target {
+        return (r, g, b);
+    }
+    let scale = target / max;
+    (r * scale, g * scale, b * scale)
+}
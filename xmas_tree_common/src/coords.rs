@@ -0,0 +1,525 @@
+//! Loading LED coordinates, and their conventions. Everything downstream of loading works in the
+//! GIFT convention: right-handed with z pointing up.
+
+use std::{error::Error, fmt, fs, path::Path, str::FromStr};
+
+use rand::{prelude::StdRng, Rng, SeedableRng};
+
+pub type Coord = (f32, f32, f32);
+
+/// Which input axis points up the tree.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UpAxis {
+    Z,
+    Y,
+    /// Guess from the shape of the tree.
+    Auto,
+}
+
+impl FromStr for UpAxis {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "z" => Ok(Self::Z),
+            "y" => Ok(Self::Y),
+            "auto" => Ok(Self::Auto),
+            _ => Err(format!("unknown up axis `{}` (expected z, y or auto)", s)),
+        }
+    }
+}
+
+impl fmt::Display for UpAxis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Z => "z",
+            Self::Y => "y",
+            Self::Auto => "auto",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Handedness {
+    Right,
+    Left,
+}
+
+impl FromStr for Handedness {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "right" => Ok(Self::Right),
+            "left" => Ok(Self::Left),
+            _ => Err(format!(
+                "unknown handedness `{}` (expected right or left)",
+                s
+            )),
+        }
+    }
+}
+
+fn bounds(coords: &[Coord], axis: fn(&Coord) -> f32) -> (f32, f32) {
+    coords
+        .iter()
+        .map(axis)
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), v| {
+            (min.min(v), max.max(v))
+        })
+}
+
+fn extent(coords: &[Coord], axis: fn(&Coord) -> f32) -> f32 {
+    let (min, max) = bounds(coords, axis);
+    (max - min).max(0.0)
+}
+
+/// Trees are taller than they are wide, so the up axis is whichever of y and z spans further.
+pub fn detect_up_axis(coords: &[Coord]) -> UpAxis {
+    if extent(coords, |c| c.1) > extent(coords, |c| c.2) {
+        UpAxis::Y
+    } else {
+        UpAxis::Z
+    }
+}
+
+/// Converts coordinates in the given convention to right-handed z-up, returning the up axis
+/// that was used.
+pub fn normalize(coords: &mut [Coord], up: UpAxis, handedness: Handedness) -> UpAxis {
+    let up = match up {
+        UpAxis::Auto => detect_up_axis(coords),
+        up => up,
+    };
+    for coord in coords.iter_mut() {
+        let (x, y, z) = *coord;
+        let x = match handedness {
+            Handedness::Right => x,
+            Handedness::Left => -x,
+        };
+        *coord = match up {
+            // A rotation about x, so right-handed input stays right-handed
+            UpAxis::Y => (x, -z, y),
+            _ => (x, y, z),
+        };
+    }
+    up
+}
+
+/// The units of a coordinates file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Units {
+    /// GIFT normalized: centred on the trunk, x and y within -1..1 and z up from 0 at the base.
+    Gift,
+    Metres,
+    Centimetres,
+    /// Guess from the size of the tree.
+    Auto,
+}
+
+impl FromStr for Units {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gift" => Ok(Self::Gift),
+            "m" | "metres" | "meters" => Ok(Self::Metres),
+            "cm" | "centimetres" | "centimeters" => Ok(Self::Centimetres),
+            "auto" => Ok(Self::Auto),
+            _ => Err(format!(
+                "unknown units `{}` (expected gift, m, cm or auto)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Units {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Gift => "gift",
+            Self::Metres => "m",
+            Self::Centimetres => "cm",
+            Self::Auto => "auto",
+        })
+    }
+}
+
+/// GIFT files are scaled so the widest LEDs sit about 1 from the trunk, with the base at 0 and
+/// the top no higher than about 3.2. Scans in metres can sit within that box too, so it's the
+/// widest LED being close to 1 that gives GIFT away: a tree scanned in metres as wide as that is
+/// barely rescaled anyway. Anything else is a physical scan, and no real tree is over 30m tall.
+pub fn detect_units(coords: &[Coord]) -> Units {
+    let horizontal = coords
+        .iter()
+        .map(|&(x, y, _)| x.abs().max(y.abs()))
+        .fold(0.0, f32::max);
+    let (bottom, top) = bounds(coords, |c| c.2);
+    if (0.75..=1.5).contains(&horizontal) && bottom >= -0.5 && top <= 4.0 {
+        Units::Gift
+    } else if extent(coords, |c| c.2) > 30.0 {
+        Units::Centimetres
+    } else {
+        Units::Metres
+    }
+}
+
+/// Rescales z-up coordinates to GIFT units, so distances in effect parameters (fall speeds,
+/// radii) mean the same thing whatever the tree was scanned in. Returns the units that were used.
+pub fn to_gift_units(coords: &mut [Coord], units: Units) -> Units {
+    let units = match units {
+        Units::Auto => detect_units(coords),
+        units => units,
+    };
+    if units == Units::Gift || coords.is_empty() {
+        return units;
+    }
+    let middle = |axis| {
+        let (min, max) = bounds(coords, axis);
+        (min + max) / 2.0
+    };
+    let centre_x = middle(|c| c.0);
+    let centre_y = middle(|c| c.1);
+    let (bottom, _) = bounds(coords, |c| c.2);
+    let radius = coords
+        .iter()
+        .map(|&(x, y, _)| (x - centre_x).abs().max((y - centre_y).abs()))
+        .fold(0.0, f32::max);
+    let scale = if radius > 0.0 { 1.0 / radius } else { 1.0 };
+    for coord in coords.iter_mut() {
+        let (x, y, z) = *coord;
+        *coord = (
+            (x - centre_x) * scale,
+            (y - centre_y) * scale,
+            (z - bottom) * scale,
+        );
+    }
+    units
+}
+
+/// How to read a coordinates file.
+#[derive(Debug, Clone, Copy)]
+pub struct LoadOptions {
+    pub up_axis: UpAxis,
+    pub handedness: Handedness,
+    pub units: Units,
+    /// Move LEDs whose measured position is clearly wrong back in line with their neighbours.
+    pub repair_outliers: bool,
+    /// Place LEDs which weren't found between their neighbours on the string.
+    pub fill_missing: bool,
+}
+
+/// A coordinates file, converted to the GIFT convention.
+#[derive(Debug, Clone)]
+pub struct Layout {
+    pub coords: Vec<Coord>,
+    pub bounds: Bounds,
+    /// The up axis and units that were used, once any guesses were made.
+    pub up_axis: UpAxis,
+    pub units: Units,
+    pub has_header: bool,
+    /// Whether the file only had x and y columns, as for a flat display.
+    pub flat: bool,
+    /// LEDs whose positions were replaced by `repair_outliers`.
+    pub repaired: Vec<usize>,
+    /// LEDs the file gives no position for, found by `find_missing`.
+    pub missing: Vec<usize>,
+    /// Missing LEDs which were given a position by `fill_missing`.
+    pub filled: Vec<usize>,
+}
+
+/// The extent of a layout, so effects don't have to work it out every frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub min: Coord,
+    pub max: Coord,
+}
+
+impl Bounds {
+    pub fn of(coords: &[Coord]) -> Self {
+        let (min_x, max_x) = bounds(coords, |c| c.0);
+        let (min_y, max_y) = bounds(coords, |c| c.1);
+        let (min_z, max_z) = bounds(coords, |c| c.2);
+        Self {
+            min: (min_x, min_y, min_z),
+            max: (max_x, max_y, max_z),
+        }
+    }
+
+    /// How far up the tree a point is, from 0 at the lowest LED to 1 at the highest.
+    pub fn height_fraction(&self, coord: Coord) -> f32 {
+        (coord.2 - self.min.2) / (self.max.2 - self.min.2).max(f32::EPSILON)
+    }
+}
+
+/// Reads a coordinates file: one LED per row, as `x,y,z` or `x,y` for a flat layout with y up.
+/// A header row is optional, and if it names `x`, `y` and `z` columns then those are used, so
+/// files with extra columns such as an index can be read too.
+pub fn parse(data: &[u8], options: &LoadOptions) -> Result<Layout, Box<dyn Error>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .comment(Some(b'#'))
+        .from_reader(data);
+    let mut rows = reader
+        .records()
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .peekable();
+    let first = rows
+        .peek()
+        .cloned()
+        .ok_or("The coordinates file has no LEDs")?;
+    let has_header = first.iter().any(|field| field.parse::<f32>().is_err());
+    let columns: Vec<usize> = if has_header {
+        rows.next();
+        let find = |name: &str| {
+            first
+                .iter()
+                .position(|field| field.eq_ignore_ascii_case(name))
+        };
+        match (find("x"), find("y"), find("z")) {
+            (Some(x), Some(y), Some(z)) => vec![x, y, z],
+            (Some(x), Some(y), None) => vec![x, y],
+            _ => (0..first.len().min(3)).collect(),
+        }
+    } else {
+        (0..first.len().min(3)).collect()
+    };
+    if columns.len() < 2 {
+        return Err("Coordinates need at least x and y columns".into());
+    }
+    let flat = columns.len() == 2;
+
+    let mut coords = Vec::new();
+    for record in rows {
+        let line = record.position().map_or(0, |p| p.line());
+        let value = |column: usize| -> Result<f32, String> {
+            let field = record.get(column).unwrap_or("");
+            field
+                .parse()
+                .map_err(|_| format!("Invalid coordinate {:?} on line {}", field, line))
+        };
+        coords.push(if flat {
+            (value(columns[0])?, 0.0, value(columns[1])?)
+        } else {
+            (value(columns[0])?, value(columns[1])?, value(columns[2])?)
+        });
+    }
+    if coords.is_empty() {
+        return Err("The coordinates file has no LEDs".into());
+    }
+
+    // Filling and repairing first keeps LEDs in the wrong place from throwing off the axis and
+    // unit detection
+    let missing = find_missing(&coords);
+    let filled = if options.fill_missing {
+        fill_missing(&mut coords, &missing)
+    } else {
+        Vec::new()
+    };
+    let repaired = if options.repair_outliers {
+        repair_outliers(&mut coords)
+    } else {
+        Vec::new()
+    };
+    // Flat layouts are already read with their up axis as z
+    let up_axis = if flat { UpAxis::Z } else { options.up_axis };
+    let up_axis = normalize(&mut coords, up_axis, options.handedness);
+    let units = to_gift_units(&mut coords, options.units);
+    Ok(Layout {
+        bounds: Bounds::of(&coords),
+        coords,
+        up_axis,
+        units,
+        has_header,
+        flat,
+        repaired,
+        missing,
+        filled,
+    })
+}
+
+pub fn load(path: &Path, options: &LoadOptions) -> Result<Layout, Box<dyn Error>> {
+    let data =
+        fs::read(path).map_err(|e| format!("Cannot read coordinates {}: {}", path.display(), e))?;
+    parse(&data, options).map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+/// A LED further than this many typical spacings from both its neighbours on the string, while
+/// they are close to each other, was measured in the wrong place.
+const OUTLIER_SPACING: f32 = 5.0;
+
+fn distance(a: Coord, b: Coord) -> f32 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2) + (a.2 - b.2).powi(2)).sqrt()
+}
+
+/// Moves LEDs which are far out of line with their neighbours along the string to between them
+/// (or in line with the next two, at the ends), returning the LEDs which were moved. Runs of
+/// several misplaced LEDs in a row are left alone, since there's nothing reliable to put them
+/// back in line with.
+pub fn repair_outliers(coords: &mut [Coord]) -> Vec<usize> {
+    let last = match coords.len() {
+        0..=2 => return Vec::new(),
+        len => len - 1,
+    };
+    // LEDs captured at the same spot as the last one (usually because they weren't found) would
+    // drag the typical spacing down, so they're left out
+    let mut spacings: Vec<f32> = coords
+        .windows(2)
+        .map(|w| distance(w[0], w[1]))
+        .filter(|&spacing| spacing > 0.0)
+        .collect();
+    if spacings.is_empty() {
+        return Vec::new();
+    }
+    spacings.sort_by(f32::total_cmp);
+    let limit = spacings[spacings.len() / 2] * OUTLIER_SPACING;
+    let along = |a: Coord, b: Coord| (2.0 * a.0 - b.0, 2.0 * a.1 - b.1, 2.0 * a.2 - b.2);
+
+    let mut repaired = Vec::new();
+    for i in 0..=last {
+        let far = |j: usize| distance(coords[i], coords[j]) > limit;
+        let close = |j: usize, k: usize| distance(coords[j], coords[k]) <= limit;
+        let replacement = if i == 0 {
+            Some(along(coords[1], coords[2])).filter(|_| far(1) && close(1, 2))
+        } else if i == last {
+            Some(along(coords[last - 1], coords[last - 2]))
+                .filter(|_| far(last - 1) && close(last - 1, last - 2))
+        } else {
+            let (a, b) = (coords[i - 1], coords[i + 1]);
+            Some(((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0, (a.2 + b.2) / 2.0))
+                .filter(|_| far(i - 1) && far(i + 1) && close(i - 1, i + 1))
+        };
+        if let Some(coord) = replacement {
+            coords[i] = coord;
+            repaired.push(i);
+        }
+    }
+    repaired
+}
+
+/// LEDs a scanner couldn't find, which it leaves at the origin (or gives no number for).
+pub fn find_missing(coords: &[Coord]) -> Vec<usize> {
+    (0..coords.len())
+        .filter(|&i| {
+            let (x, y, z) = coords[i];
+            (x == 0.0 && y == 0.0 && z == 0.0) || !(x.is_finite() && y.is_finite() && z.is_finite())
+        })
+        .collect()
+}
+
+/// Places the `missing` LEDs evenly along a straight line between the nearest found LEDs either
+/// side of them on the string, or carries on in line with the last two found LEDs past the ends,
+/// returning the LEDs which were placed. Nothing is placed if fewer than two LEDs were found.
+pub fn fill_missing(coords: &mut [Coord], missing: &[usize]) -> Vec<usize> {
+    let mut found = vec![true; coords.len()];
+    for &i in missing {
+        found[i] = false;
+    }
+    let located: Vec<usize> = (0..coords.len()).filter(|&i| found[i]).collect();
+    if located.len() < 2 {
+        return Vec::new();
+    }
+    let at = |a: Coord, b: Coord, t: f32| {
+        (
+            a.0 + (b.0 - a.0) * t,
+            a.1 + (b.1 - a.1) * t,
+            a.2 + (b.2 - a.2) * t,
+        )
+    };
+    let mut filled = Vec::new();
+    for &i in missing {
+        let after = located.partition_point(|&j| j < i);
+        // The two found LEDs to place it between, or to carry on in line with at the ends
+        let (a, b) = match after {
+            0 => (located[0], located[1]),
+            n if n == located.len() => (located[n - 2], located[n - 1]),
+            n => (located[n - 1], located[n]),
+        };
+        let t = (i as f32 - a as f32) / (b - a) as f32;
+        coords[i] = at(coords[a], coords[b], t);
+        filled.push(i);
+    }
+    filled
+}
+
+/// Moves each LED up to `amount` in a random direction, and droops it by up to `amount` more the
+/// further out from the trunk it is, to mimic measurement error and sagging branches. The same
+/// seed always moves the LEDs the same way.
+pub fn jitter(coords: &mut [Coord], amount: f32, seed: u64) {
+    let reach = coords
+        .iter()
+        .map(|&(x, y, _)| x.hypot(y))
+        .fold(f32::EPSILON, f32::max);
+    let mut rng = StdRng::seed_from_u64(seed);
+    for coord in coords {
+        // Pick a point in the unit ball, so no direction is favoured
+        let (dx, dy, dz) = loop {
+            let d: Coord = (
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            );
+            if d.0 * d.0 + d.1 * d.1 + d.2 * d.2 <= 1.0 {
+                break d;
+            }
+        };
+        let (x, y, z) = *coord;
+        let sag = amount * rng.gen::<f32>() * x.hypot(y) / reach;
+        *coord = (x + dx * amount, y + dy * amount, z + dz * amount - sag);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cone of LEDs `radius` wide and `height` tall, centred on the trunk.
+    fn cone(radius: f32, height: f32) -> Vec<Coord> {
+        (0..200)
+            .map(|i| {
+                let t = i as f32 / 200.0;
+                let angle = i as f32 * 0.7;
+                let r = radius * (1.0 - t);
+                (r * angle.cos(), r * angle.sin(), height * t)
+            })
+            .collect()
+    }
+
+    fn detect(data: &[u8]) -> Units {
+        let layout = parse(
+            data,
+            &LoadOptions {
+                up_axis: UpAxis::Auto,
+                handedness: Handedness::Right,
+                units: Units::Auto,
+                repair_outliers: false,
+                fill_missing: false,
+            },
+        )
+        .unwrap();
+        layout.units
+    }
+
+    #[test]
+    fn gift_files() {
+        assert_eq!(
+            detect(include_bytes!("../../coords/coords_2021.csv")),
+            Units::Gift
+        );
+        assert_eq!(
+            detect(include_bytes!("../../coords/pcamp_tree_coords.csv")),
+            Units::Gift
+        );
+        assert_eq!(detect_units(&cone(1.0, 3.2)), Units::Gift);
+    }
+
+    #[test]
+    fn scans() {
+        assert_eq!(detect_units(&cone(0.6, 1.8)), Units::Metres);
+        assert_eq!(detect_units(&cone(0.4, 1.2)), Units::Metres);
+        assert_eq!(detect_units(&cone(2.5, 6.0)), Units::Metres);
+        assert_eq!(detect_units(&cone(60.0, 180.0)), Units::Centimetres);
+    }
+}
@@ -0,0 +1,520 @@
+use std::{
+    error::Error,
+    fmt::Write as _,
+    io::{self, BufRead, Write},
+    ops::Range,
+    thread,
+};
+
+use crate::sequence::{Rgb, Sequence, SequenceWriter};
+
+/// Which LED channel a column holds.
+#[derive(Clone, Copy)]
+enum Column {
+    Channel { led: usize, channel: usize },
+    Ignored,
+}
+
+fn parse_column(name: &str) -> Column {
+    let name = name
+        .trim()
+        .trim_start_matches('\u{feff}')
+        .to_ascii_lowercase();
+    let channel = match name.get(..2) {
+        Some("r_") => 0,
+        Some("g_") => 1,
+        Some("b_") => 2,
+        _ => return Column::Ignored,
+    };
+    match name[2..].parse() {
+        Ok(led) => Column::Channel { led, channel },
+        Err(_) => Column::Ignored,
+    }
+}
+
+/// Reads the `FRAME_ID,R_0,G_0,B_0,...` format. This accepts the variations found in community
+/// (GIFT) sequences: header names in any case and any order, extra columns such as a leading
+/// index, CRLF line endings, a byte order mark and fractional values.
+///
+/// Files without quoted fields, which is every file we write, are split into chunks of rows
+/// and parsed on all cores.
+pub fn read(data: &[u8]) -> Result<Sequence, Box<dyn Error>> {
+    if !is_plain(data) {
+        return read_quoted(data);
+    }
+    let (columns, body_start) = read_header(data)?;
+    let led_count = led_count(&columns)?;
+
+    let columns = &columns;
+    let chunks = split_rows(
+        &data[body_start..],
+        thread::available_parallelism().map_or(1, |n| n.get()),
+    );
+    let results: Vec<Result<Chunk, RowError>> = thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .iter()
+            .map(|&chunk| scope.spawn(move || parse_rows(chunk, columns, led_count)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("CSV parsing thread panicked"))
+            .collect()
+    });
+
+    let mut frames = Vec::new();
+    let mut clamped_values = 0;
+    for result in results {
+        match result {
+            Ok(chunk) => {
+                frames.extend(chunk.frames);
+                clamped_values += chunk.clamped_values;
+            }
+            Err(error) => {
+                return Err(format!(
+                    "Invalid value {:?} in frame {}",
+                    error.field,
+                    frames.len() + error.row
+                )
+                .into())
+            }
+        }
+    }
+    Ok(Sequence {
+        led_count,
+        frames,
+        clamped_values,
+    })
+}
+
+/// Reads the columns of a plain file, and where the rows after the header start.
+fn read_header(data: &[u8]) -> Result<(Vec<Column>, usize), Box<dyn Error>> {
+    let header_end = data
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(data.len(), |i| i + 1);
+    let columns = std::str::from_utf8(&data[..header_end])?
+        .trim_end_matches(&['\r', '\n'][..])
+        .split(',')
+        .map(parse_column)
+        .collect();
+    Ok((columns, header_end))
+}
+
+fn led_count(columns: &[Column]) -> Result<usize, Box<dyn Error>> {
+    Ok(columns
+        .iter()
+        .filter_map(|column| match column {
+            Column::Channel { led, .. } => Some(led + 1),
+            Column::Ignored => None,
+        })
+        .max()
+        .ok_or("Sequence has no R_n, G_n or B_n columns")?)
+}
+
+/// Whether rows can be split on newlines and fields on commas: there are no quotes, and no
+/// old Mac style line endings.
+fn is_plain(data: &[u8]) -> bool {
+    !data.contains(&b'"')
+        && data
+            .iter()
+            .enumerate()
+            .all(|(i, &b)| b != b'\r' || data.get(i + 1) == Some(&b'\n'))
+}
+
+/// Splits rows into about `count` chunks, each ending at the end of a line.
+fn split_rows(body: &[u8], count: usize) -> Vec<&[u8]> {
+    let target = body.len() / count.max(1) + 1;
+    let mut chunks = Vec::with_capacity(count);
+    let mut rest = body;
+    while !rest.is_empty() {
+        let end = match rest
+            .get(target..)
+            .and_then(|tail| tail.iter().position(|&b| b == b'\n'))
+        {
+            Some(newline) => target + newline + 1,
+            None => rest.len(),
+        };
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
+}
+
+struct Chunk {
+    frames: Vec<Vec<Rgb>>,
+    clamped_values: usize,
+}
+
+/// A field which isn't a number, at a row counted from the start of its chunk.
+struct RowError {
+    row: usize,
+    field: String,
+}
+
+fn parse_rows(chunk: &[u8], columns: &[Column], led_count: usize) -> Result<Chunk, RowError> {
+    let mut frames = Vec::new();
+    let mut clamped_values = 0;
+    let lines = chunk
+        .split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .filter(|line| !line.is_empty());
+    for (row, line) in lines.enumerate() {
+        let mut frame = vec![[0; 3]; led_count];
+        clamped_values +=
+            parse_row(line, columns, &mut frame).map_err(|field| RowError { row, field })?;
+        frames.push(frame);
+    }
+    Ok(Chunk {
+        frames,
+        clamped_values,
+    })
+}
+
+/// Parses one row into `frame`, returning how many values were clamped, or the field which
+/// isn't a number.
+fn parse_row(line: &[u8], columns: &[Column], frame: &mut [Rgb]) -> Result<usize, String> {
+    let mut clamped_values = 0;
+    for (field, column) in line.split(|&b| b == b',').zip(columns) {
+        if let Column::Channel { led, channel } = *column {
+            let (value, clamped) = parse_value(field)
+                .ok_or_else(|| String::from_utf8_lossy(field).trim().to_string())?;
+            frame[led][channel] = value;
+            clamped_values += clamped as usize;
+        }
+    }
+    Ok(clamped_values)
+}
+
+/// Parses one channel value, and whether it was outside 0..=255. Whole numbers, which is
+/// nearly every value, skip float parsing.
+fn parse_value(field: &[u8]) -> Option<(u8, bool)> {
+    if !field.is_empty() && field.len() <= 3 && field.iter().all(u8::is_ascii_digit) {
+        let v = field
+            .iter()
+            .fold(0u32, |v, &digit| v * 10 + u32::from(digit - b'0'));
+        return Some((v.min(255) as u8, v > 255));
+    }
+    let v = std::str::from_utf8(field)
+        .ok()?
+        .trim()
+        .parse::<f32>()
+        .ok()?;
+    Some((v.round() as u8, !(0.0..=255.0).contains(&v)))
+}
+
+/// The general case, for files with quoted fields.
+fn read_quoted(data: &[u8]) -> Result<Sequence, Box<dyn Error>> {
+    let mut sequence_csv = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .flexible(true)
+        .from_reader(data);
+    let columns: Vec<Column> = sequence_csv.headers()?.iter().map(parse_column).collect();
+    let led_count = led_count(&columns)?;
+    let mut frames = Vec::new();
+    let mut clamped_values = 0;
+    for (index, record) in sequence_csv.records().enumerate() {
+        let record = record?;
+        let mut frame = vec![[0; 3]; led_count];
+        for (field, column) in record.iter().zip(&columns) {
+            if let Column::Channel { led, channel } = *column {
+                let v = field
+                    .trim()
+                    .parse::<f32>()
+                    .map_err(|_| format!("Invalid value {:?} in frame {}", field.trim(), index))?;
+                if !(0.0..=255.0).contains(&v) {
+                    clamped_values += 1;
+                }
+                frame[led][channel] = v.round() as u8;
+            }
+        }
+        frames.push(frame);
+    }
+    Ok(Sequence {
+        led_count,
+        frames,
+        clamped_values,
+    })
+}
+
+/// Where each row of a plain CSV file is, so frames can be read in any order without parsing
+/// the rows before them.
+pub struct CsvIndex {
+    columns: Vec<Column>,
+    led_count: usize,
+    rows: Vec<Range<usize>>,
+}
+
+impl CsvIndex {
+    /// Indexes a file, or returns `None` if it has quoted fields and has to be read with
+    /// [`read`].
+    pub fn build(data: &[u8]) -> Result<Option<Self>, Box<dyn Error>> {
+        if !is_plain(data) {
+            return Ok(None);
+        }
+        let (columns, mut start) = read_header(data)?;
+        let led_count = led_count(&columns)?;
+        let mut rows = Vec::new();
+        while start < data.len() {
+            let newline = data[start..]
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(data.len(), |i| start + i);
+            let end = if data[start..newline].ends_with(b"\r") {
+                newline - 1
+            } else {
+                newline
+            };
+            if end > start {
+                rows.push(start..end);
+            }
+            start = newline + 1;
+        }
+        Ok(Some(Self {
+            columns,
+            led_count,
+            rows,
+        }))
+    }
+
+    pub fn led_count(&self) -> usize {
+        self.led_count
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Parses frame `index` of `data`, the file this index was built from, into `frame`.
+    pub fn read_frame(
+        &self,
+        data: &[u8],
+        index: usize,
+        frame: &mut Vec<Rgb>,
+    ) -> Result<(), Box<dyn Error>> {
+        frame.clear();
+        frame.resize(self.led_count, [0; 3]);
+        parse_row(&data[self.rows[index].clone()], &self.columns, frame)
+            .map_err(|field| format!("Invalid value {:?} in frame {}", field, index))?;
+        Ok(())
+    }
+}
+
+/// Reads a CSV one frame at a time as it arrives, such as the output of
+/// `xmas_tree_gen generate --preview` piped into the player.
+pub struct CsvStreamReader<R: BufRead> {
+    reader: R,
+    columns: Vec<Column>,
+    led_count: usize,
+    line: Vec<u8>,
+    frames: usize,
+}
+
+impl<R: BufRead> CsvStreamReader<R> {
+    /// Reads the header, waiting for it to arrive if necessary.
+    pub fn new(mut reader: R) -> Result<Self, Box<dyn Error>> {
+        let mut header = Vec::new();
+        if reader.read_until(b'\n', &mut header)? == 0 {
+            return Err("Stream ended before its header".into());
+        }
+        let (columns, _) = read_header(&header)?;
+        let led_count = led_count(&columns)?;
+        Ok(Self {
+            reader,
+            columns,
+            led_count,
+            line: Vec::new(),
+            frames: 0,
+        })
+    }
+
+    pub fn led_count(&self) -> usize {
+        self.led_count
+    }
+
+    /// Reads the next frame into `frame`, waiting for it to arrive. Returns false at the end of
+    /// the stream.
+    pub fn read_frame(&mut self, frame: &mut Vec<Rgb>) -> Result<bool, Box<dyn Error>> {
+        loop {
+            self.line.clear();
+            if self.reader.read_until(b'\n', &mut self.line)? == 0 {
+                return Ok(false);
+            }
+            let line = self.line.strip_suffix(b"\n").unwrap_or(&self.line);
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line.is_empty() {
+                continue;
+            }
+            frame.clear();
+            frame.resize(self.led_count, [0; 3]);
+            parse_row(line, &self.columns, frame)
+                .map_err(|field| format!("Invalid value {:?} in frame {}", field, self.frames))?;
+            self.frames += 1;
+            return Ok(true);
+        }
+    }
+}
+
+pub struct CsvWriter<W: Write> {
+    inner: csv::Writer<W>,
+    next_frame: usize,
+    /// Reused for formatting the frame number.
+    frame_id: String,
+    /// Every channel value as text, so frames are written without formatting each value.
+    channel_text: Vec<String>,
+}
+
+fn channel_text() -> Vec<String> {
+    (0..=u8::MAX).map(|v| v.to_string()).collect()
+}
+
+impl<W: Write> CsvWriter<W> {
+    pub fn new(writer: W, led_count: usize) -> io::Result<Self> {
+        let mut inner = csv::Writer::from_writer(writer);
+        inner.write_field("FRAME_ID")?;
+        inner.write_record(
+            (0..led_count)
+                .flat_map(|i| [format!("R_{}", i), format!("G_{}", i), format!("B_{}", i)]),
+        )?;
+        Ok(Self {
+            inner,
+            next_frame: 0,
+            frame_id: String::new(),
+            channel_text: channel_text(),
+        })
+    }
+
+    /// Continues appending to a partially written file.
+    pub fn resume(writer: W, next_frame: usize) -> Self {
+        Self {
+            inner: csv::Writer::from_writer(writer),
+            next_frame,
+            frame_id: String::new(),
+            channel_text: channel_text(),
+        }
+    }
+}
+
+impl<W: Write> SequenceWriter for CsvWriter<W> {
+    fn write_frame(&mut self, frame: &[Rgb]) -> io::Result<()> {
+        self.frame_id.clear();
+        write!(self.frame_id, "{}", self.next_frame).unwrap();
+        self.inner.write_field(&self.frame_id)?;
+        let channel_text = &self.channel_text;
+        self.inner.write_record(
+            frame
+                .iter()
+                .flat_map(|rgb| rgb.iter())
+                .map(|&v| &channel_text[v as usize]),
+        )?;
+        self.next_frame += 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What `convert` writes for the samples below: two frames of two LEDs.
+    const CANONICAL: &str = "FRAME_ID,R_0,G_0,B_0,R_1,G_1,B_1\n\
+                             0,255,0,0,0,128,255\n\
+                             1,10,20,30,40,50,60\n";
+
+    /// Checks `sample` reads as the same frames as the canonical file by each of the readers,
+    /// and is written back out as it.
+    fn assert_canonical(sample: &str) {
+        let sequence = read(sample.as_bytes()).unwrap();
+        let canonical = read(CANONICAL.as_bytes()).unwrap();
+        assert_eq!(sequence.led_count, canonical.led_count);
+        assert_eq!(sequence.frames, canonical.frames);
+
+        let mut frame = Vec::new();
+        if let Some(index) = CsvIndex::build(sample.as_bytes()).unwrap() {
+            assert_eq!(index.frame_count(), canonical.frames.len());
+            for (i, expected) in canonical.frames.iter().enumerate() {
+                index.read_frame(sample.as_bytes(), i, &mut frame).unwrap();
+                assert_eq!(&frame, expected);
+            }
+        }
+        let mut stream = CsvStreamReader::new(sample.as_bytes()).unwrap();
+        for expected in &canonical.frames {
+            assert!(stream.read_frame(&mut frame).unwrap());
+            assert_eq!(&frame, expected);
+        }
+        assert!(!stream.read_frame(&mut frame).unwrap());
+
+        let mut written = Vec::new();
+        let mut writer = CsvWriter::new(&mut written, sequence.led_count).unwrap();
+        for frame in &sequence.frames {
+            writer.write_frame(frame).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+        assert_eq!(String::from_utf8(written).unwrap(), CANONICAL);
+    }
+
+    #[test]
+    fn canonical() {
+        assert_canonical(CANONICAL);
+    }
+
+    #[test]
+    fn byte_order_mark() {
+        assert_canonical(
+            "\u{feff}FRAME_ID,R_0,G_0,B_0,R_1,G_1,B_1\n\
+             0,255,0,0,0,128,255\n\
+             1,10,20,30,40,50,60\n",
+        );
+    }
+
+    #[test]
+    fn crlf() {
+        assert_canonical(
+            "FRAME_ID,R_0,G_0,B_0,R_1,G_1,B_1\r\n\
+             0,255,0,0,0,128,255\r\n\
+             1,10,20,30,40,50,60\r\n",
+        );
+    }
+
+    #[test]
+    fn index_column() {
+        // As written by pandas' `to_csv` with its default index
+        assert_canonical(
+            ",FRAME_ID,R_0,G_0,B_0,R_1,G_1,B_1\n\
+             0,0,255,0,0,0,128,255\n\
+             1,1,10,20,30,40,50,60\n",
+        );
+    }
+
+    #[test]
+    fn mixed_case_header() {
+        assert_canonical(
+            "frame_id,r_0,G_0,b_0,R_1,g_1,B_1\n\
+             0,255,0,0,0,128,255\n\
+             1,10,20,30,40,50,60\n",
+        );
+    }
+
+    #[test]
+    fn extra_columns() {
+        assert_canonical(
+            "FRAME_ID,TIME,R_0,G_0,B_0,R_1,G_1,B_1,NOTE\n\
+             0,0.00,255,0,0,0,128,255,start\n\
+             1,0.03,10,20,30,40,50,60,\n",
+        );
+    }
+
+    #[test]
+    fn everything_at_once() {
+        assert_canonical(
+            "\u{feff},Frame_Id,b_1,G_1,r_1,B_0,g_0,R_0,Comment\r\n\
+             0,0,255,128,0,0,0,255.0,\"red, then blue\"\r\n\
+             1,1,60,50,40,30,20,10,\r\n",
+        );
+    }
+}
@@ -0,0 +1,347 @@
+//! A compact binary sequence format for effects which leave most LEDs unchanged between frames.
+//!
+//! The file starts with [`MAGIC`], a version byte and the LED count as a little-endian `u32`.
+//! Each frame is then a tag byte followed by its payload:
+//!
+//! - [`KEYFRAME`]: every LED's RGB bytes.
+//! - [`DELTA`]: a varint run count, then for each run a varint count of unchanged LEDs to skip,
+//!   a varint count of changed LEDs, and the RGB bytes of those changed LEDs.
+//! - [`REPEAT`]: a varint count of times to repeat the previous frame.
+
+use std::io::{self, ErrorKind, Read, Write};
+
+use crate::sequence::{Rgb, Sequence, SequenceWriter};
+
+pub const MAGIC: &[u8; 4] = b"XTSD";
+pub const VERSION: u8 = 1;
+
+pub const KEYFRAME: u8 = 0;
+pub const DELTA: u8 = 1;
+pub const REPEAT: u8 = 2;
+
+fn write_varint(writer: &mut impl Write, mut value: usize) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            return writer.write_all(&[byte]);
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_varint(reader: &mut impl Read) -> io::Result<usize> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        value |= ((byte[0] & 0x7F) as usize) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(io::Error::new(ErrorKind::InvalidData, "Varint too long"))
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg.into())
+}
+
+pub struct DeltaWriter<W: Write> {
+    inner: W,
+    previous: Option<Vec<Rgb>>,
+    pending_repeats: usize,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> DeltaWriter<W> {
+    pub fn new(mut writer: W, led_count: usize) -> io::Result<Self> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        writer.write_all(&(led_count as u32).to_le_bytes())?;
+        Ok(Self::resume(writer))
+    }
+
+    /// Continues appending to a partially written file. The next frame is written as a
+    /// keyframe, since the previous frame is not known.
+    pub fn resume(writer: W) -> Self {
+        Self {
+            inner: writer,
+            previous: None,
+            pending_repeats: 0,
+            buffer: Vec::new(),
+        }
+    }
+
+    fn write_pending_repeats(&mut self) -> io::Result<()> {
+        if self.pending_repeats > 0 {
+            self.inner.write_all(&[REPEAT])?;
+            write_varint(&mut self.inner, self.pending_repeats)?;
+            self.pending_repeats = 0;
+        }
+        Ok(())
+    }
+
+    fn encode_delta(&mut self, previous: &[Rgb], frame: &[Rgb]) -> io::Result<()> {
+        let mut runs = Vec::new();
+        let mut index = 0;
+        while index < frame.len() {
+            let start = index;
+            while index < frame.len() && frame[index] == previous[index] {
+                index += 1;
+            }
+            let changed_start = index;
+            while index < frame.len() && frame[index] != previous[index] {
+                index += 1;
+            }
+            if changed_start < index {
+                runs.push((changed_start - start, changed_start..index));
+            }
+        }
+
+        self.buffer.clear();
+        self.buffer.push(DELTA);
+        write_varint(&mut self.buffer, runs.len())?;
+        for (skip, range) in runs {
+            write_varint(&mut self.buffer, skip)?;
+            write_varint(&mut self.buffer, range.len())?;
+            for rgb in &frame[range] {
+                self.buffer.extend_from_slice(rgb);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> SequenceWriter for DeltaWriter<W> {
+    fn write_frame(&mut self, frame: &[Rgb]) -> io::Result<()> {
+        if self.previous.as_deref() == Some(frame) {
+            self.pending_repeats += 1;
+            return Ok(());
+        }
+        self.write_pending_repeats()?;
+
+        let keyframe_len = 1 + frame.len() * 3;
+        match self.previous.take() {
+            Some(previous) if previous.len() == frame.len() => {
+                self.encode_delta(&previous, frame)?;
+            }
+            _ => self.buffer.clear(),
+        }
+        if self.buffer.is_empty() || self.buffer.len() >= keyframe_len {
+            self.buffer.clear();
+            self.buffer.push(KEYFRAME);
+            for rgb in frame {
+                self.buffer.extend_from_slice(rgb);
+            }
+        }
+        self.inner.write_all(&self.buffer)?;
+        self.previous = Some(frame.to_vec());
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_pending_repeats()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for DeltaWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.write_pending_repeats();
+    }
+}
+
+/// Reads the header, returning the LED count.
+fn read_header(reader: &mut impl Read) -> io::Result<usize> {
+    let mut header = [0; 9];
+    reader.read_exact(&mut header)?;
+    if &header[0..4] != MAGIC {
+        return Err(invalid_data("Not a delta sequence file"));
+    }
+    if header[4] != VERSION {
+        return Err(invalid_data(format!(
+            "Unsupported delta sequence version {}",
+            header[4]
+        )));
+    }
+    Ok(u32::from_le_bytes([header[5], header[6], header[7], header[8]]) as usize)
+}
+
+/// Reads the next frame record, updating `frame` from the frame before it (if any). Returns how
+/// many frames the record stands for, or `None` at the end of the file.
+fn read_record(
+    reader: &mut impl Read,
+    frame: &mut Option<Vec<Rgb>>,
+    led_count: usize,
+) -> io::Result<Option<usize>> {
+    let mut tag = [0];
+    match reader.read_exact(&mut tag) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    match tag[0] {
+        KEYFRAME => {
+            let frame = frame.get_or_insert_with(Vec::new);
+            frame.resize(led_count, [0; 3]);
+            for rgb in frame {
+                reader.read_exact(rgb)?;
+            }
+            Ok(Some(1))
+        }
+        DELTA => {
+            let frame = frame
+                .as_mut()
+                .ok_or_else(|| invalid_data("Delta frame without a preceding keyframe"))?;
+            let mut index: usize = 0;
+            for _ in 0..read_varint(reader)? {
+                index = index.saturating_add(read_varint(reader)?);
+                let len = read_varint(reader)?;
+                let run = frame
+                    .get_mut(index..index.saturating_add(len))
+                    .ok_or_else(|| invalid_data("Delta run past the last LED"))?;
+                for rgb in run {
+                    reader.read_exact(rgb)?;
+                }
+                index += len;
+            }
+            Ok(Some(1))
+        }
+        REPEAT => {
+            if frame.is_none() {
+                return Err(invalid_data("Repeat without a preceding frame"));
+            }
+            Ok(Some(read_varint(reader)?))
+        }
+        other => Err(invalid_data(format!("Unknown frame tag {}", other))),
+    }
+}
+
+pub fn read(mut reader: impl Read) -> io::Result<Sequence> {
+    let led_count = read_header(&mut reader)?;
+    let mut frames: Vec<Vec<Rgb>> = Vec::new();
+    let mut frame = None;
+    while let Some(count) = read_record(&mut reader, &mut frame, led_count)? {
+        if let Some(frame) = &frame {
+            frames.resize(frames.len() + count, frame.clone());
+        }
+    }
+    Ok(Sequence {
+        led_count,
+        frames,
+        clamped_values: 0,
+    })
+}
+
+/// How many frames apart the index keeps decoded copies of frames to start seeking from.
+const CHECKPOINT_INTERVAL: usize = 256;
+
+struct Record {
+    first_frame: usize,
+    offset: usize,
+}
+
+/// The frame as it is after a record.
+struct Checkpoint {
+    record: usize,
+    frame: Vec<Rgb>,
+}
+
+/// Where each record of a delta file is, with a decoded frame every [`CHECKPOINT_INTERVAL`]
+/// frames, so seeking only has to decode forward from the nearest checkpoint.
+pub struct DeltaIndex {
+    led_count: usize,
+    frame_count: usize,
+    records: Vec<Record>,
+    checkpoints: Vec<Checkpoint>,
+}
+
+/// How far a reader of a [`DeltaIndex`] has decoded, so playing forward decodes each record
+/// once.
+#[derive(Default)]
+pub struct DeltaCursor {
+    record: Option<usize>,
+    frame: Option<Vec<Rgb>>,
+}
+
+impl DeltaIndex {
+    /// Indexes a whole file, which also checks that every record is valid.
+    pub fn build(data: &[u8]) -> io::Result<Self> {
+        let mut reader = data;
+        let led_count = read_header(&mut reader)?;
+        let mut frame_count = 0;
+        let mut records = Vec::new();
+        let mut checkpoints = Vec::new();
+        let mut next_checkpoint = 0;
+        let mut frame = None;
+        loop {
+            let offset = data.len() - reader.len();
+            let count = match read_record(&mut reader, &mut frame, led_count)? {
+                Some(count) => count,
+                None => break,
+            };
+            records.push(Record {
+                first_frame: frame_count,
+                offset,
+            });
+            if let (Some(frame), true) = (&frame, frame_count >= next_checkpoint) {
+                checkpoints.push(Checkpoint {
+                    record: records.len() - 1,
+                    frame: frame.clone(),
+                });
+                next_checkpoint = frame_count + CHECKPOINT_INTERVAL;
+            }
+            frame_count += count;
+        }
+        Ok(Self {
+            led_count,
+            frame_count,
+            records,
+            checkpoints,
+        })
+    }
+
+    pub fn led_count(&self) -> usize {
+        self.led_count
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    /// Decodes frame `index` of `data`, the file this index was built from, continuing from
+    /// where `cursor` got to if that's closer than the nearest checkpoint.
+    pub fn read_frame<'c>(
+        &self,
+        data: &[u8],
+        cursor: &'c mut DeltaCursor,
+        index: usize,
+    ) -> io::Result<&'c [Rgb]> {
+        assert!(index < self.frame_count, "Frame {} out of range", index);
+        let target = self
+            .records
+            .partition_point(|record| record.first_frame <= index)
+            - 1;
+        let checkpoint =
+            &self.checkpoints[self.checkpoints.partition_point(|c| c.record <= target) - 1];
+        let start = match cursor.record {
+            Some(record) if (checkpoint.record..=target).contains(&record) => record + 1,
+            _ => {
+                cursor
+                    .frame
+                    .get_or_insert_with(Vec::new)
+                    .clone_from(&checkpoint.frame);
+                checkpoint.record + 1
+            }
+        };
+        if let Some(record) = self.records.get(start).filter(|_| start <= target) {
+            let mut reader = &data[record.offset..];
+            for _ in start..=target {
+                read_record(&mut reader, &mut cursor.frame, self.led_count)?;
+            }
+        }
+        cursor.record = Some(target);
+        Ok(cursor.frame.as_deref().unwrap_or_default())
+    }
+}
@@ -0,0 +1,47 @@
+//! Simulated LED faults, for checking how an effect looks on a tree with a few dead pixels.
+
+use rand::{prelude::StdRng, Rng, SeedableRng};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FailureMode {
+    /// Always fully on.
+    StuckOn,
+    /// Never lights.
+    StuckOff,
+    /// Red and green swapped, like an LED from a batch with a different channel order.
+    Swapped,
+}
+
+impl FailureMode {
+    pub fn apply<T: Copy>(self, [r, g, b]: [T; 3], on: T, off: T) -> [T; 3] {
+        match self {
+            Self::StuckOn => [on; 3],
+            Self::StuckOff => [off; 3],
+            Self::Swapped => [g, r, b],
+        }
+    }
+}
+
+/// Picks which LEDs fail, each with probability `rate`. The same seed always fails the same LEDs.
+pub fn pick(led_count: usize, rate: f32, seed: u64) -> Vec<Option<FailureMode>> {
+    const MODES: [FailureMode; 3] = [
+        FailureMode::StuckOn,
+        FailureMode::StuckOff,
+        FailureMode::Swapped,
+    ];
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..led_count)
+        .map(|_| {
+            let fails = rng.gen::<f32>() < rate;
+            let mode = MODES[rng.gen_range(0..MODES.len())];
+            Some(mode).filter(|_| fails)
+        })
+        .collect()
+}
+
+pub fn parse_rate(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("Failure rate must be between 0 and 1, got {}", s)),
+    }
+}
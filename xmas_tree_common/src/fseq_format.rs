@@ -0,0 +1,482 @@
+//! The `.fseq` format of xLights and Falcon Player, so sequences can be shared with the rest of
+//! the LED sequencing world, and long shows take a fraction of the space of CSV.
+//!
+//! Version 2 files start with [`MAGIC`] and a 32 byte header giving the offset of the frame
+//! data, the number of channels (three per LED), the frame count, the time each frame shows for
+//! in milliseconds and how the frames are compressed. Compressed files list their blocks next,
+//! each as its first frame and length in bytes, and the blocks follow one after the other from
+//! the data offset. Frames are written in zstd compressed blocks. Uncompressed and zlib
+//! compressed files, and version 1 files, can be read too. Files with sparse ranges are read as
+//! the channels they hold, in order.
+
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use flate2::read::ZlibDecoder;
+
+use crate::sequence::{Rgb, Sequence, SequenceWriter};
+
+pub const MAGIC: &[u8; 4] = b"PSEQ";
+/// The magic of version 1 files from older versions of xLights.
+pub const OLD_MAGIC: &[u8; 4] = b"FSEQ";
+pub const VERSION: u8 = 2;
+
+const HEADER_LEN: usize = 32;
+/// Version 1 headers stop before the compression and block count fields.
+const OLD_HEADER_LEN: usize = 28;
+const UNCOMPRESSED: u8 = 0;
+const ZSTD: u8 = 1;
+const ZLIB: u8 = 2;
+/// The header has 12 bits for the number of blocks.
+const MAX_BLOCKS: usize = 0xFFF;
+/// About how much frame data goes in each block before compression. Smaller blocks are quicker
+/// to seek within, and larger ones compress better.
+const BLOCK_BYTES: usize = 1 << 20;
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg.into())
+}
+
+fn u16_at(data: &[u8], offset: usize) -> usize {
+    u16::from_le_bytes([data[offset], data[offset + 1]]) as usize
+}
+
+fn u32_at(data: &[u8], offset: usize) -> usize {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ]) as usize
+}
+
+/// Writes version 2 files. The header needs the frame count and the position of every block,
+/// so compressed blocks are kept in memory and nothing is written until `finish`.
+pub struct FseqWriter<W: Write> {
+    inner: W,
+    channels: usize,
+    step_ms: u8,
+    frames_per_block: usize,
+    frame_count: usize,
+    /// Frames which haven't been compressed into a block yet.
+    pending: Vec<u8>,
+    /// The first frame and compressed data of each block.
+    blocks: Vec<(usize, Vec<u8>)>,
+    finished: bool,
+}
+
+impl<W: Write> FseqWriter<W> {
+    /// Frames are shown for a whole number of milliseconds, so `fps` is rounded to the nearest
+    /// rate which allows.
+    pub fn new(writer: W, led_count: usize, fps: f32) -> Self {
+        let channels = led_count * 3;
+        Self {
+            inner: writer,
+            channels,
+            step_ms: (1000.0 / fps).round().clamp(1.0, 255.0) as u8,
+            frames_per_block: (BLOCK_BYTES / channels.max(1)).max(1),
+            frame_count: 0,
+            pending: Vec::new(),
+            blocks: Vec::new(),
+            finished: false,
+        }
+    }
+
+    fn compress_pending(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let first_frame = self.frame_count - self.pending.len() / self.channels.max(1);
+        let block = zstd::stream::encode_all(&self.pending[..], zstd::DEFAULT_COMPRESSION_LEVEL)?;
+        self.blocks.push((first_frame, block));
+        self.pending.clear();
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let block_count = self.blocks.len();
+        if block_count > MAX_BLOCKS {
+            return Err(io::Error::other(format!(
+                "Too many frames for an fseq file: {} blocks of {} frames",
+                block_count, self.frames_per_block
+            )));
+        }
+        let data_offset = HEADER_LEN + block_count * 8;
+        let id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_micros() as u64);
+        let mut header = Vec::with_capacity(data_offset);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&(data_offset as u16).to_le_bytes());
+        header.extend_from_slice(&[0, VERSION]);
+        header.extend_from_slice(&(HEADER_LEN as u16).to_le_bytes());
+        header.extend_from_slice(&(self.channels as u32).to_le_bytes());
+        header.extend_from_slice(&(self.frame_count as u32).to_le_bytes());
+        header.extend_from_slice(&[
+            self.step_ms,
+            0,
+            ZSTD | ((block_count >> 8) as u8) << 4,
+            block_count as u8,
+            // No sparse ranges
+            0,
+            0,
+        ]);
+        header.extend_from_slice(&id.to_le_bytes());
+        for (first_frame, block) in &self.blocks {
+            header.extend_from_slice(&(*first_frame as u32).to_le_bytes());
+            header.extend_from_slice(&(block.len() as u32).to_le_bytes());
+        }
+        self.inner.write_all(&header)
+    }
+}
+
+impl<W: Write> SequenceWriter for FseqWriter<W> {
+    fn write_frame(&mut self, frame: &[Rgb]) -> io::Result<()> {
+        if frame.len() * 3 != self.channels {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Frame has {} LEDs, but the file has {}",
+                    frame.len(),
+                    self.channels / 3
+                ),
+            ));
+        }
+        for rgb in frame {
+            self.pending.extend_from_slice(rgb);
+        }
+        self.frame_count += 1;
+        if self.pending.len() >= self.frames_per_block * self.channels {
+            self.compress_pending()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if !self.finished {
+            self.finished = true;
+            self.compress_pending()?;
+            self.write_header()?;
+            for (_, block) in &self.blocks {
+                self.inner.write_all(block)?;
+            }
+        }
+        self.inner.flush()
+    }
+}
+
+struct Block {
+    first_frame: usize,
+    offset: usize,
+    len: usize,
+}
+
+/// Where each frame of an fseq file is, so any of them can be read without decompressing the
+/// blocks before it.
+pub struct FseqIndex {
+    channels: usize,
+    frame_count: usize,
+    step_ms: u8,
+    compression: u8,
+    data_offset: usize,
+    blocks: Vec<Block>,
+}
+
+/// The block a reader of an [`FseqIndex`] last decompressed, so playing forward decompresses
+/// each block once.
+#[derive(Default)]
+pub struct FseqCursor {
+    block: Option<usize>,
+    data: Vec<u8>,
+    frame: Vec<Rgb>,
+}
+
+impl FseqIndex {
+    /// Reads the header of a whole file, checking the frames it lists are all in the file.
+    pub fn build(data: &[u8]) -> io::Result<Self> {
+        if data.len() < HEADER_LEN || !(data.starts_with(MAGIC) || data.starts_with(OLD_MAGIC)) {
+            return Err(invalid_data("Not an fseq file"));
+        }
+        let major_version = data[7];
+        if major_version != 1 && major_version != VERSION {
+            return Err(invalid_data(format!(
+                "Unsupported fseq version {}",
+                major_version
+            )));
+        }
+        let data_offset = u16_at(data, 4);
+        let channels = u32_at(data, 10);
+        if !channels.is_multiple_of(3) {
+            return Err(invalid_data(format!(
+                "fseq file has {} channels, which isn't a whole number of RGB LEDs",
+                channels
+            )));
+        }
+        let frame_count = u32_at(data, 14);
+        // Version 1 files are never compressed, and use the following bytes for other things
+        let (header_len, compression, block_count) = if major_version == 1 {
+            (OLD_HEADER_LEN, UNCOMPRESSED, 0)
+        } else {
+            (
+                HEADER_LEN,
+                data[20] & 0x0F,
+                ((data[20] as usize & 0xF0) << 4) | data[21] as usize,
+            )
+        };
+        // Otherwise the first block would be read from the block list itself
+        if data_offset < header_len + block_count * 8 {
+            return Err(invalid_data(format!(
+                "fseq frame data starts at {}, inside the header",
+                data_offset
+            )));
+        }
+
+        let mut blocks = Vec::new();
+        match compression {
+            UNCOMPRESSED => {
+                if data.len() < data_offset + frame_count * channels {
+                    return Err(invalid_data("fseq file is shorter than its frames"));
+                }
+            }
+            ZSTD | ZLIB => {
+                if data.len() < data_offset {
+                    return Err(invalid_data("fseq file is shorter than its block list"));
+                }
+                let mut offset = data_offset;
+                for i in 0..block_count {
+                    let entry = HEADER_LEN + i * 8;
+                    let (first_frame, len) = (u32_at(data, entry), u32_at(data, entry + 4));
+                    // xLights reserves more entries than it needs, and leaves the rest empty
+                    if len == 0 {
+                        continue;
+                    }
+                    if offset + len > data.len() {
+                        return Err(invalid_data("fseq block past the end of the file"));
+                    }
+                    blocks.push(Block {
+                        first_frame,
+                        offset,
+                        len,
+                    });
+                    offset += len;
+                }
+                if frame_count > 0 && blocks.first().is_none_or(|b| b.first_frame != 0) {
+                    return Err(invalid_data("fseq file doesn't start with a block"));
+                }
+            }
+            other => {
+                return Err(invalid_data(format!(
+                    "Unknown fseq compression type {}",
+                    other
+                )))
+            }
+        }
+        Ok(Self {
+            channels,
+            frame_count,
+            step_ms: data[18],
+            compression,
+            data_offset,
+            blocks,
+        })
+    }
+
+    pub fn led_count(&self) -> usize {
+        self.channels / 3
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    /// The frame rate the file was made for.
+    pub fn fps(&self) -> Option<f32> {
+        Some(self.step_ms)
+            .filter(|&step_ms| step_ms > 0)
+            .map(|step_ms| 1000.0 / step_ms as f32)
+    }
+
+    /// Reads frame `index` of `data`, the file this index was built from, decompressing its
+    /// block unless `cursor` already has it.
+    pub fn read_frame<'c>(
+        &self,
+        data: &[u8],
+        cursor: &'c mut FseqCursor,
+        index: usize,
+    ) -> io::Result<&'c [Rgb]> {
+        assert!(index < self.frame_count, "Frame {} out of range", index);
+        let channels = if self.compression == UNCOMPRESSED {
+            let start = self.data_offset + index * self.channels;
+            &data[start..start + self.channels]
+        } else {
+            let block = self
+                .blocks
+                .partition_point(|block| block.first_frame <= index)
+                - 1;
+            if cursor.block != Some(block) {
+                cursor.block = None;
+                self.decompress(data, &self.blocks[block], &mut cursor.data)?;
+                cursor.block = Some(block);
+            }
+            let start = (index - self.blocks[block].first_frame) * self.channels;
+            cursor
+                .data
+                .get(start..start + self.channels)
+                .ok_or_else(|| invalid_data(format!("fseq frame {} is missing", index)))?
+        };
+        cursor.frame.clear();
+        cursor
+            .frame
+            .extend(channels.chunks_exact(3).map(|rgb| [rgb[0], rgb[1], rgb[2]]));
+        Ok(&cursor.frame)
+    }
+
+    fn decompress(&self, data: &[u8], block: &Block, out: &mut Vec<u8>) -> io::Result<()> {
+        let compressed = &data[block.offset..block.offset + block.len];
+        out.clear();
+        if self.compression == ZSTD {
+            zstd::stream::copy_decode(compressed, &mut *out)
+        } else {
+            ZlibDecoder::new(compressed).read_to_end(out).map(|_| ())
+        }
+    }
+}
+
+pub fn read(data: &[u8]) -> io::Result<Sequence> {
+    let index = FseqIndex::build(data)?;
+    let mut cursor = FseqCursor::default();
+    let frames = (0..index.frame_count())
+        .map(|i| index.read_frame(data, &mut cursor, i).map(<[Rgb]>::to_vec))
+        .collect::<io::Result<_>>()?;
+    Ok(Sequence {
+        led_count: index.led_count(),
+        frames,
+        clamped_values: 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use super::*;
+
+    /// Three frames of two LEDs.
+    fn frames() -> Vec<Vec<Rgb>> {
+        vec![
+            vec![[255, 0, 0], [0, 128, 255]],
+            vec![[10, 20, 30], [40, 50, 60]],
+            vec![[1, 2, 3], [4, 5, 6]],
+        ]
+    }
+
+    /// The frames as fseq stores them, one channel after another.
+    fn channels(frames: &[Vec<Rgb>]) -> Vec<u8> {
+        frames.iter().flatten().flatten().copied().collect()
+    }
+
+    /// A version 2 header for `frames()` at 40 fps, with its block list but without the frames.
+    fn header(data_offset: usize, compression: u8, blocks: &[(u32, u32)]) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&(data_offset as u16).to_le_bytes());
+        header.extend_from_slice(&[0, VERSION]);
+        header.extend_from_slice(&(HEADER_LEN as u16).to_le_bytes());
+        header.extend_from_slice(&6u32.to_le_bytes());
+        header.extend_from_slice(&3u32.to_le_bytes());
+        header.extend_from_slice(&[25, 0, compression, blocks.len() as u8, 0, 0]);
+        header.extend_from_slice(&0u64.to_le_bytes());
+        for (first_frame, len) in blocks {
+            header.extend_from_slice(&first_frame.to_le_bytes());
+            header.extend_from_slice(&len.to_le_bytes());
+        }
+        header
+    }
+
+    /// Checks `data` reads back as `frames()` through the index, in order and by seeking.
+    fn assert_frames(data: &[u8]) {
+        let expected = frames();
+        let index = FseqIndex::build(data).unwrap();
+        assert_eq!(index.led_count(), 2);
+        assert_eq!(index.frame_count(), expected.len());
+        assert_eq!(index.fps(), Some(40.0));
+        let mut cursor = FseqCursor::default();
+        for i in [0, 1, 2, 1, 0, 2] {
+            assert_eq!(
+                index.read_frame(data, &mut cursor, i).unwrap(),
+                &expected[i][..]
+            );
+        }
+    }
+
+    #[test]
+    fn written_across_blocks() {
+        let mut data = Vec::new();
+        let mut writer = FseqWriter::new(&mut data, 2, 40.0);
+        writer.frames_per_block = 2;
+        for frame in frames() {
+            writer.write_frame(&frame).unwrap();
+        }
+        writer.finish().unwrap();
+        assert_frames(&data);
+        let index = FseqIndex::build(&data).unwrap();
+        assert_eq!(index.blocks.len(), 2);
+        assert_eq!(index.blocks[1].first_frame, 2);
+    }
+
+    #[test]
+    fn uncompressed() {
+        let mut data = header(HEADER_LEN, UNCOMPRESSED, &[]);
+        data.extend(channels(&frames()));
+        assert_frames(&data);
+    }
+
+    #[test]
+    fn version_1() {
+        let mut data = Vec::new();
+        data.extend_from_slice(OLD_MAGIC);
+        data.extend_from_slice(&(OLD_HEADER_LEN as u16).to_le_bytes());
+        data.extend_from_slice(&[0, 1]);
+        data.extend_from_slice(&(OLD_HEADER_LEN as u16).to_le_bytes());
+        data.extend_from_slice(&6u32.to_le_bytes());
+        data.extend_from_slice(&3u32.to_le_bytes());
+        // Step time, flags, universe count and size, gamma, color encoding and two reserved
+        data.extend_from_slice(&[25, 0, 0, 0, 0, 0, 1, 2, 0, 0]);
+        data.extend(channels(&frames()));
+        assert_frames(&data);
+    }
+
+    #[test]
+    fn zlib() {
+        let frames = frames();
+        let blocks: Vec<_> = [&frames[..2], &frames[2..]]
+            .iter()
+            .map(|block| {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(&channels(block)).unwrap();
+                encoder.finish().unwrap()
+            })
+            .collect();
+        let list = [(0, blocks[0].len() as u32), (2, blocks[1].len() as u32)];
+        let mut data = header(HEADER_LEN + list.len() * 8, ZLIB, &list);
+        data.extend(blocks.concat());
+        assert_frames(&data);
+    }
+
+    #[test]
+    fn data_inside_header() {
+        let mut data = header(HEADER_LEN, ZLIB, &[(0, 8)]);
+        data.extend_from_slice(&[0; 8]);
+        let error = FseqIndex::build(&data).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "fseq frame data starts at 32, inside the header"
+        );
+    }
+}
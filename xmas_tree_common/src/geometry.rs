@@ -0,0 +1,310 @@
+//! Least squares fits of simple shapes to the LED point cloud, for working out where the foliage
+//! surface is.
+
+use std::str::FromStr;
+
+use crate::coords::Coord;
+
+fn sub(a: Coord, b: Coord) -> Coord {
+    (a.0 - b.0, a.1 - b.1, a.2 - b.2)
+}
+
+fn dot(a: Coord, b: Coord) -> f32 {
+    a.0 * b.0 + a.1 * b.1 + a.2 * b.2
+}
+
+fn scaled(a: Coord, f: f32) -> Coord {
+    (a.0 * f, a.1 * f, a.2 * f)
+}
+
+fn normalized(a: Coord) -> Coord {
+    let len = dot(a, a).sqrt();
+    if len > 0.0 {
+        scaled(a, 1.0 / len)
+    } else {
+        (0.0, 0.0, 1.0)
+    }
+}
+
+/// The trunk of the tree, pointing upwards.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TreeAxis {
+    /// The centroid of the LEDs. Heights are measured from here.
+    pub origin: Coord,
+    pub direction: Coord,
+    /// Height of the lowest LED along the axis.
+    pub bottom: f32,
+    /// Height of the highest LED along the axis.
+    pub top: f32,
+}
+
+impl TreeAxis {
+    pub fn fit(coords: &[Coord]) -> Self {
+        let count = coords.len().max(1) as f32;
+        let origin = coords.iter().fold((0.0, 0.0, 0.0), |acc, &c| {
+            (
+                acc.0 + c.0 / count,
+                acc.1 + c.1 / count,
+                acc.2 + c.2 / count,
+            )
+        });
+
+        // Regress horizontal position against height, so the axis follows the middle of each
+        // layer of the tree rather than wherever the LEDs happen to be densest
+        let samples = || {
+            coords
+                .iter()
+                .map(move |&(x, y, z)| ([1.0, z - origin.2], (x - origin.0, y - origin.1)))
+        };
+        let (_, dx) = least_squares(samples().map(|(h, (x, _))| (h, x))).unwrap_or((0.0, 0.0));
+        let (_, dy) = least_squares(samples().map(|(h, (_, y))| (h, y))).unwrap_or((0.0, 0.0));
+        let direction = normalized((dx, dy, 1.0));
+
+        let mut axis = Self {
+            origin,
+            direction,
+            bottom: 0.0,
+            top: 0.0,
+        };
+        let (bottom, top) = coords
+            .iter()
+            .map(|&c| axis.project(c).0)
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), h| {
+                (min.min(h), max.max(h))
+            });
+        axis.bottom = bottom;
+        axis.top = top;
+        axis
+    }
+
+    /// Splits a point into its height along the axis and its distance from it.
+    pub fn project(&self, coord: Coord) -> (f32, f32) {
+        let d = sub(coord, self.origin);
+        let height = dot(d, self.direction);
+        let radial = sub(d, scaled(self.direction, height));
+        (height, dot(radial, radial).sqrt())
+    }
+
+    pub fn point_at(&self, height: f32) -> Coord {
+        let d = scaled(self.direction, height);
+        (
+            self.origin.0 + d.0,
+            self.origin.1 + d.1,
+            self.origin.2 + d.2,
+        )
+    }
+}
+
+/// A shape with circular cross sections around the tree axis.
+pub trait Surface: Send + Sync {
+    fn axis(&self) -> &TreeAxis;
+
+    /// Radius of the surface at a height along the axis, or zero outside it.
+    fn radius_at(&self, height: f32) -> f32;
+
+    /// How far inside the surface a point is, measured horizontally. Negative outside.
+    fn depth(&self, coord: Coord) -> f32 {
+        let (height, radius) = self.axis().project(coord);
+        self.radius_at(height) - radius
+    }
+}
+
+const ENVELOPE_LAYERS: usize = 12;
+
+/// The outermost LED in each horizontal layer of the tree, as `(height, radius)`. Shapes are fitted
+/// to these so they follow the outside of the foliage rather than the average LED.
+fn envelope(axis: &TreeAxis, coords: &[Coord]) -> Vec<(f32, f32)> {
+    let span = (axis.top - axis.bottom).max(f32::EPSILON);
+    let mut layers = vec![None::<(f32, f32)>; ENVELOPE_LAYERS];
+    for &coord in coords {
+        let (height, radius) = axis.project(coord);
+        let layer = (((height - axis.bottom) / span) * ENVELOPE_LAYERS as f32) as usize;
+        let outermost = &mut layers[layer.min(ENVELOPE_LAYERS - 1)];
+        match outermost {
+            Some((_, r)) if *r >= radius => {}
+            _ => *outermost = Some((height, radius)),
+        }
+    }
+    layers.into_iter().flatten().collect()
+}
+
+/// Solves the 2x2 least squares normal equations for `y = a * x0 + b * x1`.
+fn least_squares(samples: impl Iterator<Item = ([f32; 2], f32)>) -> Option<(f32, f32)> {
+    let (mut s00, mut s01, mut s11, mut t0, mut t1) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for ([x0, x1], y) in samples {
+        s00 += x0 * x0;
+        s01 += x0 * x1;
+        s11 += x1 * x1;
+        t0 += x0 * y;
+        t1 += x1 * y;
+    }
+    let det = s00 * s11 - s01 * s01;
+    if det.abs() < f32::EPSILON {
+        return None;
+    }
+    Some(((t0 * s11 - t1 * s01) / det, (s00 * t1 - s01 * t0) / det))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cone {
+    pub axis: TreeAxis,
+    /// Height of the apex along the axis.
+    pub apex_height: f32,
+    /// How much the radius grows per unit of height below the apex.
+    pub slope: f32,
+}
+
+impl Cone {
+    /// Fits `radius = slope * (apex - height)` to the outside of the LEDs.
+    pub fn fit(coords: &[Coord]) -> Self {
+        let axis = TreeAxis::fit(coords);
+        let samples = envelope(&axis, coords)
+            .into_iter()
+            .map(|(height, radius)| ([1.0, height], radius));
+        let (intercept, gradient) = least_squares(samples).unwrap_or((0.0, 0.0));
+        if gradient >= 0.0 {
+            // Not tapering, so fall back to a cone that encloses the LEDs
+            let radius = Cylinder::fit(coords).radius;
+            let height = (axis.top - axis.bottom).max(f32::EPSILON);
+            return Self {
+                axis,
+                apex_height: axis.top,
+                slope: radius / height,
+            };
+        }
+        Self {
+            axis,
+            apex_height: -intercept / gradient,
+            slope: -gradient,
+        }
+    }
+
+    pub fn apex(&self) -> Coord {
+        self.axis.point_at(self.apex_height)
+    }
+
+    /// Radius where the cone meets the lowest LED.
+    pub fn base_radius(&self) -> f32 {
+        self.radius_at(self.axis.bottom)
+    }
+}
+
+impl Surface for Cone {
+    fn axis(&self) -> &TreeAxis {
+        &self.axis
+    }
+
+    fn radius_at(&self, height: f32) -> f32 {
+        (self.slope * (self.apex_height - height)).max(0.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cylinder {
+    pub axis: TreeAxis,
+    pub radius: f32,
+}
+
+impl Cylinder {
+    pub fn fit(coords: &[Coord]) -> Self {
+        let axis = TreeAxis::fit(coords);
+        let envelope = envelope(&axis, coords);
+        let count = envelope.len().max(1) as f32;
+        let radius = envelope.iter().map(|&(_, r)| r).sum::<f32>() / count;
+        Self { axis, radius }
+    }
+}
+
+impl Surface for Cylinder {
+    fn axis(&self) -> &TreeAxis {
+        &self.axis
+    }
+
+    fn radius_at(&self, height: f32) -> f32 {
+        if height >= self.axis.bottom && height <= self.axis.top {
+            self.radius
+        } else {
+            0.0
+        }
+    }
+}
+
+/// An ellipsoid of revolution around the tree axis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ellipsoid {
+    pub axis: TreeAxis,
+    /// Height of the centre along the axis.
+    pub centre_height: f32,
+    pub half_height: f32,
+    pub radius: f32,
+}
+
+impl Ellipsoid {
+    /// Fits `(h / half_height)^2 + (r / radius)^2 = 1` to the outside of the LEDs, centred halfway
+    /// up them.
+    pub fn fit(coords: &[Coord]) -> Self {
+        let axis = TreeAxis::fit(coords);
+        let centre_height = (axis.bottom + axis.top) / 2.0;
+        let samples = envelope(&axis, coords).into_iter().map(|(height, radius)| {
+            let h = height - centre_height;
+            ([h * h, radius * radius], 1.0)
+        });
+        let fallback = Cylinder::fit(coords).radius;
+        let (u, v) = least_squares(samples).unwrap_or((0.0, 0.0));
+        let half_height = if u > 0.0 {
+            1.0 / u.sqrt()
+        } else {
+            (axis.top - axis.bottom) / 2.0
+        };
+        let radius = if v > 0.0 { 1.0 / v.sqrt() } else { fallback };
+        Self {
+            axis,
+            centre_height,
+            half_height,
+            radius,
+        }
+    }
+}
+
+impl Surface for Ellipsoid {
+    fn axis(&self) -> &TreeAxis {
+        &self.axis
+    }
+
+    fn radius_at(&self, height: f32) -> f32 {
+        let h = (height - self.centre_height) / self.half_height.max(f32::EPSILON);
+        self.radius * (1.0 - h * h).max(0.0).sqrt()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Shape {
+    Cone,
+    Cylinder,
+    Ellipsoid,
+}
+
+impl FromStr for Shape {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cone" => Ok(Self::Cone),
+            "cylinder" => Ok(Self::Cylinder),
+            "ellipsoid" => Ok(Self::Ellipsoid),
+            _ => Err(format!(
+                "unknown shape `{}` (expected cone, cylinder or ellipsoid)",
+                s
+            )),
+        }
+    }
+}
+
+/// Fits the given kind of shape to the LEDs.
+pub fn fit(shape: Shape, coords: &[Coord]) -> Box<dyn Surface> {
+    match shape {
+        Shape::Cone => Box::new(Cone::fit(coords)),
+        Shape::Cylinder => Box::new(Cylinder::fit(coords)),
+        Shape::Ellipsoid => Box::new(Ellipsoid::fit(coords)),
+    }
+}
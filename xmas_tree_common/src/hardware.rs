@@ -0,0 +1,93 @@
+use std::{error::Error, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+/// The wire protocol used to drive the LEDs, which determines how long each pixel takes to send.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Protocol {
+    /// WS2811/WS2812 at 800 kHz.
+    #[default]
+    Ws2811,
+    /// WS2811 in its 400 kHz low speed mode.
+    Ws2811Slow,
+    /// WS2812B, which needs a much longer reset than the original WS2812.
+    Ws2812b,
+    /// SK6812 with a fourth (white) channel.
+    Sk6812Rgbw,
+}
+
+impl Protocol {
+    fn bit_time_us(self) -> f32 {
+        match self {
+            Self::Ws2811Slow => 2.5,
+            _ => 1.25,
+        }
+    }
+
+    fn bits_per_pixel(self) -> f32 {
+        match self {
+            Self::Sk6812Rgbw => 32.0,
+            _ => 24.0,
+        }
+    }
+
+    fn reset_us(self) -> f32 {
+        match self {
+            Self::Ws2812b => 280.0,
+            Self::Sk6812Rgbw => 80.0,
+            _ => 50.0,
+        }
+    }
+}
+
+fn one() -> usize {
+    1
+}
+
+/// Describes the controller driving the tree, loaded from a JSON file such as
+/// `{ "pixels_per_port": 1000, "protocol": "ws2811" }`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HardwareProfile {
+    /// The longest string of LEDs on a single output. Ports are driven in parallel, so this
+    /// limits the refresh rate rather than the total LED count.
+    pub pixels_per_port: usize,
+    #[serde(default = "one")]
+    pub ports: usize,
+    #[serde(default)]
+    pub protocol: Protocol,
+    /// Any extra time the controller spends per frame, e.g. receiving the data.
+    #[serde(default)]
+    pub overhead_us: f32,
+}
+
+impl HardwareProfile {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let data = fs::read(path)
+            .map_err(|e| format!("Cannot read hardware profile {}: {}", path.display(), e))?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    /// The fastest rate at which the hardware can refresh every LED.
+    pub fn max_fps(&self) -> f32 {
+        let protocol = self.protocol;
+        let frame_us =
+            self.pixels_per_port as f32 * protocol.bits_per_pixel() * protocol.bit_time_us()
+                + protocol.reset_us()
+                + self.overhead_us;
+        1_000_000.0 / frame_us
+    }
+
+    /// A warning message if playing at `fps` would outrun the hardware.
+    pub fn check_fps(&self, fps: f32) -> Option<String> {
+        let max_fps = self.max_fps();
+        if fps > max_fps {
+            Some(format!(
+                "{} fps is faster than the hardware can refresh ({:.1} fps for {} pixels per port), so frames will be dropped",
+                fps, max_fps, self.pixels_per_port
+            ))
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,23 @@
+//! Code shared between the generator, the player and anything else that reads or writes
+//! christmas tree sequences.
+
+pub mod calibration;
+pub mod channel_map;
+pub mod color;
+pub mod coords;
+pub mod csv_format;
+pub mod delta_format;
+pub mod failures;
+pub mod fseq_format;
+pub mod geometry;
+pub mod hardware;
+pub mod manifest;
+pub mod metadata;
+pub mod notes;
+pub mod output;
+pub mod palette;
+pub mod pipeline;
+pub mod project;
+pub mod protocols;
+pub mod seal;
+pub mod sequence;
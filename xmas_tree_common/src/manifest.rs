@@ -0,0 +1,109 @@
+//! A summary of the sequences written to a directory, so a folder full of them can be browsed
+//! without opening each one. It's kept in `sequences.jsonl` alongside them, one JSON line per
+//! sequence, and each sequence's line is replaced whenever it's written again.
+
+use std::{
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+use crate::metadata::SequenceMetadata;
+
+pub const MANIFEST_NAME: &str = "sequences.jsonl";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// The sequence's file name, in the manifest's directory.
+    pub file: String,
+    pub format: String,
+    pub frames: usize,
+    pub led_count: usize,
+    pub seconds: f32,
+    /// The effects the sequence was made from, in the order they first play.
+    pub effects: Vec<String>,
+    /// File name of the thumbnail picture, if one was written.
+    #[serde(default)]
+    pub thumbnail: Option<String>,
+    /// When the sequence was written, in RFC 3339.
+    pub written: String,
+}
+
+impl ManifestEntry {
+    pub fn new(
+        sequence_path: &Path,
+        metadata: &SequenceMetadata,
+        fps: f32,
+        thumbnail: Option<&Path>,
+    ) -> Self {
+        let file_name = |path: &Path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+        let mut effects: Vec<String> = Vec::new();
+        for credit in &metadata.credits {
+            if !effects.contains(&credit.effect) {
+                effects.push(credit.effect.clone());
+            }
+        }
+        Self {
+            file: file_name(sequence_path),
+            format: metadata.format.clone(),
+            frames: metadata.frames,
+            led_count: metadata.led_count,
+            seconds: metadata.frames as f32 / fps,
+            effects,
+            thumbnail: thumbnail.map(file_name),
+            written: Local::now().to_rfc3339(),
+        }
+    }
+}
+
+/// The manifest for the directory a sequence is in.
+pub fn manifest_path(sequence_path: &Path) -> PathBuf {
+    sequence_path
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join(MANIFEST_NAME)
+}
+
+/// Reads a manifest, which is empty if it doesn't exist yet.
+pub fn load(path: &Path) -> Result<Vec<ManifestEntry>, Box<dyn Error>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Cannot read {}: {}", path.display(), e).into()),
+    };
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| format!("{} line {}: {}", path.display(), i + 1, e).into())
+        })
+        .collect()
+}
+
+/// Adds a sequence to the manifest of its directory, replacing any older entry for it.
+pub fn record(sequence_path: &Path, entry: ManifestEntry) -> Result<(), Box<dyn Error>> {
+    let path = manifest_path(sequence_path);
+    let mut entries = load(&path)?;
+    entries.retain(|other| other.file != entry.file);
+    entries.push(entry);
+    entries.sort_by(|a, b| a.file.cmp(&b.file));
+    let mut text = String::new();
+    for entry in &entries {
+        text += &serde_json::to_string(entry)?;
+        text.push('\n');
+    }
+    // Write to a temporary file first so a crash mid-save leaves the old manifest intact
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, text)
+        .map_err(|e| format!("Cannot write {}: {}", tmp_path.display(), e))?;
+    fs::rename(&tmp_path, &path).map_err(|e| format!("Cannot write {}: {}", path.display(), e))?;
+    Ok(())
+}
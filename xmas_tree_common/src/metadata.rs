@@ -0,0 +1,219 @@
+use std::{
+    error::Error,
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::sequence::SequenceFormat;
+
+/// Bumped whenever the sidecar layout changes incompatibly.
+pub const METADATA_VERSION: u32 = 1;
+
+/// Sidecar file written next to a sequence, used to detect truncated or modified files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SequenceMetadata {
+    pub version: u32,
+    pub format: String,
+    pub frames: usize,
+    pub led_count: usize,
+    /// Hex encoded SHA-256 of the sequence file's contents.
+    pub checksum: String,
+    /// Hex encoded SHA-256 of the coordinate file the sequence was generated for.
+    #[serde(default)]
+    pub coords_hash: Option<String>,
+    /// Named points in the sequence, in frame order.
+    #[serde(default)]
+    pub markers: Vec<Marker>,
+    /// The effects the sequence was made from, in frame order.
+    #[serde(default)]
+    pub credits: Vec<Credit>,
+}
+
+/// A named frame, such as the start of a chorus, used to navigate long shows.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Marker {
+    pub name: String,
+    pub frame: usize,
+}
+
+/// Who wrote the effect playing over a range of frames, so shows built from shared effects credit
+/// their authors.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Credit {
+    pub effect: String,
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub version: Option<String>,
+    pub start_frame: usize,
+    pub frames: usize,
+}
+
+impl Credit {
+    pub fn contains(&self, frame: usize) -> bool {
+        (self.start_frame..self.start_frame + self.frames).contains(&frame)
+    }
+}
+
+impl fmt::Display for Credit {
+    /// Formats as e.g. `twinkle by Alice (v1.2)`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.effect)?;
+        if let Some(author) = &self.author {
+            write!(f, " by {}", author)?;
+        }
+        if let Some(version) = &self.version {
+            write!(f, " (v{})", version)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Marker {
+    type Err = String;
+
+    /// Parses `NAME=FRAME`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, frame) = s
+            .rsplit_once('=')
+            .ok_or_else(|| format!("Expected NAME=FRAME, got {}", s))?;
+        Ok(Self {
+            name: name.trim().into(),
+            frame: frame
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid marker frame: {}", frame))?,
+        })
+    }
+}
+
+pub fn sidecar_path(sequence_path: &Path) -> PathBuf {
+    let mut path = sequence_path.as_os_str().to_owned();
+    path.push(".meta.json");
+    path.into()
+}
+
+/// Where the small picture of a sequence written alongside it goes.
+pub fn thumbnail_path(sequence_path: &Path) -> PathBuf {
+    let mut path = sequence_path.as_os_str().to_owned();
+    path.push(".thumb.png");
+    path.into()
+}
+
+pub fn checksum(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+impl SequenceMetadata {
+    pub fn new(data: &[u8], format: SequenceFormat, frames: usize, led_count: usize) -> Self {
+        Self {
+            version: METADATA_VERSION,
+            format: format.to_string(),
+            frames,
+            led_count,
+            checksum: checksum(data),
+            coords_hash: None,
+            markers: Vec::new(),
+            credits: Vec::new(),
+        }
+    }
+
+    /// Computes metadata for an already written sequence file and saves it alongside.
+    pub fn write_sidecar(
+        sequence_path: &Path,
+        format: SequenceFormat,
+        frames: usize,
+        led_count: usize,
+        coords_hash: Option<String>,
+        mut markers: Vec<Marker>,
+        mut credits: Vec<Credit>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut metadata = Self::new(&fs::read(sequence_path)?, format, frames, led_count);
+        metadata.coords_hash = coords_hash;
+        markers.sort_by_key(|marker| marker.frame);
+        metadata.markers = markers;
+        credits.sort_by_key(|credit| credit.start_frame);
+        metadata.credits = credits;
+        metadata.save(sequence_path)?;
+        Ok(metadata)
+    }
+
+    pub fn save(&self, sequence_path: &Path) -> Result<(), Box<dyn Error>> {
+        fs::write(
+            sidecar_path(sequence_path),
+            serde_json::to_string_pretty(self)?,
+        )?;
+        Ok(())
+    }
+
+    /// Loads the sidecar for a sequence, if there is one.
+    pub fn load(sequence_path: &Path) -> Result<Option<Self>, Box<dyn Error>> {
+        let path = sidecar_path(sequence_path);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let metadata: Self = serde_json::from_slice(&fs::read(&path)?)
+            .map_err(|e| format!("Invalid sequence metadata {}: {}", path.display(), e))?;
+        if metadata.version > METADATA_VERSION {
+            return Err(format!(
+                "{} was written by a newer version (metadata version {}, expected {})",
+                path.display(),
+                metadata.version,
+                METADATA_VERSION
+            )
+            .into());
+        }
+        Ok(Some(metadata))
+    }
+
+    pub fn verify_checksum(&self, sequence_path: &Path, data: &[u8]) -> Result<(), String> {
+        if checksum(data) != self.checksum {
+            return Err(format!(
+                "{} is corrupt or does not match its metadata: checksum mismatch",
+                sequence_path.display()
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn verify_contents(
+        &self,
+        sequence_path: &Path,
+        format: SequenceFormat,
+        frames: usize,
+        led_count: usize,
+    ) -> Result<(), String> {
+        if self.format != format.to_string() || self.frames != frames || self.led_count != led_count
+        {
+            return Err(format!(
+                "{} does not match its metadata: expected {} frames of {} LEDs in {} format, found {} frames of {} LEDs in {} format",
+                sequence_path.display(),
+                self.frames,
+                self.led_count,
+                self.format,
+                frames,
+                led_count,
+                format
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks that the sequence was generated for the given coordinate file contents.
+    pub fn verify_coords(&self, coords_path: &Path, coords_data: &[u8]) -> Result<(), String> {
+        match &self.coords_hash {
+            Some(hash) if *hash != checksum(coords_data) => Err(format!(
+                "The sequence was generated for different coordinates than {}",
+                coords_path.display()
+            )),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,65 @@
+//! Review notes attached to frames of a sequence, kept in a sidecar next to it (separate from the
+//! metadata, which is checksummed and rewritten whenever the sequence is). The player adds them
+//! while watching, and `xmas_tree_gen notes` lists them.
+
+use std::{
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Note {
+    pub frame: usize,
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// When the note was written, in RFC 3339.
+    pub written: String,
+}
+
+impl Note {
+    pub fn new(frame: usize, text: String, author: Option<String>) -> Self {
+        Self {
+            frame,
+            text,
+            author,
+            written: Local::now().to_rfc3339(),
+        }
+    }
+}
+
+pub fn notes_path(sequence_path: &Path) -> PathBuf {
+    let mut path = sequence_path.as_os_str().to_owned();
+    path.push(".notes.json");
+    path.into()
+}
+
+/// Loads the notes for a sequence, which has none if there's no sidecar.
+pub fn load(sequence_path: &Path) -> Result<Vec<Note>, Box<dyn Error>> {
+    let path = notes_path(sequence_path);
+    match fs::read(&path) {
+        Ok(json) => Ok(serde_json::from_slice(&json)
+            .map_err(|e| format!("Invalid notes {}: {}", path.display(), e))?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(format!("Cannot read {}: {}", path.display(), e).into()),
+    }
+}
+
+/// Saves notes in frame order, removing the sidecar when there are none left.
+pub fn save(sequence_path: &Path, notes: &mut [Note]) -> Result<(), Box<dyn Error>> {
+    let path = notes_path(sequence_path);
+    if notes.is_empty() {
+        return match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        };
+    }
+    notes.sort_by_key(|note| note.frame);
+    fs::write(&path, serde_json::to_string_pretty(notes)?)
+        .map_err(|e| format!("Cannot write {}: {}", path.display(), e))?;
+    Ok(())
+}
@@ -0,0 +1,628 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufWriter},
+    net::{ToSocketAddrs, UdpSocket},
+    ops::Add,
+    panic,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex, Weak},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    calibration::Calibration,
+    csv_format::CsvWriter,
+    delta_format::DeltaWriter,
+    pipeline::{BrightnessCap, ChannelOrder, PipelineOptions, PipelineSink},
+    protocols::{
+        AdalightSink, ArtNetSink, DdpSink, E131Sink, LossStrategy, OpcSink, UniverseMapping,
+        ARTNET_PORT, DDP_PORT, E131_PORT, OPC_PORT, UNIVERSE_CHANNELS,
+    },
+    sequence::{Rgb, SequenceFormat, SequenceWriter},
+};
+
+#[cfg(feature = "gpio")]
+use crate::protocols::GpioSink;
+
+/// Somewhere frames can be sent as they play, such as a controller driving a real tree.
+/// Sinks may be driven from another thread, e.g. by the player's systems.
+pub trait OutputSink: Send + Sync {
+    /// Prepares for frames of `led_count` LEDs. [`OutputGroup`] calls this before the first
+    /// frame and whenever the count changes.
+    fn configure(&mut self, _led_count: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn send_frame(&mut self, frame: &[Rgb]) -> io::Result<()>;
+
+    /// Turns every LED off.
+    fn blank(&mut self, led_count: usize) -> io::Result<()> {
+        self.send_frame(&vec![[0; 3]; led_count])
+    }
+
+    /// What has been sent so far, for status displays. Sinks which don't talk to hardware
+    /// report nothing.
+    fn stats(&self) -> SinkStats {
+        SinkStats::default()
+    }
+}
+
+/// Traffic counters kept by sinks which talk to hardware.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SinkStats {
+    pub frames: u64,
+    pub packets: u64,
+    pub bytes: u64,
+    /// Frames which failed to send, counted by [`OutputGroup`].
+    pub errors: u64,
+}
+
+impl SinkStats {
+    pub fn record_packet(&mut self, len: usize) {
+        self.packets += 1;
+        self.bytes += len as u64;
+    }
+}
+
+impl Add for SinkStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            frames: self.frames + other.frames,
+            packets: self.packets + other.packets,
+            bytes: self.bytes + other.bytes,
+            errors: self.errors + other.errors,
+        }
+    }
+}
+
+/// WLED's realtime UDP protocol, using the DNRGB packet type which carries a start index so
+/// long strings can be split over several packets.
+pub struct WledSink {
+    socket: UdpSocket,
+    stats: SinkStats,
+}
+
+const WLED_PORT: u16 = 21324;
+const WLED_DNRGB: u8 = 4;
+/// Seconds WLED waits after the last packet before returning to its own effects.
+const WLED_TIMEOUT_SECS: u8 = 2;
+const WLED_LEDS_PER_PACKET: usize = 489;
+
+impl WledSink {
+    pub fn connect(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect(address)?;
+        Ok(Self {
+            socket,
+            stats: SinkStats::default(),
+        })
+    }
+}
+
+impl OutputSink for WledSink {
+    fn send_frame(&mut self, frame: &[Rgb]) -> io::Result<()> {
+        for (chunk_index, chunk) in frame.chunks(WLED_LEDS_PER_PACKET).enumerate() {
+            let start = (chunk_index * WLED_LEDS_PER_PACKET) as u16;
+            let mut packet = Vec::with_capacity(4 + chunk.len() * 3);
+            packet.extend_from_slice(&[WLED_DNRGB, WLED_TIMEOUT_SECS]);
+            packet.extend_from_slice(&start.to_be_bytes());
+            packet.extend(chunk.iter().flatten());
+            self.socket.send(&packet)?;
+            self.stats.record_packet(packet.len());
+        }
+        self.stats.frames += 1;
+        Ok(())
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.stats
+    }
+}
+
+struct WatchdogState {
+    sink: Box<dyn OutputSink>,
+    last_frame: Vec<Rgb>,
+    last_sent: Instant,
+    blanked: bool,
+}
+
+impl WatchdogState {
+    fn send_scaled(&mut self, level: f32) {
+        let scale = |v: u8| (v as f32 * level).round() as u8;
+        let frame: Vec<Rgb> = self
+            .last_frame
+            .iter()
+            .map(|&[r, g, b]| [scale(r), scale(g), scale(b)])
+            .collect();
+        // Nothing useful can be done if this fails: there is no one left to report it to
+        let _ = self.sink.send_frame(&frame);
+    }
+
+    fn blank(&mut self) {
+        if !self.blanked {
+            self.send_scaled(0.0);
+            self.blanked = true;
+        }
+    }
+}
+
+fn lock(state: &Mutex<WatchdogState>) -> std::sync::MutexGuard<'_, WatchdogState> {
+    // Still blank the lights if a panic happened mid-send
+    state
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Stops the lights freezing on the last frame when frames stop arriving. If no frame is sent
+/// within `timeout` the output fades to black over `fade`, and it is blanked immediately if the
+/// process panics or the watchdog is dropped.
+pub struct Watchdog {
+    state: Arc<Mutex<WatchdogState>>,
+}
+
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(50);
+
+impl Watchdog {
+    pub fn new(sink: Box<dyn OutputSink>, timeout: Duration, fade: Duration) -> Self {
+        let state = Arc::new(Mutex::new(WatchdogState {
+            sink,
+            last_frame: Vec::new(),
+            last_sent: Instant::now(),
+            blanked: true,
+        }));
+
+        let weak = Arc::downgrade(&state);
+        thread::spawn(move || Self::watch(weak, timeout, fade));
+
+        let weak = Arc::downgrade(&state);
+        let previous_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if let Some(state) = weak.upgrade() {
+                lock(&state).blank();
+            }
+            previous_hook(info);
+        }));
+
+        Self { state }
+    }
+
+    fn watch(state: Weak<Mutex<WatchdogState>>, timeout: Duration, fade: Duration) {
+        // Exits once the watchdog has been dropped
+        while let Some(state) = state.upgrade() {
+            {
+                let mut state = lock(&state);
+                let idle = state.last_sent.elapsed();
+                if !state.blanked && idle > timeout {
+                    let progress = (idle - timeout).as_secs_f32() / fade.as_secs_f32().max(0.001);
+                    if progress >= 1.0 {
+                        state.blank();
+                    } else {
+                        state.send_scaled(1.0 - progress);
+                    }
+                }
+            }
+            drop(state);
+            thread::sleep(WATCHDOG_INTERVAL);
+        }
+    }
+}
+
+impl OutputSink for Watchdog {
+    fn configure(&mut self, led_count: usize) -> io::Result<()> {
+        lock(&self.state).sink.configure(led_count)
+    }
+
+    fn send_frame(&mut self, frame: &[Rgb]) -> io::Result<()> {
+        let mut state = lock(&self.state);
+        state.sink.send_frame(frame)?;
+        state.last_frame = frame.to_vec();
+        state.last_sent = Instant::now();
+        state.blanked = false;
+        Ok(())
+    }
+
+    fn blank(&mut self, led_count: usize) -> io::Result<()> {
+        let mut state = lock(&self.state);
+        state.sink.blank(led_count)?;
+        state.blanked = true;
+        Ok(())
+    }
+
+    fn stats(&self) -> SinkStats {
+        lock(&self.state).sink.stats()
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        lock(&self.state).blank();
+    }
+}
+
+/// Records frames to a sequence file as they are sent, so a live session can be replayed later.
+/// Files ending in `.csv` are written as CSV, anything else in the delta format.
+pub struct RecordingSink {
+    path: PathBuf,
+    // Created once the number of LEDs is known
+    writer: Option<Box<dyn SequenceWriter + Send + Sync>>,
+    last_flush: Instant,
+}
+
+/// How often recordings are flushed, since the player may exit without dropping its outputs.
+const RECORDING_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+impl RecordingSink {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        // Fail early rather than on the first frame
+        File::create(&path)?;
+        Ok(Self {
+            path,
+            writer: None,
+            last_flush: Instant::now(),
+        })
+    }
+
+    fn format(&self) -> SequenceFormat {
+        match self.path.extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => SequenceFormat::Csv,
+            _ => SequenceFormat::Delta,
+        }
+    }
+}
+
+impl OutputSink for RecordingSink {
+    fn configure(&mut self, led_count: usize) -> io::Result<()> {
+        // A sequence file has a fixed number of LEDs, so only the first count is used
+        if self.writer.is_none() {
+            let file = BufWriter::new(File::create(&self.path)?);
+            self.writer = Some(match self.format() {
+                SequenceFormat::Csv => Box::new(CsvWriter::new(file, led_count)?),
+                _ => Box::new(DeltaWriter::new(file, led_count)?),
+            });
+        }
+        Ok(())
+    }
+
+    fn send_frame(&mut self, frame: &[Rgb]) -> io::Result<()> {
+        self.configure(frame.len())?;
+        let writer = self.writer.as_mut().unwrap();
+        writer.write_frame(frame)?;
+        if self.last_flush.elapsed() >= RECORDING_FLUSH_INTERVAL {
+            writer.flush()?;
+            self.last_flush = Instant::now();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RecordingSink {
+    fn drop(&mut self) {
+        if let Some(writer) = &mut self.writer {
+            let _ = writer.finish();
+        }
+    }
+}
+
+/// Also sends each frame to a preview, e.g. a player on another machine listening with
+/// `ddp://`, so it shows exactly what the tree is sent. The preview going away doesn't count as
+/// the output failing.
+pub struct MirrorSink {
+    inner: Box<dyn OutputSink>,
+    mirror: DdpSink,
+}
+
+impl MirrorSink {
+    pub fn new(inner: Box<dyn OutputSink>, mirror: DdpSink) -> Self {
+        Self { inner, mirror }
+    }
+}
+
+impl OutputSink for MirrorSink {
+    fn configure(&mut self, led_count: usize) -> io::Result<()> {
+        self.inner.configure(led_count)
+    }
+
+    fn send_frame(&mut self, frame: &[Rgb]) -> io::Result<()> {
+        let _ = self.mirror.send_frame(frame);
+        self.inner.send_frame(frame)
+    }
+
+    fn blank(&mut self, led_count: usize) -> io::Result<()> {
+        let _ = self.mirror.blank(led_count);
+        self.inner.blank(led_count)
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.inner.stats()
+    }
+}
+
+/// Several sinks driven together, e.g. a tree and a recording of it. A sink which fails doesn't
+/// stop the others getting the frame.
+#[derive(Default)]
+pub struct OutputGroup {
+    outputs: Vec<(String, Box<dyn OutputSink>)>,
+    /// Failures of each output, in the same order.
+    errors: Vec<u64>,
+    led_count: Option<usize>,
+}
+
+impl OutputGroup {
+    /// Adds a sink, named (usually by its URL) in errors and stats.
+    pub fn add(&mut self, name: impl Into<String>, sink: Box<dyn OutputSink>) {
+        self.outputs.push((name.into(), sink));
+        self.errors.push(0);
+        // Configure the new sink along with the rest on the next frame
+        self.led_count = None;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.outputs.is_empty()
+    }
+
+    /// Each sink's name and stats.
+    pub fn stats_by_sink(&self) -> impl Iterator<Item = (&str, SinkStats)> {
+        self.outputs
+            .iter()
+            .zip(&self.errors)
+            .map(|((name, sink), &errors)| {
+                let stats = SinkStats {
+                    errors,
+                    ..sink.stats()
+                };
+                (name.as_str(), stats)
+            })
+    }
+
+    /// Runs `f` on every sink, combining any errors into one.
+    fn for_each(
+        &mut self,
+        mut f: impl FnMut(&mut dyn OutputSink) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let errors: Vec<String> = self
+            .outputs
+            .iter_mut()
+            .zip(&mut self.errors)
+            .filter_map(|((name, sink), count)| {
+                let e = f(sink.as_mut()).err()?;
+                *count += 1;
+                Some(format!("{}: {}", name, e))
+            })
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(io::Error::other(errors.join("; ")))
+        }
+    }
+}
+
+impl OutputSink for OutputGroup {
+    fn configure(&mut self, led_count: usize) -> io::Result<()> {
+        self.led_count = Some(led_count);
+        self.for_each(|sink| sink.configure(led_count))
+    }
+
+    fn send_frame(&mut self, frame: &[Rgb]) -> io::Result<()> {
+        if self.led_count != Some(frame.len()) {
+            self.configure(frame.len())?;
+        }
+        self.for_each(|sink| sink.send_frame(frame))
+    }
+
+    fn blank(&mut self, led_count: usize) -> io::Result<()> {
+        self.for_each(|sink| sink.blank(led_count))
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.stats_by_sink()
+            .map(|(_, stats)| stats)
+            .fold(SinkStats::default(), Add::add)
+    }
+}
+
+/// Settings given after the `?` of an output URL, as `KEY=VALUE` pairs separated by `&`.
+struct UrlOptions {
+    universe: Option<u16>,
+    channels: Option<usize>,
+    opc_channel: Option<u8>,
+    loss: Option<LossStrategy>,
+    gamma: Option<f32>,
+    calibration: Option<PathBuf>,
+    order: Option<ChannelOrder>,
+    mirror: Option<String>,
+}
+
+impl FromStr for UrlOptions {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut options = Self {
+            universe: None,
+            channels: None,
+            opc_channel: None,
+            loss: None,
+            gamma: None,
+            calibration: None,
+            order: None,
+            mirror: None,
+        };
+        for pair in s.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("Expected KEY=VALUE in output URL, got {}", pair))?;
+            let invalid = || format!("Invalid {} in output URL: {}", key, value);
+            match key {
+                "universe" => options.universe = Some(value.parse().map_err(|_| invalid())?),
+                "channels" => options.channels = Some(value.parse().map_err(|_| invalid())?),
+                "channel" => options.opc_channel = Some(value.parse().map_err(|_| invalid())?),
+                "loss" => options.loss = Some(value.parse()?),
+                "gamma" => options.gamma = Some(value.parse().map_err(|_| invalid())?),
+                "calibration" => options.calibration = Some(value.into()),
+                "order" => options.order = Some(value.parse()?),
+                "mirror" => options.mirror = Some(value.into()),
+                other => return Err(format!("Unknown output URL option: {}", other)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+impl UrlOptions {
+    /// Maps LEDs to universes starting from `default_first`, unless the URL says otherwise.
+    fn mapping(&self, default_first: u16) -> Result<UniverseMapping, String> {
+        UniverseMapping::new(
+            self.universe.unwrap_or(default_first),
+            self.channels.unwrap_or(UNIVERSE_CHANNELS / 3 * 3),
+        )
+    }
+}
+
+/// Binds to `address`, which may leave out the port to use the protocol's standard one.
+fn with_port(address: &str, port: u16) -> String {
+    if address.contains(':') {
+        address.into()
+    } else {
+        format!("{}:{}", address, port)
+    }
+}
+
+/// Opens a sink from a URL:
+///
+/// - `wled://HOST`: WLED's realtime UDP protocol.
+/// - `ddp://HOST`: the Distributed Display Protocol, also supported by WLED.
+/// - `e131://HOST`, or `e131://` to multicast: E1.31 (sACN), starting at universe 1.
+/// - `artnet://HOST`: Art-Net, starting at universe 0. `HOST` may be a broadcast address.
+/// - `opc://HOST`: Open Pixel Control over TCP, to every channel unless `channel` is given.
+/// - `serial://PATH`: the Adalight protocol, to a serial port already set up with `stty`.
+/// - `gpio://PATH`: WS2812 LEDs on a Raspberry Pi's SPI pin, through a spidev device such as
+///   `/dev/spidev0.0` (the default), with channels in `grb` order unless `order` says otherwise.
+///   Only in builds with the `gpio` feature.
+/// - `preview://HOST`: a preview listening for DDP, e.g. `xmas_tree_player ddp://`, on `HOST`
+///   or this machine. Previews show colors, so take no channel order.
+/// - `file://PATH`: records to a sequence file.
+///
+/// Hosts may include a port, otherwise the protocol's standard port is used. Options follow a
+/// `?`, e.g. `e131://?universe=10&channels=510&order=grb`: `universe` is the first universe
+/// and `channels` the channels used in each (E1.31 and Art-Net only). `loss` sets how E1.31,
+/// Art-Net and DDP cope with packets which fail to send on a flaky WiFi link: `resend` sends the
+/// rest of the frame and then tries the failed packets again, and `keyframes[:FRAMES]` also only
+/// sends what changed between keyframes (every 10 frames by default). The rest set up the
+/// sink's color pipeline: `gamma` corrects for the LEDs' response, `calibration` is a file
+/// written by `calibrate analyze`, and `order` is the order the controller expects each LED's
+/// channels in. `mirror=HOST` also sends the frames, after every stage but the channel order, to
+/// a preview listening for DDP at `HOST` (e.g. `xmas_tree_player ddp://`).
+pub fn open(url: &str) -> Result<Box<dyn OutputSink>, Box<dyn Error>> {
+    open_with(url, None)
+}
+
+/// Opens a sink like [`open`], also limiting its brightness.
+pub fn open_with(
+    url: &str,
+    brightness: Option<BrightnessCap>,
+) -> Result<Box<dyn OutputSink>, Box<dyn Error>> {
+    let (scheme, rest) = url
+        .split_once("://")
+        .ok_or_else(|| format!("Expected an output URL like wled://HOST, got {}", url))?;
+    let (address, options) = rest.split_once('?').unwrap_or((rest, ""));
+    let options: UrlOptions = options.parse()?;
+    let uses_universes = matches!(scheme, "e131" | "artnet");
+    if !uses_universes && (options.universe.is_some() || options.channels.is_some()) {
+        return Err(format!("{} outputs don't have universes", scheme).into());
+    }
+    if scheme != "opc" && options.opc_channel.is_some() {
+        return Err(format!("{} outputs don't have channel numbers", scheme).into());
+    }
+    if !matches!(scheme, "e131" | "artnet" | "ddp") && options.loss.is_some() {
+        return Err(format!("{} outputs don't support loss strategies", scheme).into());
+    }
+    if scheme == "preview" && options.order.is_some() {
+        return Err("preview outputs show colors, so don't have a channel order".into());
+    }
+    let loss = options.loss.unwrap_or(LossStrategy::None);
+    let mut pipeline = PipelineOptions {
+        gamma: options.gamma,
+        calibration: options
+            .calibration
+            .as_deref()
+            .map(Calibration::load)
+            .transpose()?,
+        brightness,
+        order: options.order.unwrap_or(match scheme {
+            "gpio" => ChannelOrder::GRB,
+            _ => ChannelOrder::RGB,
+        }),
+    };
+    let mut sink: Box<dyn OutputSink> = match scheme {
+        "wled" => Box::new(WledSink::connect(with_port(address, WLED_PORT))?),
+        "ddp" => Box::new(DdpSink::connect(with_port(address, DDP_PORT))?.with_loss_strategy(loss)),
+        "e131" => {
+            let mapping = options.mapping(1)?;
+            if mapping.first == 0 {
+                return Err("E1.31 universes start at 1".into());
+            }
+            let sink = if address.is_empty() {
+                E131Sink::multicast(mapping)?
+            } else {
+                E131Sink::unicast(with_port(address, E131_PORT), mapping)?
+            };
+            Box::new(sink.with_loss_strategy(loss))
+        }
+        "artnet" => Box::new(
+            ArtNetSink::connect(with_port(address, ARTNET_PORT), options.mapping(0)?)?
+                .with_loss_strategy(loss),
+        ),
+        "opc" => Box::new(OpcSink::connect(
+            with_port(address, OPC_PORT),
+            options.opc_channel.unwrap_or(0),
+        )?),
+        "serial" => Box::new(AdalightSink::open(Path::new(address))?),
+        #[cfg(feature = "gpio")]
+        "gpio" => {
+            let path = Some(address).filter(|a| !a.is_empty());
+            let path = Path::new(path.unwrap_or("/dev/spidev0.0"));
+            Box::new(
+                GpioSink::open(path)
+                    .map_err(|e| format!("Cannot open {}: {}", path.display(), e))?,
+            )
+        }
+        #[cfg(not(feature = "gpio"))]
+        "gpio" => return Err("GPIO outputs need a build with the gpio feature".into()),
+        "preview" => {
+            let address = Some(address).filter(|a| !a.is_empty());
+            Box::new(DdpSink::connect(with_port(
+                address.unwrap_or("127.0.0.1"),
+                DDP_PORT,
+            ))?)
+        }
+        "file" => Box::new(RecordingSink::create(address)?),
+        other => return Err(format!("Unknown output type: {}", other).into()),
+    };
+    if let Some(mirror) = &options.mirror {
+        // The preview shows colors, so it gets frames before they're reordered for the controller
+        let reorder = PipelineOptions {
+            order: pipeline.order,
+            ..Default::default()
+        };
+        pipeline.order = ChannelOrder::RGB;
+        let mirror = DdpSink::connect(with_port(mirror, DDP_PORT))
+            .map_err(|e| format!("Cannot mirror to {}: {}", mirror, e))?;
+        sink = Box::new(MirrorSink::new(with_pipeline(sink, reorder), mirror));
+    }
+    Ok(with_pipeline(sink, pipeline))
+}
+
+fn with_pipeline(sink: Box<dyn OutputSink>, options: PipelineOptions) -> Box<dyn OutputSink> {
+    let pipeline = options.build();
+    if pipeline.is_empty() {
+        sink
+    } else {
+        Box::new(PipelineSink::new(sink, pipeline))
+    }
+}
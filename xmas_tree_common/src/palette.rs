@@ -0,0 +1,132 @@
+use std::{error::Error, fmt, fs, path::Path, str::FromStr};
+
+use crate::color::{mix_hue, Color};
+
+/// An ordered list of colors, written as a built-in name or hex colors separated by commas or
+/// whitespace (`#ff0000,#00ff00,#ffd700`). Palette files hold the same text, one color per line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    pub colors: Vec<Color>,
+}
+
+const BUILTIN: &[(&str, &[&str])] = &[
+    ("christmas", &["#ff0000", "#00c000", "#ffc020"]),
+    ("candy-cane", &["#ff0000", "#ffffff"]),
+    ("ice", &["#80c0ff", "#ffffff", "#2040ff"]),
+    ("warm-white", &["#ffb060", "#ff9030", "#ffd0a0"]),
+    (
+        "jewel",
+        &["#c00030", "#1040d0", "#00a040", "#8020c0", "#ffa000"],
+    ),
+    (
+        "rainbow",
+        &[
+            "#ff0000", "#ffff00", "#00ff00", "#00ffff", "#0000ff", "#ff00ff",
+        ],
+    ),
+];
+
+pub fn parse_hex(hex: &str) -> Option<Color> {
+    let hex = hex.trim().trim_start_matches('#');
+    if hex.len() != 6 {
+        return None;
+    }
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    let channel = |shift: u32| ((value >> shift) & 0xFF) as f32 / 255.0;
+    Some((channel(16), channel(8), channel(0)))
+}
+
+fn to_hex(color: Color) -> String {
+    let channel = |v: f32| (v * 255.0).round() as u8;
+    format!(
+        "#{:02x}{:02x}{:02x}",
+        channel(color.0),
+        channel(color.1),
+        channel(color.2)
+    )
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let hexes: Vec<&str> = match BUILTIN.iter().find(|(name, _)| *name == s) {
+            Some((_, hexes)) => hexes.to_vec(),
+            None => s
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|hex| !hex.is_empty())
+                .collect(),
+        };
+        let colors = hexes
+            .iter()
+            .map(|hex| parse_hex(hex).ok_or_else(|| format!("Invalid palette color: {}", hex)))
+            .collect::<Result<Vec<_>, _>>()?;
+        if colors.is_empty() {
+            return Err("Palette must contain at least one color".into());
+        }
+        Ok(Self { colors })
+    }
+}
+
+impl fmt::Display for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hexes: Vec<_> = self.colors.iter().map(|&c| to_hex(c)).collect();
+        f.write_str(&hexes.join(","))
+    }
+}
+
+fn distance_squared(a: Color, b: Color) -> f32 {
+    (a.0 - b.0).powi(2) + (a.1 - b.1).powi(2) + (a.2 - b.2).powi(2)
+}
+
+impl Palette {
+    pub fn builtin_names() -> impl Iterator<Item = &'static str> {
+        BUILTIN.iter().map(|(name, _)| *name)
+    }
+
+    /// The color for index `i`, wrapping around the palette.
+    pub fn cycle(&self, i: usize) -> Color {
+        self.colors[i % self.colors.len()]
+    }
+
+    /// Part way from color `i` to the next, wrapping around the palette, blending by hue.
+    pub fn lerp(&self, i: usize, t: f32) -> Color {
+        mix_hue(self.cycle(i), self.cycle(i + 1), t)
+    }
+
+    /// Blends smoothly through the colors as `t` goes from 0 to 1.
+    pub fn gradient(&self, t: f32) -> Color {
+        let last = self.colors.len() - 1;
+        if last == 0 {
+            return self.colors[0];
+        }
+        let position = t.clamp(0.0, 1.0) * last as f32;
+        let i = (position as usize).min(last - 1);
+        self.lerp(i, position - i as f32)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read palette {}: {}", path.display(), e))?;
+        Ok(text.parse()?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut text = String::new();
+        for &color in &self.colors {
+            text += &to_hex(color);
+            text.push('\n');
+        }
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn nearest(&self, color: Color) -> Color {
+        self.colors
+            .iter()
+            .copied()
+            .min_by(|&a, &b| distance_squared(a, color).total_cmp(&distance_squared(b, color)))
+            .unwrap()
+    }
+}
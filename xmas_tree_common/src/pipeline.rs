@@ -0,0 +1,263 @@
+//! Color corrections applied on the way to one particular output. Frames themselves keep their
+//! ideal colors, so the player's preview can show them as designed while each sink applies its
+//! own device's corrections.
+
+use std::{io, str::FromStr};
+
+use chrono::{Local, NaiveTime};
+
+use crate::{
+    calibration::Calibration,
+    output::{OutputSink, SinkStats},
+    sequence::Rgb,
+};
+
+/// One step of a [`ColorPipeline`], which corrects a frame in place.
+pub trait ColorStage: Send + Sync {
+    fn apply(&mut self, frame: &mut [Rgb]);
+}
+
+/// Raises each channel to a power, since LEDs are linear but sequences are made to look right
+/// on a screen.
+pub struct Gamma {
+    table: [u8; 256],
+}
+
+impl Gamma {
+    pub fn new(gamma: f32) -> Self {
+        let mut table = [0; 256];
+        for (i, v) in table.iter_mut().enumerate() {
+            *v = ((i as f32 / 255.0).powf(gamma) * 255.0).round() as u8;
+        }
+        Self { table }
+    }
+}
+
+impl ColorStage for Gamma {
+    fn apply(&mut self, frame: &mut [Rgb]) {
+        for rgb in frame {
+            *rgb = rgb.map(|v| self.table[v as usize]);
+        }
+    }
+}
+
+impl ColorStage for Calibration {
+    fn apply(&mut self, frame: &mut [Rgb]) {
+        for (led, rgb) in frame.iter_mut().enumerate().take(self.gains.len()) {
+            let gain = self.gains[led];
+            for (v, gain) in rgb.iter_mut().zip(gain) {
+                *v = (*v as f32 * gain).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
+
+/// Brightness levels by time of day, written as `HH:MM=LEVEL` entries separated by commas, e.g.
+/// `17:00=1.0,22:00=0.6`. Each level applies from its time until the next entry, wrapping
+/// around midnight.
+#[derive(Debug, Clone, Default)]
+pub struct Schedule {
+    entries: Vec<(NaiveTime, f32)>,
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut entries = s
+            .split(',')
+            .map(|entry| {
+                let (time, level) = entry
+                    .split_once('=')
+                    .ok_or_else(|| format!("Expected HH:MM=LEVEL, got {}", entry))?;
+                let time = NaiveTime::parse_from_str(time.trim(), "%H:%M")
+                    .map_err(|_| format!("Invalid time: {}", time))?;
+                let level: f32 = level
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("Invalid brightness: {}", level))?;
+                if !(0.0..=1.0).contains(&level) {
+                    return Err(format!("Brightness must be between 0 and 1, got {}", level));
+                }
+                Ok((time, level))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        entries.sort_by_key(|&(time, _)| time);
+        Ok(Self { entries })
+    }
+}
+
+impl Schedule {
+    pub fn brightness_at(&self, time: NaiveTime) -> f32 {
+        self.entries
+            .iter()
+            .rev()
+            .find(|&&(start, _)| start <= time)
+            .or_else(|| self.entries.last())
+            .map_or(1.0, |&(_, level)| level)
+    }
+}
+
+/// Limits brightness at send time, so the same sequence can be shown at full brightness in the
+/// evening and dimmed late at night.
+#[derive(Debug, Clone, Default)]
+pub struct BrightnessCap {
+    /// Maximum brightness from 0 to 1.
+    pub cap: f32,
+    pub schedule: Schedule,
+}
+
+impl ColorStage for BrightnessCap {
+    fn apply(&mut self, frame: &mut [Rgb]) {
+        let level = self.cap * self.schedule.brightness_at(Local::now().time());
+        if level >= 1.0 {
+            return;
+        }
+        for rgb in frame {
+            *rgb = rgb.map(|v| (v as f32 * level).round() as u8);
+        }
+    }
+}
+
+/// The order a controller expects each LED's channels in, e.g. `grb` for most WS2811 strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelOrder([usize; 3]);
+
+impl ChannelOrder {
+    pub const RGB: Self = Self([0, 1, 2]);
+    pub const GRB: Self = Self([1, 0, 2]);
+
+    pub fn apply(self, rgb: Rgb) -> Rgb {
+        let [a, b, c] = self.0;
+        [rgb[a], rgb[b], rgb[c]]
+    }
+}
+
+impl FromStr for ChannelOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let indices: Vec<usize> = s
+            .to_ascii_lowercase()
+            .chars()
+            .filter_map(|c| "rgb".find(c))
+            .collect();
+        match indices[..] {
+            [a, b, c] if s.len() == 3 && a != b && b != c && a != c => Ok(Self([a, b, c])),
+            _ => Err(format!(
+                "Channel order must be r, g and b in some order, e.g. grb, got {}",
+                s
+            )),
+        }
+    }
+}
+
+impl ColorStage for ChannelOrder {
+    fn apply(&mut self, frame: &mut [Rgb]) {
+        let order = *self;
+        for rgb in frame {
+            *rgb = order.apply(*rgb);
+        }
+    }
+}
+
+/// The corrections one output wants. Stages always run in the order gamma, calibration,
+/// brightness cap, channel order: calibration gains are measured against linear output, and
+/// reordering must come last since the other stages work on red, green and blue.
+#[derive(Debug, Clone)]
+pub struct PipelineOptions {
+    pub gamma: Option<f32>,
+    pub calibration: Option<Calibration>,
+    pub brightness: Option<BrightnessCap>,
+    pub order: ChannelOrder,
+}
+
+impl Default for PipelineOptions {
+    fn default() -> Self {
+        Self {
+            gamma: None,
+            calibration: None,
+            brightness: None,
+            order: ChannelOrder::RGB,
+        }
+    }
+}
+
+impl PipelineOptions {
+    pub fn build(self) -> ColorPipeline {
+        let mut pipeline = ColorPipeline::default();
+        if let Some(gamma) = self.gamma.filter(|&gamma| gamma != 1.0) {
+            pipeline.push(Gamma::new(gamma));
+        }
+        if let Some(calibration) = self.calibration {
+            pipeline.push(calibration);
+        }
+        if let Some(brightness) = self.brightness {
+            pipeline.push(brightness);
+        }
+        if self.order != ChannelOrder::RGB {
+            pipeline.push(self.order);
+        }
+        pipeline
+    }
+}
+
+/// Stages run one after another on each frame.
+#[derive(Default)]
+pub struct ColorPipeline {
+    stages: Vec<Box<dyn ColorStage>>,
+}
+
+impl ColorPipeline {
+    pub fn push(&mut self, stage: impl ColorStage + 'static) {
+        self.stages.push(Box::new(stage));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    pub fn apply(&mut self, frame: &mut [Rgb]) {
+        for stage in &mut self.stages {
+            stage.apply(frame);
+        }
+    }
+}
+
+/// Runs frames through a pipeline before passing them on.
+pub struct PipelineSink {
+    inner: Box<dyn OutputSink>,
+    pipeline: ColorPipeline,
+    frame: Vec<Rgb>,
+}
+
+impl PipelineSink {
+    pub fn new(inner: Box<dyn OutputSink>, pipeline: ColorPipeline) -> Self {
+        Self {
+            inner,
+            pipeline,
+            frame: Vec::new(),
+        }
+    }
+}
+
+impl OutputSink for PipelineSink {
+    fn configure(&mut self, led_count: usize) -> io::Result<()> {
+        self.inner.configure(led_count)
+    }
+
+    fn send_frame(&mut self, frame: &[Rgb]) -> io::Result<()> {
+        self.frame.clear();
+        self.frame.extend_from_slice(frame);
+        self.pipeline.apply(&mut self.frame);
+        self.inner.send_frame(&self.frame)
+    }
+
+    fn blank(&mut self, led_count: usize) -> io::Result<()> {
+        self.inner.blank(led_count)
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.inner.stats()
+    }
+}
@@ -0,0 +1,97 @@
+//! Project files, which tie together everything about one tree so the whole setup can be shared
+//! as a single `.xtree` file rather than a pile of flags. They are TOML, e.g.
+//!
+//! ```toml
+//! coords = "coords.csv"
+//! up_axis = "y"
+//! units = "cm"
+//! fps = 40
+//! hardware = "hardware.json"
+//! calibration = "calibration.json"
+//! playlist = "show.toml"
+//! outputs = ["e131://?universe=1&order=grb"]
+//! key = "a secret shared with whoever plays this project's shows"
+//!
+//! [palettes]
+//! house = "palettes/house.palette"
+//! ```
+//!
+//! Every entry is optional, and options given on the command line take precedence. Relative
+//! paths to files are relative to the project file, although output URLs are left as they are.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::{de, Deserialize, Deserializer};
+
+use crate::coords::{Handedness, Units, UpAxis};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Project {
+    pub coords: Option<PathBuf>,
+    #[serde(default, deserialize_with = "parse")]
+    pub up_axis: Option<UpAxis>,
+    #[serde(default, deserialize_with = "parse")]
+    pub handedness: Option<Handedness>,
+    #[serde(default, deserialize_with = "parse")]
+    pub units: Option<Units>,
+    #[serde(default)]
+    pub repair_outliers: bool,
+    #[serde(default)]
+    pub fill_missing: bool,
+    pub fps: Option<f32>,
+    /// Hardware profile, as for `--hardware`.
+    pub hardware: Option<PathBuf>,
+    /// Calibration written by `calibrate analyze`, which the generator corrects sequences with.
+    pub calibration: Option<PathBuf>,
+    /// The playlist `compose` renders when it isn't given one.
+    pub playlist: Option<PathBuf>,
+    /// Output URLs, used when none are given with `--output`.
+    #[serde(default)]
+    pub outputs: Vec<String>,
+    /// Palette files by name, which effect parameters can refer to as `@NAME`.
+    #[serde(default)]
+    pub palettes: HashMap<String, PathBuf>,
+    /// Secret which sealed sequences are signed or encrypted with, see [`crate::seal`].
+    pub key: Option<String>,
+}
+
+/// Reads options such as the up axis the same way as their flags.
+fn parse<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| s.parse().map_err(de::Error::custom))
+        .transpose()
+}
+
+impl Project {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read project {}: {}", path.display(), e))?;
+        let mut project: Self =
+            toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let paths = project
+            .coords
+            .iter_mut()
+            .chain(&mut project.hardware)
+            .chain(&mut project.calibration)
+            .chain(&mut project.playlist)
+            .chain(project.palettes.values_mut());
+        for path in paths {
+            *path = dir.join(&*path);
+        }
+        Ok(project)
+    }
+}
@@ -0,0 +1,746 @@
+//! Lighting control protocols understood by most pixel controllers (Falcon, ESPixelStick, WLED
+//! and so on), as alternatives to WLED's own UDP protocol.
+
+use std::{
+    convert::TryFrom,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    net::{Ipv4Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket},
+    path::Path,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+
+use crate::{
+    output::{OutputSink, SinkStats},
+    sequence::Rgb,
+};
+
+/// Channels in a DMX universe.
+pub const UNIVERSE_CHANNELS: usize = 512;
+
+/// How a frame is split into DMX universes.
+#[derive(Debug, Clone, Copy)]
+pub struct UniverseMapping {
+    /// Universe the first LED is in.
+    pub first: u16,
+    /// Channels used in each universe. The default of 510 keeps every LED's channels in one
+    /// universe.
+    pub channels: usize,
+}
+
+impl UniverseMapping {
+    pub fn new(first: u16, channels: usize) -> Result<Self, String> {
+        if !(1..=UNIVERSE_CHANNELS).contains(&channels) {
+            return Err(format!(
+                "Channels per universe must be between 1 and {}, got {}",
+                UNIVERSE_CHANNELS, channels
+            ));
+        }
+        Ok(Self { first, channels })
+    }
+
+    /// Each universe's channel data.
+    fn split<'a>(&self, data: &'a [u8]) -> Vec<&'a [u8]> {
+        data.chunks(self.channels).collect()
+    }
+
+    /// The universe of the `index`th chunk from [`split`](Self::split).
+    fn universe(&self, index: usize) -> u16 {
+        self.first.wrapping_add(index as u16)
+    }
+}
+
+fn flatten(frame: &[Rgb], data: &mut Vec<u8>) {
+    data.clear();
+    data.extend(frame.iter().flatten());
+}
+
+fn bind(address: impl ToSocketAddrs) -> io::Result<(UdpSocket, SocketAddr)> {
+    let target = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Address did not resolve"))?;
+    let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+    socket.set_broadcast(true)?;
+    Ok((socket, target))
+}
+
+/// Frames between keyframes with `loss=keyframes`, unless given.
+const DEFAULT_KEYFRAME_INTERVAL: u32 = 10;
+
+/// How a UDP sink copes with packets which don't get out, as happens on a busy WiFi link. Any
+/// universe which misses a frame shows an older one than the rest until another arrives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LossStrategy {
+    /// Send each packet once, giving up on the rest of the frame if one fails.
+    None,
+    /// Send the rest of the frame when a packet fails, then send the ones which failed again.
+    Resend,
+    /// Resend as above, but between keyframes, which send every packet, only send the packets
+    /// whose data changed or which failed last time. Less traffic means less loss on a crowded
+    /// network, and a universe which missed a change catches up at the next keyframe.
+    Keyframes(u32),
+}
+
+impl FromStr for LossStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "none" => Ok(Self::None),
+            None if s == "resend" => Ok(Self::Resend),
+            None if s == "keyframes" => Ok(Self::Keyframes(DEFAULT_KEYFRAME_INTERVAL)),
+            Some(("keyframes", interval)) => match interval.parse() {
+                Ok(interval) if interval > 0 => Ok(Self::Keyframes(interval)),
+                _ => Err(format!("Invalid keyframe interval: {}", interval)),
+            },
+            _ => Err(format!(
+                "Unknown loss strategy {}, expected none, resend or keyframes[:FRAMES]",
+                s
+            )),
+        }
+    }
+}
+
+/// Decides which of a frame's packets a sink sends, following a [`LossStrategy`].
+struct Recovery {
+    strategy: LossStrategy,
+    /// Each packet's data as last sent, with `Keyframes`.
+    sent: Vec<Vec<u8>>,
+    /// Packets which didn't get out last time, so are sent whether or not they changed.
+    failed: Vec<bool>,
+    since_keyframe: u32,
+}
+
+impl Recovery {
+    fn new(strategy: LossStrategy) -> Self {
+        Self {
+            strategy,
+            sent: Vec::new(),
+            failed: Vec::new(),
+            since_keyframe: 0,
+        }
+    }
+
+    /// Sends a frame split into `chunks`, with `send(index, chunk, push)` sending one packet.
+    /// `push` marks the last packet sent, for protocols which show the frame once it arrives.
+    fn send(
+        &mut self,
+        chunks: &[&[u8]],
+        mut send: impl FnMut(usize, &[u8], bool) -> io::Result<()>,
+    ) -> io::Result<()> {
+        if self.failed.len() != chunks.len() {
+            self.sent = vec![Vec::new(); chunks.len()];
+            self.failed = vec![false; chunks.len()];
+            self.since_keyframe = 0;
+        }
+        let keyframe = match self.strategy {
+            LossStrategy::Keyframes(interval) => {
+                let keyframe = self.since_keyframe == 0;
+                self.since_keyframe = (self.since_keyframe + 1) % interval;
+                keyframe
+            }
+            _ => true,
+        };
+        let planned: Vec<usize> = (0..chunks.len())
+            .filter(|&i| keyframe || self.failed[i] || self.sent[i] != chunks[i])
+            .collect();
+        let mut failed = Vec::new();
+        for (n, &i) in planned.iter().enumerate() {
+            match send(i, chunks[i], n + 1 == planned.len()) {
+                Ok(()) => self.delivered(i, chunks[i]),
+                Err(e) if self.strategy == LossStrategy::None => return Err(e),
+                Err(_) => failed.push(i),
+            }
+        }
+        // Sends mostly fail because the WiFi driver's queue is briefly full, so a second try
+        // after the rest of the frame often gets through
+        let mut errors = 0;
+        let mut last_error = None;
+        for (n, &i) in failed.iter().enumerate() {
+            match send(i, chunks[i], n + 1 == failed.len()) {
+                Ok(()) => self.delivered(i, chunks[i]),
+                Err(e) => {
+                    self.failed[i] = true;
+                    errors += 1;
+                    last_error = Some(e);
+                }
+            }
+        }
+        match last_error {
+            None => Ok(()),
+            Some(e) => Err(io::Error::new(
+                e.kind(),
+                format!(
+                    "{} of {} packets failed to send: {}",
+                    errors,
+                    planned.len(),
+                    e
+                ),
+            )),
+        }
+    }
+
+    fn delivered(&mut self, index: usize, chunk: &[u8]) {
+        self.failed[index] = false;
+        if let LossStrategy::Keyframes(_) = self.strategy {
+            self.sent[index].clear();
+            self.sent[index].extend_from_slice(chunk);
+        }
+    }
+}
+
+pub const E131_PORT: u16 = 5568;
+const E131_SOURCE_NAME: &[u8] = b"xmas_tree";
+const E131_PRIORITY: u8 = 100;
+
+/// E1.31 (streaming ACN), sent to each universe's multicast group or to a single controller.
+pub struct E131Sink {
+    socket: UdpSocket,
+    /// Where to send every universe, or `None` to multicast.
+    target: Option<SocketAddr>,
+    mapping: UniverseMapping,
+    /// Identifies this sender to receivers, which may merge several.
+    cid: [u8; 16],
+    sequence: u8,
+    data: Vec<u8>,
+    packet: Vec<u8>,
+    stats: SinkStats,
+    recovery: Recovery,
+}
+
+impl E131Sink {
+    pub fn multicast(mapping: UniverseMapping) -> io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        Ok(Self::new(socket, None, mapping))
+    }
+
+    pub fn unicast(address: impl ToSocketAddrs, mapping: UniverseMapping) -> io::Result<Self> {
+        let (socket, target) = bind(address)?;
+        Ok(Self::new(socket, Some(target), mapping))
+    }
+
+    fn new(socket: UdpSocket, target: Option<SocketAddr>, mapping: UniverseMapping) -> Self {
+        Self {
+            socket,
+            target,
+            mapping,
+            cid: rand::thread_rng().gen(),
+            sequence: 0,
+            data: Vec::new(),
+            packet: Vec::new(),
+            stats: SinkStats::default(),
+            recovery: Recovery::new(LossStrategy::None),
+        }
+    }
+
+    pub fn with_loss_strategy(mut self, strategy: LossStrategy) -> Self {
+        self.recovery = Recovery::new(strategy);
+        self
+    }
+
+    fn multicast_address(universe: u16) -> SocketAddr {
+        let [hi, lo] = universe.to_be_bytes();
+        (Ipv4Addr::new(239, 255, hi, lo), E131_PORT).into()
+    }
+}
+
+/// Builds an E1.31 data packet: root, framing and DMP layers, then the DMX start code and
+/// channel data.
+fn build_e131_packet(
+    packet: &mut Vec<u8>,
+    cid: &[u8; 16],
+    sequence: u8,
+    universe: u16,
+    channels: &[u8],
+) {
+    let total = 126 + channels.len();
+    // Each layer starts with its length from that point, with the top flag bits set
+    let flags_length = |from: usize| (0x7000 | (total - from) as u16).to_be_bytes();
+    packet.clear();
+    // Root layer
+    packet.extend_from_slice(&0x0010u16.to_be_bytes());
+    packet.extend_from_slice(&0u16.to_be_bytes());
+    packet.extend_from_slice(b"ASC-E1.17\0\0\0");
+    packet.extend_from_slice(&flags_length(16));
+    packet.extend_from_slice(&4u32.to_be_bytes());
+    packet.extend_from_slice(cid);
+    // Framing layer
+    packet.extend_from_slice(&flags_length(38));
+    packet.extend_from_slice(&2u32.to_be_bytes());
+    let mut source_name = [0; 64];
+    source_name[..E131_SOURCE_NAME.len()].copy_from_slice(E131_SOURCE_NAME);
+    packet.extend_from_slice(&source_name);
+    packet.push(E131_PRIORITY);
+    packet.extend_from_slice(&0u16.to_be_bytes());
+    packet.push(sequence);
+    packet.push(0);
+    packet.extend_from_slice(&universe.to_be_bytes());
+    // DMP layer
+    packet.extend_from_slice(&flags_length(115));
+    packet.extend_from_slice(&[0x02, 0xa1]);
+    packet.extend_from_slice(&0u16.to_be_bytes());
+    packet.extend_from_slice(&1u16.to_be_bytes());
+    packet.extend_from_slice(&(1 + channels.len() as u16).to_be_bytes());
+    packet.push(0);
+    packet.extend_from_slice(channels);
+}
+
+impl OutputSink for E131Sink {
+    fn send_frame(&mut self, frame: &[Rgb]) -> io::Result<()> {
+        flatten(frame, &mut self.data);
+        let Self {
+            socket,
+            target,
+            mapping,
+            cid,
+            sequence,
+            data,
+            packet,
+            stats,
+            recovery,
+        } = self;
+        let result = recovery.send(&mapping.split(data), |i, channels, _| {
+            let universe = mapping.universe(i);
+            build_e131_packet(packet, cid, *sequence, universe, channels);
+            let target = target.unwrap_or_else(|| Self::multicast_address(universe));
+            socket.send_to(packet, target)?;
+            stats.record_packet(packet.len());
+            Ok(())
+        });
+        *sequence = sequence.wrapping_add(1);
+        if result.is_ok() {
+            stats.frames += 1;
+        }
+        result
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.stats
+    }
+}
+
+pub const ARTNET_PORT: u16 = 6454;
+const ARTNET_OP_DMX: u16 = 0x5000;
+const ARTNET_PROTOCOL_VERSION: u16 = 14;
+
+/// Art-Net `ArtDmx` packets, sent to a controller or a broadcast address.
+pub struct ArtNetSink {
+    socket: UdpSocket,
+    target: SocketAddr,
+    mapping: UniverseMapping,
+    sequence: u8,
+    data: Vec<u8>,
+    packet: Vec<u8>,
+    stats: SinkStats,
+    recovery: Recovery,
+}
+
+impl ArtNetSink {
+    pub fn connect(address: impl ToSocketAddrs, mapping: UniverseMapping) -> io::Result<Self> {
+        let (socket, target) = bind(address)?;
+        Ok(Self {
+            socket,
+            target,
+            mapping,
+            sequence: 1,
+            data: Vec::new(),
+            packet: Vec::new(),
+            stats: SinkStats::default(),
+            recovery: Recovery::new(LossStrategy::None),
+        })
+    }
+
+    pub fn with_loss_strategy(mut self, strategy: LossStrategy) -> Self {
+        self.recovery = Recovery::new(strategy);
+        self
+    }
+}
+
+impl OutputSink for ArtNetSink {
+    fn send_frame(&mut self, frame: &[Rgb]) -> io::Result<()> {
+        flatten(frame, &mut self.data);
+        let Self {
+            socket,
+            target,
+            mapping,
+            sequence,
+            data,
+            packet,
+            stats,
+            recovery,
+        } = self;
+        let result = recovery.send(&mapping.split(data), |i, channels, _| {
+            packet.clear();
+            packet.extend_from_slice(b"Art-Net\0");
+            packet.extend_from_slice(&ARTNET_OP_DMX.to_le_bytes());
+            packet.extend_from_slice(&ARTNET_PROTOCOL_VERSION.to_be_bytes());
+            packet.push(*sequence);
+            packet.push(0);
+            // Port-address: sub-net and universe, then net
+            packet.extend_from_slice(&mapping.universe(i).to_le_bytes());
+            // The length must be even, so odd universes are padded
+            let padding = channels.len() % 2;
+            packet.extend_from_slice(&((channels.len() + padding) as u16).to_be_bytes());
+            packet.extend_from_slice(channels);
+            packet.resize(packet.len() + padding, 0);
+            socket.send_to(packet, *target)?;
+            stats.record_packet(packet.len());
+            Ok(())
+        });
+        // Zero means sequencing is disabled, so skip it
+        *sequence = sequence.checked_add(1).unwrap_or(1);
+        if result.is_ok() {
+            stats.frames += 1;
+        }
+        result
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.stats
+    }
+}
+
+pub const DDP_PORT: u16 = 4048;
+const DDP_VERSION_1: u8 = 0x40;
+const DDP_PUSH: u8 = 0x01;
+const DDP_TYPE_RGB24: u8 = 0x0b;
+const DDP_DESTINATION_DISPLAY: u8 = 1;
+/// Keeps packets within a standard Ethernet MTU, and a whole number of LEDs.
+const DDP_MAX_DATA: usize = 1440;
+
+/// The Distributed Display Protocol, which WLED and many pixel controllers accept. It has no
+/// universes: the frame is sent as one buffer, split into packets by offset.
+pub struct DdpSink {
+    socket: UdpSocket,
+    target: SocketAddr,
+    sequence: u8,
+    data: Vec<u8>,
+    packet: Vec<u8>,
+    stats: SinkStats,
+    recovery: Recovery,
+}
+
+impl DdpSink {
+    pub fn connect(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let (socket, target) = bind(address)?;
+        Ok(Self {
+            socket,
+            target,
+            sequence: 1,
+            data: Vec::new(),
+            packet: Vec::new(),
+            stats: SinkStats::default(),
+            recovery: Recovery::new(LossStrategy::None),
+        })
+    }
+
+    pub fn with_loss_strategy(mut self, strategy: LossStrategy) -> Self {
+        self.recovery = Recovery::new(strategy);
+        self
+    }
+}
+
+impl OutputSink for DdpSink {
+    fn send_frame(&mut self, frame: &[Rgb]) -> io::Result<()> {
+        flatten(frame, &mut self.data);
+        let Self {
+            socket,
+            target,
+            sequence,
+            data,
+            packet,
+            stats,
+            recovery,
+        } = self;
+        let chunks: Vec<&[u8]> = data.chunks(DDP_MAX_DATA).collect();
+        let result = recovery.send(&chunks, |i, chunk, last| {
+            // Controllers show the frame once the packet with the push flag arrives
+            let push = if last { DDP_PUSH } else { 0 };
+            packet.clear();
+            packet.push(DDP_VERSION_1 | push);
+            packet.push(*sequence);
+            packet.push(DDP_TYPE_RGB24);
+            packet.push(DDP_DESTINATION_DISPLAY);
+            packet.extend_from_slice(&((i * DDP_MAX_DATA) as u32).to_be_bytes());
+            packet.extend_from_slice(&(chunk.len() as u16).to_be_bytes());
+            packet.extend_from_slice(chunk);
+            socket.send_to(packet, *target)?;
+            stats.record_packet(packet.len());
+            Ok(())
+        });
+        // Sequence numbers run from 1 to 15, with 0 meaning unused
+        *sequence = *sequence % 15 + 1;
+        if result.is_ok() {
+            stats.frames += 1;
+        }
+        result
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.stats
+    }
+}
+
+/// Header bytes before a DDP packet's data, without the optional timecode.
+const DDP_HEADER_LEN: usize = 10;
+const DDP_TIMECODE: u8 = 0x10;
+/// Largest UDP payload, so no sender's packets are cut short.
+const DDP_MAX_PACKET: usize = 65507;
+
+/// Receives DDP frames, such as those a [`DdpSink`] sends, so another machine can show what a
+/// tree is being sent.
+pub struct DdpReceiver {
+    socket: UdpSocket,
+    packet: Vec<u8>,
+    /// The frame so far. Packets only overwrite their part of it, so a sender which skips
+    /// unchanged packets still gives whole frames.
+    data: Vec<u8>,
+}
+
+impl DdpReceiver {
+    pub fn bind(address: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(Self {
+            socket: UdpSocket::bind(address)?,
+            packet: vec![0; DDP_MAX_PACKET],
+            data: Vec::new(),
+        })
+    }
+
+    /// Waits for the packet with the push flag, then gives the whole frame.
+    pub fn receive(&mut self, frame: &mut Vec<Rgb>) -> io::Result<()> {
+        loop {
+            let len = self.socket.recv(&mut self.packet)?;
+            let packet = &self.packet[..len];
+            // Anything which isn't DDP version 1 is ignored, as controllers do
+            if len < DDP_HEADER_LEN || packet[0] & 0xc0 != DDP_VERSION_1 {
+                continue;
+            }
+            let header = if packet[0] & DDP_TIMECODE != 0 {
+                DDP_HEADER_LEN + 4
+            } else {
+                DDP_HEADER_LEN
+            };
+            let offset = u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]) as usize;
+            let length = u16::from_be_bytes([packet[8], packet[9]]) as usize;
+            let data = packet.get(header..).unwrap_or_default();
+            let data = &data[..length.min(data.len())];
+            let end = offset + data.len();
+            if self.data.len() < end {
+                self.data.resize(end, 0);
+            }
+            self.data[offset..end].copy_from_slice(data);
+            if packet[0] & DDP_PUSH != 0 {
+                frame.clear();
+                frame.extend(self.data.chunks_exact(3).map(|c| [c[0], c[1], c[2]]));
+                return Ok(());
+            }
+        }
+    }
+}
+
+pub const OPC_PORT: u16 = 7890;
+const OPC_SET_PIXELS: u8 = 0;
+const OPC_CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long to wait after a failed connection before trying again, so an unreachable server
+/// doesn't stall every frame.
+const OPC_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Open Pixel Control over TCP, as used by Fadecandy and many LED servers. Reconnects on the
+/// next frame if the connection drops.
+pub struct OpcSink {
+    target: SocketAddr,
+    /// OPC channel (strip) to address, where 0 means every channel.
+    channel: u8,
+    stream: Option<TcpStream>,
+    retry_at: Option<Instant>,
+    packet: Vec<u8>,
+    stats: SinkStats,
+}
+
+impl OpcSink {
+    pub fn connect(address: impl ToSocketAddrs, channel: u8) -> io::Result<Self> {
+        let target = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Address did not resolve"))?;
+        let mut sink = Self {
+            target,
+            channel,
+            stream: None,
+            retry_at: None,
+            packet: Vec::new(),
+            stats: SinkStats::default(),
+        };
+        // Fail early if the server isn't there
+        sink.stream()?;
+        Ok(sink)
+    }
+
+    fn stream(&mut self) -> io::Result<&mut TcpStream> {
+        if self.stream.is_none() {
+            if self.retry_at.is_some_and(|at| Instant::now() < at) {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    "Waiting to reconnect",
+                ));
+            }
+            let stream = TcpStream::connect_timeout(&self.target, OPC_CONNECT_TIMEOUT)
+                .and_then(|stream| stream.set_nodelay(true).map(|()| stream));
+            match stream {
+                Ok(stream) => self.stream = Some(stream),
+                Err(e) => {
+                    self.retry_at = Some(Instant::now() + OPC_RETRY_DELAY);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(self.stream.as_mut().unwrap())
+    }
+}
+
+impl OutputSink for OpcSink {
+    fn send_frame(&mut self, frame: &[Rgb]) -> io::Result<()> {
+        let data_len = u16::try_from(frame.len() * 3).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Too many LEDs for one OPC message",
+            )
+        })?;
+        let mut packet = std::mem::take(&mut self.packet);
+        packet.clear();
+        packet.extend_from_slice(&[self.channel, OPC_SET_PIXELS]);
+        packet.extend_from_slice(&data_len.to_be_bytes());
+        packet.extend(frame.iter().flatten());
+        let result = self.stream().and_then(|stream| stream.write_all(&packet));
+        if result.is_err() {
+            self.stream = None;
+        } else {
+            self.stats.record_packet(packet.len());
+            self.stats.frames += 1;
+        }
+        self.packet = packet;
+        result
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.stats
+    }
+}
+
+/// The Adalight serial protocol, spoken by Arduino-style controllers: `Ada`, the LED count
+/// minus one and a checksum, then RGB data. The port's baud rate isn't set here; configure it
+/// first, e.g. `stty -F /dev/ttyUSB0 115200 raw`.
+pub struct AdalightSink {
+    port: File,
+    packet: Vec<u8>,
+    stats: SinkStats,
+}
+
+impl AdalightSink {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            port: OpenOptions::new().write(true).open(path)?,
+            packet: Vec::new(),
+            stats: SinkStats::default(),
+        })
+    }
+}
+
+impl OutputSink for AdalightSink {
+    fn send_frame(&mut self, frame: &[Rgb]) -> io::Result<()> {
+        let count = u16::try_from(frame.len().saturating_sub(1)).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Too many LEDs for Adalight")
+        })?;
+        let [hi, lo] = count.to_be_bytes();
+        self.packet.clear();
+        self.packet.extend_from_slice(b"Ada");
+        self.packet.extend_from_slice(&[hi, lo, hi ^ lo ^ 0x55]);
+        self.packet.extend(frame.iter().flatten());
+        self.port.write_all(&self.packet)?;
+        self.port.flush()?;
+        self.stats.record_packet(self.packet.len());
+        self.stats.frames += 1;
+        Ok(())
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.stats
+    }
+}
+
+/// SPI clock for driving WS2812-style LEDs, at which three SPI bits make one LED bit.
+#[cfg(feature = "gpio")]
+const SPI_SPEED_HZ: u32 = 2_400_000;
+/// `SPI_IOC_WR_MAX_SPEED_HZ` from `linux/spi/spidev.h`.
+#[cfg(feature = "gpio")]
+const SPI_IOC_WR_MAX_SPEED_HZ: u32 = 0x4004_6b04;
+/// Zero bytes sent after each frame to hold the line low long enough for the LEDs to latch it,
+/// 280µs for the newer WS2812B.
+#[cfg(feature = "gpio")]
+const SPI_LATCH_BYTES: usize = 90;
+
+/// WS2812-style LEDs wired straight to a Raspberry Pi, driven from its SPI MOSI pin (GPIO 10 for
+/// `/dev/spidev0.0`) through the kernel's spidev driver, so no root access or DMA channel is
+/// needed. Each bit of LED data is sent as three SPI bits, `110` for a one and `100` for a zero.
+///
+/// Frames are written in one transfer, which spidev limits to 4096 bytes (about 450 LEDs) unless
+/// `spidev.bufsiz=65536` is added to the kernel command line.
+#[cfg(feature = "gpio")]
+pub struct GpioSink {
+    device: File,
+    packet: Vec<u8>,
+    stats: SinkStats,
+}
+
+#[cfg(feature = "gpio")]
+impl GpioSink {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+
+        let device = OpenOptions::new().write(true).open(path)?;
+        let speed = SPI_SPEED_HZ;
+        // SAFETY: the request takes a pointer to a u32, which outlives the call
+        let result = unsafe {
+            libc::ioctl(
+                device.as_raw_fd(),
+                SPI_IOC_WR_MAX_SPEED_HZ as _,
+                &speed as *const u32,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            device,
+            packet: Vec::new(),
+            stats: SinkStats::default(),
+        })
+    }
+}
+
+#[cfg(feature = "gpio")]
+impl OutputSink for GpioSink {
+    fn send_frame(&mut self, frame: &[Rgb]) -> io::Result<()> {
+        self.packet.clear();
+        for &byte in frame.iter().flatten() {
+            let mut bits = 0u32;
+            for bit in (0..8).rev() {
+                bits = bits << 3 | if byte >> bit & 1 == 1 { 0b110 } else { 0b100 };
+            }
+            self.packet.extend_from_slice(&bits.to_be_bytes()[1..]);
+        }
+        self.packet.resize(self.packet.len() + SPI_LATCH_BYTES, 0);
+        self.device.write_all(&self.packet)?;
+        self.stats.record_packet(self.packet.len());
+        self.stats.frames += 1;
+        Ok(())
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.stats
+    }
+}
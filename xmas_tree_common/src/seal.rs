@@ -0,0 +1,256 @@
+//! Sealed sequence files, for sharing shows which shouldn't be quietly altered or re-exported.
+//! A sealed file wraps an ordinary sequence file of any format:
+//!
+//! ```text
+//! "XTSEAL1\0" | flags (1 byte) | salt (16 bytes) | nonce (12 bytes) | contents | tag (32 bytes)
+//! ```
+//!
+//! Two keys are derived from the project's secret and the file's salt with PBKDF2: one for the
+//! tag, an HMAC-SHA-256 of everything before it, so a file which has been changed, or sealed by
+//! someone without the secret, is refused; and one for encrypting the contents, if asked to, with
+//! ChaCha20-Poly1305.
+//!
+//! Sealing uses a secret shared between the people a project's shows are shared with, not a
+//! public key, so it proves a file was sealed by someone holding the secret but not by whom:
+//! anyone who can check a signed show can also forge one, and anyone who can play an encrypted
+//! show could still re-export it. This keeps honest people honest rather than stopping a
+//! determined copier.
+//!
+//! Readers unseal files transparently once a key is set, either with [`set_key`] (from the
+//! project file) or the `XMAS_TREE_KEY` environment variable.
+
+use std::{borrow::Cow, env, error::Error, fmt, fs, path::Path, str::FromStr, sync::RwLock};
+
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Nonce,
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+pub const MAGIC: &[u8] = b"XTSEAL1\0";
+/// The environment variable the key is read from when the project doesn't set one.
+pub const KEY_VARIABLE: &str = "XMAS_TREE_KEY";
+
+const ENCRYPTED: u8 = 1;
+const SIGNED: u8 = 2;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const SALT_START: usize = MAGIC.len() + 1;
+const NONCE_START: usize = SALT_START + SALT_LEN;
+const HEADER_LEN: usize = NONCE_START + NONCE_LEN;
+const TAG_LEN: usize = 32;
+/// PBKDF2 rounds, so a stolen file can't be used to try passphrases quickly. Tests, built
+/// without optimizations, would take seconds over each file.
+const KDF_ROUNDS: u32 = if cfg!(test) { 1_000 } else { 100_000 };
+
+static KEY: RwLock<Option<SealKey>> = RwLock::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SealMode {
+    /// Readable by anyone with the key, and refused if changed.
+    Sign,
+    /// Signed, and unreadable without the key.
+    Encrypt,
+}
+
+impl FromStr for SealMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sign" => Ok(Self::Sign),
+            "encrypt" => Ok(Self::Encrypt),
+            other => Err(format!("Unknown seal mode: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for SealMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Sign => "sign",
+            Self::Encrypt => "encrypt",
+        })
+    }
+}
+
+/// The secret files are sealed with, from which each file's keys are derived.
+#[derive(Clone)]
+pub struct SealKey(Vec<u8>);
+
+/// The keys for one sealed file.
+struct FileKeys {
+    mac: [u8; 32],
+    cipher: [u8; 32],
+}
+
+impl SealKey {
+    /// Takes a secret, such as a passphrase, of any length.
+    pub fn from_secret(secret: &str) -> Self {
+        Self(secret.as_bytes().to_vec())
+    }
+
+    /// Derives the keys for the file with `salt`.
+    fn file_keys(&self, salt: &[u8]) -> FileKeys {
+        let mut derived = [0; 64];
+        pbkdf2::pbkdf2_hmac::<Sha256>(&self.0, salt, KDF_ROUNDS, &mut derived);
+        let mut keys = FileKeys {
+            mac: [0; 32],
+            cipher: [0; 32],
+        };
+        keys.mac.copy_from_slice(&derived[..32]);
+        keys.cipher.copy_from_slice(&derived[32..]);
+        keys
+    }
+}
+
+impl FileKeys {
+    fn mac(&self, data: &[u8]) -> Hmac<Sha256> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.mac)
+            .expect("HMAC takes keys of any length");
+        mac.update(data);
+        mac
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(&self.cipher.into())
+    }
+}
+
+/// Sets the key sealed files are read and written with, overriding `XMAS_TREE_KEY`.
+pub fn set_key(secret: &str) {
+    *KEY.write().unwrap() = Some(SealKey::from_secret(secret));
+}
+
+/// The key set with [`set_key`], or else from the environment.
+pub fn key() -> Option<SealKey> {
+    if let Some(key) = KEY.read().unwrap().clone() {
+        return Some(key);
+    }
+    let secret = env::var(KEY_VARIABLE).ok()?;
+    Some(SealKey::from_secret(&secret))
+}
+
+pub fn is_sealed(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+pub fn seal(data: &[u8], mode: SealMode, key: &SealKey) -> Vec<u8> {
+    let flags = match mode {
+        SealMode::Sign => SIGNED,
+        SealMode::Encrypt => SIGNED | ENCRYPTED,
+    };
+    let salt: [u8; SALT_LEN] = rand::random();
+    let nonce: [u8; NONCE_LEN] = rand::random();
+    let keys = key.file_keys(&salt);
+    let mut sealed = Vec::with_capacity(HEADER_LEN + data.len() + TAG_LEN);
+    sealed.extend_from_slice(MAGIC);
+    sealed.push(flags);
+    sealed.extend_from_slice(&salt);
+    sealed.extend_from_slice(&nonce);
+    match mode {
+        SealMode::Sign => sealed.extend_from_slice(data),
+        SealMode::Encrypt => {
+            let payload = Payload {
+                msg: data,
+                aad: &sealed,
+            };
+            let encrypted = keys
+                .cipher()
+                .encrypt(Nonce::from_slice(&nonce), payload)
+                .expect("sequences are far smaller than ChaCha20 can encrypt");
+            sealed.extend_from_slice(&encrypted);
+        }
+    }
+    let tag = keys.mac(&sealed).finalize().into_bytes();
+    sealed.extend_from_slice(&tag);
+    sealed
+}
+
+/// Seals the sequence file at `path` in place, with the key set for reading.
+pub fn seal_file(path: &Path, mode: SealMode) -> Result<(), Box<dyn Error>> {
+    let key = key().ok_or_else(|| {
+        format!(
+            "Sealing sequences needs a key, set in the project or {}",
+            KEY_VARIABLE
+        )
+    })?;
+    let data = fs::read(path)?;
+    fs::write(path, seal(&data, mode, &key))
+        .map_err(|e| format!("Cannot write {}: {}", path.display(), e))?;
+    Ok(())
+}
+
+/// The checked, and if need be decrypted, contents of a sealed file. Files which aren't sealed
+/// are given back as they are.
+pub fn unseal(data: &[u8]) -> Result<Cow<'_, [u8]>, String> {
+    if !is_sealed(data) {
+        return Ok(Cow::Borrowed(data));
+    }
+    if data.len() < HEADER_LEN + TAG_LEN {
+        return Err("Sealed sequence is truncated".into());
+    }
+    let key = key().ok_or_else(|| {
+        format!(
+            "Sequence is sealed, and can only be read with its project's key or {}",
+            KEY_VARIABLE
+        )
+    })?;
+    let (body, tag) = data.split_at(data.len() - TAG_LEN);
+    let changed = "Sealed sequence has been changed, or was sealed with another key";
+    let keys = key.file_keys(&body[SALT_START..NONCE_START]);
+    keys.mac(body)
+        .verify_slice(tag)
+        .map_err(|_| changed.to_string())?;
+    let flags = body[MAGIC.len()];
+    if flags & !(SIGNED | ENCRYPTED) != 0 {
+        return Err(format!("Sealed sequence has unknown flags {:#04x}", flags));
+    }
+    let contents = &body[HEADER_LEN..];
+    if flags & ENCRYPTED == 0 {
+        return Ok(Cow::Borrowed(contents));
+    }
+    let payload = Payload {
+        msg: contents,
+        aad: &body[..HEADER_LEN],
+    };
+    let contents = keys
+        .cipher()
+        .decrypt(Nonce::from_slice(&body[NONCE_START..HEADER_LEN]), payload)
+        .map_err(|_| changed.to_string())?;
+    Ok(Cow::Owned(contents))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        set_key("open sesame");
+        let key = key().unwrap();
+        let contents = b"FRAME_ID,R_0,G_0,B_0\n0,255,0,0\n";
+        for mode in [SealMode::Sign, SealMode::Encrypt] {
+            let sealed = seal(contents, mode, &key);
+            assert_eq!(unseal(&sealed).unwrap()[..], contents[..]);
+            let readable = sealed
+                .windows(contents.len())
+                .any(|window| window == contents);
+            assert_eq!(readable, mode == SealMode::Sign);
+
+            // Any change to the header, contents or tag is refused
+            for i in [
+                MAGIC.len(),
+                SALT_START,
+                NONCE_START,
+                HEADER_LEN,
+                sealed.len() - 1,
+            ] {
+                let mut changed = sealed.clone();
+                changed[i] ^= 1;
+                assert!(unseal(&changed).is_err());
+            }
+        }
+    }
+}
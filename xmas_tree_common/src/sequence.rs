@@ -0,0 +1,343 @@
+use std::{
+    error::Error,
+    fmt,
+    fs::{self, File},
+    io::{self, BufRead},
+    ops::Deref,
+    path::Path,
+    str::FromStr,
+};
+
+use memmap2::Mmap;
+
+use crate::{
+    csv_format::{self, CsvIndex},
+    delta_format::{self, DeltaCursor, DeltaIndex},
+    fseq_format::{self, FseqCursor, FseqIndex},
+    metadata::SequenceMetadata,
+    seal,
+};
+
+pub type Rgb = [u8; 3];
+
+pub struct Sequence {
+    pub led_count: usize,
+    pub frames: Vec<Vec<Rgb>>,
+    /// Number of values which were outside 0..=255 in the source file and had to be clamped.
+    pub clamped_values: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceFormat {
+    Csv,
+    Delta,
+    /// The xLights and Falcon Player format.
+    Fseq,
+}
+
+impl FromStr for SequenceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "delta" => Ok(Self::Delta),
+            "fseq" => Ok(Self::Fseq),
+            other => Err(format!("Unknown sequence format: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for SequenceFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Csv => "csv",
+            Self::Delta => "delta",
+            Self::Fseq => "fseq",
+        })
+    }
+}
+
+impl SequenceFormat {
+    /// Works out the format of a file from its first few bytes.
+    pub fn sniff(reader: &mut impl BufRead) -> io::Result<Self> {
+        let start = reader.fill_buf()?;
+        Ok(if start.starts_with(delta_format::MAGIC) {
+            Self::Delta
+        } else if start.starts_with(fseq_format::MAGIC) || start.starts_with(fseq_format::OLD_MAGIC)
+        {
+            Self::Fseq
+        } else {
+            Self::Csv
+        })
+    }
+}
+
+pub trait SequenceWriter {
+    fn write_frame(&mut self, frame: &[Rgb]) -> io::Result<()>;
+    fn flush(&mut self) -> io::Result<()>;
+
+    /// Ends the file, writing anything held back until the end. Nothing can be written after.
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl<W: SequenceWriter + ?Sized> SequenceWriter for Box<W> {
+    fn write_frame(&mut self, frame: &[Rgb]) -> io::Result<()> {
+        (**self).write_frame(frame)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        (**self).finish()
+    }
+}
+
+/// Black frames added around a sequence, since some controllers glitch if the first frame
+/// they see isn't dark.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Blanking {
+    pub lead_in: usize,
+    pub lead_out: usize,
+    /// Force the first frame of the sequence itself to black.
+    pub blank_first: bool,
+}
+
+impl Blanking {
+    pub fn total_frames(&self, frames: usize) -> usize {
+        self.lead_in + frames + self.lead_out
+    }
+}
+
+/// Wraps another writer to apply [`Blanking`]. Lead-out frames are only written by `finish`.
+pub struct BlankingWriter<W> {
+    inner: W,
+    blanking: Blanking,
+    black: Vec<Rgb>,
+    started: bool,
+}
+
+impl<W: SequenceWriter> BlankingWriter<W> {
+    pub fn new(inner: W, blanking: Blanking, led_count: usize) -> Self {
+        Self {
+            inner,
+            blanking,
+            black: vec![[0; 3]; led_count],
+            started: false,
+        }
+    }
+
+    /// Continues a partially written sequence, whose lead-in has already been written.
+    pub fn resume(inner: W, blanking: Blanking, led_count: usize) -> Self {
+        Self {
+            started: true,
+            ..Self::new(inner, blanking, led_count)
+        }
+    }
+}
+
+impl<W: SequenceWriter> SequenceWriter for BlankingWriter<W> {
+    fn write_frame(&mut self, frame: &[Rgb]) -> io::Result<()> {
+        if self.started {
+            return self.inner.write_frame(frame);
+        }
+        self.started = true;
+        for _ in 0..self.blanking.lead_in {
+            self.inner.write_frame(&self.black)?;
+        }
+        if self.blanking.blank_first {
+            self.inner.write_frame(&self.black)
+        } else {
+            self.inner.write_frame(frame)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        for _ in 0..self.blanking.lead_out {
+            self.inner.write_frame(&self.black)?;
+        }
+        self.inner.finish()
+    }
+}
+
+/// Unseals a sequence file's contents if it's sealed. The checksum in the sidecar is of the file
+/// as it is on disk, so is checked first.
+fn unseal(path: &Path, data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    Ok(seal::unseal(data)
+        .map_err(|e| format!("{}: {}", path.display(), e))?
+        .into_owned())
+}
+
+/// Reads a sequence file and its sidecar metadata, verifying its checksum if there is one.
+fn load(path: &Path) -> Result<(Vec<u8>, Option<SequenceMetadata>), Box<dyn Error>> {
+    let data = fs::read(path)?;
+    let metadata = SequenceMetadata::load(path)?;
+    if let Some(metadata) = &metadata {
+        metadata.verify_checksum(path, &data)?;
+    }
+    let data = if seal::is_sealed(&data) {
+        unseal(path, &data)?
+    } else {
+        data
+    };
+    Ok((data, metadata))
+}
+
+/// The contents of a sequence file being indexed.
+enum Contents {
+    Mapped(Mmap),
+    /// A sealed file, which has to be unsealed into memory.
+    Unsealed(Vec<u8>),
+}
+
+impl Deref for Contents {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Mapped(map) => map,
+            Self::Unsealed(data) => data,
+        }
+    }
+}
+
+/// Maps a sequence file into memory, so only the parts being read take up space, and verifies
+/// its checksum as [`load`] does.
+fn map(path: &Path) -> Result<(Contents, Option<SequenceMetadata>), Box<dyn Error>> {
+    let file = File::open(path)?;
+    // SAFETY: the map is only ever read. Another program truncating the file while it's mapped
+    // would crash the reader, which is the price of not holding whole shows in memory.
+    let data = unsafe { Mmap::map(&file)? };
+    let metadata = SequenceMetadata::load(path)?;
+    if let Some(metadata) = &metadata {
+        metadata.verify_checksum(path, &data)?;
+    }
+    let data = if seal::is_sealed(&data) {
+        Contents::Unsealed(unseal(path, &data)?)
+    } else {
+        Contents::Mapped(data)
+    };
+    Ok((data, metadata))
+}
+
+/// Reads a sequence in any supported format, verifying it against its sidecar metadata if
+/// there is one.
+pub fn read(path: &Path) -> Result<Sequence, Box<dyn Error>> {
+    let (data, metadata) = load(path)?;
+    let mut reader = &data[..];
+    let format = SequenceFormat::sniff(&mut reader)?;
+    let sequence = match format {
+        SequenceFormat::Csv => csv_format::read(reader)?,
+        SequenceFormat::Delta => delta_format::read(reader)?,
+        SequenceFormat::Fseq => fseq_format::read(reader)?,
+    };
+    if let Some(metadata) = &metadata {
+        metadata.verify_contents(path, format, sequence.frames.len(), sequence.led_count)?;
+    }
+    Ok(sequence)
+}
+
+enum IndexKind {
+    Csv(CsvIndex),
+    Delta(DeltaIndex),
+    Fseq(FseqIndex),
+    /// A CSV file with quoted fields, which can only be read in order so is decoded up front.
+    Decoded(Sequence),
+}
+
+/// A sequence file indexed so any frame can be read without decoding the frames before it, for
+/// seeking around long shows. The file is mapped rather than read, so even shows larger than
+/// memory can be played, unless it's sealed.
+pub struct SequenceIndex {
+    data: Contents,
+    kind: IndexKind,
+}
+
+/// Where a reader of a [`SequenceIndex`] is up to. Each independent reader, such as a preview
+/// and a hardware output running ahead of it, should have its own.
+#[derive(Default)]
+pub struct Cursor {
+    delta: DeltaCursor,
+    fseq: FseqCursor,
+    frame: Vec<Rgb>,
+}
+
+impl SequenceIndex {
+    /// Indexes a sequence in any supported format, verifying it against its sidecar metadata if
+    /// there is one.
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let (data, metadata) = map(path)?;
+        let format = SequenceFormat::sniff(&mut &data[..])?;
+        let kind = match format {
+            SequenceFormat::Csv => match CsvIndex::build(&data)? {
+                Some(index) => IndexKind::Csv(index),
+                None => IndexKind::Decoded(csv_format::read(&data)?),
+            },
+            SequenceFormat::Delta => IndexKind::Delta(DeltaIndex::build(&data)?),
+            SequenceFormat::Fseq => IndexKind::Fseq(FseqIndex::build(&data)?),
+        };
+        let index = Self { data, kind };
+        if let Some(metadata) = &metadata {
+            metadata.verify_contents(path, format, index.frame_count(), index.led_count())?;
+        }
+        Ok(index)
+    }
+
+    pub fn led_count(&self) -> usize {
+        match &self.kind {
+            IndexKind::Csv(index) => index.led_count(),
+            IndexKind::Delta(index) => index.led_count(),
+            IndexKind::Fseq(index) => index.led_count(),
+            IndexKind::Decoded(sequence) => sequence.led_count,
+        }
+    }
+
+    pub fn frame_count(&self) -> usize {
+        match &self.kind {
+            IndexKind::Csv(index) => index.frame_count(),
+            IndexKind::Delta(index) => index.frame_count(),
+            IndexKind::Fseq(index) => index.frame_count(),
+            IndexKind::Decoded(sequence) => sequence.frames.len(),
+        }
+    }
+
+    /// The frame rate recorded in the file, for formats which have one.
+    pub fn fps(&self) -> Option<f32> {
+        match &self.kind {
+            IndexKind::Fseq(index) => index.fps(),
+            _ => None,
+        }
+    }
+
+    /// Reads frame `index`, which must be less than the frame count.
+    pub fn frame<'c>(
+        &self,
+        cursor: &'c mut Cursor,
+        index: usize,
+    ) -> Result<&'c [Rgb], Box<dyn Error>> {
+        match &self.kind {
+            IndexKind::Csv(csv_index) => {
+                csv_index.read_frame(&self.data, index, &mut cursor.frame)?;
+                Ok(&cursor.frame)
+            }
+            IndexKind::Delta(delta_index) => {
+                Ok(delta_index.read_frame(&self.data, &mut cursor.delta, index)?)
+            }
+            IndexKind::Fseq(fseq_index) => {
+                Ok(fseq_index.read_frame(&self.data, &mut cursor.fseq, index)?)
+            }
+            IndexKind::Decoded(sequence) => {
+                cursor.frame.clone_from(&sequence.frames[index]);
+                Ok(&cursor.frame)
+            }
+        }
+    }
+}
@@ -0,0 +1,53 @@
+//! Benchmarks over the stress-test layouts: reading them, building their neighbour graphs, and a
+//! frame of every effect on each. Run one group with e.g. `cargo bench -- effects/dense-cone`.
+
+use std::time::Duration;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use xmas_tree_gen::stress::{self, Layout, Workload};
+
+const LAYOUTS: &[(&str, Layout)] = &[
+    ("dense-cone", Layout::DenseCone),
+    ("irregular-scan", Layout::IrregularScan),
+];
+
+fn load(c: &mut Criterion) {
+    let mut group = c.benchmark_group("load");
+    for &(name, layout) in LAYOUTS {
+        let csv = stress::layout_csv(layout).unwrap();
+        group.bench_function(name, |b| b.iter(|| stress::load(black_box(&csv)).unwrap()));
+    }
+    group.finish();
+}
+
+fn neighbours(c: &mut Criterion) {
+    let mut group = c.benchmark_group("neighbours");
+    group.sample_size(10);
+    for &(name, layout) in LAYOUTS {
+        let workload = Workload::new(layout).unwrap();
+        group.bench_function(name, |b| {
+            b.iter(|| stress::neighbour_graph(black_box(workload.coords())))
+        });
+    }
+    group.finish();
+}
+
+fn effects(c: &mut Criterion) {
+    for &(name, layout) in LAYOUTS {
+        let workload = Workload::new(layout).unwrap();
+        let mut group = c.benchmark_group(format!("effects/{}", name));
+        // Every effect on every layout adds up, so each gets a short run
+        group
+            .sample_size(10)
+            .warm_up_time(Duration::from_millis(500))
+            .measurement_time(Duration::from_secs(2));
+        for effect in stress::effect_names() {
+            let mut run = workload.effect(effect).unwrap();
+            group.bench_function(effect, |b| b.iter(|| black_box(run.render_frame()).len()));
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, load, neighbours, effects);
+criterion_main!(benches);
@@ -0,0 +1,175 @@
+//! Red-green color blindness, for checking that a show can be followed by everyone watching and
+//! for making one that can. The common forms lose most of the difference between reds and greens,
+//! so candy-cane stripes or a red and green chase can turn into a single muddy color.
+//!
+//! Frames are judged by how much of the contrast between their lit LEDs survives a simulation of
+//! the color vision (Machado, Oliveira and Fernandes, 2009), and shows are made safer by moving
+//! greens and cyans round to blues, which stay distinct from reds and yellows.
+
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use xmas_tree_common::color;
+
+use crate::effects::Color;
+
+/// Channel value below which an LED is treated as off, and left out of contrast measurements.
+const LIT: f32 = 0.1;
+/// Red-green contrast below which a frame has nothing to lose.
+const MIN_RED_GREEN: f32 = 0.15;
+/// Share of a frame's contrast below which it counts as confusable.
+const MIN_RETAINED: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorVision {
+    /// Missing green cones, the most common form.
+    Deuteranopia,
+    /// Missing red cones, which also makes reds look dark.
+    Protanopia,
+}
+
+impl FromStr for ColorVision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deuteranopia" | "deutan" => Ok(Self::Deuteranopia),
+            "protanopia" | "protan" => Ok(Self::Protanopia),
+            _ => Err(format!(
+                "Unknown color vision {}, expected deuteranopia or protanopia",
+                s
+            )),
+        }
+    }
+}
+
+impl ColorVision {
+    /// How a color looks with this color vision.
+    pub fn simulate(self, (r, g, b): Color) -> Color {
+        let m = match self {
+            Self::Deuteranopia => [
+                [0.367322, 0.860646, -0.227968],
+                [0.280085, 0.672501, 0.047413],
+                [-0.011820, 0.042940, 0.968881],
+            ],
+            Self::Protanopia => [
+                [0.152286, 1.052583, -0.204868],
+                [0.114503, 0.786281, 0.099216],
+                [-0.003882, -0.048116, 1.051998],
+            ],
+        };
+        let row = |[mr, mg, mb]: [f32; 3]| (mr * r + mg * g + mb * b).clamp(0.0, 1.0);
+        (row(m[0]), row(m[1]), row(m[2]))
+    }
+
+    /// A color which stays distinct from the others of a palette with this color vision, keeping
+    /// its brightness and saturation.
+    pub fn remap(self, rgb: Color) -> Color {
+        // Hues in degrees, and where they move to. Greens head for sky blue, and for protanopia
+        // reds lean towards orange so they don't go dark.
+        let points: &[(f32, f32)] = match self {
+            Self::Deuteranopia => &[
+                (0.0, 0.0),
+                (60.0, 50.0),
+                (120.0, 200.0),
+                (180.0, 215.0),
+                (240.0, 250.0),
+                (300.0, 300.0),
+                (360.0, 360.0),
+            ],
+            Self::Protanopia => &[
+                (0.0, 20.0),
+                (60.0, 55.0),
+                (120.0, 200.0),
+                (180.0, 215.0),
+                (240.0, 250.0),
+                (300.0, 320.0),
+                (360.0, 380.0),
+            ],
+        };
+        let (hue, saturation, value) = color::rgb_to_hsv(rgb);
+        if saturation <= 0.0 {
+            return rgb;
+        }
+        let degrees = hue.rem_euclid(1.0) * 360.0;
+        let moved = points
+            .windows(2)
+            .find(|pair| degrees <= pair[1].0)
+            .map(|pair| {
+                let ((from_a, to_a), (from_b, to_b)) = (pair[0], pair[1]);
+                to_a + (degrees - from_a) / (from_b - from_a) * (to_b - to_a)
+            })
+            .unwrap_or(degrees);
+        color::hsv_to_rgb(moved / 360.0, saturation, value)
+    }
+}
+
+/// How well a frame reads with and without a color vision deficiency.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameContrast {
+    /// Spread of the lit LEDs along the red-green axis.
+    pub red_green: f32,
+    /// Share of the spread between lit LEDs, in every direction, which survives simulation.
+    pub retained: f32,
+}
+
+impl FrameContrast {
+    /// Whether the frame relies on a red-green difference which mostly disappears.
+    pub fn confusable(&self) -> bool {
+        self.red_green >= MIN_RED_GREEN && self.retained < MIN_RETAINED
+    }
+}
+
+/// A color as lightness and two opponent axes, red-green and yellow-blue, roughly as eyes
+/// compare colors.
+fn opponent((r, g, b): Color) -> [f32; 3] {
+    [(r + g + b) / 3.0, r - g, (r + g) / 2.0 - b]
+}
+
+/// The root mean square distance of colors from their mean.
+fn spread(colors: &[[f32; 3]]) -> f32 {
+    if colors.is_empty() {
+        return 0.0;
+    }
+    let n = colors.len() as f32;
+    let mut mean = [0.0; 3];
+    for color in colors {
+        for (m, v) in mean.iter_mut().zip(color) {
+            *m += v / n;
+        }
+    }
+    let variance: f32 = colors
+        .iter()
+        .map(|color| {
+            color
+                .iter()
+                .zip(&mean)
+                .map(|(v, m)| (v - m) * (v - m))
+                .sum::<f32>()
+        })
+        .sum::<f32>()
+        / n;
+    variance.sqrt()
+}
+
+/// How much of a frame's contrast is lost with `vision`.
+pub fn measure(frame: &[Color], vision: ColorVision) -> FrameContrast {
+    let lit: Vec<Color> = frame
+        .iter()
+        .copied()
+        .filter(|&(r, g, b)| r.max(g).max(b) >= LIT)
+        .collect();
+    let seen: Vec<[f32; 3]> = lit.iter().map(|&c| opponent(c)).collect();
+    let simulated: Vec<[f32; 3]> = lit.iter().map(|&c| opponent(vision.simulate(c))).collect();
+    let red_green: Vec<[f32; 3]> = seen.iter().map(|&[_, rg, _]| [0.0, rg, 0.0]).collect();
+    let total = spread(&seen);
+    FrameContrast {
+        red_green: spread(&red_green),
+        retained: if total > 0.0 {
+            (spread(&simulated) / total).min(1.0)
+        } else {
+            1.0
+        },
+    }
+}
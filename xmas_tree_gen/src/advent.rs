@@ -0,0 +1,170 @@
+use std::{
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use chrono::{Datelike, NaiveDate};
+
+use crate::{
+    effects::{mix, smoothstep, Color, Coord},
+    generate::Length,
+    params::ParamArg,
+};
+
+/// What a day of the calendar shows.
+#[derive(Debug, Clone)]
+pub enum Entry {
+    Effect {
+        name: String,
+        params: Vec<ParamArg>,
+        len: Length,
+    },
+    /// A pre-rendered sequence file, played on a loop.
+    Sequence {
+        path: PathBuf,
+        /// The coordinates the sequence was made for, if not the tree's.
+        coords: Option<PathBuf>,
+        /// The rate the sequence was made at, if not recorded in its directory's manifest.
+        fps: Option<f32>,
+    },
+}
+
+/// The list of things to unlock, one per day from the 1st of December. Each line is either an
+/// effect name followed by `NAME=VALUE` parameters (with `len=FRAMES` setting the loop length, as
+/// with `--len`), or `@PATH` for a sequence file, optionally followed by `coords=PATH` for the
+/// coordinates it was made for and `fps=N`. Blank lines and `#` comments are ignored. If there
+/// are fewer than 24 entries, the list repeats.
+#[derive(Debug, Clone)]
+pub struct Calendar {
+    entries: Vec<Entry>,
+}
+
+fn parse_entry(line: &str, base: &Path) -> Result<Entry, String> {
+    if let Some(path) = line.strip_prefix('@') {
+        // Options come after the path, which may itself contain spaces
+        let mut path = path.trim();
+        let (mut coords, mut fps) = (None, None);
+        while let Some((rest, word)) = path.rsplit_once(char::is_whitespace) {
+            match word.split_once('=') {
+                Some(("coords", value)) => coords = Some(base.join(value)),
+                Some(("fps", value)) => {
+                    fps = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("Invalid fps: {}", value))?,
+                    )
+                }
+                _ => break,
+            }
+            path = rest.trim_end();
+        }
+        return Ok(Entry::Sequence {
+            path: base.join(path),
+            coords,
+            fps,
+        });
+    }
+    let mut words = line.split_whitespace();
+    let name = words.next().unwrap_or_default().to_string();
+    let mut params = Vec::new();
+    let mut len = Length::Auto { cycles: 1 };
+    for word in words {
+        let param: ParamArg = word.parse()?;
+        if param.name == "len" {
+            len = param.value.parse()?;
+        } else {
+            params.push(param);
+        }
+    }
+    Ok(Entry::Effect { name, params, len })
+}
+
+impl Calendar {
+    /// Loads a calendar. Sequence paths are relative to the calendar file.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        let entries = fs::read_to_string(path)?
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(i, line)| {
+                parse_entry(line, base)
+                    .map_err(|e| format!("{}, line {}: {}", path.display(), i + 1, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if entries.is_empty() {
+            return Err(format!("{} has no entries", path.display()).into());
+        }
+        Ok(Self { entries })
+    }
+
+    /// The entry unlocked on a day of advent, counting from 1.
+    pub fn entry(&self, day: u32) -> &Entry {
+        &self.entries[(day.max(1) - 1) as usize % self.entries.len()]
+    }
+}
+
+/// The last day of advent. Every day after it in December shows the final entry.
+pub const LAST_DAY: u32 = 24;
+
+/// Which day of advent a date falls on, or `None` outside December.
+pub fn day_of_advent(date: NaiveDate) -> Option<u32> {
+    if date.month() == 12 {
+        Some(date.day().min(LAST_DAY))
+    } else {
+        None
+    }
+}
+
+/// Remembers the last date the reveal was shown, so restarting later the same day goes straight
+/// to the effect.
+pub struct AdventState {
+    path: PathBuf,
+}
+
+impl AdventState {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Whether nothing has been shown yet on this date.
+    pub fn is_first_showing(&self, date: NaiveDate) -> Result<bool, Box<dyn Error>> {
+        match fs::read_to_string(&self.path) {
+            Ok(last) => Ok(last.trim().parse::<NaiveDate>().ok() != Some(date)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(true),
+            Err(e) => Err(format!("Cannot read {}: {}", self.path.display(), e).into()),
+        }
+    }
+
+    pub fn record(&self, date: NaiveDate) -> Result<(), Box<dyn Error>> {
+        fs::write(&self.path, format!("{}\n", date))
+            .map_err(|e| format!("Cannot write {}: {}", self.path.display(), e).into())
+    }
+}
+
+/// How long the reveal animation takes.
+pub const REVEAL_SECONDS: f32 = 5.0;
+
+const REVEAL_EDGE: Color = (1.0, 0.9, 0.6);
+
+/// Uncovers the day's effect from the bottom of the tree upwards behind a bright edge, as
+/// `progress` goes from 0 to 1.
+pub fn reveal(coords: &[Coord], progress: f32, frame: &mut [Color]) {
+    const EDGE_WIDTH: f32 = 0.15;
+    let (bottom, top) = coords
+        .iter()
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), c| {
+            (min.min(c.2), max.max(c.2))
+        });
+    let span = (top - bottom).max(f32::EPSILON);
+    // Start and finish with the edge just off the tree
+    let line = -EDGE_WIDTH + progress * (1.0 + 2.0 * EDGE_WIDTH);
+    for (color, coord) in frame.iter_mut().zip(coords) {
+        let height = (coord.2 - bottom) / span;
+        let covered = smoothstep(line - EDGE_WIDTH, line, height);
+        let edge = 1.0 - ((height - line).abs() / EDGE_WIDTH).min(1.0);
+        *color = mix(mix(*color, (0.0, 0.0, 0.0), covered), REVEAL_EDGE, edge);
+    }
+}
@@ -0,0 +1,296 @@
+//! Lines a pre-rendered show up with its music. Community shows are often made against a slightly
+//! different copy of a song, or at a rate that drifts from the one they're played at, so the
+//! lights land a little before or after the beat and slip further as the song goes on.
+//!
+//! Onsets (notes and hits starting) are found in the audio and cuts (frames where much of the tree
+//! changes at once) in the sequence, and every offset and speed within range is tried to find the
+//! one which puts the most cuts on onsets. The sequence frame `i` then plays at frame
+//! `offset + i * speed` of the audio.
+
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use serde::Serialize;
+use tracing::info;
+use xmas_tree_common::{
+    csv_format::CsvWriter,
+    delta_format::DeltaWriter,
+    fseq_format::FseqWriter,
+    metadata::{Credit, Marker, SequenceMetadata},
+    seal::{self, SealMode},
+    sequence::{self, Rgb, Sequence, SequenceFormat, SequenceWriter},
+};
+
+use crate::{audio::AudioTrack, report::Report};
+
+/// Steps the search takes through offsets, in frames, and speeds.
+const OFFSET_STEP: f32 = 0.25;
+const SPEED_STEP: f32 = 0.0005;
+/// How far from an onset a cut can be and still count as on it, in frames.
+const TOLERANCE: f32 = 1.5;
+/// How far a peak must rise above the average around it to count as an event.
+const PEAK_THRESHOLD: f32 = 1.5;
+/// Seconds either side of a frame its surroundings are averaged over.
+const PEAK_WINDOW: f32 = 1.0;
+/// Seconds between events, so one long change isn't counted several times.
+const PEAK_GAP: f32 = 0.1;
+/// Share of the strongest event's size below which nothing counts, so noise in quiet passages
+/// and slow fades are left out.
+const PEAK_FLOOR: f32 = 0.1;
+/// How many more of the cuts the best alignment must put on onsets before it's worth changing.
+const MIN_IMPROVEMENT: f32 = 0.05;
+
+#[derive(Debug, Serialize)]
+pub struct AlignReport {
+    pub onsets: usize,
+    pub cuts: usize,
+    /// Share of the cuts on an onset as the sequence is.
+    pub matched_before: f32,
+    /// Seconds to delay the sequence by, which is negative to bring it forward.
+    pub offset_secs: f32,
+    pub speed: f32,
+    /// Share of the cuts on an onset once aligned.
+    pub matched_after: f32,
+    /// Whether the alignment is worth making.
+    pub improved: bool,
+    /// Where the aligned sequence was written, if it was.
+    pub written: Option<String>,
+}
+
+impl Report for AlignReport {
+    fn write_text(&self, out: &mut dyn io::Write) -> io::Result<()> {
+        writeln!(out, "Audio onsets:      {}", self.onsets)?;
+        writeln!(out, "Sequence cuts:     {}", self.cuts)?;
+        writeln!(
+            out,
+            "On the beat:       {:.0}% as is, {:.0}% aligned",
+            self.matched_before * 100.0,
+            self.matched_after * 100.0
+        )?;
+        writeln!(out, "Offset:            {:+.3}s", self.offset_secs)?;
+        writeln!(out, "Speed:             {:.4}x", self.speed)?;
+        if !self.improved {
+            writeln!(out, "The sequence already lines up as well as it can")?;
+        }
+        if let Some(path) = &self.written {
+            writeln!(out, "Wrote:             {}", path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Frames which stand out from those around them, at most one in each gap.
+fn peaks(curve: &[f32], fps: f32) -> Vec<usize> {
+    let window = (PEAK_WINDOW * fps).round() as usize;
+    let gap = ((PEAK_GAP * fps).round() as usize).max(1);
+    let floor = curve.iter().copied().fold(0.0, f32::max) * PEAK_FLOOR;
+    (0..curve.len())
+        .filter(|&i| {
+            let value = curve[i];
+            let near = &curve[i.saturating_sub(gap)..(i + gap + 1).min(curve.len())];
+            let around = &curve[i.saturating_sub(window)..(i + window + 1).min(curve.len())];
+            let average = around.iter().sum::<f32>() / around.len() as f32;
+            // The first of equal neighbours wins, so a plateau gives one event
+            let first_max = near.iter().all(|&other| other <= value)
+                && curve[i.saturating_sub(gap)..i]
+                    .iter()
+                    .all(|&other| other < value);
+            value > floor && value > average * PEAK_THRESHOLD && first_max
+        })
+        .collect()
+}
+
+/// How much of the tree changes on each frame, from 0 to 1.
+fn changes(sequence: &Sequence) -> Vec<f32> {
+    let mut changes = vec![0.0; sequence.frames.len()];
+    for (i, pair) in sequence.frames.windows(2).enumerate() {
+        let total: u32 = pair[1]
+            .iter()
+            .flatten()
+            .zip(pair[0].iter().flatten())
+            .map(|(&now, &before)| (now as i32 - before as i32).unsigned_abs())
+            .sum();
+        changes[i + 1] = total as f32 / (sequence.led_count.max(1) * 3 * 255) as f32;
+    }
+    changes
+}
+
+/// How well cuts land on onsets with an alignment: each cut counts fully when it's on an onset,
+/// falling to nothing at `TOLERANCE` frames away. Also gives how many are within it.
+fn score(cuts: &[usize], onsets: &[usize], offset: f32, speed: f32) -> (f32, usize) {
+    let mut total = 0.0;
+    let mut matched = 0;
+    for &cut in cuts {
+        let at = offset + cut as f32 * speed;
+        let next = onsets.partition_point(|&onset| (onset as f32) < at);
+        let distance = [next.checked_sub(1), Some(next)]
+            .iter()
+            .flatten()
+            .filter_map(|&i| onsets.get(i))
+            .map(|&onset| (onset as f32 - at).abs())
+            .fold(f32::INFINITY, f32::min);
+        if distance <= TOLERANCE {
+            total += 1.0 - distance / TOLERANCE;
+            matched += 1;
+        }
+    }
+    (total, matched)
+}
+
+/// Finds the offset and speed which best line up `sequence` with the audio at `audio_path`,
+/// searching offsets up to `max_offset` seconds either way and speeds up to `max_speed` faster
+/// or slower. If `output` is given and the alignment is worth making, writes the retimed
+/// sequence there.
+#[allow(clippy::too_many_arguments)]
+pub fn align(
+    sequence_path: &Path,
+    audio_path: &Path,
+    fps: f32,
+    max_offset: f32,
+    max_speed: f32,
+    output: Option<&Path>,
+    format: SequenceFormat,
+    seal: Option<SealMode>,
+) -> Result<AlignReport, Box<dyn Error>> {
+    let sequence = sequence::read(sequence_path)?;
+    let audio = AudioTrack::load(audio_path, fps)?;
+    let onsets = peaks(&audio.onset_strength(), fps);
+    let cuts = peaks(&changes(&sequence), fps);
+    if onsets.is_empty() || cuts.is_empty() {
+        return Err(format!(
+            "Found {} onsets in {} and {} cuts in {}, so there's nothing to line up",
+            onsets.len(),
+            audio_path.display(),
+            cuts.len(),
+            sequence_path.display()
+        )
+        .into());
+    }
+
+    let (before, matched_before) = score(&cuts, &onsets, 0.0, 1.0);
+    let offset_steps = (max_offset.abs() * fps / OFFSET_STEP).round() as i32;
+    let speed_steps = (max_speed.abs() / SPEED_STEP).round() as i32;
+    let mut best = (before, matched_before, 0.0, 1.0);
+    // Closest to unchanged first, so ties keep the smallest change
+    let mut speeds: Vec<i32> = (-speed_steps..=speed_steps).collect();
+    speeds.sort_by_key(|step| step.abs());
+    let mut offsets: Vec<i32> = (-offset_steps..=offset_steps).collect();
+    offsets.sort_by_key(|step| step.abs());
+    for &speed_step in &speeds {
+        let speed = 1.0 + speed_step as f32 * SPEED_STEP;
+        for &offset_step in &offsets {
+            let offset = offset_step as f32 * OFFSET_STEP;
+            let (total, matched) = score(&cuts, &onsets, offset, speed);
+            if total > best.0 {
+                best = (total, matched, offset, speed);
+            }
+        }
+    }
+    let share = |matched: usize| matched as f32 / cuts.len() as f32;
+    let (_, matched, offset, speed) = best;
+    let improved = share(matched) - share(matched_before) >= MIN_IMPROVEMENT;
+    let (matched_after, offset, speed) = if improved {
+        info!(
+            "Best alignment delays by {:.2} frames at {:.4}x speed",
+            offset, speed
+        );
+        (matched, offset, speed)
+    } else {
+        (matched_before, 0.0, 1.0)
+    };
+
+    let written = match output {
+        Some(output) if improved => {
+            retime(sequence_path, &sequence, output, format, fps, offset, speed)?;
+            if let Some(mode) = seal {
+                seal::seal_file(output, mode)?;
+            }
+            Some(output.display().to_string())
+        }
+        _ => None,
+    };
+    Ok(AlignReport {
+        onsets: onsets.len(),
+        cuts: cuts.len(),
+        matched_before: share(matched_before),
+        offset_secs: offset / fps,
+        speed,
+        matched_after: share(matched_after),
+        improved,
+        written,
+    })
+}
+
+/// Writes the sequence so its frame `i` plays at `offset + i * speed`, with black before it
+/// starts. Frames are repeated or dropped rather than blended, so cuts stay sharp.
+fn retime(
+    sequence_path: &Path,
+    sequence: &Sequence,
+    output: &Path,
+    format: SequenceFormat,
+    fps: f32,
+    offset: f32,
+    speed: f32,
+) -> Result<(), Box<dyn Error>> {
+    let moved = |frame: usize| (offset + frame as f32 * speed).round();
+    let len = moved(sequence.frames.len()).max(1.0) as usize;
+    let file: Box<dyn Write> = Box::new(BufWriter::new(File::create(output)?));
+    let mut writer: Box<dyn SequenceWriter> = match format {
+        SequenceFormat::Csv => Box::new(CsvWriter::new(file, sequence.led_count)?),
+        SequenceFormat::Delta => Box::new(DeltaWriter::new(file, sequence.led_count)?),
+        SequenceFormat::Fseq => Box::new(FseqWriter::new(file, sequence.led_count, fps)),
+    };
+    let black: Vec<Rgb> = vec![[0; 3]; sequence.led_count];
+    for frame in 0..len {
+        let source = ((frame as f32 - offset) / speed).round();
+        let colors = if source < 0.0 {
+            &black
+        } else {
+            let last = sequence.frames.len() - 1;
+            &sequence.frames[(source as usize).min(last)]
+        };
+        writer.write_frame(colors)?;
+    }
+    writer.finish()?;
+    drop(writer);
+
+    let (coords_hash, markers, credits) = match SequenceMetadata::load(sequence_path)? {
+        Some(metadata) => (metadata.coords_hash, metadata.markers, metadata.credits),
+        None => (None, Vec::new(), Vec::new()),
+    };
+    let markers = markers
+        .into_iter()
+        .filter(|marker| moved(marker.frame) >= 0.0)
+        .map(|marker| Marker {
+            frame: moved(marker.frame) as usize,
+            ..marker
+        })
+        .collect();
+    let credits = credits
+        .into_iter()
+        .filter_map(|credit| {
+            let start = moved(credit.start_frame);
+            let end = moved(credit.start_frame + credit.frames);
+            (end > 0.0).then(|| Credit {
+                start_frame: start.max(0.0) as usize,
+                frames: (end - start.max(0.0)) as usize,
+                ..credit
+            })
+        })
+        .collect();
+    SequenceMetadata::write_sidecar(
+        output,
+        format,
+        len,
+        sequence.led_count,
+        coords_hash,
+        markers,
+        credits,
+    )?;
+    info!("Wrote {} aligned frames to {}", len, output.display());
+    Ok(())
+}
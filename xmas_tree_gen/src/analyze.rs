@@ -0,0 +1,171 @@
+use std::io;
+
+use serde::Serialize;
+use xmas_tree_common::{
+    hardware::HardwareProfile,
+    sequence::{Rgb, Sequence},
+};
+
+use crate::{
+    accessibility::{self, ColorVision},
+    report::Report,
+};
+
+#[derive(Debug, Serialize)]
+pub struct AnalyzeReport {
+    pub frames: usize,
+    pub leds: usize,
+    pub fps: f32,
+    pub duration_secs: f32,
+    pub mean_brightness: f32,
+    pub peak_frame_brightness: f32,
+    pub peak_frame: usize,
+    pub out_of_range_values: usize,
+    /// Fastest refresh rate of the hardware profile, if one was given.
+    pub hardware_max_fps: Option<f32>,
+    /// How the sequence reads with a color vision deficiency, if asked for.
+    pub accessibility: Option<AccessibilityReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccessibilityReport {
+    pub color_vision: ColorVision,
+    /// Mean spread of each frame's lit LEDs along the red-green axis.
+    pub mean_red_green_contrast: f32,
+    /// Mean share of each frame's contrast which survives the color vision.
+    pub mean_retained: f32,
+    pub confusable_frames: usize,
+    /// Runs of frames which rely on a red-green difference that mostly disappears, as first and
+    /// last frame.
+    pub confusable_spans: Vec<(usize, usize)>,
+}
+
+fn accessibility(sequence: &Sequence, vision: ColorVision) -> AccessibilityReport {
+    let channel = |v: u8| v as f32 / 255.0;
+    let mut red_green = 0.0;
+    let mut retained = 0.0;
+    let mut confusable_frames = 0;
+    let mut confusable_spans: Vec<(usize, usize)> = Vec::new();
+    for (index, frame) in sequence.frames.iter().enumerate() {
+        let colors: Vec<_> = frame
+            .iter()
+            .map(|&[r, g, b]| (channel(r), channel(g), channel(b)))
+            .collect();
+        let contrast = accessibility::measure(&colors, vision);
+        red_green += contrast.red_green;
+        retained += contrast.retained;
+        if !contrast.confusable() {
+            continue;
+        }
+        confusable_frames += 1;
+        match confusable_spans.last_mut() {
+            Some((_, last)) if *last + 1 == index => *last = index,
+            _ => confusable_spans.push((index, index)),
+        }
+    }
+    let frames = sequence.frames.len().max(1) as f32;
+    AccessibilityReport {
+        color_vision: vision,
+        mean_red_green_contrast: red_green / frames,
+        mean_retained: retained / frames,
+        confusable_frames,
+        confusable_spans,
+    }
+}
+
+fn frame_brightness(colors: &[Rgb]) -> f32 {
+    if colors.is_empty() {
+        return 0.0;
+    }
+    let total: u32 = colors.iter().flatten().map(|&v| v as u32).sum();
+    total as f32 / (colors.len() * 3 * 255) as f32
+}
+
+pub fn analyze(
+    sequence: &Sequence,
+    fps: f32,
+    hardware: Option<&HardwareProfile>,
+    vision: Option<ColorVision>,
+) -> AnalyzeReport {
+    let mut total_brightness = 0.0;
+    let mut peak_frame_brightness = 0.0;
+    let mut peak_frame = 0;
+    for (index, frame) in sequence.frames.iter().enumerate() {
+        let brightness = frame_brightness(frame);
+        total_brightness += brightness;
+        if brightness > peak_frame_brightness {
+            peak_frame_brightness = brightness;
+            peak_frame = index;
+        }
+    }
+    let frames = sequence.frames.len();
+    AnalyzeReport {
+        frames,
+        leds: sequence.led_count,
+        fps,
+        duration_secs: frames as f32 / fps,
+        mean_brightness: if frames > 0 {
+            total_brightness / frames as f32
+        } else {
+            0.0
+        },
+        peak_frame_brightness,
+        peak_frame,
+        out_of_range_values: sequence.clamped_values,
+        hardware_max_fps: hardware.map(HardwareProfile::max_fps),
+        accessibility: vision.map(|vision| accessibility(sequence, vision)),
+    }
+}
+
+impl Report for AnalyzeReport {
+    fn write_text(&self, out: &mut dyn io::Write) -> io::Result<()> {
+        writeln!(out, "Frames:            {}", self.frames)?;
+        writeln!(out, "LEDs:              {}", self.leds)?;
+        writeln!(
+            out,
+            "Duration:          {:.1}s at {} fps",
+            self.duration_secs, self.fps
+        )?;
+        writeln!(out, "Mean brightness:   {:.3}", self.mean_brightness)?;
+        writeln!(
+            out,
+            "Peak brightness:   {:.3} (frame {})",
+            self.peak_frame_brightness, self.peak_frame
+        )?;
+        writeln!(out, "Out of range:      {}", self.out_of_range_values)?;
+        if let Some(max_fps) = self.hardware_max_fps {
+            writeln!(out, "Hardware max fps:  {:.1}", max_fps)?;
+        }
+        if let Some(report) = &self.accessibility {
+            writeln!(
+                out,
+                "Red-green:         {:.3} mean contrast, {:.0}% kept with {:?}",
+                report.mean_red_green_contrast,
+                report.mean_retained * 100.0,
+                report.color_vision
+            )?;
+            let spans: Vec<String> = report
+                .confusable_spans
+                .iter()
+                .map(|&(first, last)| {
+                    if first == last {
+                        first.to_string()
+                    } else {
+                        format!("{}-{}", first, last)
+                    }
+                })
+                .collect();
+            write!(
+                out,
+                "Confusable:        {} frames",
+                report.confusable_frames
+            )?;
+            if spans.is_empty() {
+                writeln!(out)?;
+            } else {
+                writeln!(out, " ({})", spans.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,392 @@
+//! Reads WAV files and analyses them once per frame, so effects can react to music. Frame `n`
+//! of the analysis is `n / fps` seconds into the audio, so a sequence lines up with the track
+//! when both start together.
+
+use std::{
+    convert::{TryFrom, TryInto},
+    error::Error,
+    f32::consts::PI,
+    fs,
+    ops::Range,
+    path::Path,
+};
+
+/// The audio of a WAV file, mixed down to mono.
+pub struct Wav {
+    pub sample_rate: u32,
+    pub samples: Vec<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    Pcm,
+    Float,
+}
+
+struct Format {
+    encoding: Encoding,
+    channels: usize,
+    sample_rate: u32,
+    bits: u16,
+}
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+/// The real format is then given by the first two bytes of the sub-format GUID.
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+impl Format {
+    fn parse(chunk: &[u8]) -> Result<Self, String> {
+        if chunk.len() < 16 {
+            return Err("fmt chunk is too short".into());
+        }
+        let tag = match u16_at(chunk, 0) {
+            WAVE_FORMAT_EXTENSIBLE if chunk.len() >= 26 => u16_at(chunk, 24),
+            tag => tag,
+        };
+        let encoding = match tag {
+            WAVE_FORMAT_PCM => Encoding::Pcm,
+            WAVE_FORMAT_IEEE_FLOAT => Encoding::Float,
+            other => return Err(format!("unsupported encoding {:#x}", other)),
+        };
+        let format = Self {
+            encoding,
+            channels: u16_at(chunk, 2) as usize,
+            sample_rate: u32_at(chunk, 4),
+            bits: u16_at(chunk, 14),
+        };
+        if format.channels == 0 || format.sample_rate == 0 {
+            return Err("no channels".into());
+        }
+        match (format.encoding, format.bits) {
+            (Encoding::Pcm, 8 | 16 | 24 | 32) | (Encoding::Float, 32 | 64) => Ok(format),
+            (encoding, bits) => Err(format!("unsupported {}-bit {:?} samples", bits, encoding)),
+        }
+    }
+
+    fn sample(&self, bytes: &[u8]) -> f32 {
+        match (self.encoding, self.bits) {
+            (Encoding::Pcm, 8) => (bytes[0] as f32 - 128.0) / 128.0,
+            (Encoding::Pcm, 16) => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+            // Shift into the top of an i32 to sign extend
+            (Encoding::Pcm, 24) => {
+                i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) as f32 / 2147483648.0
+            }
+            (Encoding::Pcm, _) => {
+                i32::from_le_bytes(bytes.try_into().unwrap()) as f32 / 2147483648.0
+            }
+            (Encoding::Float, 32) => f32::from_le_bytes(bytes.try_into().unwrap()),
+            (Encoding::Float, _) => f64::from_le_bytes(bytes.try_into().unwrap()) as f32,
+        }
+    }
+}
+
+impl Wav {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::parse(&fs::read(path)?)
+            .map_err(|e| format!("Cannot read {}: {}", path.display(), e).into())
+    }
+
+    fn parse(data: &[u8]) -> Result<Self, String> {
+        if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+            return Err("not a WAV file".into());
+        }
+        let mut format = None;
+        let mut body = None;
+        let mut offset = 12;
+        while offset + 8 <= data.len() {
+            let len = u32_at(data, offset + 4) as usize;
+            let chunk = &data[offset + 8..(offset + 8).saturating_add(len).min(data.len())];
+            match &data[offset..offset + 4] {
+                b"fmt " => format = Some(Format::parse(chunk)?),
+                b"data" => body = Some(chunk),
+                _ => {}
+            }
+            // Chunks are padded to an even length
+            offset = offset.saturating_add(8 + len + len % 2);
+        }
+        let format = format.ok_or("no fmt chunk")?;
+        let body = body.ok_or("no data chunk")?;
+        let sample_bytes = format.bits as usize / 8;
+        let samples = body
+            .chunks_exact(sample_bytes * format.channels)
+            .map(|frame| {
+                let sum: f32 = frame
+                    .chunks_exact(sample_bytes)
+                    .map(|bytes| format.sample(bytes))
+                    .sum();
+                sum / format.channels as f32
+            })
+            .collect();
+        Ok(Self {
+            sample_rate: format.sample_rate,
+            samples,
+        })
+    }
+}
+
+/// Samples analysed around each frame. At 44.1 kHz this is about 46 ms, long enough to resolve
+/// bass notes.
+const WINDOW: usize = 2048;
+/// Bands of the spectrum effects can show.
+pub const SPECTRUM_BANDS: usize = 16;
+const SPECTRUM_LOW_HZ: f32 = 40.0;
+const SPECTRUM_HIGH_HZ: f32 = 16000.0;
+const BASS_HZ: (f32, f32) = (20.0, 250.0);
+const MID_HZ: (f32, f32) = (250.0, 4000.0);
+const TREBLE_HZ: (f32, f32) = (4000.0, 16000.0);
+/// Features are scaled so that this fraction of frames are at or below 1.
+const LOUD_PERCENTILE: f32 = 0.95;
+/// A beat is bass this many times louder than its average over the last second...
+const BEAT_THRESHOLD: f32 = 1.4;
+/// ...and at least this far into the loud range, so quiet passages don't trigger on noise.
+const BEAT_FLOOR: f32 = 0.2;
+/// Seconds after a beat before another can start.
+const BEAT_GAP: f32 = 0.25;
+
+/// What the audio is doing at one frame.
+#[derive(Debug, Clone, Default)]
+pub struct AudioFrame {
+    /// Energy in each range, scaled so the loud parts of the track are near 1.
+    pub bass: f32,
+    pub mid: f32,
+    pub treble: f32,
+    /// Whether a beat starts on this frame.
+    pub beat: bool,
+    /// Energy in `SPECTRUM_BANDS` bands evenly spaced in pitch from 40 Hz to 16 kHz, each scaled
+    /// like the ranges.
+    pub spectrum: Vec<f32>,
+}
+
+impl AudioFrame {
+    /// Overall loudness, from the loudest of the three ranges.
+    pub fn level(&self) -> f32 {
+        self.bass.max(self.mid).max(self.treble)
+    }
+}
+
+/// The analysis of a whole track, one entry per frame.
+#[derive(Debug, Clone)]
+pub struct AudioTrack {
+    frames: Vec<AudioFrame>,
+    /// Frames which start a beat, in order.
+    beats: Vec<usize>,
+    silence: AudioFrame,
+}
+
+/// In-place radix-2 FFT. `twiddles` are `e^(-2 pi i k / n)` for `k < n / 2`.
+fn fft(re: &mut [f32], im: &mut [f32], twiddles: &[(f32, f32)]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let stride = n / len;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_re, w_im) = twiddles[k * stride];
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Divides every value by the track's loud level, capping at 1.
+fn normalize(values: &mut [f32]) {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let loud = sorted[((sorted.len() - 1) as f32 * LOUD_PERCENTILE) as usize];
+    if loud > 0.0 {
+        for value in values {
+            *value = (*value / loud).min(1.0);
+        }
+    }
+}
+
+impl AudioTrack {
+    pub fn load(path: &Path, fps: f32) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::analyze(&Wav::load(path)?, fps))
+    }
+
+    pub fn analyze(wav: &Wav, fps: f32) -> Self {
+        let rate = wav.sample_rate as f32;
+        let frame_count = (wav.samples.len() as f32 / rate * fps).ceil() as usize;
+        let window: Vec<f32> = (0..WINDOW)
+            .map(|i| 0.5 - 0.5 * (PI * 2.0 * i as f32 / WINDOW as f32).cos())
+            .collect();
+        let twiddles: Vec<(f32, f32)> = (0..WINDOW / 2)
+            .map(|k| {
+                let angle = -PI * 2.0 * k as f32 / WINDOW as f32;
+                (angle.cos(), angle.sin())
+            })
+            .collect();
+        // FFT bins covered by a range of frequencies, at least one wide
+        let bins = |(low, high): (f32, f32)| {
+            let bin = |hz: f32| ((hz * WINDOW as f32 / rate) as usize).min(WINDOW / 2);
+            let start = bin(low).max(1);
+            start..bin(high).max(start + 1)
+        };
+        let ranges = [bins(BASS_HZ), bins(MID_HZ), bins(TREBLE_HZ)];
+        let spectrum_ranges: Vec<_> = (0..SPECTRUM_BANDS)
+            .map(|band| {
+                let hz = |i: usize| {
+                    SPECTRUM_LOW_HZ
+                        * (SPECTRUM_HIGH_HZ / SPECTRUM_LOW_HZ)
+                            .powf(i as f32 / SPECTRUM_BANDS as f32)
+                };
+                bins((hz(band), hz(band + 1)))
+            })
+            .collect();
+
+        // One row per feature: bass, mid, treble, then the spectrum bands
+        let mut features = vec![vec![0.0; frame_count]; 3 + SPECTRUM_BANDS];
+        let (mut re, mut im) = (vec![0.0; WINDOW], vec![0.0; WINDOW]);
+        let mut power = vec![0.0; WINDOW / 2 + 1];
+        for frame in 0..frame_count {
+            let centre = (frame as f32 / fps * rate) as isize;
+            for (i, (re, im)) in re.iter_mut().zip(&mut im).enumerate() {
+                let sample = usize::try_from(centre - (WINDOW / 2) as isize + i as isize)
+                    .ok()
+                    .and_then(|index| wav.samples.get(index));
+                *re = sample.map_or(0.0, |&sample| sample * window[i]);
+                *im = 0.0;
+            }
+            fft(&mut re, &mut im, &twiddles);
+            for (bin, power) in power.iter_mut().enumerate() {
+                *power = re[bin].powi(2) + im[bin].powi(2);
+            }
+            // Root mean power, so energies grow in proportion to amplitude
+            let energy = |range: &Range<usize>| {
+                (power[range.clone()].iter().sum::<f32>() / range.len() as f32).sqrt()
+            };
+            for (row, range) in features
+                .iter_mut()
+                .zip(ranges.iter().chain(&spectrum_ranges))
+            {
+                row[frame] = energy(range);
+            }
+        }
+
+        let raw_bass = features[0].clone();
+        for row in &mut features {
+            if !row.is_empty() {
+                normalize(row);
+            }
+        }
+        let beats = Self::find_beats(&raw_bass, &features[0], fps);
+        let frames = (0..frame_count)
+            .map(|frame| AudioFrame {
+                bass: features[0][frame],
+                mid: features[1][frame],
+                treble: features[2][frame],
+                beat: beats.binary_search(&frame).is_ok(),
+                spectrum: features[3..].iter().map(|row| row[frame]).collect(),
+            })
+            .collect();
+        Self {
+            frames,
+            beats,
+            silence: AudioFrame {
+                spectrum: vec![0.0; SPECTRUM_BANDS],
+                ..AudioFrame::default()
+            },
+        }
+    }
+
+    /// Finds frames where the bass jumps well above its recent average.
+    fn find_beats(raw_bass: &[f32], bass: &[f32], fps: f32) -> Vec<usize> {
+        let history = (fps.round() as usize).max(1);
+        let gap = (BEAT_GAP * fps).round() as usize;
+        let mut beats: Vec<usize> = Vec::new();
+        for frame in 1..raw_bass.len() {
+            let recent = &raw_bass[frame.saturating_sub(history)..frame];
+            let average = recent.iter().sum::<f32>() / recent.len() as f32;
+            let rising = raw_bass[frame] > raw_bass[frame - 1];
+            let spaced = beats.last().is_none_or(|&last| frame - last >= gap);
+            if rising
+                && spaced
+                && bass[frame] >= BEAT_FLOOR
+                && raw_bass[frame] > average * BEAT_THRESHOLD
+            {
+                beats.push(frame);
+            }
+        }
+        beats
+    }
+
+    /// Number of analysed frames, covering the whole track.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Number of beats found in the track.
+    pub fn beat_count(&self) -> usize {
+        self.beats.len()
+    }
+
+    /// The analysis at a frame, or silence past the end of the track.
+    pub fn at(&self, frame: usize) -> &AudioFrame {
+        self.frames.get(frame).unwrap_or(&self.silence)
+    }
+
+    /// The latest beat at or before a frame, and how many beats came before it.
+    pub fn last_beat(&self, frame: usize) -> Option<(usize, usize)> {
+        let count = self.beats.partition_point(|&beat| beat <= frame);
+        let index = count.checked_sub(1)?;
+        Some((self.beats[index], index))
+    }
+
+    /// How sharply sound starts on each frame: the rise in energy since the frame before, summed
+    /// over the spectrum bands, so notes and hits count as well as bass beats.
+    pub fn onset_strength(&self) -> Vec<f32> {
+        let mut strength = vec![0.0; self.frames.len()];
+        for (frame, pair) in self.frames.windows(2).enumerate() {
+            strength[frame + 1] = pair[1]
+                .spectrum
+                .iter()
+                .zip(&pair[0].spectrum)
+                .map(|(now, before)| (now - before).max(0.0))
+                .sum();
+        }
+        strength
+    }
+
+    /// The analysis from `frames` in, for an effect which starts part way through the track.
+    pub fn skip(&self, frames: usize) -> Self {
+        Self {
+            frames: self.frames.iter().skip(frames).cloned().collect(),
+            beats: self
+                .beats
+                .iter()
+                .filter_map(|&beat| beat.checked_sub(frames))
+                .collect(),
+            silence: self.silence.clone(),
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use std::{
+    error::Error,
+    io, mem,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use xmas_tree_common::{
+    csv_format::CsvWriter,
+    delta_format::DeltaWriter,
+    fseq_format::FseqWriter,
+    palette::Palette,
+    sequence::{Rgb, SequenceWriter},
+};
+
+use crate::{
+    effects::{self, Bounds, Color, Coord, EffectContext, EffectInfo, EFFECTS},
+    filters::{Brightness, PostFilter, Quantize},
+    generate::{to_rgb, Length},
+    grading::{Grade, GradeFilter},
+    neighbours::{NeighbourGraph, NEIGHBOUR_COUNT},
+    params::Params,
+    report::Report,
+    visibility, Opt,
+};
+
+#[derive(Debug, Serialize)]
+pub struct Timing {
+    pub name: String,
+    pub ms_per_frame: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BenchReport {
+    pub leds: usize,
+    pub frames: usize,
+    pub neighbour_graph_ms: f64,
+    pub effects: Vec<Timing>,
+    pub writers: Vec<Timing>,
+    pub filters: Vec<Timing>,
+}
+
+fn ms_per_frame(elapsed: Duration, frames: usize) -> f64 {
+    elapsed.as_secs_f64() * 1000.0 / frames.max(1) as f64
+}
+
+/// Frames rendered by an effect, and how long rendering them took.
+struct Rendered {
+    elapsed: Duration,
+    frames: Vec<Vec<Rgb>>,
+}
+
+fn render(
+    info: &EffectInfo,
+    opt: &Opt,
+    coords: &[Coord],
+    neighbours: &NeighbourGraph,
+    visibility: &[f32],
+    frames: usize,
+) -> Result<Rendered, Box<dyn Error>> {
+    let params = Params::resolve(info, &[], &opt.project.palettes, opt.seed, 0)?;
+    // Time the start of a sequence of the effect's natural length, since some effects assume
+    // there's room for a whole cycle
+    let total_frames = Length::Auto { cycles: 1 }
+        .resolve(info, coords, &params)
+        .unwrap_or(frames)
+        .max(frames);
+    let mut previous: Option<Vec<Color>> = None;
+    let mut colors = vec![(0.0, 0.0, 0.0); coords.len()];
+    let mut output = Vec::with_capacity(frames);
+    let mut elapsed = Duration::default();
+    let bounds = Bounds::of(coords);
+    for frame in 0..frames {
+        let ctx = EffectContext {
+            coords,
+            bounds,
+            frame,
+            total_frames,
+            fps: opt.fps,
+            seed: opt.seed,
+            previous: previous.as_deref(),
+            neighbours,
+            visibility,
+            params: &params,
+            audio: None,
+        };
+        let start = Instant::now();
+        (info.render)(&ctx, &mut colors);
+        elapsed += start.elapsed();
+        output.push(colors.iter().copied().map(to_rgb).collect());
+        match &mut previous {
+            Some(previous) => mem::swap(previous, &mut colors),
+            None => previous = Some(colors.clone()),
+        }
+    }
+    Ok(Rendered {
+        elapsed,
+        frames: output,
+    })
+}
+
+fn time_writer(
+    mut writer: impl SequenceWriter,
+    frames: &[Vec<Rgb>],
+) -> Result<Duration, Box<dyn Error>> {
+    let start = Instant::now();
+    for frame in frames {
+        writer.write_frame(frame)?;
+    }
+    writer.finish()?;
+    Ok(start.elapsed())
+}
+
+fn time_filter(mut filter: impl PostFilter, ctx: &EffectContext, frames: &[Vec<Rgb>]) -> Duration {
+    let mut colors = Vec::with_capacity(ctx.coords.len());
+    let mut elapsed = Duration::default();
+    for frame in frames {
+        colors.clear();
+        colors.extend(
+            frame
+                .iter()
+                .map(|&[r, g, b]| (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0)),
+        );
+        let start = Instant::now();
+        filter.apply(ctx, &mut colors);
+        elapsed += start.elapsed();
+    }
+    elapsed
+}
+
+/// Times each effect, and filtering and writing the output of the first, over the current coordinates. Use
+/// `coords make` for standard large or messy layouts.
+pub fn bench(
+    opt: &Opt,
+    coords: &[Coord],
+    names: &[String],
+    frames: usize,
+) -> Result<BenchReport, Box<dyn Error>> {
+    let infos = if names.is_empty() {
+        EFFECTS.iter().collect()
+    } else {
+        names
+            .iter()
+            .map(|name| effects::lookup(name).ok_or_else(|| format!("Unknown effect: {}", name)))
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    let start = Instant::now();
+    let neighbours = NeighbourGraph::knn(coords, NEIGHBOUR_COUNT);
+    let neighbour_graph_ms = start.elapsed().as_secs_f64() * 1000.0;
+    let visibility = visibility::estimate(coords);
+
+    let mut effects = Vec::new();
+    let mut sample = None;
+    for &info in &infos {
+        let rendered = render(info, opt, coords, &neighbours, &visibility, frames)?;
+        effects.push(Timing {
+            name: info.name.into(),
+            ms_per_frame: ms_per_frame(rendered.elapsed, frames),
+        });
+        sample.get_or_insert(rendered.frames);
+    }
+
+    let mut writers = Vec::new();
+    if let Some(sample) = &sample {
+        let led_count = coords.len();
+        writers.push(Timing {
+            name: "csv".into(),
+            ms_per_frame: ms_per_frame(
+                time_writer(CsvWriter::new(io::sink(), led_count)?, sample)?,
+                frames,
+            ),
+        });
+        writers.push(Timing {
+            name: "delta".into(),
+            ms_per_frame: ms_per_frame(
+                time_writer(DeltaWriter::new(io::sink(), led_count)?, sample)?,
+                frames,
+            ),
+        });
+        writers.push(Timing {
+            name: "fseq".into(),
+            ms_per_frame: ms_per_frame(
+                time_writer(FseqWriter::new(io::sink(), led_count, opt.fps), sample)?,
+                frames,
+            ),
+        });
+    }
+
+    let mut filters = Vec::new();
+    if let (Some(sample), Some(info)) = (&sample, infos.first()) {
+        let params = Params::resolve(info, &[], &opt.project.palettes, opt.seed, 0)?;
+        let ctx = EffectContext {
+            coords,
+            bounds: Bounds::of(coords),
+            frame: 0,
+            total_frames: frames,
+            fps: opt.fps,
+            seed: opt.seed,
+            previous: None,
+            neighbours: &neighbours,
+            visibility: &visibility,
+            params: &params,
+            audio: None,
+        };
+        let grade = Grade {
+            lift: 0.05,
+            gamma: 2.2,
+            gain: 1.1,
+            ..Grade::default()
+        };
+        let palette: Palette = "christmas".parse()?;
+        let timings: [(&str, Duration); 5] = [
+            (
+                "brightness",
+                time_filter(Brightness { factor: 0.8 }, &ctx, sample),
+            ),
+            ("grade", time_filter(GradeFilter::new(grade), &ctx, sample)),
+            (
+                "grade-hue",
+                time_filter(
+                    GradeFilter::new(Grade {
+                        hue_shift: 30.0,
+                        ..grade
+                    }),
+                    &ctx,
+                    sample,
+                ),
+            ),
+            (
+                "quantize",
+                time_filter(Quantize::new(palette.clone(), false), &ctx, sample),
+            ),
+            (
+                "quantize-dither",
+                time_filter(Quantize::new(palette, true), &ctx, sample),
+            ),
+        ];
+        filters.extend(timings.iter().map(|&(name, elapsed)| Timing {
+            name: name.into(),
+            ms_per_frame: ms_per_frame(elapsed, frames),
+        }));
+    }
+
+    Ok(BenchReport {
+        leds: coords.len(),
+        frames,
+        neighbour_graph_ms,
+        effects,
+        writers,
+        filters,
+    })
+}
+
+impl Report for BenchReport {
+    fn write_text(&self, out: &mut dyn io::Write) -> io::Result<()> {
+        writeln!(out, "LEDs:               {}", self.leds)?;
+        writeln!(out, "Frames:             {}", self.frames)?;
+        writeln!(out, "Neighbour graph:    {:.2} ms", self.neighbour_graph_ms)?;
+        for (heading, timings) in [
+            ("Effects", &self.effects),
+            ("Writers", &self.writers),
+            ("Filters", &self.filters),
+        ] {
+            writeln!(out, "{}:", heading)?;
+            for timing in timings {
+                writeln!(
+                    out,
+                    "  {:<18} {:>9.3} ms/frame",
+                    timing.name, timing.ms_per_frame
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
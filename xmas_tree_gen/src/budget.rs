@@ -0,0 +1,210 @@
+//! Keeps `live` within its frame deadlines on slow machines such as a Raspberry Pi. Each effect's
+//! render time is measured, and when it's been over its budget for a while the effect is made
+//! cheaper a step at a time: first optional filters such as `--blur` are skipped, then its detail
+//! parameter (e.g. the number of snakes) is halved, and finally it renders every other frame and
+//! shows each twice. Once it's comfortably within budget again it steps back up.
+//!
+//! How often each effect was degraded is logged with the output stats and kept in `--history`.
+
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use structopt::StructOpt;
+use tracing::{debug, info, warn};
+
+/// Weight of the newest frame in the smoothed render time, so a single slow frame (e.g. when the
+/// machine is briefly busy) doesn't degrade an effect.
+const SMOOTHING: f32 = 0.1;
+/// How long to wait after a step before judging whether another is needed.
+const SETTLE: Duration = Duration::from_secs(1);
+/// The share of the budget an effect has to stay under, for `RECOVER_AFTER`, to step back up.
+const RECOVER_BELOW: f32 = 0.5;
+const RECOVER_AFTER: Duration = Duration::from_secs(10);
+
+#[derive(Debug, StructOpt)]
+pub struct BudgetOpt {
+    /// Share of each frame's time an effect may take to render before `live` makes it cheaper,
+    /// by skipping optional filters, lowering its detail, then rendering every other frame.
+    #[structopt(long, default_value = "0.8")]
+    cpu_budget: f32,
+    /// Never make effects cheaper, even when they miss frame deadlines. Render times are still
+    /// measured and logged.
+    #[structopt(long)]
+    no_degrade: bool,
+}
+
+/// How much an effect has been cut back, from not at all to the most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Degradation {
+    Full,
+    SkipOptionalFilters,
+    LessDetail,
+    HalfRate,
+}
+
+impl Degradation {
+    fn worse(self) -> Option<Self> {
+        match self {
+            Self::Full => Some(Self::SkipOptionalFilters),
+            Self::SkipOptionalFilters => Some(Self::LessDetail),
+            Self::LessDetail => Some(Self::HalfRate),
+            Self::HalfRate => None,
+        }
+    }
+
+    fn better(self) -> Option<Self> {
+        match self {
+            Self::Full => None,
+            Self::SkipOptionalFilters => Some(Self::Full),
+            Self::LessDetail => Some(Self::SkipOptionalFilters),
+            Self::HalfRate => Some(Self::LessDetail),
+        }
+    }
+}
+
+impl fmt::Display for Degradation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Full => "full quality",
+            Self::SkipOptionalFilters => "skipping optional filters",
+            Self::LessDetail => "reduced detail",
+            Self::HalfRate => "half frame rate",
+        })
+    }
+}
+
+/// Times one effect's frames and decides how far to degrade it.
+pub struct Budget {
+    name: &'static str,
+    /// Seconds each frame may take to render.
+    budget: f32,
+    enabled: bool,
+    level: Degradation,
+    /// Smoothed render time, in seconds.
+    average: f32,
+    changed: Instant,
+    /// When the render time last went under `RECOVER_BELOW` of the budget, while degraded.
+    calm_since: Option<Instant>,
+    /// Whether a degraded frame has been shown since `take_degraded` was last called.
+    degraded_since_taken: bool,
+    frames: u64,
+    degraded_frames: u64,
+    degradations: u64,
+}
+
+impl Budget {
+    pub fn new(opt: &BudgetOpt, name: &'static str, fps: f32) -> Self {
+        Self {
+            name,
+            budget: opt.cpu_budget / fps,
+            enabled: !opt.no_degrade,
+            level: Degradation::Full,
+            average: 0.0,
+            changed: Instant::now(),
+            calm_since: None,
+            degraded_since_taken: false,
+            frames: 0,
+            degraded_frames: 0,
+            degradations: 0,
+        }
+    }
+
+    pub fn level(&self) -> Degradation {
+        self.level
+    }
+
+    /// Counts a frame shown again rather than rendered, at half rate.
+    pub fn repeated(&mut self) {
+        self.frames += 1;
+        self.degraded_frames += 1;
+        self.degraded_since_taken = true;
+    }
+
+    /// Records how long a frame took to render, stepping the degradation up or down if need be.
+    pub fn record(&mut self, elapsed: Duration) {
+        self.frames += 1;
+        if self.level > Degradation::Full {
+            self.degraded_frames += 1;
+            self.degraded_since_taken = true;
+        }
+        let elapsed = elapsed.as_secs_f32();
+        self.average = if self.frames == 1 {
+            elapsed
+        } else {
+            self.average + (elapsed - self.average) * SMOOTHING
+        };
+        if !self.enabled || self.changed.elapsed() < SETTLE {
+            return;
+        }
+        // At half rate each render has two frames' time
+        let allowed = match self.level {
+            Degradation::HalfRate => self.budget * 2.0,
+            _ => self.budget,
+        };
+        if self.average > allowed {
+            self.calm_since = None;
+            if let Some(worse) = self.level.worse() {
+                warn!(
+                    "{} takes {:.1} ms per frame, over its budget of {:.1} ms, switching to {}",
+                    self.name,
+                    self.average * 1000.0,
+                    self.budget * 1000.0,
+                    worse
+                );
+                self.degradations += 1;
+                self.step(worse);
+            }
+        } else if self.average < self.budget * RECOVER_BELOW {
+            let calm_since = *self.calm_since.get_or_insert_with(Instant::now);
+            if let Some(better) = self.level.better() {
+                if calm_since.elapsed() >= RECOVER_AFTER {
+                    info!(
+                        "{} is within its budget again, switching to {}",
+                        self.name, better
+                    );
+                    self.step(better);
+                }
+            }
+        } else {
+            self.calm_since = None;
+        }
+    }
+
+    fn step(&mut self, level: Degradation) {
+        self.level = level;
+        self.changed = Instant::now();
+        self.calm_since = None;
+    }
+
+    /// Whether any frame shown since the last call was degraded.
+    pub fn take_degraded(&mut self) -> bool {
+        std::mem::take(&mut self.degraded_since_taken)
+    }
+
+    /// Logs how the effect has kept up so far.
+    pub fn log_stats(&self) {
+        if self.frames == 0 {
+            return;
+        }
+        let share = self.degraded_frames as f64 / self.frames as f64 * 100.0;
+        if self.degradations > 0 {
+            info!(
+                "{}: {:.1} ms per frame, degraded {} times, {:.1}% of frames degraded, now at {}",
+                self.name,
+                self.average * 1000.0,
+                self.degradations,
+                share,
+                self.level
+            );
+        } else {
+            debug!(
+                "{}: {:.1} ms per frame, within its budget of {:.1} ms",
+                self.name,
+                self.average * 1000.0,
+                self.budget * 1000.0
+            );
+        }
+    }
+}
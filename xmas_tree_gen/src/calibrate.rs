@@ -0,0 +1,371 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use image::RgbImage;
+use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
+use tracing::{info, warn};
+use xmas_tree_common::{calibration::Calibration, output, sequence::Rgb};
+
+use crate::capture::Camera;
+
+#[derive(Debug, StructOpt)]
+pub enum CalibrateCommand {
+    /// Shows calibration patterns on the tree and photographs each of them. Repeat from other
+    /// sides of the tree into separate directories to cover the LEDs at the back.
+    Capture {
+        /// Where to send the patterns, e.g. `wled://192.168.1.50`.
+        #[structopt(long)]
+        output: String,
+        /// Command which saves a PNG photo to the path given in place of `{}`. Lock the camera's
+        /// exposure and white balance, and expose so the brightest LEDs don't clip.
+        #[structopt(long)]
+        camera: Camera,
+        /// Directory to save the photos in.
+        #[structopt(parse(from_os_str))]
+        dir: PathBuf,
+        /// How long to wait after changing the LEDs before taking a photo, in milliseconds.
+        #[structopt(long, default_value = "500")]
+        settle: u64,
+    },
+    /// Compares the LEDs in captured photos and writes a calibration file for `--calibration`.
+    Analyze {
+        /// Directories written by `calibrate capture`.
+        #[structopt(parse(from_os_str), required = true)]
+        dirs: Vec<PathBuf>,
+        #[structopt(short, long, parse(from_os_str))]
+        output: PathBuf,
+        /// LEDs brighter than this percentile of all LEDs are dimmed to match it.
+        #[structopt(long, default_value = "25")]
+        percentile: f32,
+    },
+}
+
+/// Grey levels shown to measure each LED's response.
+const GREY_LEVELS: &[f32] = &[0.25, 0.5, 0.75, 1.0];
+
+const MANIFEST: &str = "calibration.json";
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", tag = "kind")]
+enum Pattern {
+    /// Everything off, to subtract ambient light.
+    Dark,
+    /// LEDs whose index has this bit set (or clear, if inverted) are on, so every LED can be
+    /// found in the photos from its on/off code.
+    Code { bit: u32, inverted: bool },
+    /// Every LED the same grey.
+    Grey { level: f32 },
+}
+
+impl Pattern {
+    fn frame(self, led_count: usize) -> Vec<Rgb> {
+        (0..led_count)
+            .map(|led| {
+                let value = match self {
+                    Self::Dark => 0,
+                    Self::Code { bit, inverted } => {
+                        if ((led >> bit) & 1 == 1) != inverted {
+                            255
+                        } else {
+                            0
+                        }
+                    }
+                    Self::Grey { level } => (level * 255.0).round() as u8,
+                };
+                [value; 3]
+            })
+            .collect()
+    }
+}
+
+/// Lists the photos in a capture directory and what each one shows.
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    led_count: usize,
+    photos: Vec<(String, Pattern)>,
+}
+
+fn code_bits(led_count: usize) -> u32 {
+    usize::BITS - led_count.saturating_sub(1).leading_zeros()
+}
+
+pub fn capture(
+    led_count: usize,
+    url: &str,
+    camera: &Camera,
+    dir: &Path,
+    settle: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let mut patterns = vec![Pattern::Dark];
+    for bit in 0..code_bits(led_count) {
+        for &inverted in &[false, true] {
+            patterns.push(Pattern::Code { bit, inverted });
+        }
+    }
+    patterns.extend(GREY_LEVELS.iter().map(|&level| Pattern::Grey { level }));
+
+    fs::create_dir_all(dir)?;
+    let mut sink = output::open(url)?;
+    let mut photos = Vec::new();
+    for (i, &pattern) in patterns.iter().enumerate() {
+        let name = format!("{:03}.png", i);
+        info!("Capturing {} of {}", i + 1, patterns.len());
+        sink.send_frame(&pattern.frame(led_count))?;
+        thread::sleep(settle);
+        camera.capture(&dir.join(&name))?;
+        photos.push((name, pattern));
+    }
+    sink.send_frame(&Pattern::Dark.frame(led_count))?;
+
+    let manifest = Manifest { led_count, photos };
+    fs::write(dir.join(MANIFEST), serde_json::to_vec_pretty(&manifest)?)?;
+    Ok(())
+}
+
+/// How much brighter than its inverse a pixel must be for its code to count, out of 765.
+const MIN_CONTRAST: i32 = 60;
+/// Pixels an LED must cover to count as found, so stray pixels which happen to decode to its
+/// index are ignored.
+const MIN_PIXELS: usize = 4;
+/// Radius around each LED which is averaged to measure it.
+const SAMPLE_RADIUS: i32 = 3;
+/// Channel values at or above this are clipped and can't be measured.
+const CLIPPED: u8 = 250;
+
+/// One LED as seen in one capture directory.
+#[derive(Debug, Clone, Copy)]
+struct Sighting {
+    pixels: usize,
+    response: [f32; 3],
+}
+
+fn load(dir: &Path, name: &str) -> Result<RgbImage, Box<dyn Error>> {
+    let path = dir.join(name);
+    Ok(image::open(&path)
+        .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?
+        .to_rgb8())
+}
+
+fn luma(image: &RgbImage, x: u32, y: u32) -> i32 {
+    image.get_pixel(x, y).0.iter().map(|&v| v as i32).sum()
+}
+
+/// Finds where each LED is in the photos, as the centroid of the pixels which decode to it.
+fn locate(
+    led_count: usize,
+    codes: &[(u32, RgbImage, RgbImage)],
+    (width, height): (u32, u32),
+) -> Vec<Option<(f32, f32, usize)>> {
+    let mut sums = vec![(0.0, 0.0, 0); led_count];
+    for y in 0..height {
+        for x in 0..width {
+            let mut led = 0;
+            let mut decoded = true;
+            for (bit, on, off) in codes {
+                let difference = luma(on, x, y) - luma(off, x, y);
+                if difference.abs() < MIN_CONTRAST {
+                    decoded = false;
+                    break;
+                }
+                if difference > 0 {
+                    led |= 1 << bit;
+                }
+            }
+            if let Some(sum) = sums.get_mut(led).filter(|_| decoded) {
+                sum.0 += x as f32;
+                sum.1 += y as f32;
+                sum.2 += 1;
+            }
+        }
+    }
+    sums.into_iter()
+        .map(|(x, y, pixels)| {
+            (pixels >= MIN_PIXELS).then(|| (x / pixels as f32, y / pixels as f32, pixels))
+        })
+        .collect()
+}
+
+/// Average color around a point, with the dark frame subtracted, or `None` if any of it clipped.
+fn sample(image: &RgbImage, dark: &RgbImage, (cx, cy): (f32, f32)) -> Option<[f32; 3]> {
+    let mut total = [0.0; 3];
+    let mut count = 0;
+    for dy in -SAMPLE_RADIUS..=SAMPLE_RADIUS {
+        for dx in -SAMPLE_RADIUS..=SAMPLE_RADIUS {
+            if dx * dx + dy * dy > SAMPLE_RADIUS * SAMPLE_RADIUS {
+                continue;
+            }
+            let (x, y) = (cx.round() as i32 + dx, cy.round() as i32 + dy);
+            if x < 0 || y < 0 || x >= image.width() as i32 || y >= image.height() as i32 {
+                continue;
+            }
+            let pixel = image.get_pixel(x as u32, y as u32).0;
+            if pixel.iter().any(|&v| v >= CLIPPED) {
+                return None;
+            }
+            let background = dark.get_pixel(x as u32, y as u32).0;
+            for c in 0..3 {
+                total[c] += pixel[c] as f32 - background[c] as f32;
+            }
+            count += 1;
+        }
+    }
+    (count > 0).then(|| total.map(|v| v / count as f32))
+}
+
+fn percentile(values: &mut [f32], percentile: f32) -> f32 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    let index = (percentile / 100.0 * (values.len() - 1) as f32).round() as usize;
+    values[index.min(values.len() - 1)]
+}
+
+/// Measures every LED visible in one capture directory, relative to the median LED so that
+/// directories captured with different exposures can be combined.
+fn measure(dir: &Path, led_count: usize) -> Result<Vec<Option<Sighting>>, Box<dyn Error>> {
+    let manifest: Manifest = serde_json::from_slice(&fs::read(dir.join(MANIFEST))?)?;
+    if manifest.led_count != led_count {
+        return Err(format!(
+            "{} was captured with {} LEDs, but the tree has {}",
+            dir.display(),
+            manifest.led_count,
+            led_count
+        )
+        .into());
+    }
+    let mut dark = None;
+    let mut on = Vec::new();
+    let mut off = Vec::new();
+    let mut greys = Vec::new();
+    for (name, pattern) in &manifest.photos {
+        let image = load(dir, name)?;
+        match *pattern {
+            Pattern::Dark => dark = Some(image),
+            Pattern::Code {
+                bit,
+                inverted: false,
+            } => on.push((bit, image)),
+            Pattern::Code {
+                bit,
+                inverted: true,
+            } => off.push((bit, image)),
+            Pattern::Grey { level } => greys.push((level, image)),
+        }
+    }
+    let dark = dark.ok_or_else(|| format!("{} has no dark photo", dir.display()))?;
+    let size = dark.dimensions();
+    let codes: Vec<_> = on
+        .into_iter()
+        .filter_map(|(bit, on)| {
+            let index = off.iter().position(|(b, _)| *b == bit)?;
+            Some((bit, on, off.swap_remove(index).1))
+        })
+        .collect();
+    if codes.len() as u32 != code_bits(manifest.led_count)
+        || greys
+            .iter()
+            .map(|(_, image)| image)
+            .chain(codes.iter().flat_map(|(_, on, off)| [on, off]))
+            .any(|image| image.dimensions() != size)
+    {
+        return Err(format!("{} has missing or mismatched photos", dir.display()).into());
+    }
+
+    let positions = locate(manifest.led_count, &codes, size);
+    let mut sightings: Vec<Option<Sighting>> = positions
+        .iter()
+        .map(|position| {
+            let (x, y, pixels) = (*position)?;
+            // Fit measured = response * level through the origin, over the unclipped levels
+            let mut fit = [(0.0, 0.0); 3];
+            for (level, image) in &greys {
+                if let Some(color) = sample(image, &dark, (x, y)) {
+                    for c in 0..3 {
+                        fit[c].0 += color[c] * level;
+                        fit[c].1 += level * level;
+                    }
+                }
+            }
+            if fit[0].1 == 0.0 {
+                return None;
+            }
+            let response = fit.map(|(numerator, denominator)| numerator / denominator);
+            Some(Sighting { pixels, response }).filter(|s| s.response.iter().all(|&r| r > 0.0))
+        })
+        .collect();
+
+    let found = sightings.iter().flatten().count();
+    info!(
+        "Found {} of {} LEDs in {}",
+        found,
+        manifest.led_count,
+        dir.display()
+    );
+    if found > 0 {
+        for c in 0..3 {
+            let mut responses: Vec<f32> =
+                sightings.iter().flatten().map(|s| s.response[c]).collect();
+            let median = percentile(&mut responses, 50.0);
+            for sighting in sightings.iter_mut().flatten() {
+                sighting.response[c] /= median;
+            }
+        }
+    }
+    Ok(sightings)
+}
+
+pub fn analyze(
+    led_count: usize,
+    dirs: &[PathBuf],
+    output: &Path,
+    target_percentile: f32,
+) -> Result<(), Box<dyn Error>> {
+    // Each LED is measured from whichever direction it covers the most pixels, i.e. the one
+    // facing the camera most directly
+    let mut best: Vec<Option<Sighting>> = vec![None; led_count];
+    for dir in dirs {
+        let sightings = measure(dir, led_count)?;
+        for (best, sighting) in best.iter_mut().zip(sightings) {
+            match (&best, sighting) {
+                (Some(current), Some(sighting)) if current.pixels >= sighting.pixels => {}
+                (_, None) => {}
+                (_, sighting) => *best = sighting,
+            }
+        }
+    }
+
+    let seen: Vec<Sighting> = best.iter().flatten().copied().collect();
+    if seen.is_empty() {
+        return Err("No LEDs were found in the photos".into());
+    }
+    let missing = led_count - seen.len();
+    if missing > 0 {
+        warn!(
+            "{} LEDs were never seen and will be left as they are",
+            missing
+        );
+    }
+    let mut target = [0.0; 3];
+    for (c, target) in target.iter_mut().enumerate() {
+        let mut responses: Vec<f32> = seen.iter().map(|s| s.response[c]).collect();
+        *target = percentile(&mut responses, target_percentile.clamp(0.0, 100.0));
+    }
+    let gains = best
+        .iter()
+        .map(|sighting| match sighting {
+            Some(sighting) => {
+                let mut gain = [1.0; 3];
+                for c in 0..3 {
+                    gain[c] = (target[c] / sighting.response[c]).min(1.0);
+                }
+                gain
+            }
+            None => [1.0; 3],
+        })
+        .collect();
+    Calibration { gains }.save(output)
+}
@@ -0,0 +1,72 @@
+//! Timed text in the SubRip (`.srt`) format, which most lyric and subtitle tools can export:
+//! numbered cues separated by blank lines, each with a time range and one or more lines of text.
+//!
+//! ```text
+//! 1
+//! 00:00:12,000 --> 00:00:15,500
+//! Jingle bells, jingle bells
+//!
+//! 2
+//! 00:00:15,500 --> 00:00:18,000
+//! Jingle all the way
+//! ```
+//!
+//! The cue numbers are optional, and milliseconds may be written after a `.` as in WebVTT.
+
+use std::{error::Error, fs, path::Path};
+
+/// Text shown from `start` until `end`, in seconds.
+#[derive(Debug, Clone)]
+pub struct Cue {
+    pub start: f32,
+    pub end: f32,
+    pub text: String,
+}
+
+/// Parses a time such as `00:01:02,500`, in seconds.
+fn parse_time(s: &str) -> Result<f32, String> {
+    let invalid = || format!("Invalid caption time: {}", s);
+    let (hms, millis) = s.split_once([',', '.']).unwrap_or((s, "0"));
+    let mut seconds = 0.0;
+    for part in hms.split(':') {
+        seconds = seconds * 60.0 + part.trim().parse::<u32>().map_err(|_| invalid())? as f32;
+    }
+    let millis: u32 = millis.trim().parse().map_err(|_| invalid())?;
+    Ok(seconds + millis as f32 / 1000.0)
+}
+
+pub fn parse(text: &str) -> Result<Vec<Cue>, String> {
+    let mut cues = Vec::new();
+    let mut lines = text.lines().map(|line| line.trim_start_matches('\u{feff}'));
+    while let Some(line) = lines.next() {
+        if line.trim().is_empty() || (!line.contains("-->") && line.trim().parse::<u32>().is_ok()) {
+            continue;
+        }
+        let (start, end) = line
+            .split_once("-->")
+            .ok_or_else(|| format!("Expected a caption time range, got: {}", line))?;
+        // WebVTT allows cue settings after the end time
+        let end = end.split_whitespace().next().unwrap_or("");
+        let (start, end) = (parse_time(start.trim())?, parse_time(end)?);
+        if end <= start {
+            return Err(format!("Caption ends before it starts: {}", line));
+        }
+        let text: Vec<&str> = lines
+            .by_ref()
+            .take_while(|line| !line.trim().is_empty())
+            .map(str::trim)
+            .collect();
+        cues.push(Cue {
+            start,
+            end,
+            text: text.join(" "),
+        });
+    }
+    Ok(cues)
+}
+
+pub fn load(path: &Path) -> Result<Vec<Cue>, Box<dyn Error>> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Cannot read captions {}: {}", path.display(), e))?;
+    parse(&text).map_err(|e| format!("{}: {}", path.display(), e).into())
+}
@@ -0,0 +1,43 @@
+use std::{error::Error, path::Path, process::Command, str::FromStr};
+
+/// Takes photos of the tree by running an external program, e.g.
+/// `ffmpeg -y -loglevel error -f v4l2 -i /dev/video0 -frames:v 1 {}`, where `{}` is replaced by
+/// the path the image should be written to. Images are read back as PNG.
+#[derive(Debug, Clone)]
+pub struct Camera {
+    program: String,
+    args: Vec<String>,
+}
+
+impl FromStr for Camera {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace().map(String::from);
+        let program = words
+            .next()
+            .ok_or_else(|| "Expected a camera command".to_string())?;
+        let args: Vec<String> = words.collect();
+        if !args.iter().any(|arg| arg.contains("{}")) {
+            return Err(format!(
+                "Camera command needs a {{}} where the image path goes, got {}",
+                s
+            ));
+        }
+        Ok(Self { program, args })
+    }
+}
+
+impl Camera {
+    pub fn capture(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let path = path.to_string_lossy();
+        let status = Command::new(&self.program)
+            .args(self.args.iter().map(|arg| arg.replace("{}", &path)))
+            .status()
+            .map_err(|e| format!("Cannot run camera command {}: {}", self.program, e))?;
+        if !status.success() {
+            return Err(format!("Camera command failed ({}) capturing {}", status, path).into());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    cell::Cell,
+    error::Error,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::effects::Color;
+
+/// What a render was asked for, which must be the same to resume it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RenderSettings {
+    pub effect: String,
+    pub format: String,
+    pub len: usize,
+    pub seed: u64,
+    #[serde(default)]
+    pub lead_in: usize,
+    /// The resolved parameters, as `--param` flags.
+    #[serde(default)]
+    pub params: String,
+    /// Checksum of the coordinates file.
+    #[serde(default)]
+    pub coords_hash: String,
+    /// Checksum of everything else which shapes the frames: the meta-effect, filter and
+    /// correction options, the calibration, and the contents of the script and audio track.
+    #[serde(default)]
+    pub options_hash: String,
+}
+
+/// Everything needed to continue an interrupted render. Only pipelines which keep nothing from
+/// one frame to the next but the frame itself can be checkpointed (see `Effect::stateful`), so
+/// the previous frame is all the state there is to save.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    #[serde(flatten)]
+    pub settings: RenderSettings,
+    pub next_frame: usize,
+    pub output_len: u64,
+    pub previous_frame: Option<Vec<Color>>,
+}
+
+impl Checkpoint {
+    pub fn path_for(output: &Path) -> PathBuf {
+        let mut path = output.as_os_str().to_owned();
+        path.push(".checkpoint");
+        path.into()
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let file = fs::File::open(path)
+            .map_err(|e| format!("Cannot resume from {}: {}", path.display(), e))?;
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        // Write to a temporary file first so a crash mid-save leaves the old checkpoint intact
+        let tmp_path = path.with_extension("checkpoint.tmp");
+        serde_json::to_writer(fs::File::create(&tmp_path)?, self)?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Checks the render being resumed is the one the checkpoint was saved from, so two
+    /// different renders aren't spliced together.
+    pub fn check_matches(&self, settings: &RenderSettings) -> Result<(), String> {
+        let saved = &self.settings;
+        if saved.effect != settings.effect
+            || saved.format != settings.format
+            || saved.len != settings.len
+            || saved.seed != settings.seed
+            || saved.lead_in != settings.lead_in
+        {
+            return Err(format!(
+                "Checkpoint is for effect {} with --format {} --len {} --seed {} --lead-in {}",
+                saved.effect, saved.format, saved.len, saved.seed, saved.lead_in
+            ));
+        }
+        if saved.params != settings.params {
+            return Err(format!(
+                "Checkpoint was saved with the parameters `{}`, not `{}`",
+                saved.params, settings.params
+            ));
+        }
+        if saved.coords_hash != settings.coords_hash {
+            return Err("Checkpoint was saved with different coordinates".into());
+        }
+        if saved.options_hash != settings.options_hash {
+            return Err(
+                "Checkpoint was saved with different meta-effect, filter, correction or \
+                 calibration options, or a different script or audio track"
+                    .into(),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Tracks how many bytes have been written, so checkpoints know where the output ends.
+/// The count is shared so it can still be read once the writer is owned by a sequence writer.
+pub struct CountingWriter<W> {
+    inner: W,
+    count: Rc<Cell<u64>>,
+}
+
+impl<W> CountingWriter<W> {
+    pub fn new(inner: W, count: u64) -> Self {
+        Self {
+            inner,
+            count: Rc::new(Cell::new(count)),
+        }
+    }
+
+    pub fn counter(&self) -> Rc<Cell<u64>> {
+        self.count.clone()
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count.set(self.count.get() + written as u64);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
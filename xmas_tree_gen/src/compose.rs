@@ -0,0 +1,737 @@
+//! Renders a playlist of effects into one sequence, with transitions between them and layers
+//! blended on top.
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use structopt::StructOpt;
+use tracing::{debug, warn};
+use xmas_tree_common::{
+    color::mix_hue,
+    csv_format::CsvWriter,
+    delta_format::DeltaWriter,
+    fseq_format::FseqWriter,
+    metadata::{self, Credit, Marker, SequenceMetadata},
+    seal::{self, SealMode},
+    sequence::{SequenceFormat, SequenceWriter},
+};
+
+use crate::{
+    accessibility::ColorVision,
+    captions,
+    correction::CorrectionOpt,
+    effects::{self, mix, smoothstep, Authorship, Bounds, Color, Coord, Effect, EffectContext},
+    filters::FilterOpt,
+    generate::Length,
+    hooks::{Hook, Hooks},
+    load_coords, load_hardware,
+    neighbours::{NeighbourGraph, NEIGHBOUR_COUNT},
+    params::{ParamArg, Params},
+    progress_bar,
+    script::{self, ScriptEffect},
+    stats::{EffectStats, StatsCollector, StatsOpt},
+    thumbnail::Thumbnail,
+    visibility, Opt,
+};
+
+#[derive(Debug, StructOpt)]
+pub struct ComposeOpt {
+    /// Playlist to render, as TOML with one `[[effect]]` table per effect [default: the
+    /// project's playlist].
+    #[structopt(parse(from_os_str))]
+    playlist: Option<PathBuf>,
+    /// Write the sequence to this file instead of stdout.
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+    /// Output format: `csv`, `delta` for the compact run-length encoded format, or `fseq` for
+    /// xLights and Falcon Player (which time frames in whole milliseconds, so round `--fps`).
+    #[structopt(long, default_value = "csv")]
+    format: SequenceFormat,
+    /// Filters applied to the combined sequence.
+    #[structopt(flatten)]
+    filters: FilterOpt,
+    #[structopt(flatten)]
+    correction: CorrectionOpt,
+    #[structopt(flatten)]
+    stats: StatsOpt,
+}
+
+/// A playlist file, where each name is a built in effect or the path of a `.rhai` script, e.g.
+///
+/// ```toml
+/// [[effect]]
+/// name = "barber-pole"
+/// len = 600
+/// params = { palette = "ice" }
+///
+/// [[effect]]
+/// name = "shells"
+/// label = "bridge"
+/// len = "auto:2"
+/// transition = "wipe-up"
+/// transition_len = 70
+/// layer = { name = "twinkle", blend = "add", opacity = 0.5 }
+///
+/// [captions]
+/// file = "lyrics.srt"
+/// params = { height = 0.3, palette = "ice" }
+///
+/// [[hook]]
+/// at = "00:23.5"
+/// len = 3
+/// script = "fill([1.0, 1.0, 1.0])"
+///
+/// [[hook]]
+/// at = "bridge"
+/// script = "dim_layer(0.5)"
+/// ```
+///
+/// A top level `color_vision = "deuteranopia"` makes the show safe for that color vision, as with
+/// `--color-vision`.
+///
+/// See [`crate::hooks`] for what hook scripts can do.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Playlist {
+    #[serde(rename = "effect")]
+    effects: Vec<EntrySpec>,
+    captions: Option<CaptionSpec>,
+    #[serde(default, rename = "hook")]
+    hooks: Vec<HookSpec>,
+    color_vision: Option<ColorVision>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EntrySpec {
+    name: String,
+    /// Names this part of the show, such as `chorus`, for its marker and for hooks [default: the
+    /// effect's name].
+    label: Option<String>,
+    /// As with `--len` [default: one cycle of the effect].
+    len: Option<LenSpec>,
+    #[serde(default)]
+    params: BTreeMap<String, toml::Value>,
+    /// How this effect takes over from the one before it.
+    #[serde(default)]
+    transition: Transition,
+    /// Frames the transition overlaps the two effects for [default: one second].
+    transition_len: Option<usize>,
+    layer: Option<LayerSpec>,
+}
+
+/// A second effect rendered alongside an entry, looping over its own length.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LayerSpec {
+    name: String,
+    len: Option<LenSpec>,
+    #[serde(default)]
+    params: BTreeMap<String, toml::Value>,
+    blend: Blend,
+    #[serde(default = "full_opacity")]
+    opacity: f32,
+}
+
+/// A timed text file, such as a song's lyrics, whose cues are each drawn over the show with
+/// `scroll-text` from their start time to their end.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CaptionSpec {
+    /// The cues, as SubRip (`.srt`).
+    file: PathBuf,
+    /// Parameters for `scroll-text`, apart from the text.
+    #[serde(default)]
+    params: BTreeMap<String, toml::Value>,
+    #[serde(default)]
+    blend: Blend,
+    #[serde(default = "full_opacity")]
+    opacity: f32,
+}
+
+/// A script run on a span of frames to tweak them.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HookSpec {
+    /// A frame number, a time such as `"01:23.5"`, or the label of an effect to run for all of.
+    at: AtSpec,
+    /// Frames to run for [default: 1, or the whole effect `at` names].
+    len: Option<usize>,
+    script: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum AtSpec {
+    Frame(usize),
+    Text(String),
+}
+
+/// Seconds into the show of a time written as `[HOURS:]MINUTES:SECONDS`, e.g. `01:23.5`.
+fn parse_time(text: &str) -> Option<f32> {
+    text.split(':').try_fold(0.0, |total, part| {
+        let part: f32 = part.trim().parse().ok()?;
+        Some(total * 60.0 + part)
+    })
+}
+
+fn full_opacity() -> f32 {
+    1.0
+}
+
+/// Lengths may be written as a number of frames or as a string like `"auto:2"`.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum LenSpec {
+    Frames(usize),
+    Text(String),
+}
+
+impl LenSpec {
+    fn parse(spec: Option<&Self>) -> Result<Length, String> {
+        match spec {
+            None => Ok(Length::Auto { cycles: 1 }),
+            Some(Self::Frames(frames)) => Ok(Length::Frames(*frames)),
+            Some(Self::Text(text)) => text.parse(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Transition {
+    #[default]
+    Cut,
+    Crossfade,
+    /// The new effect rises up the tree over the old one.
+    WipeUp,
+    WipeDown,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Blend {
+    #[default]
+    Add,
+    Multiply,
+    /// Shows the entry only where the layer is lit.
+    Mask,
+}
+
+impl Blend {
+    fn apply(self, base: Color, layer: Color) -> Color {
+        match self {
+            Self::Add => (base.0 + layer.0, base.1 + layer.1, base.2 + layer.2),
+            Self::Multiply => (base.0 * layer.0, base.1 * layer.1, base.2 * layer.2),
+            Self::Mask => {
+                let level = layer.0.max(layer.1).max(layer.2).clamp(0.0, 1.0);
+                (base.0 * level, base.1 * level, base.2 * level)
+            }
+        }
+    }
+}
+
+const DEFAULT_TRANSITION_SECONDS: f32 = 1.0;
+/// Height of the soft edge of a wipe, as a fraction of the tree.
+const WIPE_EDGE: f32 = 0.1;
+
+fn param_args(params: &BTreeMap<String, toml::Value>) -> Vec<ParamArg> {
+    params
+        .iter()
+        .map(|(name, value)| ParamArg {
+            name: name.clone(),
+            value: match value {
+                toml::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            },
+        })
+        .collect()
+}
+
+/// One effect rendering a frame at a time, looping every `len` frames.
+struct Track {
+    authorship: Authorship,
+    bounds: Bounds,
+    visibility: Vec<f32>,
+    params: Params,
+    effect: Box<dyn Effect>,
+    len: usize,
+    frame: usize,
+    colors: Vec<Color>,
+    previous: Option<Vec<Color>>,
+}
+
+impl Track {
+    fn open(
+        name: &str,
+        params: &BTreeMap<String, toml::Value>,
+        len: Option<&LenSpec>,
+        opt: &Opt,
+        coords: &[Coord],
+    ) -> Result<Self, Box<dyn Error>> {
+        let (info, effect, authorship): (_, Box<dyn Effect>, _) = match script::path(name) {
+            Some(path) => {
+                let script = ScriptEffect::load(path, &opt.sandbox)?;
+                let authorship = script.authorship.clone();
+                (&script::INFO, Box::new(script), authorship)
+            }
+            None => {
+                let info =
+                    effects::lookup(name).ok_or_else(|| format!("Unknown effect: {}", name))?;
+                (info, Box::new(info.render), Authorship::builtin())
+            }
+        };
+        let params = Params::resolve(
+            info,
+            &param_args(params),
+            &opt.project.palettes,
+            opt.seed,
+            0,
+        )?;
+        let len = LenSpec::parse(len)?.resolve(info, coords, &params)?;
+        if len == 0 {
+            return Err(format!("{} has no frames", name).into());
+        }
+        Ok(Self {
+            authorship,
+            bounds: Bounds::of(coords),
+            visibility: visibility::estimate(coords),
+            params,
+            effect,
+            len,
+            frame: 0,
+            colors: vec![(0.0, 0.0, 0.0); coords.len()],
+            previous: None,
+        })
+    }
+
+    fn render(&mut self, opt: &Opt, coords: &[Coord], neighbours: &NeighbourGraph) -> &[Color] {
+        let ctx = EffectContext {
+            coords,
+            bounds: self.bounds,
+            frame: self.frame % self.len,
+            total_frames: self.len,
+            fps: opt.fps,
+            seed: opt.seed,
+            previous: self.previous.as_deref(),
+            neighbours,
+            visibility: &self.visibility,
+            params: &self.params,
+            audio: None,
+        };
+        self.effect.render(&ctx, &mut self.colors);
+        let previous = self.previous.get_or_insert_with(Vec::new);
+        previous.clear();
+        previous.extend_from_slice(&self.colors);
+        self.frame += 1;
+        &self.colors
+    }
+}
+
+/// An effect's place in the combined sequence.
+struct Entry {
+    name: String,
+    label: Option<String>,
+    track: Track,
+    layer: Option<(Track, Blend, f32)>,
+    start: usize,
+    transition: Transition,
+    /// Frames at the start which overlap the previous entry.
+    overlap: usize,
+    /// The entry's effect, and its layer's if it has one.
+    credits: Vec<Credit>,
+}
+
+impl Entry {
+    fn end(&self) -> usize {
+        self.start + self.track.len
+    }
+
+    /// Names the entry goes by in markers and hooks.
+    fn label(&self) -> &str {
+        self.label.as_deref().unwrap_or(&self.name)
+    }
+
+    /// Renders the entry's next frame, with its layer's opacity scaled by `layer_opacity`.
+    fn render(
+        &mut self,
+        opt: &Opt,
+        coords: &[Coord],
+        neighbours: &NeighbourGraph,
+        layer_opacity: f32,
+    ) -> Vec<Color> {
+        let mut colors = self.track.render(opt, coords, neighbours).to_vec();
+        if let Some((layer, blend, opacity)) = &mut self.layer {
+            let layer = layer.render(opt, coords, neighbours);
+            let opacity = (*opacity * layer_opacity).clamp(0.0, 1.0);
+            for (color, &over) in colors.iter_mut().zip(layer) {
+                *color = mix(*color, blend.apply(*color, over), opacity);
+            }
+        }
+        colors
+    }
+}
+
+/// A caption cue, playing `scroll-text` from its first frame so the text starts in the same
+/// place each time.
+struct Caption {
+    start: usize,
+    track: Track,
+}
+
+/// The caption cues, drawn over whatever else is playing.
+struct Captions {
+    cues: Vec<Caption>,
+    blend: Blend,
+    opacity: f32,
+}
+
+impl Captions {
+    fn load(spec: &CaptionSpec, opt: &Opt, coords: &[Coord]) -> Result<Self, Box<dyn Error>> {
+        let mut cues = Vec::new();
+        for (i, cue) in captions::load(&spec.file)?.into_iter().enumerate() {
+            let mut params = spec.params.clone();
+            params.insert("text".into(), toml::Value::String(cue.text));
+            let start = (cue.start * opt.fps).round() as usize;
+            let len = ((cue.end * opt.fps).round() as usize)
+                .saturating_sub(start)
+                .max(1);
+            let track = Track::open(
+                "scroll-text",
+                &params,
+                Some(&LenSpec::Frames(len)),
+                opt,
+                coords,
+            )
+            .map_err(|e| format!("Caption {}: {}", i + 1, e))?;
+            cues.push(Caption { start, track });
+        }
+        Ok(Self {
+            cues,
+            blend: spec.blend,
+            opacity: spec.opacity.clamp(0.0, 1.0),
+        })
+    }
+
+    fn render(
+        &mut self,
+        frame: usize,
+        opt: &Opt,
+        coords: &[Coord],
+        neighbours: &NeighbourGraph,
+        colors: &mut [Color],
+    ) {
+        let (blend, opacity) = (self.blend, self.opacity);
+        for cue in &mut self.cues {
+            if !(cue.start..cue.start + cue.track.len).contains(&frame) {
+                continue;
+            }
+            let text = cue.track.render(opt, coords, neighbours);
+            for (color, &over) in colors.iter_mut().zip(text) {
+                *color = mix(*color, blend.apply(*color, over), opacity);
+            }
+        }
+    }
+}
+
+/// A playlist laid out and ready to render.
+struct Show {
+    entries: Vec<Entry>,
+    captions: Option<Captions>,
+    hooks: Hooks,
+    color_vision: Option<ColorVision>,
+}
+
+/// Lays the playlist out end to end, overlapping each entry with the one before for its
+/// transition. Overlaps are cut short so that no more than two entries ever play at once.
+fn load(path: &Path, opt: &Opt, coords: &[Coord]) -> Result<Show, Box<dyn Error>> {
+    let playlist: Playlist = toml::from_str(&fs::read_to_string(path)?)
+        .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    if playlist.effects.is_empty() {
+        return Err(format!("{} has no effects", path.display()).into());
+    }
+    let captions = match &playlist.captions {
+        Some(spec) => Some(Captions::load(spec, opt, coords)?),
+        None => None,
+    };
+    let default_overlap = (DEFAULT_TRANSITION_SECONDS * opt.fps).round() as usize;
+    let mut entries: Vec<Entry> = Vec::new();
+    for (i, spec) in playlist.effects.iter().enumerate() {
+        let context = |e: Box<dyn Error>| format!("Effect {} ({}): {}", i + 1, spec.name, e);
+        let track = Track::open(&spec.name, &spec.params, spec.len.as_ref(), opt, coords)
+            .map_err(context)?;
+        let layer = match &spec.layer {
+            Some(layer) => Some((
+                Track::open(&layer.name, &layer.params, layer.len.as_ref(), opt, coords)
+                    .map_err(context)?,
+                layer.blend,
+                layer.opacity.clamp(0.0, 1.0),
+            )),
+            None => None,
+        };
+        let (start, overlap) = match entries.last() {
+            None => (0, 0),
+            Some(last) if spec.transition == Transition::Cut => (last.end(), 0),
+            Some(last) => {
+                let overlap = spec
+                    .transition_len
+                    .unwrap_or(default_overlap)
+                    .min(last.track.len - last.overlap)
+                    .min(track.len);
+                (last.end() - overlap, overlap)
+            }
+        };
+        let mut credits =
+            vec![track
+                .authorship
+                .credit(script::credit_name(&spec.name), start, track.len)];
+        if let (Some(spec), Some((layer, _, _))) = (&spec.layer, &layer) {
+            credits.push(layer.authorship.credit(
+                script::credit_name(&spec.name),
+                start,
+                track.len,
+            ));
+        }
+        entries.push(Entry {
+            name: spec.name.clone(),
+            label: spec.label.clone(),
+            track,
+            layer,
+            start,
+            transition: spec.transition,
+            overlap,
+            credits,
+        });
+    }
+    let hooks = playlist
+        .hooks
+        .iter()
+        .enumerate()
+        .map(|(i, spec)| {
+            let name = format!("Hook {}", i + 1);
+            let (start, whole) = match &spec.at {
+                AtSpec::Frame(frame) => (*frame, 1),
+                AtSpec::Text(text) if text.contains(':') => {
+                    let seconds = parse_time(text)
+                        .ok_or_else(|| format!("{}: invalid time {}", name, text))?;
+                    ((seconds * opt.fps).round() as usize, 1)
+                }
+                AtSpec::Text(label) => {
+                    let entry = entries
+                        .iter()
+                        .find(|entry| entry.label() == label)
+                        .ok_or_else(|| format!("{}: no effect is labelled {}", name, label))?;
+                    (entry.start, entry.track.len)
+                }
+            };
+            Ok(Hook {
+                name,
+                start,
+                len: spec.len.unwrap_or(whole).max(1),
+                script: spec.script.clone(),
+            })
+        })
+        .collect::<Result<_, String>>()?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let hooks = Hooks::compile(hooks, &opt.sandbox, dir, coords)?;
+    Ok(Show {
+        entries,
+        captions,
+        hooks,
+        color_vision: playlist.color_vision,
+    })
+}
+
+/// How far up the tree each LED is, from 0 to 1.
+fn heights(coords: &[Coord]) -> Vec<f32> {
+    let (min, max) = coords
+        .iter()
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), c| {
+            (min.min(c.2), max.max(c.2))
+        });
+    let range = (max - min).max(f32::EPSILON);
+    coords.iter().map(|c| (c.2 - min) / range).collect()
+}
+
+/// Blends from `old` to `new`, `progress` of the way through a transition.
+fn transition(kind: Transition, progress: f32, heights: &[f32], old: &[Color], new: &mut [Color]) {
+    for ((color, &old), &height) in new.iter_mut().zip(old).zip(heights) {
+        let t = match kind {
+            Transition::Cut => 1.0,
+            Transition::Crossfade => progress,
+            Transition::WipeUp | Transition::WipeDown => {
+                let height = if kind == Transition::WipeUp {
+                    height
+                } else {
+                    1.0 - height
+                };
+                // Sweep the edge from just below the tree to just above it
+                let edge = progress * (1.0 + WIPE_EDGE);
+                1.0 - smoothstep(edge - WIPE_EDGE, edge, height)
+            }
+        };
+        *color = mix_hue(old, *color, t);
+    }
+}
+
+pub fn compose(opt: &Opt, compose: &ComposeOpt) -> Result<(), Box<dyn Error>> {
+    let playlist = compose
+        .playlist
+        .as_deref()
+        .or(opt.project.playlist.as_deref())
+        .ok_or("No playlist given, and no project with a playlist")?;
+    let coords = load_coords(opt)?;
+    load_hardware(opt)?;
+    let Show {
+        mut entries,
+        mut captions,
+        mut hooks,
+        color_vision,
+    } = load(playlist, opt, &coords)?;
+    let len = entries.last().unwrap().end();
+    let late = captions
+        .iter()
+        .flat_map(|captions| &captions.cues)
+        .filter(|cue| cue.start >= len)
+        .count();
+    if late > 0 {
+        warn!("{} captions start after the show ends", late);
+    }
+    for entry in &entries {
+        debug!(
+            "{} plays frames {}..{}",
+            entry.name,
+            entry.start,
+            entry.end()
+        );
+    }
+    let neighbours = NeighbourGraph::knn(&coords, NEIGHBOUR_COUNT);
+    let heights = heights(&coords);
+    let bounds = Bounds::of(&coords);
+    let visibility = visibility::estimate(&coords);
+    let mut filters = compose
+        .filters
+        .build(&coords, opt.calibration.as_ref(), color_vision);
+    let mut correction = compose.correction.build();
+    let params = Params::default();
+
+    let output: Box<dyn Write> = match &compose.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    let mut writer: Box<dyn SequenceWriter> = match compose.format {
+        SequenceFormat::Csv => Box::new(CsvWriter::new(output, coords.len())?),
+        SequenceFormat::Delta => Box::new(DeltaWriter::new(output, coords.len())?),
+        SequenceFormat::Fseq => Box::new(FseqWriter::new(output, coords.len(), opt.fps)),
+    };
+
+    let progress = progress_bar(opt, len);
+    let mut previous: Option<Vec<Color>> = None;
+    let mut rgb = Vec::with_capacity(coords.len());
+    let mut current = 0;
+    let mut stats = StatsCollector::new(opt.fps);
+    let mut thumbnail = Thumbnail::new(len);
+    for frame in 0..len {
+        while entries[current].end() <= frame {
+            current += 1;
+        }
+        let tweaks = hooks.run(frame, opt.fps);
+        let layer_opacity = tweaks.layer_opacity();
+        let mut colors = entries[current].render(opt, &coords, &neighbours, layer_opacity);
+        // The next entry starts during this one's last frames
+        if let Some(next) = entries
+            .get_mut(current + 1)
+            .filter(|next| next.start <= frame)
+        {
+            let mut incoming = next.render(opt, &coords, &neighbours, layer_opacity);
+            let progress = (frame - next.start + 1) as f32 / (next.overlap + 1) as f32;
+            transition(next.transition, progress, &heights, &colors, &mut incoming);
+            colors = incoming;
+        }
+        if let Some(captions) = &mut captions {
+            captions.render(frame, opt, &coords, &neighbours, &mut colors);
+        }
+        tweaks.apply(&mut colors);
+
+        let ctx = EffectContext {
+            coords: &coords,
+            bounds,
+            frame,
+            total_frames: len,
+            fps: opt.fps,
+            seed: opt.seed,
+            previous: previous.as_deref(),
+            neighbours: &neighbours,
+            visibility: &visibility,
+            params: &params,
+            audio: None,
+        };
+        let mut filtered = colors.clone();
+        for filter in &mut filters {
+            filter.apply(&ctx, &mut filtered);
+        }
+        stats.add_frame(frame, &filtered);
+        let limited = correction.apply(&filtered, &mut rgb);
+        stats.add_output(frame, &rgb, limited);
+        writer.write_frame(&rgb)?;
+        thumbnail.add(&rgb);
+        previous = Some(colors);
+        progress.inc(1);
+    }
+    writer.finish()?;
+    progress.finish_and_clear();
+    drop(writer);
+
+    if let Some(output) = &compose.output {
+        if let Some(mode) = opt.seal {
+            seal::seal_file(output, mode)?;
+        }
+        let metadata = SequenceMetadata::write_sidecar(
+            output,
+            compose.format,
+            len,
+            coords.len(),
+            Some(metadata::checksum(&fs::read(&opt.coords_path)?)),
+            entries
+                .iter()
+                .map(|entry| Marker {
+                    name: entry.label().to_string(),
+                    frame: entry.start,
+                })
+                .collect(),
+            entries
+                .iter()
+                .flat_map(|entry| entry.credits.iter().cloned())
+                .collect(),
+        )?;
+        thumbnail.finish(
+            output,
+            &coords,
+            &metadata,
+            opt.fps,
+            !opt.no_thumbnail && opt.seal != Some(SealMode::Encrypt),
+        )?;
+    }
+
+    let report = stats.finish(
+        entries
+            .iter()
+            .map(|entry| EffectStats {
+                name: entry.name.clone(),
+                start_frame: entry.start,
+                frames: entry.track.len,
+                duration_secs: entry.track.len as f32 / opt.fps,
+            })
+            .collect(),
+        len,
+        opt.seed,
+        compose.output.as_deref(),
+    );
+    compose.stats.emit(&report, opt.quiet)
+}
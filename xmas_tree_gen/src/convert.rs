@@ -0,0 +1,71 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use tracing::{info, warn};
+use xmas_tree_common::{
+    csv_format::CsvWriter,
+    delta_format::DeltaWriter,
+    fseq_format::FseqWriter,
+    metadata::SequenceMetadata,
+    seal::{self, SealMode},
+    sequence::{self, SequenceFormat, SequenceWriter},
+};
+
+/// Rewrites a sequence, including loosely formatted community ones, in one of this crate's
+/// formats with sidecar metadata. `fps` is only recorded in fseq files. Sealed sequences can be
+/// converted by anyone with the key, and are only sealed again if `seal` is given.
+pub fn convert(
+    input: &Path,
+    output: &Path,
+    format: SequenceFormat,
+    fps: f32,
+    seal: Option<SealMode>,
+) -> Result<(), Box<dyn Error>> {
+    let sequence = sequence::read(input)?;
+    if sequence.clamped_values > 0 {
+        warn!(
+            "Clamped {} values which were outside 0-255",
+            sequence.clamped_values
+        );
+    }
+    let (coords_hash, markers, credits) = match SequenceMetadata::load(input)? {
+        Some(metadata) => (metadata.coords_hash, metadata.markers, metadata.credits),
+        None => (None, Vec::new(), Vec::new()),
+    };
+
+    let file: Box<dyn Write> = Box::new(BufWriter::new(File::create(output)?));
+    let mut writer: Box<dyn SequenceWriter> = match format {
+        SequenceFormat::Csv => Box::new(CsvWriter::new(file, sequence.led_count)?),
+        SequenceFormat::Delta => Box::new(DeltaWriter::new(file, sequence.led_count)?),
+        SequenceFormat::Fseq => Box::new(FseqWriter::new(file, sequence.led_count, fps)),
+    };
+    for frame in &sequence.frames {
+        writer.write_frame(frame)?;
+    }
+    writer.finish()?;
+    drop(writer);
+    if let Some(mode) = seal {
+        seal::seal_file(output, mode)?;
+    }
+    SequenceMetadata::write_sidecar(
+        output,
+        format,
+        sequence.frames.len(),
+        sequence.led_count,
+        coords_hash,
+        markers,
+        credits,
+    )?;
+
+    info!(
+        "Converted {} frames of {} LEDs to {}",
+        sequence.frames.len(),
+        sequence.led_count,
+        format
+    );
+    Ok(())
+}
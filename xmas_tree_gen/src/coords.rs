@@ -0,0 +1,455 @@
+use std::{collections::HashSet, error::Error, f32::consts::PI, io, str::FromStr};
+
+use rand::{prelude::StdRng, Rng, SeedableRng};
+use serde::Serialize;
+
+use xmas_tree_common::{channel_map::format_indices, coords::Layout as CoordsLayout};
+
+use crate::{
+    effects::Coord,
+    neighbours::{spatial_tour, NeighbourGraph, NEIGHBOUR_COUNT},
+    report::Report,
+    visibility,
+};
+
+#[derive(Debug, Serialize)]
+pub struct CoordsReport {
+    pub leds: usize,
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+    pub height: f32,
+    pub duplicates: Vec<usize>,
+    /// LEDs the scan didn't find, which `--fill-missing` places between their neighbours.
+    pub missing: Vec<usize>,
+    /// Missing LEDs which have been placed between their neighbours.
+    pub filled: Vec<usize>,
+    /// LEDs out of line with their neighbours, which `--repair-outliers` would move.
+    pub outliers: Vec<usize>,
+}
+
+pub fn check(layout: &CoordsLayout) -> CoordsReport {
+    let coords = &layout.coords;
+    let mut min = [f32::INFINITY; 3];
+    let mut max = [f32::NEG_INFINITY; 3];
+    let mut seen = HashSet::new();
+    let mut duplicates = Vec::new();
+    for (index, &(x, y, z)) in coords.iter().enumerate() {
+        // Missing LEDs are somewhere arbitrary unless they've been filled in
+        if layout.missing.contains(&index) && !layout.filled.contains(&index) {
+            continue;
+        }
+        for (axis, &v) in [x, y, z].iter().enumerate() {
+            min[axis] = min[axis].min(v);
+            max[axis] = max[axis].max(v);
+        }
+        if !seen.insert([x.to_bits(), y.to_bits(), z.to_bits()]) {
+            duplicates.push(index);
+        }
+    }
+    CoordsReport {
+        leds: coords.len(),
+        min,
+        max,
+        height: max[2] - min[2],
+        duplicates,
+        missing: layout.missing.clone(),
+        filled: layout.filled.clone(),
+        outliers: xmas_tree_common::coords::repair_outliers(&mut coords.to_vec()),
+    }
+}
+
+impl Report for CoordsReport {
+    fn write_text(&self, out: &mut dyn io::Write) -> io::Result<()> {
+        writeln!(out, "LEDs:       {}", self.leds)?;
+        writeln!(
+            out,
+            "Bounds:     ({:.3}, {:.3}, {:.3}) to ({:.3}, {:.3}, {:.3})",
+            self.min[0], self.min[1], self.min[2], self.max[0], self.max[1], self.max[2]
+        )?;
+        writeln!(out, "Height:     {:.3}", self.height)?;
+        writeln!(out, "Duplicates: {:?}", self.duplicates)?;
+        writeln!(out, "Missing:    {:?}", self.missing)?;
+        writeln!(out, "Filled in:  {:?}", self.filled)?;
+        writeln!(out, "Outliers:   {:?}", self.outliers)?;
+        Ok(())
+    }
+}
+
+/// Height bands the density report splits the tree into.
+const DENSITY_BANDS: usize = 5;
+/// LEDs listed at each end of the density report.
+const DENSITY_LISTED: usize = 10;
+
+#[derive(Debug, Serialize)]
+pub struct DensityBand {
+    /// Heights covered, as fractions of the tree's height.
+    pub from: f32,
+    pub to: f32,
+    pub leds: usize,
+    pub median_density: f32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DensityReport {
+    pub leds: usize,
+    /// LEDs per cubic unit around each LED.
+    pub density: Vec<f32>,
+    pub median_density: f32,
+    /// How much denser the densest tenth of LEDs are than the sparsest tenth.
+    pub spread: f32,
+    pub densest: Vec<usize>,
+    pub sparsest: Vec<usize>,
+    pub bands: Vec<DensityBand>,
+}
+
+fn median(values: &mut [f32]) -> f32 {
+    values.sort_by(f32::total_cmp);
+    values.get(values.len() / 2).copied().unwrap_or(0.0)
+}
+
+/// Estimates how densely packed the LEDs are around each one, to see whether parts of the tree
+/// will look brighter than the rest (which `--density-balance` evens out).
+pub fn density(coords: &[Coord]) -> DensityReport {
+    let density = NeighbourGraph::knn(coords, NEIGHBOUR_COUNT).density();
+    let mut order: Vec<usize> = (0..coords.len()).collect();
+    order.sort_by(|&a, &b| density[b].total_cmp(&density[a]));
+    let tenth = |index: usize| density[order[index]];
+    let spread = match order.len() {
+        0 => 1.0,
+        n => tenth(n / 10) / tenth(n - 1 - n / 10),
+    };
+
+    let bottom = coords.iter().map(|c| c.2).fold(f32::INFINITY, f32::min);
+    let top = coords.iter().map(|c| c.2).fold(f32::NEG_INFINITY, f32::max);
+    let height = (top - bottom).max(f32::EPSILON);
+    let bands = (0..DENSITY_BANDS)
+        .map(|band| {
+            let from = band as f32 / DENSITY_BANDS as f32;
+            let to = (band + 1) as f32 / DENSITY_BANDS as f32;
+            let mut in_band: Vec<f32> = coords
+                .iter()
+                .zip(&density)
+                .filter(|(coord, _)| {
+                    let h = (coord.2 - bottom) / height;
+                    from <= h && (h < to || band == DENSITY_BANDS - 1)
+                })
+                .map(|(_, &d)| d)
+                .collect();
+            DensityBand {
+                from,
+                to,
+                leds: in_band.len(),
+                median_density: median(&mut in_band),
+            }
+        })
+        .collect();
+    DensityReport {
+        leds: coords.len(),
+        median_density: median(&mut density.clone()),
+        spread,
+        densest: order.iter().take(DENSITY_LISTED).copied().collect(),
+        sparsest: order.iter().rev().take(DENSITY_LISTED).copied().collect(),
+        bands,
+        density,
+    }
+}
+
+impl Report for DensityReport {
+    fn write_text(&self, out: &mut dyn io::Write) -> io::Result<()> {
+        writeln!(out, "LEDs:       {}", self.leds)?;
+        writeln!(out, "Median:     {:.1} LEDs per unit³", self.median_density)?;
+        writeln!(
+            out,
+            "Spread:     {:.1}x (densest tenth vs sparsest)",
+            self.spread
+        )?;
+        for band in self.bands.iter().rev() {
+            let relative = band.median_density / self.median_density.max(f32::EPSILON);
+            writeln!(
+                out,
+                "Height {:>3.0}-{:.0}%: {} LEDs, {:.2}x median density",
+                band.from * 100.0,
+                band.to * 100.0,
+                band.leds,
+                relative
+            )?;
+        }
+        writeln!(out, "Densest:    {:?}", self.densest)?;
+        writeln!(out, "Sparsest:   {:?}", self.sparsest)?;
+        Ok(())
+    }
+}
+
+/// LEDs below this visibility count as hidden in the visibility report.
+const HIDDEN_BELOW: f32 = 0.3;
+
+#[derive(Debug, Serialize)]
+pub struct VisibilityReport {
+    pub leds: usize,
+    /// How visible each LED is from outside the tree, from 0 to 1.
+    pub visibility: Vec<f32>,
+    pub median: f32,
+    /// LEDs mostly hidden inside the tree, least visible first.
+    pub hidden: Vec<usize>,
+}
+
+/// Estimates which LEDs are hidden inside the tree, as effects' `outward` param sees them.
+pub fn visibility(coords: &[Coord]) -> VisibilityReport {
+    let visibility = visibility::estimate(coords);
+    let mut hidden: Vec<usize> = (0..coords.len())
+        .filter(|&index| visibility[index] < HIDDEN_BELOW)
+        .collect();
+    hidden.sort_by(|&a, &b| visibility[a].total_cmp(&visibility[b]));
+    VisibilityReport {
+        leds: coords.len(),
+        median: median(&mut visibility.clone()),
+        hidden,
+        visibility,
+    }
+}
+
+impl Report for VisibilityReport {
+    fn write_text(&self, out: &mut dyn io::Write) -> io::Result<()> {
+        writeln!(out, "LEDs:       {}", self.leds)?;
+        writeln!(out, "Median:     {:.2}", self.median)?;
+        writeln!(
+            out,
+            "Hidden:     {} LEDs below {:.1}",
+            self.hidden.len(),
+            HIDDEN_BELOW
+        )?;
+        writeln!(
+            out,
+            "Worst:      {:?}",
+            &self.hidden[..self.hidden.len().min(20)]
+        )?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    Csv,
+    Json,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            other => Err(format!("Unknown graph format: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct GraphNode {
+    led: usize,
+    position: [f32; 3],
+    /// Where this LED comes in the spatial tour.
+    tour_position: usize,
+    neighbours: Vec<GraphEdge>,
+}
+
+#[derive(Debug, Serialize)]
+struct GraphEdge {
+    led: usize,
+    distance: f32,
+}
+
+#[derive(Debug, Serialize)]
+struct Graph {
+    k: usize,
+    nodes: Vec<GraphNode>,
+    tour: Vec<usize>,
+}
+
+/// Writes the k nearest neighbour graph and spatial tour of the (normalized) coordinates, for
+/// tools outside the generator. The CSV has one row per LED, with columns for each neighbour.
+pub fn write_graph(
+    out: &mut dyn io::Write,
+    coords: &[Coord],
+    k: usize,
+    format: GraphFormat,
+) -> Result<(), Box<dyn Error>> {
+    let graph = NeighbourGraph::knn(coords, k);
+    let tour = spatial_tour(coords);
+    let mut tour_positions = vec![0; coords.len()];
+    for (position, &led) in tour.iter().enumerate() {
+        tour_positions[led] = position;
+    }
+    let nodes = coords
+        .iter()
+        .zip(graph.neighbours)
+        .enumerate()
+        .map(|(led, (&(x, y, z), neighbours))| GraphNode {
+            led,
+            position: [x, y, z],
+            tour_position: tour_positions[led],
+            neighbours: neighbours
+                .into_iter()
+                .map(|(led, distance)| GraphEdge { led, distance })
+                .collect(),
+        })
+        .collect();
+    let graph = Graph { k, nodes, tour };
+
+    match format {
+        GraphFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, &graph)?;
+            writeln!(out)?;
+        }
+        GraphFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            let mut header = vec![
+                "led".to_string(),
+                "x".into(),
+                "y".into(),
+                "z".into(),
+                "tour_position".into(),
+            ];
+            for n in 1..=k {
+                header.push(format!("neighbour_{}", n));
+                header.push(format!("distance_{}", n));
+            }
+            writer.write_record(&header)?;
+            for node in &graph.nodes {
+                let mut record = vec![
+                    node.led.to_string(),
+                    node.position[0].to_string(),
+                    node.position[1].to_string(),
+                    node.position[2].to_string(),
+                    node.tour_position.to_string(),
+                ];
+                for edge in &node.neighbours {
+                    record.push(edge.led.to_string());
+                    record.push(edge.distance.to_string());
+                }
+                // Pad out trees with fewer than k + 1 LEDs
+                record.resize(header.len(), String::new());
+                writer.write_record(&record)?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Synthetic LED layouts, giving performance work standard workloads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// A large tree packed with LEDs throughout its foliage, in GIFT units.
+    DenseCone,
+    /// A messy scan in centimetres, tilted, with noise, outliers, duplicates and missing LEDs.
+    IrregularScan,
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dense-cone" => Ok(Self::DenseCone),
+            "irregular-scan" => Ok(Self::IrregularScan),
+            other => Err(format!(
+                "Unknown layout: {} (expected dense-cone or irregular-scan)",
+                other
+            )),
+        }
+    }
+}
+
+impl Layout {
+    pub fn default_count(self) -> usize {
+        match self {
+            Self::DenseCone => 10_000,
+            Self::IrregularScan => 500,
+        }
+    }
+}
+
+/// A point in a cone of the given height and base radius, nearer the surface than the trunk.
+fn in_cone(rng: &mut StdRng, height: f32, radius: f32) -> Coord {
+    let z = height * (1.0 - rng.gen::<f32>().sqrt());
+    let depth = 1.0 - rng.gen::<f32>().powi(3);
+    let r = radius * (1.0 - z / height) * depth;
+    let angle = rng.gen::<f32>() * PI * 2.0;
+    (r * angle.cos(), r * angle.sin(), z)
+}
+
+pub fn make_layout(layout: Layout, count: usize, seed: u64) -> Vec<Coord> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    match layout {
+        Layout::DenseCone => (0..count).map(|_| in_cone(&mut rng, 3.2, 1.0)).collect(),
+        Layout::IrregularScan => {
+            const TILT: f32 = 0.05;
+            let mut coords: Vec<Coord> = (0..count)
+                .map(|_| {
+                    let (x, y, z) = in_cone(&mut rng, 180.0, 55.0);
+                    let mut noise = || (rng.gen::<f32>() - 0.5) * 4.0;
+                    (
+                        x + z * TILT + noise() + 12.0,
+                        y + noise() - 30.0,
+                        z + noise() + 40.0,
+                    )
+                })
+                .collect();
+            for i in 0..count {
+                match rng.gen_range(0..50) {
+                    // Reflections picked up a long way from the tree
+                    0 => {
+                        let (x, y, z) = coords[i];
+                        coords[i] = (x * 4.0, y * 3.0, z + rng.gen_range(-150.0..150.0));
+                    }
+                    // The same LED found twice
+                    1 if i > 0 => coords[i] = coords[rng.gen_range(0..i)],
+                    // Never found
+                    2 => coords[i] = (0.0, 0.0, 0.0),
+                    _ => {}
+                }
+            }
+            coords
+        }
+    }
+}
+
+/// Writes coordinates as `write_coords` does, after a comment listing the LEDs which were placed
+/// between their neighbours, or which still need placing by hand.
+pub fn write_filled(out: &mut dyn io::Write, layout: &CoordsLayout) -> Result<(), Box<dyn Error>> {
+    if !layout.filled.is_empty() {
+        writeln!(
+            out,
+            "# LEDs {} weren't found, and were placed between their neighbours on the string",
+            format_indices(&layout.filled)
+        )?;
+    }
+    let unplaced: Vec<usize> = layout
+        .missing
+        .iter()
+        .copied()
+        .filter(|i| !layout.filled.contains(i))
+        .collect();
+    if !unplaced.is_empty() {
+        writeln!(
+            out,
+            "# LEDs {} weren't found, and too few were to place them",
+            format_indices(&unplaced)
+        )?;
+    }
+    write_coords(out, &layout.coords)
+}
+
+/// Writes coordinates in the same headerless CSV format they are read from.
+pub fn write_coords(out: &mut dyn io::Write, coords: &[Coord]) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(out);
+    for coord in coords {
+        writer.serialize(coord)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
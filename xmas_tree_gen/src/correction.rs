@@ -0,0 +1,149 @@
+//! The last step before frames are written, turning the colors effects render into the values
+//! sent to the LEDs. WS2811 strings light in proportion to the value they're sent, so writing
+//! colors as they are makes every fade look washed out, and the current the tree draws depends on
+//! these values rather than the colors.
+//!
+//! Colors brighter than the LEDs can show, from brightened or blended effects, are brought back
+//! into range by `--clip`. By default the whole color is scaled down, so an over-bright orange
+//! stays orange rather than clipping to yellow. The `--max-amps` limiter and the outputs'
+//! brightness caps scale every channel alike, after gamma, so they keep hues as they are.
+
+use std::str::FromStr;
+
+use structopt::StructOpt;
+use xmas_tree_common::{
+    color::{self, ClipMode},
+    sequence::Rgb,
+};
+
+use crate::effects::Color;
+
+/// Current drawn by one fully lit channel of a typical WS2811 pixel.
+pub const AMPS_PER_CHANNEL: f32 = 0.02;
+
+#[derive(Debug, StructOpt)]
+pub struct CorrectionOpt {
+    /// Gamma correction for the LEDs, where 1 writes colors as they are.
+    #[structopt(long, default_value = "2.2")]
+    gamma: f32,
+    /// Scale red, green and blue by these amounts, e.g. `1,0.85,0.7` for LEDs whose white looks
+    /// blue.
+    #[structopt(long)]
+    white_balance: Option<WhiteBalance>,
+    /// Carry each LED's rounding error over to its next frame, so slow fades of dim colors
+    /// don't step.
+    #[structopt(long)]
+    temporal_dither: bool,
+    /// Dim frames which would draw more than this many amps, at 20 mA per channel.
+    #[structopt(long)]
+    max_amps: Option<f32>,
+    /// How colors too bright for the LEDs are brought into range: `hue` scales the whole color
+    /// down, `soft` also rolls brightness off gently from 80% so over-bright gradients stay
+    /// visible, and `channel` clamps each channel on its own, which shifts hues.
+    #[structopt(long, default_value = "hue")]
+    clip: ClipMode,
+}
+
+/// Gains for red, green and blue, written `R,G,B`.
+#[derive(Debug, Clone, Copy)]
+pub struct WhiteBalance([f32; 3]);
+
+impl FromStr for WhiteBalance {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let gains = s
+            .split(',')
+            .map(|gain| match gain.trim().parse::<f32>() {
+                Ok(gain) if gain >= 0.0 => Ok(gain),
+                _ => Err(format!("Invalid white balance gain: {}", gain)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        match gains[..] {
+            [r, g, b] => Ok(Self([r, g, b])),
+            _ => Err(format!("White balance must be R,G,B, got {}", s)),
+        }
+    }
+}
+
+impl CorrectionOpt {
+    pub fn build(&self) -> Correction {
+        Correction {
+            gamma: self.gamma,
+            balance: self.white_balance.map_or([1.0; 3], |balance| balance.0),
+            max_amps: self.max_amps,
+            clip: self.clip,
+            error: if self.temporal_dither {
+                Some(Vec::new())
+            } else {
+                None
+            },
+            corrected: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Correction {
+    gamma: f32,
+    balance: [f32; 3],
+    max_amps: Option<f32>,
+    clip: ClipMode,
+    /// Each LED's rounding error from the last frame, when dithering.
+    error: Option<Vec<[f32; 3]>>,
+    corrected: Vec<[f32; 3]>,
+}
+
+impl Correction {
+    /// Converts a frame to the values written for it, returning whether it had to be dimmed to
+    /// stay within `--max-amps`.
+    pub fn apply(&mut self, colors: &[Color], rgb: &mut Vec<Rgb>) -> bool {
+        let (gamma, balance, clip) = (self.gamma, self.balance, self.clip);
+        self.corrected.clear();
+        self.corrected.extend(colors.iter().map(|&(r, g, b)| {
+            let mut channels = [r, g, b];
+            for (v, gain) in channels.iter_mut().zip(balance) {
+                *v = v.max(0.0).powf(gamma) * gain;
+            }
+            // Clipped in linear light, where scaling the channels alike keeps the hue exactly
+            let (r, g, b) = color::clip((channels[0], channels[1], channels[2]), clip);
+            [r, g, b]
+        }));
+
+        // Each channel draws current in proportion to its value, so scaling them all brings the
+        // frame within the limit exactly
+        let amps = self.corrected.iter().flatten().sum::<f32>() * AMPS_PER_CHANNEL;
+        let scale = match self.max_amps {
+            Some(max_amps) if amps > max_amps => max_amps / amps,
+            _ => 1.0,
+        };
+
+        rgb.clear();
+        match &mut self.error {
+            Some(error) => {
+                error.resize(colors.len(), [0.0; 3]);
+                rgb.extend(self.corrected.iter().zip(error).map(|(channels, error)| {
+                    let mut out = [0; 3];
+                    for i in 0..3 {
+                        let target = channels[i] * scale * 255.0 + error[i];
+                        out[i] = target.round().clamp(0.0, 255.0) as u8;
+                        error[i] = target - out[i] as f32;
+                    }
+                    out
+                }));
+            }
+            None => rgb.extend(
+                self.corrected
+                    .iter()
+                    .map(|channels| channels.map(|v| (v * scale * 255.0).round() as u8)),
+            ),
+        }
+        scale < 1.0
+    }
+}
+
+/// The current a frame is estimated to draw, in amps.
+pub fn amps(frame: &[Rgb]) -> f32 {
+    let total: u32 = frame.iter().flatten().map(|&v| v as u32).sum();
+    total as f32 / 255.0 * AMPS_PER_CHANNEL
+}
@@ -0,0 +1,187 @@
+//! Time travel for `generate --preview --snapshot-every N`, for finding out why an effect which
+//! looks back at its previous frame goes wrong thousands of frames in. Effects are functions of
+//! the frame, seed and previous frame, so the state carried from one frame to the next is small
+//! and is snapshotted every N frames. Commands typed while previewing jump to another frame by
+//! going back to the snapshot before it and replaying from there without sending the frames,
+//! which gives exactly the frames the preview showed the first time round:
+//!
+//! - `goto FRAME`, `back N`, `forward N` (or `g`, `b`, `f`)
+//! - `pause`, `play` and `step` (or `p`, `r`, `s`) to go frame by frame
+
+use std::{
+    collections::BTreeMap,
+    io::{self, BufRead},
+    str::FromStr,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+};
+
+use tracing::{info, warn};
+
+use crate::{correction::Correction, effects::Color};
+
+/// Everything carried from one frame of the preview to the next.
+#[derive(Clone)]
+pub struct EffectState {
+    pub previous: Option<Vec<Color>>,
+    /// Holds the error carried between frames by `--temporal-dither`.
+    pub correction: Correction,
+}
+
+enum DebugCommand {
+    Goto(usize),
+    Back(usize),
+    Forward(usize),
+    Pause,
+    Play,
+    Step,
+}
+
+impl FromStr for DebugCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let command = words.next().unwrap_or("");
+        let mut number = || {
+            words
+                .next()
+                .and_then(|n| n.parse().ok())
+                .ok_or_else(|| format!("{} needs a number of frames", command))
+        };
+        Ok(match command {
+            "goto" | "g" => Self::Goto(number()?),
+            "back" | "b" => Self::Back(number()?),
+            "forward" | "f" => Self::Forward(number()?),
+            "pause" | "p" => Self::Pause,
+            "play" | "r" => Self::Play,
+            "step" | "s" => Self::Step,
+            _ => {
+                return Err(format!(
+                    "Unknown command {:?}: expected goto, back, forward, pause, play or step",
+                    s
+                ))
+            }
+        })
+    }
+}
+
+pub struct Debugger {
+    every: usize,
+    len: usize,
+    /// The state just before each snapshotted frame was rendered.
+    snapshots: BTreeMap<usize, EffectState>,
+    commands: Receiver<DebugCommand>,
+    paused: bool,
+}
+
+impl Debugger {
+    /// Starts reading commands from stdin, for a preview `len` frames long.
+    pub fn start(every: usize, len: usize) -> Self {
+        let (sender, commands) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match line.parse() {
+                    Ok(command) => {
+                        if sender.send(command).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => warn!("{}", e),
+                }
+            }
+        });
+        info!(
+            "Snapshotting every {} frames; type goto, back, forward, pause, play or step",
+            every
+        );
+        Self {
+            every: every.max(1),
+            len: len.max(1),
+            snapshots: BTreeMap::new(),
+            commands,
+            paused: false,
+        }
+    }
+
+    /// Called before rendering each frame, to snapshot it if it's due.
+    pub fn snapshot(&mut self, frame: usize, state: &EffectState) {
+        if frame.is_multiple_of(self.every) {
+            self.snapshots.insert(frame, state.clone());
+        }
+    }
+
+    /// Handles the commands typed since the last frame, waiting for more while paused. Returns
+    /// the frame to jump to, if there is one, before `frame` is rendered.
+    pub fn poll(&mut self, frame: usize) -> Option<usize> {
+        loop {
+            let command = if self.paused {
+                match self.commands.recv() {
+                    Ok(command) => command,
+                    // Nothing more can be typed, so there's no way to unpause other than this
+                    Err(_) => {
+                        self.paused = false;
+                        return None;
+                    }
+                }
+            } else {
+                match self.commands.try_recv() {
+                    Ok(command) => command,
+                    Err(TryRecvError::Empty | TryRecvError::Disconnected) => return None,
+                }
+            };
+            let target = match command {
+                DebugCommand::Goto(target) => target,
+                DebugCommand::Back(frames) => frame.saturating_sub(frames),
+                DebugCommand::Forward(frames) => frame + frames,
+                DebugCommand::Pause => {
+                    self.paused = true;
+                    info!("Paused before frame {}", frame);
+                    continue;
+                }
+                DebugCommand::Play => {
+                    self.paused = false;
+                    continue;
+                }
+                DebugCommand::Step => {
+                    self.paused = true;
+                    info!("Frame {}", frame);
+                    return None;
+                }
+            };
+            return Some(target.min(self.len - 1));
+        }
+    }
+
+    /// Puts `state` as it was just before `target` was rendered, replaying frames with `render`
+    /// from the nearest snapshot before it, or from `frame` (where `state` is up to) if that's
+    /// nearer.
+    pub fn seek(
+        &self,
+        frame: usize,
+        target: usize,
+        state: &mut EffectState,
+        mut render: impl FnMut(usize, &mut EffectState),
+    ) {
+        let start = match self.snapshots.range(..=target).next_back() {
+            Some((&start, _)) if (start..=target).contains(&frame) => frame,
+            Some((&start, snapshot)) => {
+                *state = snapshot.clone();
+                start
+            }
+            // Frame 0 is always snapshotted first, so this can't happen
+            None => frame,
+        };
+        for frame in start..target {
+            render(frame, state);
+        }
+        info!("Jumped to frame {}, replaying from frame {}", target, start);
+    }
+}
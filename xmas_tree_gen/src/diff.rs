@@ -0,0 +1,150 @@
+use std::{error::Error, io, path::Path};
+
+use serde::Serialize;
+use xmas_tree_common::sequence::{Rgb, Sequence};
+
+use crate::report::Report;
+
+#[derive(Debug, Serialize)]
+pub struct FirstDifference {
+    pub frame: usize,
+    pub led: usize,
+    pub a: Rgb,
+    pub b: Rgb,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiffReport {
+    pub frames: (usize, usize),
+    pub leds: (usize, usize),
+    pub identical: bool,
+    pub first_difference: Option<FirstDifference>,
+    pub differing_frames: usize,
+    pub mean_rms: f32,
+    pub max_rms: f32,
+    pub max_rms_frame: usize,
+    /// RMS error (0-255) of each frame present in both sequences.
+    pub frame_rms: Vec<f32>,
+}
+
+/// Root mean square of the channel differences between two colors.
+fn led_error(a: Rgb, b: Rgb) -> f32 {
+    let sum: f32 = a
+        .iter()
+        .zip(&b)
+        .map(|(&x, &y)| (x as f32 - y as f32).powi(2))
+        .sum();
+    (sum / 3.0).sqrt()
+}
+
+/// Compares two sequences frame by frame. If `heatmap` is given, the error of every LED in
+/// every frame is written there as a CSV in the same layout as a sequence.
+pub fn diff(
+    a: &Sequence,
+    b: &Sequence,
+    heatmap: Option<&Path>,
+) -> Result<DiffReport, Box<dyn Error>> {
+    let leds = a.led_count.min(b.led_count);
+    let mut heatmap = heatmap
+        .map(|path| csv::WriterBuilder::new().from_path(path))
+        .transpose()?;
+    if let Some(writer) = &mut heatmap {
+        let mut header = vec!["FRAME_ID".to_string()];
+        header.extend((0..leds).map(|i| format!("E_{}", i)));
+        writer.write_record(&header)?;
+    }
+
+    let mut first_difference = None;
+    let mut frame_rms = Vec::new();
+    for (index, (frame_a, frame_b)) in a.frames.iter().zip(&b.frames).enumerate() {
+        let errors: Vec<f32> = frame_a[..leds]
+            .iter()
+            .zip(&frame_b[..leds])
+            .map(|(&x, &y)| led_error(x, y))
+            .collect();
+        if first_difference.is_none() {
+            if let Some(led) = errors.iter().position(|&e| e > 0.0) {
+                first_difference = Some(FirstDifference {
+                    frame: index,
+                    led,
+                    a: frame_a[led],
+                    b: frame_b[led],
+                });
+            }
+        }
+        let mean_square = errors.iter().map(|e| e * e).sum::<f32>() / leds.max(1) as f32;
+        frame_rms.push(mean_square.sqrt());
+        if let Some(writer) = &mut heatmap {
+            writer.write_field(index.to_string())?;
+            for error in &errors {
+                writer.write_field(format!("{:.1}", error))?;
+            }
+            writer.write_record(None::<&[u8]>)?;
+        }
+    }
+    if let Some(writer) = &mut heatmap {
+        writer.flush()?;
+    }
+
+    let (max_rms_frame, max_rms) =
+        frame_rms
+            .iter()
+            .copied()
+            .enumerate()
+            .fold(
+                (0, 0.0),
+                |best, (i, rms)| if rms > best.1 { (i, rms) } else { best },
+            );
+    let frames = (a.frames.len(), b.frames.len());
+    let leds = (a.led_count, b.led_count);
+    Ok(DiffReport {
+        identical: first_difference.is_none() && frames.0 == frames.1 && leds.0 == leds.1,
+        frames,
+        leds,
+        first_difference,
+        differing_frames: frame_rms.iter().filter(|&&rms| rms > 0.0).count(),
+        mean_rms: frame_rms.iter().sum::<f32>() / frame_rms.len().max(1) as f32,
+        max_rms,
+        max_rms_frame,
+        frame_rms,
+    })
+}
+
+impl Report for DiffReport {
+    fn write_text(&self, out: &mut dyn io::Write) -> io::Result<()> {
+        if self.identical {
+            writeln!(out, "Sequences are identical ({} frames)", self.frames.0)?;
+            return Ok(());
+        }
+        if self.frames.0 != self.frames.1 {
+            writeln!(
+                out,
+                "Frames:           {} vs {}",
+                self.frames.0, self.frames.1
+            )?;
+        }
+        if self.leds.0 != self.leds.1 {
+            writeln!(out, "LEDs:             {} vs {}", self.leds.0, self.leds.1)?;
+        }
+        if let Some(first) = &self.first_difference {
+            writeln!(
+                out,
+                "First difference: frame {} LED {} ({:?} vs {:?})",
+                first.frame, first.led, first.a, first.b
+            )?;
+        }
+        writeln!(
+            out,
+            "Differing frames: {} of {}",
+            self.differing_frames,
+            self.frame_rms.len()
+        )?;
+        writeln!(out, "Mean RMS error:   {:.3}", self.mean_rms)?;
+        writeln!(
+            out,
+            "Max RMS error:    {:.3} (frame {})",
+            self.max_rms, self.max_rms_frame
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,45 @@
+use std::{io, path::Path};
+
+use crate::effects::EFFECTS;
+
+/// Writes a Markdown catalogue of every registered effect and its parameters.
+pub fn write_markdown(out: &mut dyn io::Write, previews: &Path) -> io::Result<()> {
+    writeln!(out, "# Effects")?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "Generated by `xmas_tree_gen docs`. Set parameters with `--param NAME=VALUE`."
+    )?;
+    for info in EFFECTS {
+        writeln!(out)?;
+        writeln!(out, "## {}", info.name)?;
+        writeln!(out)?;
+        writeln!(out, "{}", info.description)?;
+        writeln!(out)?;
+        writeln!(
+            out,
+            "![{}]({})",
+            info.name,
+            previews.join(format!("{}.png", info.name)).display()
+        )?;
+        writeln!(out)?;
+        if info.params.is_empty() {
+            writeln!(out, "No parameters.")?;
+            continue;
+        }
+        writeln!(out, "| Parameter | Type | Default | Description |")?;
+        writeln!(out, "|-----------|------|---------|-------------|")?;
+        for param in info.params {
+            let default = match param.default {
+                "" => "(none)".to_string(),
+                default => format!("`{}`", default),
+            };
+            writeln!(
+                out,
+                "| `{}` | {} | {} | {} |",
+                param.name, param.kind, default, param.description
+            )?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,1852 @@
+use std::{f32::consts::PI, sync::Arc};
+
+use chrono::{Datelike, Local};
+use rand::{
+    prelude::{SliceRandom, StdRng},
+    Rng, SeedableRng,
+};
+use tracing::warn;
+
+use xmas_tree_common::{
+    color::lerp_angle,
+    geometry::{self, Shape, Surface},
+    metadata::Credit,
+};
+
+use crate::{
+    audio::AudioTrack,
+    media,
+    memo::{self, Memo},
+    neighbours::{spatial_tour, NeighbourGraph},
+    params::{ParamInfo, ParamKind, Params},
+    path::{self, Polyline},
+    rotation::{self, Axis, Matrix, RotationScript},
+    text::{self, TextStrip},
+};
+
+pub use xmas_tree_common::coords::{Bounds, Coord};
+pub type Color = (f32, f32, f32);
+
+#[derive(Clone, Copy)]
+pub struct EffectContext<'a> {
+    pub coords: &'a [Coord],
+    /// The extent of `coords`, worked out once rather than every frame.
+    pub bounds: Bounds,
+    pub frame: usize,
+    pub total_frames: usize,
+    pub fps: f32,
+    pub seed: u64,
+    /// The colors this effect produced for the previous frame (before post filters), if any.
+    pub previous: Option<&'a [Color]>,
+    pub neighbours: &'a NeighbourGraph,
+    /// How visible each LED is from outside the tree, from 0 (hidden in the middle) to 1 (on the
+    /// outside), for keeping details to the LEDs people can see.
+    pub visibility: &'a [f32],
+    pub params: &'a Params,
+    /// The music the sequence plays along to, starting at this effect's first frame.
+    pub audio: Option<&'a AudioTrack>,
+}
+
+pub type EffectFn = fn(&EffectContext, &mut [Color]);
+
+pub trait Effect {
+    /// Writes the color of every LED into `out`. The buffer is reused from frame to frame, so
+    /// every entry must be set.
+    fn render(&mut self, ctx: &EffectContext, out: &mut [Color]);
+
+    /// Whether the effect carries anything from one frame to the next besides the previous
+    /// frame in the context, so a render of it can't be resumed part way through.
+    fn stateful(&self) -> bool {
+        false
+    }
+}
+
+impl Effect for EffectFn {
+    fn render(&mut self, ctx: &EffectContext, out: &mut [Color]) {
+        self(ctx, out)
+    }
+}
+
+/// Who wrote an effect, recorded in the credits of sequences which use it.
+#[derive(Debug, Clone, Default)]
+pub struct Authorship {
+    pub author: Option<String>,
+    pub version: Option<String>,
+}
+
+impl Authorship {
+    /// Effects in `EFFECTS` are credited to this crate.
+    pub fn builtin() -> Self {
+        Self {
+            author: Some("xmas_tree".into()),
+            version: Some(env!("CARGO_PKG_VERSION").into()),
+        }
+    }
+
+    pub fn credit(&self, effect: &str, start_frame: usize, frames: usize) -> Credit {
+        Credit {
+            effect: effect.into(),
+            author: self.author.clone(),
+            version: self.version.clone(),
+            start_frame,
+            frames,
+        }
+    }
+}
+
+/// Works out how many frames one complete cycle of an effect takes.
+pub type CycleFn = fn(coords: &[Coord], params: &Params) -> f32;
+
+pub struct EffectInfo {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub params: &'static [ParamInfo],
+    pub render: EffectFn,
+    /// The natural cycle length, for effects which loop. Sequences which are a whole number
+    /// of cycles long play at the intended speed.
+    pub cycle: Option<CycleFn>,
+    /// Whether the effect reads `ctx.previous`, so has to render its frames one after another.
+    pub uses_previous: bool,
+    /// An `Int` parameter, such as a number of particles, which makes the effect cheaper to
+    /// render when lowered. `live` lowers it when the effect can't keep up.
+    pub detail: Option<&'static str>,
+}
+
+const FALL_DOWN_PARAMS: &[ParamInfo] = &[
+    ParamInfo {
+        name: "layers",
+        kind: ParamKind::Int,
+        range: Some((6.0, 10.0)),
+        default: "8",
+        description: "Number of layers which fall to fill the tree.",
+    },
+    ParamInfo {
+        name: "speed",
+        kind: ParamKind::Float,
+        range: Some((0.1, 0.2)),
+        default: "0.15",
+        description: "How far a layer falls each frame, in coordinate units.",
+    },
+    ParamInfo {
+        name: "pause",
+        kind: ParamKind::Int,
+        range: Some((5.0, 15.0)),
+        default: "10",
+        description: "Frames to wait before dropping each layer.",
+    },
+    ParamInfo {
+        name: "palette",
+        kind: ParamKind::OptionalPalette,
+        range: None,
+        default: "",
+        description: "Colors to use in turn. By default colors are picked around the color wheel.",
+    },
+    ParamInfo {
+        name: "hue-stride",
+        kind: ParamKind::Float,
+        range: Some((0.3, 0.6)),
+        default: "0.45",
+        description:
+            "How far around the color wheel to step between colors when there is no palette.",
+    },
+    SOFTNESS,
+];
+
+const OUTWARD: ParamInfo = ParamInfo {
+    name: "outward",
+    kind: ParamKind::Float,
+    range: None,
+    default: "0.0",
+    description: "How much to keep the picture to the LEDs on the outside of the tree, fading out those hidden inside the branches (0 = all LEDs, 1 = only the outside).",
+};
+
+const SOFTNESS: ParamInfo = ParamInfo {
+    name: "softness",
+    kind: ParamKind::Float,
+    range: None,
+    default: "0.0",
+    description:
+        "Width of the blended band at color boundaries, in coordinate units (0 for hard edges).",
+};
+
+pub static EFFECTS: &[EffectInfo] = &[
+    EffectInfo {
+        name: "barber-pole",
+        description: "Stripes spiralling around the tree.",
+        params: &[
+            ParamInfo {
+                name: "stripes",
+                kind: ParamKind::Int,
+                range: None,
+                default: "1",
+                description: "Number of times the pattern repeats around the tree.",
+            },
+            ParamInfo {
+                name: "pitch",
+                kind: ParamKind::Float,
+                range: Some((3.0, 8.0)),
+                default: "5.0",
+                description: "How tightly the stripes twist, in radians per unit of height.",
+            },
+            ParamInfo {
+                name: "direction",
+                kind: ParamKind::Choice(&["forward", "backward"]),
+                range: None,
+                default: "forward",
+                description: "Which way the stripes rotate.",
+            },
+            ParamInfo {
+                name: "duty",
+                kind: ParamKind::Float,
+                range: Some((0.3, 0.7)),
+                default: "0.5",
+                description: "Fraction of each repeat taken by the first color; the rest share the remainder equally.",
+            },
+            ParamInfo {
+                name: "palette",
+                kind: ParamKind::Palette,
+                range: None,
+                default: "#ff0000,#7f7f7f",
+                description: "The colors of the stripes, in order.",
+            },
+            SOFTNESS,
+        ],
+        render: barber_pole,
+        cycle: Some(|_, _| BARBER_POLE_CYCLE),
+        uses_previous: false,
+        detail: None,
+    },
+    EffectInfo {
+        name: "fill-up",
+        description: "The tree fills up from the bottom with a new color, over and over.",
+        params: &[SOFTNESS],
+        render: fill_up,
+        cycle: Some(|_, _| FILL_UP_CYCLE as f32),
+        uses_previous: false,
+        detail: None,
+    },
+    EffectInfo {
+        name: "snake",
+        description: "Fading snakes of color running along the string of LEDs.",
+        params: &[
+            ParamInfo {
+                name: "count",
+                kind: ParamKind::Int,
+                range: None,
+                default: "1",
+                description: "Number of snakes, spaced evenly along the path.",
+            },
+            ParamInfo {
+                name: "lengths",
+                kind: ParamKind::IntList,
+                range: None,
+                default: "20",
+                description: "Length of each snake in LEDs, repeated if there are fewer lengths than snakes.",
+            },
+            ParamInfo {
+                name: "palette",
+                kind: ParamKind::OptionalPalette,
+                range: None,
+                default: "",
+                description: "Color of each snake. By default the snakes slowly cycle through the color wheel.",
+            },
+            ParamInfo {
+                name: "path",
+                kind: ParamKind::Choice(&["index", "spatial"]),
+                range: None,
+                default: "index",
+                description: "Follow the LEDs in wiring order, or in a tour where each step goes to the nearest unvisited LED.",
+            },
+            ParamInfo {
+                name: "direction",
+                kind: ParamKind::Choice(&["forward", "backward", "both"]),
+                range: None,
+                default: "forward",
+                description: "Which way the snakes move along the path; `both` alternates between snakes.",
+            },
+            ParamInfo {
+                name: "flash",
+                kind: ParamKind::Float,
+                range: None,
+                default: "0.0",
+                description: "How far LEDs flash towards white where snakes overlap (0 disables).",
+            },
+        ],
+        render: snake,
+        cycle: None,
+        uses_previous: false,
+        detail: Some("count"),
+    },
+    EffectInfo {
+        name: "fall-down",
+        description: "Layers of color fall from the top of the tree and stack up.",
+        params: FALL_DOWN_PARAMS,
+        render: fall_down,
+        cycle: Some(fall_down_cycle),
+        uses_previous: false,
+        detail: None,
+    },
+    EffectInfo {
+        name: "fall-down-rainbow",
+        description: "Like fall-down, but every layer is a different color.",
+        params: FALL_DOWN_PARAMS,
+        render: fall_down_rainbow,
+        cycle: Some(fall_down_cycle),
+        uses_previous: false,
+        detail: None,
+    },
+    EffectInfo {
+        name: "accelerate",
+        description: "Horizontal bands of color scrolling along the tree ever faster.",
+        params: &[
+            ParamInfo {
+                name: "acceleration",
+                kind: ParamKind::Float,
+                range: Some((0.00001, 0.00003)),
+                default: "0.00002",
+                description: "Scale of the distance travelled, in coordinate units.",
+            },
+            ParamInfo {
+                name: "exponent",
+                kind: ParamKind::Float,
+                range: Some((2.0, 2.4)),
+                default: "2.2",
+                description: "Distance grows with the frame number raised to this power.",
+            },
+            ParamInfo {
+                name: "max-speed",
+                kind: ParamKind::Float,
+                range: None,
+                default: "0.0",
+                description: "Top speed in coordinate units per frame (0 for no limit).",
+            },
+            ParamInfo {
+                name: "mode",
+                kind: ParamKind::Choice(&["once", "bounce", "loop"]),
+                range: None,
+                default: "once",
+                description: "Speed up for the whole sequence, speed up then slow back down, or repeat that every `period` frames.",
+            },
+            ParamInfo {
+                name: "period",
+                kind: ParamKind::Int,
+                range: None,
+                default: "300",
+                description: "Frames per speed up and slow down in `loop` mode.",
+            },
+            ParamInfo {
+                name: "direction",
+                kind: ParamKind::Choice(&["down", "up"]),
+                range: None,
+                default: "down",
+                description: "Which way the bands move.",
+            },
+        ],
+        render: accelerate,
+        cycle: None,
+        uses_previous: false,
+        detail: None,
+    },
+    EffectInfo {
+        name: "roll-around",
+        description: "The tree is split into colored octants which tumble around.",
+        params: &[
+            ParamInfo {
+            name: "rotation",
+            kind: ParamKind::Script(rotation::validate),
+            range: None,
+            default: "",
+            description: "Looping rotation steps separated by `;`, each `AXIS:DEGREES:FRAMES[:EASING]` or `hold:FRAMES`, e.g. `z:90:30:ease-in-out;hold:30`. By default the tree tumbles between a fixed set of orientations.",
+        },
+            ParamInfo {
+                name: "partition",
+                kind: ParamKind::Choice(&["octants", "sectors", "bands"]),
+                range: None,
+                default: "octants",
+                description: "How the rotating tree is split into colored regions: octants, `parts` angular sectors around the vertical axis, or `parts` horizontal bands.",
+            },
+            ParamInfo {
+                name: "parts",
+                kind: ParamKind::Int,
+                range: None,
+                default: "8",
+                description: "Number of sectors or bands.",
+            },
+            ParamInfo {
+                name: "palette",
+                kind: ParamKind::OptionalPalette,
+                range: None,
+                default: "",
+                description: "Colors of the regions. By default colors are picked around the color wheel.",
+            },
+        ],
+        render: roll_around,
+        cycle: Some(|_, params| match params.script("rotation") {
+            "" => ROLL_AROUND_CYCLE as f32,
+            script => script.parse::<RotationScript>().unwrap().frames(),
+        }),
+        uses_previous: false,
+        detail: None,
+    },
+    EffectInfo {
+        name: "twinkle",
+        description: "LEDs randomly assigned to phases fade in and out in turn.",
+        params: &[ParamInfo {
+            name: "phases",
+            kind: ParamKind::Int,
+            range: Some((3.0, 6.0)),
+            default: "4",
+            description: "Number of groups of LEDs which light up in turn.",
+        }],
+        render: twinkle,
+        cycle: None,
+        uses_previous: false,
+        detail: None,
+    },
+    EffectInfo {
+        name: "sparkle",
+        description: "Random white sparkles which fade out slowly.",
+        params: &[
+            ParamInfo {
+                name: "decay",
+                kind: ParamKind::Float,
+                range: Some((0.8, 0.9)),
+                default: "0.85",
+                description: "Fraction of its brightness a sparkle keeps each frame.",
+            },
+            ParamInfo {
+                name: "chance",
+                kind: ParamKind::Float,
+                range: Some((0.005, 0.02)),
+                default: "0.01",
+                description: "Chance of each LED sparkling on any given frame.",
+            },
+        ],
+        render: sparkle,
+        cycle: None,
+        uses_previous: true,
+        detail: None,
+    },
+    EffectInfo {
+        name: "path-chase",
+        description: "Comets which run along a path through the tree, such as a garland.",
+        params: &[
+            ParamInfo {
+                name: "path",
+                kind: ParamKind::Script(path::validate),
+                range: None,
+                default: "",
+                description: "Points separated by `;`, each `X,Y,Z` or the index of an LED to pass through, e.g. `0;120;0.5,0.5,2`. By default a garland spirals up the tree.",
+            },
+            ParamInfo {
+                name: "comets",
+                kind: ParamKind::Int,
+                range: Some((2.0, 5.0)),
+                default: "3",
+                description: "Number of comets, evenly spaced along the path.",
+            },
+            ParamInfo {
+                name: "speed",
+                kind: ParamKind::Float,
+                range: Some((0.02, 0.05)),
+                default: "0.03",
+                description: "How far the comets move each frame, in coordinate units.",
+            },
+            ParamInfo {
+                name: "tail",
+                kind: ParamKind::Float,
+                range: Some((0.6, 1.4)),
+                default: "1.0",
+                description: "Length of each comet's fading tail, in coordinate units.",
+            },
+            ParamInfo {
+                name: "width",
+                kind: ParamKind::Float,
+                range: Some((0.2, 0.4)),
+                default: "0.3",
+                description: "How far from the path LEDs still light up.",
+            },
+            ParamInfo {
+                name: "palette",
+                kind: ParamKind::Palette,
+                range: None,
+                default: "christmas",
+                description: "Colors of the comets, in turn.",
+            },
+        ],
+        render: path_chase,
+        cycle: Some(|coords, params| {
+            chase_path(coords, params).length() / params.float("speed").max(0.0001)
+        }),
+        uses_previous: false,
+        detail: Some("comets"),
+    },
+    EffectInfo {
+        name: "ornaments",
+        description: "Well spaced LEDs glow like baubles in slowly changing jewel tones over a dim background.",
+        params: &[
+            ParamInfo {
+                name: "spacing",
+                kind: ParamKind::Float,
+                range: Some((0.4, 0.7)),
+                default: "0.5",
+                description: "Minimum distance between ornaments, in coordinate units.",
+            },
+            ParamInfo {
+                name: "depth",
+                kind: ParamKind::Float,
+                range: None,
+                default: "0.3",
+                description: "How far inside the outline of the tree ornaments can hang.",
+            },
+            ParamInfo {
+                name: "palette",
+                kind: ParamKind::Palette,
+                range: None,
+                default: "jewel",
+                description: "Colors the ornaments take in turn.",
+            },
+            ParamInfo {
+                name: "background",
+                kind: ParamKind::Palette,
+                range: None,
+                default: "#003010",
+                description: "Color of every other LED.",
+            },
+            ParamInfo {
+                name: "period",
+                kind: ParamKind::Int,
+                range: Some((200.0, 400.0)),
+                default: "300",
+                description: "Frames each ornament spends on one color before fading to the next.",
+            },
+        ],
+        render: ornaments,
+        cycle: Some(|_, params| {
+            let colors = params.palette("palette").colors.len();
+            (params.int("period").max(1) * colors) as f32
+        }),
+        uses_previous: false,
+        detail: None,
+    },
+    EffectInfo {
+        name: "santa",
+        description: "Tracks a journey around the world as a sweep around the tree, with a bright marker at the current longitude. Feed it live with `live --feed longitude=PATH`.",
+        params: &[
+            ParamInfo {
+                name: "longitude",
+                kind: ParamKind::Float,
+                range: None,
+                default: "180",
+                description: "Current longitude in degrees, east positive.",
+            },
+            ParamInfo {
+                name: "start",
+                kind: ParamKind::Float,
+                range: None,
+                default: "180",
+                description: "Longitude the journey started from. The trail covers everything westward from here to the current longitude.",
+            },
+            ParamInfo {
+                name: "width",
+                kind: ParamKind::Float,
+                range: None,
+                default: "15",
+                description: "Width of the marker, in degrees.",
+            },
+            ParamInfo {
+                name: "palette",
+                kind: ParamKind::Palette,
+                range: None,
+                default: "#ff0000,#ffc020,#001020",
+                description: "Colors of the marker, the trail behind it and the rest of the world.",
+            },
+        ],
+        render: santa,
+        cycle: Some(|_, _| SANTA_PULSE_CYCLE as f32),
+        uses_previous: false,
+        detail: None,
+    },
+    EffectInfo {
+        name: "shells",
+        description: "Bands of color which sink in from the surface of the foliage.",
+        params: &[
+            ParamInfo {
+                name: "shape",
+                kind: ParamKind::Choice(&["cone", "cylinder", "ellipsoid"]),
+                range: None,
+                default: "cone",
+                description: "Shape fitted to the LEDs to find the surface.",
+            },
+            ParamInfo {
+                name: "thickness",
+                kind: ParamKind::Float,
+                range: Some((0.1, 0.25)),
+                default: "0.15",
+                description: "Thickness of each band, in coordinate units.",
+            },
+            ParamInfo {
+                name: "speed",
+                kind: ParamKind::Float,
+                range: Some((0.003, 0.008)),
+                default: "0.005",
+                description: "How far the bands sink each frame, in coordinate units.",
+            },
+            ParamInfo {
+                name: "palette",
+                kind: ParamKind::Palette,
+                range: None,
+                default: "christmas",
+                description: "Colors of the bands, outermost first.",
+            },
+            SOFTNESS,
+        ],
+        render: shells,
+        cycle: Some(|_, params| {
+            let bands = params.palette("palette").colors.len() as f32;
+            params.float("thickness") * bands / params.float("speed").max(0.0001)
+        }),
+        uses_previous: false,
+        detail: None,
+    },
+    EffectInfo {
+        name: "vu-meter",
+        description: "The tree fills from the bottom as the music gets louder, with a falling peak marker. Pass the music with `generate --audio`.",
+        params: &[
+            ParamInfo {
+                name: "band",
+                kind: ParamKind::Choice(&["level", "bass", "mid", "treble"]),
+                range: None,
+                default: "level",
+                description: "Which part of the music drives the meter: overall loudness, or one range of pitches.",
+            },
+            ParamInfo {
+                name: "fall",
+                kind: ParamKind::Float,
+                range: Some((0.01, 0.03)),
+                default: "0.02",
+                description: "How far the peak marker falls each frame, as a fraction of the tree's height.",
+            },
+            ParamInfo {
+                name: "palette",
+                kind: ParamKind::Palette,
+                range: None,
+                default: "#00c000,#ffc020,#ff0000",
+                description: "Colors blended from the bottom of the meter to the top. The last is also the peak marker's.",
+            },
+            SOFTNESS,
+        ],
+        render: vu_meter,
+        cycle: None,
+        uses_previous: false,
+        detail: None,
+    },
+    EffectInfo {
+        name: "beat-pulse",
+        description: "The tree pulses in a new color on every beat, glowing with the bass in between. Pass the music with `generate --audio`.",
+        params: &[
+            ParamInfo {
+                name: "shape",
+                kind: ParamKind::Choice(&["flash", "ripple"]),
+                range: None,
+                default: "ripple",
+                description: "Light the whole tree at once, or send a shell of light out from the base of the trunk.",
+            },
+            ParamInfo {
+                name: "decay",
+                kind: ParamKind::Float,
+                range: Some((0.3, 0.6)),
+                default: "0.4",
+                description: "Seconds for each pulse to fade out.",
+            },
+            ParamInfo {
+                name: "glow",
+                kind: ParamKind::Float,
+                range: Some((0.1, 0.3)),
+                default: "0.2",
+                description: "Brightness of the glow which follows the bass between beats.",
+            },
+            ParamInfo {
+                name: "palette",
+                kind: ParamKind::Palette,
+                range: None,
+                default: "christmas",
+                description: "Colors the beats take in turn.",
+            },
+        ],
+        render: beat_pulse,
+        cycle: None,
+        uses_previous: false,
+        detail: None,
+    },
+    EffectInfo {
+        name: "spectrum-spiral",
+        description: "A spinning spiral up the tree showing the spectrum of the music, bass at the bottom. Pass the music with `generate --audio`.",
+        params: &[
+            ParamInfo {
+                name: "turns",
+                kind: ParamKind::Float,
+                range: Some((2.0, 4.0)),
+                default: "3.0",
+                description: "Number of times the spiral winds around the tree.",
+            },
+            ParamInfo {
+                name: "spin",
+                kind: ParamKind::Float,
+                range: Some((0.01, 0.03)),
+                default: "0.02",
+                description: "How far the spiral turns each frame, in radians.",
+            },
+            ParamInfo {
+                name: "palette",
+                kind: ParamKind::OptionalPalette,
+                range: None,
+                default: "",
+                description: "Colors blended from the lowest pitches to the highest. By default the spectrum runs around the color wheel.",
+            },
+            ParamInfo {
+                name: "flash",
+                kind: ParamKind::Float,
+                range: None,
+                default: "0.3",
+                description: "How far lit LEDs flash towards white on each beat (0 disables).",
+            },
+        ],
+        render: spectrum_spiral,
+        cycle: None,
+        uses_previous: false,
+        detail: None,
+    },
+    EffectInfo {
+        name: "scroll-text",
+        description: "A message scrolls around the tree, in any language the built in fonts cover, including Chinese, Japanese and Korean. Right-to-left text scrolls the other way.",
+        params: &[
+            ParamInfo {
+                name: "text",
+                kind: ParamKind::Script(text::validate),
+                range: None,
+                default: "Merry Christmas",
+                description: "The message, or `@PATH` to read it from a file.",
+            },
+            ParamInfo {
+                name: "height",
+                kind: ParamKind::Float,
+                range: Some((0.2, 0.35)),
+                default: "0.25",
+                description: "Height of the letters, as a fraction of the tree's height.",
+            },
+            ParamInfo {
+                name: "position",
+                kind: ParamKind::Float,
+                range: Some((0.4, 0.6)),
+                default: "0.5",
+                description: "How far up the tree the middle of the text is, from 0 at the bottom to 1 at the top.",
+            },
+            ParamInfo {
+                name: "speed",
+                kind: ParamKind::Float,
+                range: Some((0.015, 0.03)),
+                default: "0.02",
+                description: "How far the text moves each frame, in coordinate units.",
+            },
+            ParamInfo {
+                name: "palette",
+                kind: ParamKind::Palette,
+                range: None,
+                default: "christmas",
+                description: "Colors of the letters, in turn.",
+            },
+            OUTWARD,
+        ],
+        render: scroll_text,
+        cycle: Some(|coords, params| {
+            let layout = TextLayout::new(coords, Bounds::of(coords), params);
+            layout.period * layout.pixel / params.float("speed").max(0.0001)
+        }),
+        uses_previous: false,
+        detail: None,
+    },
+    EffectInfo {
+        name: "text-marquee",
+        description: "A message scrolls across the tree like a marquee sign, flat as seen from the front rather than wrapped around, so it reads from one viewpoint. Right-to-left text scrolls the other way.",
+        params: &[
+            ParamInfo {
+                name: "text",
+                kind: ParamKind::Script(text::validate),
+                range: None,
+                default: "Merry Christmas",
+                description: "The message, or `@PATH` to read it from a file.",
+            },
+            ParamInfo {
+                name: "height",
+                kind: ParamKind::Float,
+                range: Some((0.25, 0.4)),
+                default: "0.3",
+                description: "Height of the letters, as a fraction of the tree's height.",
+            },
+            ParamInfo {
+                name: "position",
+                kind: ParamKind::Float,
+                range: Some((0.35, 0.5)),
+                default: "0.4",
+                description: "How far up the tree the middle of the text is, from 0 at the bottom to 1 at the top.",
+            },
+            ParamInfo {
+                name: "speed",
+                kind: ParamKind::Float,
+                range: Some((0.015, 0.03)),
+                default: "0.02",
+                description: "How far the text moves each frame, in coordinate units.",
+            },
+            ParamInfo {
+                name: "palette",
+                kind: ParamKind::Palette,
+                range: None,
+                default: "christmas",
+                description: "Colors of the letters, in turn.",
+            },
+            OUTWARD,
+        ],
+        render: text_marquee,
+        cycle: Some(|coords, params| {
+            let bounds = Bounds::of(coords);
+            let layout = TextLayout::new(coords, bounds, params);
+            let width = (bounds.max.0 - bounds.min.0) / layout.pixel;
+            (layout.strip.width as f32 + width) * layout.pixel / params.float("speed").max(0.0001)
+        }),
+        uses_previous: false,
+        detail: None,
+    },
+    EffectInfo {
+        name: "image-wrap",
+        description: "A picture wrapped around the tree like a label around a can, turning slowly.",
+        params: &[
+            ParamInfo {
+                name: "image",
+                kind: ParamKind::Path(media::validate_image),
+                range: None,
+                default: "",
+                description: "PNG to wrap around the tree, its left and right edges meeting at the back and its top at the top of the tree. Required.",
+            },
+            ParamInfo {
+                name: "speed",
+                kind: ParamKind::Float,
+                range: Some((0.003, 0.01)),
+                default: "0.005",
+                description: "How far the picture turns each frame, in turns. Negative turns it the other way.",
+            },
+            OUTWARD,
+        ],
+        render: image_wrap,
+        cycle: Some(|_, params| 1.0 / params.float("speed").abs().max(0.0001)),
+        uses_previous: false,
+        detail: None,
+    },
+    EffectInfo {
+        name: "video",
+        description: "Plays a sequence of images on the tree, one after another.",
+        params: &[
+            ParamInfo {
+                name: "frames",
+                kind: ParamKind::Path(media::validate_frames),
+                range: None,
+                default: "",
+                description: "Directory of PNG frames, played in order of their names, e.g. from `ffmpeg -i clip.mp4 -vf scale=96:-1 DIR/%05d.png`. Required.",
+            },
+            ParamInfo {
+                name: "step",
+                kind: ParamKind::Float,
+                range: None,
+                default: "1",
+                description: "How many frames of the video play for each frame of the sequence, e.g. 0.5 for a video at half the sequence's frame rate.",
+            },
+            ParamInfo {
+                name: "mapping",
+                kind: ParamKind::Choice(&["planar", "cylindrical"]),
+                range: None,
+                default: "planar",
+                description: "`planar` projects the video onto the tree from the front, and `cylindrical` wraps it around.",
+            },
+        ],
+        render: video,
+        cycle: Some(|_, params| match media::frames(params.path("frames")) {
+            Ok(frames) => frames.len() as f32 / params.float("step").max(0.0001),
+            Err(_) => 1.0,
+        }),
+        uses_previous: false,
+        detail: None,
+    },
+    EffectInfo {
+        name: "idle",
+        description: "A few dim, warm twinkles drifting slowly about the tree, for leaving on overnight. It never repeats, so run it live with a long `--len`, e.g. 1000000 (about eight hours).",
+        params: &[
+            ParamInfo {
+                name: "count",
+                kind: ParamKind::Int,
+                range: Some((4.0, 10.0)),
+                default: "6",
+                description: "Number of twinkles lit at once.",
+            },
+            ParamInfo {
+                name: "level",
+                kind: ParamKind::Float,
+                range: Some((0.25, 0.45)),
+                default: "0.35",
+                description: "Brightness of a twinkle at its brightest, from 0 to 1 before gamma correction.",
+            },
+            ParamInfo {
+                name: "day",
+                kind: ParamKind::Int,
+                range: None,
+                default: "0",
+                description: "Seeds the variation, so each night looks different. 0 uses the date the evening started on.",
+            },
+        ],
+        render: idle,
+        cycle: None,
+        uses_previous: false,
+        detail: Some("count"),
+    },
+];
+
+pub fn lookup(name: &str) -> Option<&'static EffectInfo> {
+    EFFECTS.iter().find(|info| info.name == name)
+}
+
+const BARBER_POLE_SPEED: f32 = 0.05;
+const BARBER_POLE_CYCLE: f32 = PI * 2.0 / BARBER_POLE_SPEED;
+
+pub fn barber_pole(ctx: &EffectContext, out: &mut [Color]) {
+    let (coords, frame, total_frames) = (ctx.coords, ctx.frame, ctx.total_frames);
+    let complete_cycles = (total_frames as f32 / BARBER_POLE_CYCLE).floor();
+    let actual_speed = (complete_cycles * PI * 2.0) / (total_frames as f32);
+    let offset = match ctx.params.choice("direction") {
+        "backward" => -(frame as f32) * actual_speed,
+        _ => frame as f32 * actual_speed,
+    };
+    let stripes = ctx.params.int("stripes").max(1) as f32;
+    let pitch = ctx.params.float("pitch");
+    let duty = ctx.params.float("duty").clamp(0.0, 1.0);
+    let palette = ctx.params.palette("palette");
+    let softness = ctx.params.float("softness");
+    let colors = &palette.colors;
+    let others = colors.len() - 1;
+    // Where each color's band starts within one repeat of the pattern
+    let starts: Vec<f32> = (0..colors.len())
+        .map(|i| match i {
+            0 => 0.0,
+            _ => duty + (1.0 - duty) * (i - 1) as f32 / others as f32,
+        })
+        .collect();
+    fill_each(out, coords, |(x, y, z)| {
+        let angle = f32::atan2(x, y) * stripes + z * pitch + offset;
+        // Position within one repeat of the pattern, from 0 to 1
+        let t = angle.rem_euclid(PI * 2.0) / (PI * 2.0);
+        let band = starts.iter().rposition(|&start| start <= t).unwrap_or(0);
+        let start = starts[band];
+        let end = starts.get(band + 1).copied().unwrap_or(1.0);
+        // Convert the softness from distance into a fraction of the pattern, using how
+        // quickly the angle changes around this LED
+        let radius = (x * x + y * y).sqrt().max(0.01);
+        let gradient = ((stripes / radius).powi(2) + pitch * pitch).sqrt();
+        let half_width = softness * gradient / (PI * 4.0);
+        let next = |i: usize| colors[(i + 1) % colors.len()];
+        if t - start < end - t {
+            let prev = colors[(band + others) % colors.len()];
+            mix(prev, colors[band], soft_step(t, start, half_width))
+        } else {
+            mix(colors[band], next(band), soft_step(t, end, half_width))
+        }
+    });
+}
+
+/// Goes smoothly from 0 to 1 as `x` goes from `edge0` to `edge1`.
+pub fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    if edge0 >= edge1 {
+        return if x < edge0 { 0.0 } else { 1.0 };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// How far `x` is above `threshold`, fading from 0 to 1 over a band `2 * half_width` wide
+/// centred on the threshold. Use this instead of a hard comparison so LEDs don't pop across
+/// moving boundaries.
+pub fn soft_step(x: f32, threshold: f32, half_width: f32) -> f32 {
+    smoothstep(threshold - half_width, threshold + half_width, x)
+}
+
+/// Sets every LED's color from its position.
+fn fill_each(out: &mut [Color], coords: &[Coord], mut color: impl FnMut(Coord) -> Color) {
+    for (out, &coord) in out.iter_mut().zip(coords) {
+        *out = color(coord);
+    }
+}
+
+/// Fades each LED by how hidden it is inside the tree, as much as the `outward` param asks.
+fn keep_outward(ctx: &EffectContext, out: &mut [Color]) {
+    let outward = ctx.params.float("outward").clamp(0.0, 1.0);
+    if outward > 0.0 {
+        for (color, &visibility) in out.iter_mut().zip(ctx.visibility) {
+            *color = scale(*color, lerp(1.0, visibility, outward));
+        }
+    }
+}
+
+pub fn mix(a: Color, b: Color, t: f32) -> Color {
+    (lerp(a.0, b.0, t), lerp(a.1, b.1, t), lerp(a.2, b.2, t))
+}
+
+fn scale(color: Color, f: f32) -> Color {
+    (color.0 * f, color.1 * f, color.2 * f)
+}
+
+pub fn saturated_color(hue: f32) -> (f32, f32, f32) {
+    let r = hue.rem_euclid(1.0) * 6.0;
+    if r < 1.0 {
+        (1.0, r, 0.0)
+    } else if r < 2.0 {
+        (2.0 - r, 1.0, 0.0)
+    } else if r < 3.0 {
+        (0.0, 1.0, r - 2.0)
+    } else if r < 4.0 {
+        (0.0, 4.0 - r, 1.0)
+    } else if r < 5.0 {
+        (r - 4.0, 0.0, 1.0)
+    } else {
+        (1.0, 0.0, 6.0 - r)
+    }
+}
+
+const FILL_UP_CYCLE: usize = 60;
+
+pub fn fill_up(ctx: &EffectContext, out: &mut [Color]) {
+    let (coords, frame, total_frames) = (ctx.coords, ctx.frame, ctx.total_frames);
+    // Sequences shorter than a cycle still fill once, just faster
+    let complete_fills = (total_frames / FILL_UP_CYCLE).max(1);
+    let frames_per_fill = total_frames / complete_fills;
+
+    let color_seed0 = (frame * complete_fills) / total_frames;
+    let color_seed1 = (color_seed0 + 1) % complete_fills;
+    let color0 = saturated_color(color_seed0 as f32 * 0.45);
+    let color1 = saturated_color(color_seed1 as f32 * 0.45);
+    let max_height = ctx.bounds.max.2;
+    let base_frame = (color_seed0 * total_frames) / complete_fills;
+    let height = (frame - base_frame) as f32 * max_height / (frames_per_fill as f32);
+    let half_width = ctx.params.float("softness") * 0.5;
+    // Measured from the LED, so that LEDs exactly at the fill height are not yet filled
+    fill_each(out, coords, |coord| {
+        mix(color0, color1, soft_step(height, coord.2, half_width))
+    });
+}
+
+pub fn snake(ctx: &EffectContext, out: &mut [Color]) {
+    let (coords, frame) = (ctx.coords, ctx.frame);
+    let led_count = coords.len();
+    let count = ctx.params.int("count").max(1);
+    let lengths = ctx.params.int_list("lengths");
+    let palette = ctx.params.optional_palette("palette");
+    let flash = ctx.params.float("flash");
+    let direction = ctx.params.choice("direction");
+    static SPATIAL_TOURS: Memo<u64, Vec<usize>> = Memo::new();
+    let path = match ctx.params.choice("path") {
+        "spatial" => SPATIAL_TOURS.get(memo::coords_key(coords), || spatial_tour(coords)),
+        _ => Arc::new((0..led_count).collect()),
+    };
+
+    let colors = out;
+    colors.fill((0.0, 0.0, 0.0));
+    let mut overlaps = vec![0; led_count];
+    for s in 0..count {
+        let snake_len = lengths[s % lengths.len()];
+        let color = match &palette {
+            Some(palette) => palette.cycle(s),
+            None => saturated_color(frame as f32 / 60.0 + s as f32 / count as f32),
+        };
+        let backward = match direction {
+            "backward" => true,
+            "both" => s % 2 == 1,
+            _ => false,
+        };
+        let offset = s * led_count / count;
+        for (i, &led) in path.iter().enumerate() {
+            let i = if backward { led_count - 1 - i } else { i };
+            let index = (i + frame + offset) % led_count;
+            if index < snake_len {
+                let f = 1.0 - index as f32 / (snake_len as f32);
+                let c = &mut colors[led];
+                *c = (c.0 + color.0 * f, c.1 + color.1 * f, c.2 + color.2 * f);
+                overlaps[led] += 1;
+            }
+        }
+    }
+    if flash > 0.0 {
+        for (color, &overlap) in colors.iter_mut().zip(&overlaps) {
+            if overlap > 1 {
+                *color = mix(*color, (1.0, 1.0, 1.0), flash);
+            }
+        }
+    }
+}
+
+fn fall_down_cycle(coords: &[Coord], params: &Params) -> f32 {
+    let max_height = Bounds::of(coords).max.2;
+    let num_layers = params.int("layers").max(1) as f32;
+    let layer_height = max_height / num_layers;
+    let total_dist = (max_height + layer_height) * num_layers * 0.5 + max_height;
+    (params.int("pause") as f32) * (num_layers + 1.0)
+        + total_dist / params.float("speed").max(0.001)
+}
+
+/// Which colors the layers of a fall-down effect get.
+#[derive(Clone, Copy, PartialEq)]
+enum LayerColors {
+    /// Every layer in a cycle shares one color.
+    PerCycle,
+    /// Every layer gets its own color.
+    PerLayer,
+}
+
+pub fn fall_down(ctx: &EffectContext, out: &mut [Color]) {
+    fall_down_with(ctx, LayerColors::PerCycle, out)
+}
+
+pub fn fall_down_rainbow(ctx: &EffectContext, out: &mut [Color]) {
+    fall_down_with(ctx, LayerColors::PerLayer, out)
+}
+
+fn fall_down_with(ctx: &EffectContext, layer_colors: LayerColors, out: &mut [Color]) {
+    let (coords, frame, total_frames) = (ctx.coords, ctx.frame, ctx.total_frames);
+    let max_height = ctx.bounds.max.2;
+    let num_layers = ctx.params.int("layers").max(1);
+    let layer_height = max_height / (num_layers as f32);
+    let fall_speed = ctx.params.float("speed").max(0.001);
+    let pause_frames = ctx.params.int("pause");
+    let frames_per_cycle = fall_down_cycle(coords, ctx.params);
+    let total_cycles = (total_frames as f32 / frames_per_cycle).floor();
+    let actual_frames_per_cycle = total_frames as f32 / total_cycles;
+    let scaling_factor = frames_per_cycle / actual_frames_per_cycle;
+    let mut scaled_frame = (frame as f32) * scaling_factor;
+    let cycle = (scaled_frame / frames_per_cycle).floor();
+    let palette = ctx.params.optional_palette("palette");
+    let hue_stride = ctx.params.float("hue-stride");
+    let colors: Vec<_> = (0..num_layers)
+        .map(|layer| {
+            let index = match layer_colors {
+                LayerColors::PerCycle => cycle as usize,
+                LayerColors::PerLayer => layer + num_layers * cycle as usize,
+            };
+            match &palette {
+                Some(palette) => palette.cycle(index),
+                None => saturated_color(index as f32 * hue_stride),
+            }
+        })
+        .collect();
+    scaled_frame %= frames_per_cycle;
+
+    let mut base_level = 0.0;
+    let mut layer_level_min = 0.0;
+    let mut layer_level_max = 0.0;
+    let mut current_layer = 0;
+    for layer in 0..num_layers {
+        let num_frames =
+            (max_height - layer_height * (layer as f32)) / fall_speed + (pause_frames as f32);
+        if scaled_frame < num_frames {
+            current_layer = layer;
+            layer_level_min = (max_height - scaled_frame * fall_speed).max(base_level);
+            layer_level_max = layer_level_min + layer_height;
+            scaled_frame = 0.0;
+            break;
+        } else {
+            scaled_frame -= num_frames;
+            base_level += layer_height;
+        }
+    }
+    base_level -= scaled_frame * fall_speed;
+
+    let half_width = ctx.params.float("softness") * 0.5;
+    fill_each(out, coords, |coord| {
+        let below = 1.0 - soft_step(coord.2, base_level, half_width);
+        let in_layer = soft_step(coord.2, layer_level_min, half_width)
+            * (1.0 - soft_step(coord.2, layer_level_max, half_width));
+        let stacked = colors[((coord.2 / layer_height) as usize).min(num_layers - 1)];
+        mix(scale(colors[current_layer], in_layer), stacked, below)
+    });
+}
+
+pub fn accelerate(ctx: &EffectContext, out: &mut [Color]) {
+    let (coords, frame, total_frames) = (ctx.coords, ctx.frame, ctx.total_frames);
+    let acceleration = ctx.params.float("acceleration");
+    let exponent = ctx.params.float("exponent");
+    let max_speed = ctx.params.float("max-speed");
+    // Frame at which the speed reaches its limit, after which the bands move at a constant speed
+    let limit_frame = if max_speed > 0.0 && exponent > 1.0 {
+        (max_speed / (acceleration * exponent)).powf(1.0 / (exponent - 1.0))
+    } else {
+        f32::INFINITY
+    };
+    let dist = |f: f32| {
+        if f < limit_frame {
+            acceleration * f.powf(exponent)
+        } else {
+            acceleration * limit_frame.powf(exponent) + max_speed * (f - limit_frame)
+        }
+    };
+    let period = match ctx.params.choice("mode") {
+        "bounce" => total_frames,
+        "loop" => ctx.params.int("period").max(2),
+        _ => 0,
+    };
+    let mut base_dist = if period == 0 {
+        dist(frame as f32)
+    } else {
+        // Mirror the second half of each period so the speed comes back down smoothly
+        let half = period as f32 / 2.0;
+        let cycle = (frame / period) as f32;
+        let f = (frame % period) as f32;
+        let within = if f <= half {
+            dist(f)
+        } else {
+            2.0 * dist(half) - dist(period as f32 - f)
+        };
+        cycle * 2.0 * dist(half) + within
+    };
+    if ctx.params.choice("direction") == "up" {
+        base_dist = -base_dist;
+    }
+    let max_height = ctx.bounds.max.2;
+    let level_height = max_height / 4.0;
+    let double_height = level_height * 2.0;
+
+    fill_each(out, coords, |coord| {
+        let dist = base_dist + coord.2;
+        let color_index = (dist / double_height).floor();
+        if dist.rem_euclid(double_height) < level_height {
+            saturated_color(color_index * 0.45)
+        } else {
+            (0.0, 0.0, 0.0)
+        }
+    });
+}
+
+fn lerp(a: f32, b: f32, c: f32) -> f32 {
+    a * (1.0 - c) + b * c
+}
+
+const ROLL_AROUND_ROTATION: usize = 60;
+const ROLL_AROUND_CYCLE: usize = ROLL_AROUND_ROTATION * 8;
+
+pub fn roll_around(ctx: &EffectContext, out: &mut [Color]) {
+    let (coords, frame) = (ctx.coords, ctx.frame);
+    let orientation = match ctx.params.script("rotation") {
+        "" => default_roll(frame, ctx.total_frames),
+        script => script
+            .parse::<RotationScript>()
+            .unwrap()
+            .orientation(frame as f32),
+    };
+    let max_height = ctx.bounds.max.2;
+    let z_offset = max_height / 2.0;
+    let centred = |coord: Coord| (coord.0, coord.1, coord.2 - z_offset);
+    let radius = coords
+        .iter()
+        .map(|&coord| {
+            let c = centred(coord);
+            (c.0 * c.0 + c.1 * c.1 + c.2 * c.2).sqrt()
+        })
+        .fold(f32::EPSILON, f32::max);
+    let parts = ctx.params.int("parts").max(1);
+    let partition = ctx.params.choice("partition");
+    let palette = ctx.params.optional_palette("palette");
+
+    fill_each(out, coords, |coord| {
+        let coord = rotation::transform(&orientation, centred(coord));
+        let region = match partition {
+            "sectors" => {
+                let angle = coord.1.atan2(coord.0).rem_euclid(PI * 2.0);
+                ((angle / (PI * 2.0) * parts as f32) as usize).min(parts - 1)
+            }
+            "bands" => {
+                let height = (coord.2 + radius) / (radius * 2.0);
+                ((height * parts as f32) as usize).min(parts - 1)
+            }
+            _ => {
+                let mut octant = 0;
+                if coord.0 > 0.0 {
+                    octant += 1;
+                }
+                if coord.1 > 0.0 {
+                    octant += 2;
+                }
+                if coord.2 > 0.0 {
+                    octant += 4;
+                }
+                octant
+            }
+        };
+        match &palette {
+            Some(palette) => palette.cycle(region),
+            None => saturated_color(region as f32 * 0.45),
+        }
+    });
+}
+
+/// Tumbles between a fixed set of orientations, turning about the z and x axes at once.
+fn default_roll(frame: usize, total_frames: usize) -> Matrix {
+    let frames_per_rotation = ROLL_AROUND_ROTATION;
+    let frames_per_cycle = ROLL_AROUND_CYCLE;
+    let num_cycles = total_frames / frames_per_cycle;
+    let actual_frames = total_frames / num_cycles;
+    let scaling_factor = actual_frames as f32 / (total_frames as f32);
+    let scaled_frame = (frame as f32 * scaling_factor) % (frames_per_cycle as f32);
+    let rotation_progress = scaled_frame / (frames_per_rotation as f32);
+    let rotation_index = rotation_progress as usize;
+    let lerp_factor = (rotation_progress.fract() * 2.0).min(1.0);
+    let angle_values = [PI * 0.5, PI * 0.5, 0.0, 0.0, PI * -0.5, PI * -0.5, 0.0, 0.0];
+    let z_angle_start = angle_values[rotation_index];
+    let z_angle_end = angle_values[(rotation_index + 1) % 8];
+    let x_angle_start = z_angle_end;
+    let x_angle_end = angle_values[(rotation_index + 2) % 8];
+    let z_angle = lerp_angle(z_angle_start, z_angle_end, lerp_factor);
+    let x_angle = lerp_angle(x_angle_start, x_angle_end, lerp_factor);
+    rotation::multiply(
+        &rotation::rotation(Axis::X, x_angle),
+        &rotation::rotation(Axis::Z, z_angle),
+    )
+}
+
+pub fn twinkle(ctx: &EffectContext, out: &mut [Color]) {
+    let (coords, frame, total_frames) = (ctx.coords, ctx.frame, ctx.total_frames);
+    let num_phases = ctx.params.int("phases").max(1);
+    let mut phases: Vec<_> = (0..coords.len()).map(|i| i % num_phases).collect();
+    let mut rng = StdRng::seed_from_u64(ctx.seed);
+    phases.shuffle(&mut rng);
+
+    let angle = frame as f32 * PI * 6.0 / (total_frames as f32);
+
+    for (color, phase) in out.iter_mut().zip(phases) {
+        let phase_color = saturated_color(phase as f32 * 0.3);
+        let phase_angle = (phase as f32 * PI * 2.0 / (num_phases as f32)) - angle;
+        let brightness = phase_angle.sin().max(0.0);
+        *color = (
+            phase_color.0 * brightness,
+            phase_color.1 * brightness,
+            phase_color.2 * brightness,
+        );
+    }
+}
+
+pub fn sparkle(ctx: &EffectContext, out: &mut [Color]) {
+    let decay = ctx.params.float("decay");
+    let sparkle_chance = f64::from(ctx.params.float("chance").clamp(0.0, 1.0));
+    let mut rng = StdRng::seed_from_u64(ctx.seed ^ ctx.frame as u64);
+    for (i, color) in out.iter_mut().enumerate() {
+        *color = if rng.gen_bool(sparkle_chance) {
+            (1.0, 1.0, 1.0)
+        } else {
+            let prev = ctx.previous.map_or((0.0, 0.0, 0.0), |frame| frame[i]);
+            (prev.0 * decay, prev.1 * decay, prev.2 * decay * 0.9)
+        };
+    }
+}
+
+/// Turns the default garland spirals around the tree.
+const GARLAND_TURNS: f32 = 5.0;
+
+/// The path the comets follow, built the first time it's needed for these coordinates.
+fn chase_path(coords: &[Coord], params: &Params) -> Arc<Polyline> {
+    static PATHS: Memo<(u64, String), Polyline> = Memo::new();
+    let script = params.script("path");
+    let key = (memo::coords_key(coords), script.to_string());
+    PATHS.get(key, || match script {
+        "" => Polyline::spiral(coords, GARLAND_TURNS),
+        script => Polyline::from_script(&script.parse().unwrap(), coords),
+    })
+}
+
+pub fn path_chase(ctx: &EffectContext, out: &mut [Color]) {
+    let path = chase_path(ctx.coords, ctx.params);
+    let length = path.length();
+    if length <= 0.0 {
+        out.fill((0.0, 0.0, 0.0));
+        return;
+    }
+    let comets = ctx.params.int("comets").max(1);
+    let spacing = length / comets as f32;
+    let head = ctx.frame as f32 * ctx.params.float("speed");
+    let tail = ctx.params.float("tail").max(0.001);
+    let width = ctx.params.float("width").max(0.001);
+    let palette = ctx.params.palette("palette");
+    fill_each(out, ctx.coords, |coord| {
+        let (along, away) = path.project(coord);
+        if away >= width {
+            return (0.0, 0.0, 0.0);
+        }
+        // How far behind the nearest comet ahead of this point it is
+        let behind = (head - along).rem_euclid(spacing);
+        if behind >= tail {
+            return (0.0, 0.0, 0.0);
+        }
+        let comet = ((head - along - behind) / spacing).round() as i64;
+        let color = palette.cycle(comet.rem_euclid(comets as i64) as usize);
+        let brightness = (1.0 - behind / tail) * (1.0 - smoothstep(0.0, width, away));
+        scale(color, brightness)
+    });
+}
+
+/// Picks a random but repeatable set of LEDs near the surface of the tree, no two closer than
+/// `spacing`, by trying every LED in a shuffled order.
+fn place_ornaments(ctx: &EffectContext, spacing: f32, depth: f32) -> Vec<usize> {
+    let surface = geometry::Cone::fit(ctx.coords);
+    let mut candidates: Vec<usize> = (0..ctx.coords.len())
+        .filter(|&i| surface.depth(ctx.coords[i]) <= depth)
+        .collect();
+    candidates.shuffle(&mut StdRng::seed_from_u64(ctx.seed));
+    let mut placed: Vec<usize> = Vec::new();
+    for i in candidates {
+        let (x, y, z) = ctx.coords[i];
+        let clear = placed.iter().all(|&j| {
+            let (a, b, c) = ctx.coords[j];
+            (x - a).powi(2) + (y - b).powi(2) + (z - c).powi(2) >= spacing * spacing
+        });
+        if clear {
+            placed.push(i);
+        }
+    }
+    placed
+}
+
+pub fn ornaments(ctx: &EffectContext, out: &mut [Color]) {
+    static PLACEMENTS: Memo<(u64, u64, u32, u32), Vec<usize>> = Memo::new();
+    let (spacing, depth) = (ctx.params.float("spacing"), ctx.params.float("depth"));
+    let key = (
+        memo::coords_key(ctx.coords),
+        ctx.seed,
+        spacing.to_bits(),
+        depth.to_bits(),
+    );
+    let placed = PLACEMENTS.get(key, || place_ornaments(ctx, spacing, depth));
+    let palette = ctx.params.palette("palette");
+    let background = ctx.params.palette("background").cycle(0);
+    let period = ctx.params.int("period").max(1);
+    let step = ctx.frame / period;
+    // Hold each color, then fade to the next over the last quarter of the period
+    let fade = smoothstep(0.75, 1.0, (ctx.frame % period) as f32 / period as f32);
+    out.fill(background);
+    for (n, &i) in placed.iter().enumerate() {
+        out[i] = palette.lerp(n + step, fade);
+    }
+}
+
+/// Frames per pulse of the santa marker.
+const SANTA_PULSE_CYCLE: usize = 30;
+
+pub fn santa(ctx: &EffectContext, out: &mut [Color]) {
+    let longitude = ctx.params.float("longitude");
+    let start = ctx.params.float("start");
+    let half_width = ctx.params.float("width").max(0.1) / 2.0;
+    let palette = ctx.params.palette("palette");
+    let (marker, trail, ahead) = (palette.cycle(0), palette.cycle(1), palette.cycle(2));
+    // Degrees travelled westward from the start
+    let travelled = (start - longitude).rem_euclid(360.0);
+    // The marker pulses gently so it stands out from the trail
+    let pulse = 0.75 + 0.25 * (ctx.frame as f32 * PI * 2.0 / SANTA_PULSE_CYCLE as f32).sin();
+    fill_each(out, ctx.coords, |(x, y, _)| {
+        let led_longitude = f32::atan2(y, x).to_degrees();
+        let behind = (start - led_longitude).rem_euclid(360.0);
+        let base = if behind <= travelled { trail } else { ahead };
+        let offset = (led_longitude - longitude + 180.0).rem_euclid(360.0) - 180.0;
+        let closeness = 1.0 - smoothstep(0.0, half_width, offset.abs());
+        mix(base, scale(marker, pulse), closeness)
+    });
+}
+
+pub fn shells(ctx: &EffectContext, out: &mut [Color]) {
+    let shape = ctx.params.choice("shape").parse().unwrap();
+    static SURFACES: Memo<(u64, Shape), Box<dyn Surface>> = Memo::new();
+    let surface = SURFACES.get((memo::coords_key(ctx.coords), shape), || {
+        geometry::fit(shape, ctx.coords)
+    });
+    let thickness = ctx.params.float("thickness").max(0.001);
+    let offset = ctx.frame as f32 * ctx.params.float("speed");
+    let palette = ctx.params.palette("palette");
+    let half_width = ctx.params.float("softness") / 2.0;
+    fill_each(out, ctx.coords, |coord| {
+        // LEDs poking out of the surface are treated as being on it
+        let depth = surface.depth(coord).max(0.0) - offset;
+        let band = (depth / thickness).floor();
+        let color =
+            |band: f32| palette.cycle(band.rem_euclid(palette.colors.len() as f32) as usize);
+        let start = band * thickness;
+        if depth - start < thickness / 2.0 {
+            mix(
+                color(band - 1.0),
+                color(band),
+                soft_step(depth, start, half_width),
+            )
+        } else {
+            let end = start + thickness;
+            mix(
+                color(band),
+                color(band + 1.0),
+                soft_step(depth, end, half_width),
+            )
+        }
+    });
+}
+
+/// Half the height of the vu-meter's peak marker, as a fraction of the tree's height.
+const VU_PEAK_WIDTH: f32 = 0.04;
+
+pub fn vu_meter(ctx: &EffectContext, out: &mut [Color]) {
+    let audio = match ctx.audio {
+        Some(audio) => audio,
+        None => return out.fill((0.0, 0.0, 0.0)),
+    };
+    let band = ctx.params.choice("band");
+    let value = |frame: usize| {
+        let audio = audio.at(frame);
+        match band {
+            "bass" => audio.bass,
+            "mid" => audio.mid,
+            "treble" => audio.treble,
+            _ => audio.level(),
+        }
+    };
+    let level = value(ctx.frame);
+    // The marker falls steadily from the highest recent level, until the level catches it up
+    let fall = ctx.params.float("fall").max(0.001);
+    let peak = (0..=((1.0 / fall).ceil() as usize).min(ctx.frame))
+        .map(|age| value(ctx.frame - age) - age as f32 * fall)
+        .fold(level, f32::max);
+    let palette = ctx.params.palette("palette");
+    let marker = palette.cycle(palette.colors.len() - 1);
+    let max_height = ctx.bounds.max.2.max(f32::EPSILON);
+    let half_width = ctx.params.float("softness") * 0.5 / max_height;
+    fill_each(out, ctx.coords, |coord| {
+        let height = coord.2 / max_height;
+        let lit = scale(
+            palette.gradient(height),
+            soft_step(level, height, half_width),
+        );
+        if peak <= 0.0 {
+            return lit;
+        }
+        let closeness = 1.0 - smoothstep(0.0, VU_PEAK_WIDTH, (height - peak).abs());
+        mix(lit, marker, closeness)
+    });
+}
+
+/// Thickness of the shell of light sent out by the beat-pulse ripple, in coordinate units.
+const RIPPLE_WIDTH: f32 = 0.4;
+
+pub fn beat_pulse(ctx: &EffectContext, out: &mut [Color]) {
+    let audio = match ctx.audio {
+        Some(audio) => audio,
+        None => return out.fill((0.0, 0.0, 0.0)),
+    };
+    let palette = ctx.params.palette("palette");
+    let decay = ctx.params.float("decay").max(0.01);
+    let glow = ctx.params.float("glow");
+    let bass = audio.at(ctx.frame).bass;
+    let (color, progress) = match audio.last_beat(ctx.frame) {
+        Some((beat, index)) => (
+            palette.cycle(index),
+            (ctx.frame - beat) as f32 / ctx.fps / decay,
+        ),
+        // Before the first beat, glow in the color of the first
+        None => (palette.cycle(0), 1.0),
+    };
+    let background = scale(color, glow * bass);
+    if progress >= 1.0 {
+        return out.fill(background);
+    }
+    let strength = (1.0 - progress).powi(2);
+    match ctx.params.choice("shape") {
+        "flash" => out.fill(mix(background, color, strength)),
+        _ => {
+            let distance = |(x, y, z): Coord| (x * x + y * y + z * z).sqrt();
+            let reach = ctx.coords.iter().copied().map(distance).fold(0.0, f32::max);
+            // Start inside the trunk and finish just beyond the furthest LED
+            let radius = progress * (reach + RIPPLE_WIDTH);
+            fill_each(out, ctx.coords, |coord| {
+                let away = (distance(coord) - radius).abs();
+                let brightness = strength * (1.0 - smoothstep(0.0, RIPPLE_WIDTH, away));
+                mix(background, color, brightness)
+            });
+        }
+    }
+}
+
+pub fn spectrum_spiral(ctx: &EffectContext, out: &mut [Color]) {
+    let audio = match ctx.audio {
+        Some(audio) => audio.at(ctx.frame),
+        None => return out.fill((0.0, 0.0, 0.0)),
+    };
+    let turns = ctx.params.float("turns").max(0.1);
+    let spin = ctx.frame as f32 * ctx.params.float("spin");
+    let palette = ctx.params.optional_palette("palette");
+    let flash = if audio.beat {
+        ctx.params.float("flash")
+    } else {
+        0.0
+    };
+    let bands = audio.spectrum.len();
+    let max_height = ctx.bounds.max.2.max(f32::EPSILON);
+    fill_each(out, ctx.coords, |(x, y, z)| {
+        // Turns of the spiral below this LED's height, and how far around it is from the
+        // spiral's starting angle
+        let around = ((f32::atan2(y, x) - spin) / (PI * 2.0)).rem_euclid(1.0);
+        let winding = (z / max_height * turns - around).round();
+        let along = ((winding + around) / turns).clamp(0.0, 1.0);
+        // Interpolate between the centres of neighbouring bands
+        let position = (along * bands as f32 - 0.5).clamp(0.0, (bands - 1) as f32);
+        let band = (position as usize).min(bands - 2);
+        let energy = lerp(
+            audio.spectrum[band],
+            audio.spectrum[band + 1],
+            position - band as f32,
+        );
+        let color = match &palette {
+            Some(palette) => palette.gradient(along),
+            None => saturated_color(along * 0.8),
+        };
+        scale(mix(color, (1.0, 1.0, 1.0), flash), energy)
+    });
+}
+
+/// Rows of pixels the text is drawn with, before it's sampled at each LED.
+const TEXT_ROWS: usize = 24;
+
+/// Where `scroll-text` draws its message.
+struct TextLayout {
+    strip: TextStrip,
+    /// The size of a pixel of the strip, in coordinate units.
+    pixel: f32,
+    /// The height of the top of the strip.
+    top: f32,
+    /// The distance around the tree where the text is, in pixels.
+    circumference: f32,
+    /// The distance in pixels from the start of the message to the start of its next repeat.
+    period: f32,
+}
+
+impl TextLayout {
+    /// The layout for an effect's text, laid out the first time it's needed for these
+    /// coordinates and parameters, since drawing the text is far slower than sampling it.
+    fn get(ctx: &EffectContext) -> Arc<Self> {
+        static LAYOUTS: Memo<(u64, String, u32, u32), TextLayout> = Memo::new();
+        let key = (
+            memo::coords_key(ctx.coords),
+            ctx.params.script("text").to_string(),
+            ctx.params.float("height").to_bits(),
+            ctx.params.float("position").to_bits(),
+        );
+        LAYOUTS.get(key, || Self::new(ctx.coords, ctx.bounds, ctx.params))
+    }
+
+    fn new(coords: &[Coord], bounds: Bounds, params: &Params) -> Self {
+        let strip = TextStrip::render(params.script("text"), TEXT_ROWS);
+        let tree_height = (bounds.max.2 - bounds.min.2).max(f32::EPSILON);
+        let pixel = params.float("height").max(0.01) * tree_height / TEXT_ROWS as f32;
+        let centre = bounds.min.2 + params.float("position") * tree_height;
+        let top = centre + pixel * TEXT_ROWS as f32 / 2.0;
+        let bottom = top - pixel * TEXT_ROWS as f32;
+
+        // The text is stretched around the tree as if it were a cylinder, as wide as the
+        // branches are at the text's height
+        let radius = |&(x, y, _): &Coord| (x * x + y * y).sqrt();
+        let mut radii: Vec<f32> = coords
+            .iter()
+            .filter(|c| (bottom..=top).contains(&c.2))
+            .map(radius)
+            .collect();
+        if radii.is_empty() {
+            radii = coords.iter().map(radius).collect();
+        }
+        radii.sort_by(f32::total_cmp);
+        let radius = radii.get(radii.len() / 2).copied().unwrap_or(1.0);
+        let circumference = PI * 2.0 * radius / pixel;
+        // Short messages only go around once at a time
+        let period = (strip.width + TEXT_ROWS) as f32;
+        Self {
+            strip,
+            pixel,
+            top,
+            circumference,
+            period: period.max(circumference),
+        }
+    }
+}
+
+/// Where the text a marquee shows starts, for it to have scrolled `moved` pixels. The text
+/// enters from the side it starts reading from and leaves before coming round again.
+fn marquee_offset(layout: &TextLayout, view: f32, moved: f32) -> f32 {
+    let moved = moved.rem_euclid(layout.strip.width as f32 + view);
+    if layout.strip.rtl {
+        layout.strip.width as f32 - moved
+    } else {
+        moved - view
+    }
+}
+
+pub fn text_marquee(ctx: &EffectContext, out: &mut [Color]) {
+    let layout = TextLayout::get(ctx);
+    let left = ctx.bounds.min.0;
+    let view = (ctx.bounds.max.0 - left) / layout.pixel;
+    let moved = ctx.frame as f32 * ctx.params.float("speed") / layout.pixel;
+    let offset = marquee_offset(&layout, view, moved);
+    let palette = ctx.params.palette("palette");
+    fill_each(out, ctx.coords, |(x, _, z)| {
+        let column = (x - left) / layout.pixel + offset;
+        let row = (layout.top - z) / layout.pixel;
+        match layout.strip.sample(column, row) {
+            Some((coverage, character)) => scale(palette.cycle(character), coverage),
+            None => (0.0, 0.0, 0.0),
+        }
+    });
+    keep_outward(ctx, out);
+}
+
+/// How far around the tree a point is, from 0 to 1, increasing to the right as seen from
+/// outside.
+fn turn_fraction(x: f32, y: f32) -> f32 {
+    f32::atan2(y, x) / (PI * 2.0) + 0.5
+}
+
+pub fn image_wrap(ctx: &EffectContext, out: &mut [Color]) {
+    // Only a missing image gets this far, since a given one is checked up front
+    let texture = match media::image(ctx.params.path("image")) {
+        Ok(texture) => texture,
+        Err(_) => return out.fill((0.0, 0.0, 0.0)),
+    };
+    let turned = ctx.frame as f32 * ctx.params.float("speed");
+    fill_each(out, ctx.coords, |(x, y, z)| {
+        let height = ctx.bounds.height_fraction((x, y, z));
+        texture.sample_wrapped(turn_fraction(x, y) + turned, 1.0 - height)
+    });
+    keep_outward(ctx, out);
+}
+
+pub fn video(ctx: &EffectContext, out: &mut [Color]) {
+    let frames = match media::frames(ctx.params.path("frames")) {
+        Ok(frames) => frames,
+        Err(_) => return out.fill((0.0, 0.0, 0.0)),
+    };
+    let index = (ctx.frame as f32 * ctx.params.float("step")) as usize % frames.len();
+    let texture = match media::image(&frames[index]) {
+        Ok(texture) => texture,
+        Err(e) => {
+            warn!("Skipping video frame: {}", e);
+            return out.fill((0.0, 0.0, 0.0));
+        }
+    };
+    let cylindrical = ctx.params.choice("mapping") == "cylindrical";
+    let (left, width) = (ctx.bounds.min.0, ctx.bounds.max.0 - ctx.bounds.min.0);
+    fill_each(out, ctx.coords, |(x, y, z)| {
+        let v = 1.0 - ctx.bounds.height_fraction((x, y, z));
+        if cylindrical {
+            texture.sample_wrapped(turn_fraction(x, y), v)
+        } else {
+            texture.sample((x - left) / width.max(f32::EPSILON), v)
+        }
+    });
+}
+
+pub fn scroll_text(ctx: &EffectContext, out: &mut [Color]) {
+    let layout = TextLayout::get(ctx);
+    let moved = ctx.frame as f32 * ctx.params.float("speed") / layout.pixel;
+    // Text enters from the side it starts reading from
+    let offset = if layout.strip.rtl { -moved } else { moved };
+    let palette = ctx.params.palette("palette");
+    fill_each(out, ctx.coords, |(x, y, z)| {
+        let around = f32::atan2(y, x) / (PI * 2.0) * layout.circumference;
+        let column = (around + offset).rem_euclid(layout.period);
+        let row = (layout.top - z) / layout.pixel;
+        match layout.strip.sample(column, row) {
+            Some((coverage, character)) => scale(palette.cycle(character), coverage),
+            None => (0.0, 0.0, 0.0),
+        }
+    });
+    keep_outward(ctx, out);
+}
+
+/// Warm whites and ambers the idle twinkles are tinted from.
+const IDLE_TINTS: [Color; 3] = [(1.0, 0.7, 0.35), (1.0, 0.55, 0.2), (1.0, 0.62, 0.28)];
+/// Shortest and longest time one twinkle glows for, in seconds.
+const IDLE_GLOW_SECONDS: (f32, f32) = (40.0, 90.0);
+/// How far a twinkle's glow reaches, as a fraction of the tree's height.
+const IDLE_RADIUS: f32 = 0.05;
+/// Seconds over which the overall brightness wanders.
+const IDLE_BREATH_SECONDS: f32 = 1200.0;
+
+/// The day idle's variation is seeded from, counting from the evening so it doesn't jump at
+/// midnight.
+fn idle_day(params: &Params) -> u64 {
+    match params.int("day") {
+        0 => {
+            let evening = Local::now() - chrono::Duration::hours(12);
+            evening.date_naive().num_days_from_ce() as u64
+        }
+        day => day as u64,
+    }
+}
+
+pub fn idle(ctx: &EffectContext, out: &mut [Color]) {
+    let count = ctx.params.int("count");
+    let level = ctx.params.float("level").clamp(0.0, 1.0);
+    let seed = ctx.seed ^ idle_day(ctx.params).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    let seconds = ctx.frame as f32 / ctx.fps;
+    let height = (ctx.bounds.max.2 - ctx.bounds.min.2).max(f32::EPSILON);
+    let radius = IDLE_RADIUS * height;
+
+    // The whole tree slowly brightens and dims between random levels, over tens of minutes
+    let breath_at = |i: u64| 0.6 + 0.4 * StdRng::seed_from_u64(seed ^ i).gen::<f32>();
+    let breath_time = seconds / IDLE_BREATH_SECONDS;
+    let breath_index = breath_time as u64;
+    let breath = lerp(
+        breath_at(breath_index),
+        breath_at(breath_index + 1),
+        smoothstep(0.0, 1.0, breath_time.fract()),
+    );
+
+    out.fill((0.0, 0.0, 0.0));
+    if ctx.coords.is_empty() {
+        return;
+    }
+    for slot in 0..count as u64 {
+        let slot_rng = |epoch: u64| {
+            StdRng::seed_from_u64(seed ^ (slot << 48) ^ epoch.wrapping_mul(0xbf58_476d_1ce4_e5b9))
+        };
+        // Each slot has its own pace, and starts part way through so they don't all fade together
+        let mut rng = slot_rng(u64::MAX);
+        let duration = rng.gen_range(IDLE_GLOW_SECONDS.0..IDLE_GLOW_SECONDS.1);
+        let time = seconds / duration + slot as f32 / count as f32;
+        let progress = time.fract();
+
+        // Every glow drifts from one LED towards another, fading in and out on the way
+        let mut rng = slot_rng(time as u64);
+        let from = ctx.coords[rng.gen_range(0..ctx.coords.len())];
+        let to = ctx.coords[rng.gen_range(0..ctx.coords.len())];
+        let tint = IDLE_TINTS[rng.gen_range(0..IDLE_TINTS.len())];
+        let drift = 0.15 * progress;
+        let centre = (
+            lerp(from.0, to.0, drift),
+            lerp(from.1, to.1, drift),
+            lerp(from.2, to.2, drift),
+        );
+        let brightness = level * breath * (PI * progress).sin().powi(2);
+        for (color, &(x, y, z)) in out.iter_mut().zip(ctx.coords) {
+            let distance =
+                ((x - centre.0).powi(2) + (y - centre.1).powi(2) + (z - centre.2).powi(2)).sqrt();
+            let glow = brightness * (1.0 - smoothstep(0.0, radius, distance));
+            if glow > 0.0 {
+                *color = (
+                    color.0 + tint.0 * glow,
+                    color.1 + tint.1 * glow,
+                    color.2 + tint.2 * glow,
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use std::{error::Error, fs::File, io::BufWriter, path::Path};
+
+use tracing::{info, warn};
+use xmas_tree_common::{
+    csv_format::CsvWriter,
+    sequence::{self, SequenceWriter},
+};
+
+/// Writes a sequence in exactly the format the GIFT community repository accepts: a
+/// `FRAME_ID,R_0,G_0,B_0,...` header and integer values from 0 to 255. No sidecar metadata is
+/// written, since the submission is just the CSV.
+pub fn gift(input: &Path, output: &Path, led_count: usize) -> Result<(), Box<dyn Error>> {
+    let sequence = sequence::read(input)?;
+    if sequence.frames.is_empty() {
+        return Err("Sequence has no frames".into());
+    }
+    if sequence.led_count != led_count {
+        warn!(
+            "Sequence has {} LEDs but the coordinates have {}, so it may be rejected",
+            sequence.led_count, led_count
+        );
+    }
+    if sequence.clamped_values > 0 {
+        warn!(
+            "Clamped {} values which were outside 0-255",
+            sequence.clamped_values
+        );
+    }
+
+    let mut writer = CsvWriter::new(BufWriter::new(File::create(output)?), sequence.led_count)?;
+    for frame in &sequence.frames {
+        writer.write_frame(frame)?;
+    }
+    writer.flush()?;
+
+    info!(
+        "Exported {} frames of {} LEDs",
+        sequence.frames.len(),
+        sequence.led_count
+    );
+    Ok(())
+}
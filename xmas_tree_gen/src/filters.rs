@@ -0,0 +1,327 @@
+use std::path::Path;
+use structopt::StructOpt;
+
+use xmas_tree_common::{
+    calibration::Calibration,
+    failures::{self, FailureMode},
+    palette::Palette,
+};
+
+use crate::{
+    accessibility::ColorVision,
+    effects::{Color, Coord, EffectContext},
+    grading::{Grade, GradeFilter},
+    lanes::{self, Planar},
+    mask::Mask,
+    neighbours::{self, NeighbourGraph, NEIGHBOUR_COUNT},
+};
+
+/// Transforms each frame after the effect has rendered it.
+pub trait PostFilter {
+    fn apply(&mut self, ctx: &EffectContext, frame: &mut Vec<Color>);
+
+    /// Whether the filter only adds polish, so `live` can skip it when it can't keep up.
+    fn optional(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct FilterOpt {
+    /// Restrict the effect to LEDs matching an expression, e.g. `z > 0.5 * max_z`.
+    #[structopt(long)]
+    mask: Option<Mask>,
+    /// Blur each frame over the LED neighbour graph (0 = off, 1 = maximum).
+    #[structopt(long)]
+    blur: Option<f32>,
+    /// Dim LEDs in densely packed parts of the tree, so they don't look brighter than sparse
+    /// parts when everything is lit (0 = off, 1 = even light per volume).
+    #[structopt(long)]
+    density_balance: Option<f32>,
+    /// Rotate every color's hue by this many degrees.
+    #[structopt(long)]
+    hue_shift: Option<f32>,
+    /// Warm (positive) or cool (negative) the colors, from -1 to 1.
+    #[structopt(long)]
+    temperature: Option<f32>,
+    /// Boost (positive) or mute (negative) the saturation of less saturated colors.
+    #[structopt(long)]
+    vibrance: Option<f32>,
+    /// Raise the black level, from 0 to 1.
+    #[structopt(long)]
+    lift: Option<f32>,
+    /// Gamma adjustment of the grade, where values above 1 brighten midtones.
+    #[structopt(long)]
+    grade_gamma: Option<f32>,
+    /// Multiply every channel by this amount.
+    #[structopt(long)]
+    gain: Option<f32>,
+    /// Snap every color to the nearest entry of a palette (a built-in name or `#rrggbb,...`).
+    #[structopt(long)]
+    quantize: Option<Palette>,
+    /// Diffuse quantization error to neighbouring LEDs.
+    #[structopt(long)]
+    dither: bool,
+    /// Move colors which look alike with this color vision (deuteranopia or protanopia) apart,
+    /// turning greens to blues so they stand out from reds.
+    #[structopt(long)]
+    color_vision: Option<ColorVision>,
+    /// Correct each LED with a calibration file written by `calibrate analyze`.
+    #[structopt(long, parse(try_from_str = load_calibration))]
+    calibration: Option<Calibration>,
+    /// Simulate faulty LEDs: this fraction of them are stuck on, stuck off or have their red and
+    /// green swapped.
+    #[structopt(long, parse(try_from_str = failures::parse_rate))]
+    simulate_failures: Option<f32>,
+    /// Seed choosing which LEDs `--simulate-failures` breaks.
+    #[structopt(long, default_value = "1")]
+    failure_seed: u64,
+    /// Scale the brightness of all output, from 0 to 1. Applied after every other filter.
+    #[structopt(long, parse(try_from_str = parse_brightness))]
+    brightness: Option<f32>,
+}
+
+fn parse_brightness(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(brightness) if (0.0..=1.0).contains(&brightness) => Ok(brightness),
+        _ => Err(format!("Brightness must be between 0 and 1, got {}", s)),
+    }
+}
+
+fn load_calibration(s: &str) -> Result<Calibration, String> {
+    Calibration::load(Path::new(s)).map_err(|e| e.to_string())
+}
+
+impl FilterOpt {
+    /// Builds the filters, correcting with `calibration` (the project's) unless `--calibration`
+    /// was given, and remapping colors for `color_vision` (the show's) unless `--color-vision`
+    /// was given.
+    pub fn build(
+        &self,
+        coords: &[Coord],
+        calibration: Option<&Calibration>,
+        color_vision: Option<ColorVision>,
+    ) -> Vec<Box<dyn PostFilter>> {
+        let mut filters: Vec<Box<dyn PostFilter>> = Vec::new();
+        if let Some(mask) = &self.mask {
+            filters.push(Box::new(MaskFilter {
+                inside: mask.evaluate(coords),
+            }));
+        }
+        if let Some(strength) = self.blur {
+            filters.push(Box::new(Blur { strength }));
+        }
+        if let Some(grade) = self.grade() {
+            filters.push(Box::new(GradeFilter::new(grade)));
+        }
+        if let Some(strength) = self.density_balance {
+            let density = NeighbourGraph::knn(coords, NEIGHBOUR_COUNT).density();
+            filters.push(Box::new(DensityBalance {
+                gains: neighbours::density_gains(&density, strength.clamp(0.0, 1.0)),
+            }));
+        }
+        if let Some(palette) = &self.quantize {
+            filters.push(Box::new(Quantize::new(palette.clone(), self.dither)));
+        }
+        if let Some(vision) = self.color_vision.or(color_vision) {
+            filters.push(Box::new(ColorVisionFilter { vision }));
+        }
+        if let Some(calibration) = self.calibration.as_ref().or(calibration) {
+            filters.push(Box::new(CalibrationFilter {
+                calibration: calibration.clone(),
+            }));
+        }
+        if let Some(rate) = self.simulate_failures {
+            filters.push(Box::new(SimulatedFailures {
+                failures: failures::pick(coords.len(), rate, self.failure_seed),
+            }));
+        }
+        if let Some(factor) = self.brightness {
+            filters.push(Box::new(Brightness { factor }));
+        }
+        filters
+    }
+
+    fn grade(&self) -> Option<Grade> {
+        let default = Grade::default();
+        let grade = Grade {
+            hue_shift: self.hue_shift.unwrap_or(default.hue_shift),
+            temperature: self.temperature.unwrap_or(default.temperature),
+            vibrance: self.vibrance.unwrap_or(default.vibrance),
+            lift: self.lift.unwrap_or(default.lift),
+            gamma: self.grade_gamma.unwrap_or(default.gamma),
+            gain: self.gain.unwrap_or(default.gain),
+        };
+        Some(grade).filter(|grade| *grade != default)
+    }
+}
+
+pub struct Blur {
+    pub strength: f32,
+}
+
+impl PostFilter for Blur {
+    fn apply(&mut self, ctx: &EffectContext, frame: &mut Vec<Color>) {
+        *frame = ctx.neighbours.blur(frame, self.strength);
+    }
+
+    fn optional(&self) -> bool {
+        true
+    }
+}
+
+/// Blacks out every LED outside a mask.
+pub struct MaskFilter {
+    pub inside: Vec<bool>,
+}
+
+impl PostFilter for MaskFilter {
+    fn apply(&mut self, _ctx: &EffectContext, frame: &mut Vec<Color>) {
+        for (color, &inside) in frame.iter_mut().zip(&self.inside) {
+            if !inside {
+                *color = (0.0, 0.0, 0.0);
+            }
+        }
+    }
+}
+
+pub struct Quantize {
+    pub palette: Palette,
+    pub dither: bool,
+    planar: Planar,
+    /// For each LED, the neighbours which are quantized after it.
+    later: Vec<Vec<usize>>,
+}
+
+impl Quantize {
+    pub fn new(palette: Palette, dither: bool) -> Self {
+        Self {
+            palette,
+            dither,
+            planar: Planar::default(),
+            later: Vec::new(),
+        }
+    }
+}
+
+impl PostFilter for Quantize {
+    fn apply(&mut self, ctx: &EffectContext, frame: &mut Vec<Color>) {
+        if !self.dither {
+            self.planar.load(frame);
+            lanes::quantize(&mut self.planar, &self.palette);
+            self.planar.store(frame);
+            return;
+        }
+
+        // Error diffusion in LED order, spreading each LED's error over its neighbours which
+        // haven't been quantized yet. Each LED depends on the ones before it, so this can't be
+        // done in lanes.
+        if self.later.len() != frame.len() {
+            self.later = ctx
+                .neighbours
+                .neighbours
+                .iter()
+                .enumerate()
+                .map(|(i, neighbours)| {
+                    neighbours
+                        .iter()
+                        .map(|&(j, _)| j)
+                        .filter(|&j| j > i)
+                        .collect()
+                })
+                .collect();
+        }
+        for i in 0..frame.len() {
+            let color = frame[i];
+            let quantized = self.palette.nearest(color);
+            frame[i] = quantized;
+            let error = (
+                color.0 - quantized.0,
+                color.1 - quantized.1,
+                color.2 - quantized.2,
+            );
+            let later = &self.later[i];
+            let share = 1.0 / later.len().max(1) as f32;
+            for &j in later {
+                let c = &mut frame[j];
+                *c = (
+                    c.0 + error.0 * share,
+                    c.1 + error.1 * share,
+                    c.2 + error.2 * share,
+                );
+            }
+        }
+    }
+}
+
+pub struct Brightness {
+    pub factor: f32,
+}
+
+impl PostFilter for Brightness {
+    fn apply(&mut self, _ctx: &EffectContext, frame: &mut Vec<Color>) {
+        for color in frame.iter_mut() {
+            *color = (
+                color.0 * self.factor,
+                color.1 * self.factor,
+                color.2 * self.factor,
+            );
+        }
+    }
+}
+
+/// Remaps every color to one which stays distinct with a color vision deficiency.
+pub struct ColorVisionFilter {
+    pub vision: ColorVision,
+}
+
+impl PostFilter for ColorVisionFilter {
+    fn apply(&mut self, _ctx: &EffectContext, frame: &mut Vec<Color>) {
+        for color in frame.iter_mut() {
+            *color = self.vision.remap(*color);
+        }
+    }
+}
+
+/// Dims each LED by its `density_gains`.
+pub struct DensityBalance {
+    pub gains: Vec<f32>,
+}
+
+impl PostFilter for DensityBalance {
+    fn apply(&mut self, _ctx: &EffectContext, frame: &mut Vec<Color>) {
+        for (color, &gain) in frame.iter_mut().zip(&self.gains) {
+            *color = (color.0 * gain, color.1 * gain, color.2 * gain);
+        }
+    }
+}
+
+/// Scales each LED's channels by its measured correction.
+pub struct CalibrationFilter {
+    pub calibration: Calibration,
+}
+
+impl PostFilter for CalibrationFilter {
+    fn apply(&mut self, _ctx: &EffectContext, frame: &mut Vec<Color>) {
+        for (led, color) in frame.iter_mut().enumerate() {
+            let [r, g, b] = self.calibration.gain(led);
+            *color = (color.0 * r, color.1 * g, color.2 * b);
+        }
+    }
+}
+
+/// Breaks some LEDs, to check an effect still reads well with a few dead pixels.
+pub struct SimulatedFailures {
+    pub failures: Vec<Option<FailureMode>>,
+}
+
+impl PostFilter for SimulatedFailures {
+    fn apply(&mut self, _ctx: &EffectContext, frame: &mut Vec<Color>) {
+        for (color, failure) in frame.iter_mut().zip(&self.failures) {
+            if let Some(mode) = failure {
+                let [r, g, b] = mode.apply([color.0, color.1, color.2], 1.0, 0.0);
+                *color = (r, g, b);
+            }
+        }
+    }
+}
@@ -0,0 +1,617 @@
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fs::{self, File, OpenOptions},
+    io::{self, Seek, SeekFrom, Write},
+    mem,
+    ops::Range,
+    path::{Path, PathBuf},
+    str::FromStr,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use rayon::prelude::*;
+use structopt::StructOpt;
+use tracing::{debug, error, info};
+use xmas_tree_common::{
+    color::{self, ClipMode},
+    csv_format::CsvWriter,
+    delta_format::DeltaWriter,
+    fseq_format::FseqWriter,
+    metadata::{self, Marker, SequenceMetadata},
+    seal::{self, SealMode},
+    sequence::{Blanking, BlankingWriter, Rgb, SequenceFormat, SequenceWriter},
+};
+
+use crate::{
+    audio::AudioTrack,
+    checkpoint::{Checkpoint, CountingWriter, RenderSettings},
+    correction::CorrectionOpt,
+    debugger::{Debugger, EffectState},
+    effects::{
+        self, Authorship, Bounds, Color, Coord, Effect, EffectContext, EffectFn, EffectInfo,
+    },
+    filters::FilterOpt,
+    load_coords, load_hardware,
+    meta::MetaOpt,
+    neighbours::{NeighbourGraph, NEIGHBOUR_COUNT},
+    params::{ParamArg, Params},
+    progress_bar,
+    script::{self, ScriptEffect},
+    stats::{EffectStats, StatsCollector, StatsOpt},
+    thumbnail::Thumbnail,
+    visibility, Opt,
+};
+
+#[derive(Debug, StructOpt)]
+pub struct GenerateOpt {
+    /// Name of the effect, or the path of a `.rhai` script.
+    pub effect: String,
+    /// Sets an effect parameter, as NAME=VALUE (see the `docs` command for each effect's parameters).
+    #[structopt(long = "param", number_of_values = 1)]
+    params: Vec<ParamArg>,
+    /// Nudge the effect's parameters within their safe ranges, differently for each N, so a
+    /// show can change subtly from one run to the next. 0 uses the parameters as given.
+    #[structopt(long, default_value = "0")]
+    variation: u64,
+    /// Number of frames, `auto[:CYCLES]` to use a whole number of the effect's cycles, or
+    /// `audio` to last as long as the `--audio` track.
+    #[structopt(long, default_value = "1000")]
+    len: Length,
+    /// WAV file for audio-reactive effects to follow. Frame N of the sequence (including any
+    /// lead in) lines up with N / fps seconds into the track.
+    #[structopt(long, parse(from_os_str))]
+    audio: Option<PathBuf>,
+    /// Write the sequence to this file instead of stdout.
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+    /// Output format: `csv`, `delta` for the compact run-length encoded format, or `fseq` for
+    /// xLights and Falcon Player (which time frames in whole milliseconds, so round `--fps`).
+    #[structopt(long, default_value = "csv")]
+    format: SequenceFormat,
+    /// Save a checkpoint every N frames so an interrupted render can be resumed.
+    #[structopt(long)]
+    checkpoint: Option<usize>,
+    /// Resume from the checkpoint saved alongside the output file.
+    #[structopt(long)]
+    resume: bool,
+    /// Number of black frames to write before the sequence.
+    #[structopt(long, default_value = "0")]
+    lead_in: usize,
+    /// Number of black frames to write after the sequence.
+    #[structopt(long, default_value = "0")]
+    lead_out: usize,
+    /// Force the first frame of the effect to black.
+    #[structopt(long)]
+    blank_first: bool,
+    /// Keep running, and generate the sequence again whenever the script or a parameter file
+    /// (`NAME=@PATH`) changes. Errors are reported rather than ending the run.
+    #[structopt(long)]
+    watch: bool,
+    /// Stream CSV frames to stdout in real time, looping the effect until stopped, for a live
+    /// preview with `| xmas_tree_player -`.
+    #[structopt(long, conflicts_with_all = &["output", "checkpoint", "resume", "watch"])]
+    preview: bool,
+    /// Snapshot the preview's state every N frames, and read commands from stdin to jump back
+    /// and replay it deterministically: `goto FRAME`, `back N`, `forward N`, `pause`, `play` and
+    /// `step`.
+    #[structopt(long, value_name = "N", requires = "preview")]
+    snapshot_every: Option<usize>,
+    /// Names a frame of the effect, as NAME=FRAME, so players can jump to it.
+    #[structopt(long = "marker", number_of_values = 1)]
+    markers: Vec<Marker>,
+    #[structopt(flatten)]
+    meta: MetaOpt,
+    #[structopt(flatten)]
+    filters: FilterOpt,
+    #[structopt(flatten)]
+    correction: CorrectionOpt,
+    #[structopt(flatten)]
+    stats: StatsOpt,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Length {
+    Frames(usize),
+    Auto {
+        cycles: usize,
+    },
+    /// As long as the audio track.
+    Audio,
+}
+
+impl FromStr for Length {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Expected a number of frames, auto[:CYCLES] or audio, got {}",
+                s
+            )
+        };
+        if s == "audio" {
+            return Ok(Self::Audio);
+        }
+        let mut parts = s.splitn(2, ':');
+        if parts.next() != Some("auto") {
+            return s.parse().map(Self::Frames).map_err(|_| invalid());
+        }
+        let cycles = match parts.next() {
+            Some(cycles) => cycles.parse().map_err(|_| invalid())?,
+            None => 1,
+        };
+        if cycles == 0 {
+            return Err(invalid());
+        }
+        Ok(Self::Auto { cycles })
+    }
+}
+
+impl Length {
+    pub fn resolve(
+        self,
+        info: &EffectInfo,
+        coords: &[Coord],
+        params: &Params,
+    ) -> Result<usize, String> {
+        match self {
+            Self::Frames(frames) => Ok(frames),
+            Self::Auto { cycles } => {
+                let cycle = info.cycle.ok_or_else(|| {
+                    format!(
+                        "Effect {} has no natural cycle length, so needs an explicit --len",
+                        info.name
+                    )
+                })?;
+                // Round up so that effects which fit as many whole cycles as possible into
+                // the sequence get exactly the requested number
+                Ok((cycle(coords, params) * cycles as f32).ceil() as usize)
+            }
+            Self::Audio => Err("Only `generate --audio` can match the length of a track".into()),
+        }
+    }
+}
+
+pub fn to_rgb(color: Color) -> Rgb {
+    // Over-bright colors are scaled down whole, so they keep their hue
+    let (r, g, b) = color::clip(color, ClipMode::Hue);
+    let channel = |v: f32| (v * 255.0) as u8;
+    [channel(r), channel(g), channel(b)]
+}
+
+/// Frames each thread renders in a row when rendering in parallel. Meta-effects such as
+/// `--led-offset` reuse renders of nearby frames, so work best over a run of them.
+const PARALLEL_CHUNK: usize = 16;
+
+/// Renders `frames` of a built in effect across every core, each thread wrapping its own copy in
+/// the meta-effects. Frames are rendered as if there were no previous frame.
+fn render_batch(
+    render: EffectFn,
+    meta: &MetaOpt,
+    ctx: &EffectContext,
+    frames: Range<usize>,
+) -> VecDeque<Vec<Color>> {
+    let chunks: Vec<Vec<Vec<Color>>> = frames
+        .clone()
+        .step_by(PARALLEL_CHUNK)
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|start| {
+            let mut effect = meta.wrap(Box::new(render));
+            (start..frames.end.min(start + PARALLEL_CHUNK))
+                .map(|frame| {
+                    let mut colors = vec![(0.0, 0.0, 0.0); ctx.coords.len()];
+                    let ctx = EffectContext {
+                        frame,
+                        previous: None,
+                        ..*ctx
+                    };
+                    effect.render(&ctx, &mut colors);
+                    colors
+                })
+                .collect()
+        })
+        .collect();
+    chunks.into_iter().flatten().collect()
+}
+
+/// How often `--watch` checks parameter files for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+pub fn generate(opt: &Opt, gen: &GenerateOpt) -> Result<(), Box<dyn Error>> {
+    if !gen.watch {
+        return generate_once(opt, gen);
+    }
+    if gen.output.is_none() {
+        return Err("--watch needs an --output file to write to".into());
+    }
+    let watched: Vec<&Path> = gen
+        .params
+        .iter()
+        .filter_map(|arg| arg.value.strip_prefix('@'))
+        .map(Path::new)
+        .chain(script::path(&gen.effect))
+        .collect();
+    if watched.is_empty() {
+        return Err("--watch needs a script, or a parameter read from a file as NAME=@PATH".into());
+    }
+    let modified = || -> Vec<Option<SystemTime>> {
+        watched
+            .iter()
+            .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+            .collect()
+    };
+    loop {
+        let last_modified = modified();
+        if let Err(e) = generate_once(opt, gen) {
+            error!("{}", e);
+        }
+        info!("Waiting for changes to {:?}", watched);
+        while modified() == last_modified {
+            thread::sleep(WATCH_INTERVAL);
+        }
+    }
+}
+
+/// What a checkpointed render was asked for, to check it's the same render being resumed.
+fn render_settings(
+    opt: &Opt,
+    gen: &GenerateOpt,
+    info: &EffectInfo,
+    params: &Params,
+    len: usize,
+) -> Result<RenderSettings, Box<dyn Error>> {
+    let mut options = format!(
+        "{:?}\n{:?}\n{:?}\n{:?}\n",
+        gen.meta, gen.filters, gen.correction, opt.calibration
+    )
+    .into_bytes();
+    let inputs = script::path(&gen.effect)
+        .into_iter()
+        .chain(gen.audio.as_deref());
+    for path in inputs {
+        options.extend(fs::read(path)?);
+    }
+    Ok(RenderSettings {
+        effect: gen.effect.clone(),
+        format: gen.format.to_string(),
+        len,
+        seed: opt.seed,
+        lead_in: gen.lead_in,
+        params: params.cli_flags(info),
+        coords_hash: metadata::checksum(&fs::read(&opt.coords_path)?),
+        options_hash: metadata::checksum(&options),
+    })
+}
+
+fn generate_once(opt: &Opt, gen: &GenerateOpt) -> Result<(), Box<dyn Error>> {
+    let (info, effect, authorship): (&EffectInfo, Box<dyn Effect>, _) =
+        match script::path(&gen.effect) {
+            Some(path) => {
+                let script = ScriptEffect::load(path, &opt.sandbox)?;
+                let authorship = script.authorship.clone();
+                (&script::INFO, Box::new(script), authorship)
+            }
+            None => {
+                let info = effects::lookup(&gen.effect)
+                    .ok_or_else(|| format!("Unknown effect: {}", gen.effect))?;
+                (info, Box::new(info.render), Authorship::builtin())
+            }
+        };
+    let params = Params::resolve(
+        info,
+        &gen.params,
+        &opt.project.palettes,
+        opt.seed,
+        gen.variation,
+    )?;
+    if gen.variation != 0 {
+        info!(
+            "Variation {} uses: {}",
+            gen.variation,
+            params.cli_flags(info)
+        );
+    }
+    let mut effect = gen.meta.wrap(effect);
+    let coords = load_coords(opt)?;
+    load_hardware(opt)?;
+    debug!(
+        "Loaded {} LEDs from {}",
+        coords.len(),
+        opt.coords_path.display()
+    );
+
+    // The effect starts after the lead in, so skip that much of the track
+    let audio = match &gen.audio {
+        Some(path) => {
+            let track = AudioTrack::load(path, opt.fps)?;
+            info!(
+                "Analysed {} ({} frames, {} beats)",
+                path.display(),
+                track.len(),
+                track.beat_count()
+            );
+            Some(track.skip(gen.lead_in))
+        }
+        None => None,
+    };
+    let len = match (gen.len, &audio) {
+        (Length::Audio, Some(audio)) => audio.len(),
+        (Length::Audio, None) => return Err("--len audio needs an --audio track".into()),
+        (len, _) => len.resolve(info, &coords, &params)?,
+    };
+    if let Some(marker) = gen.markers.iter().find(|marker| marker.frame >= len) {
+        return Err(format!(
+            "Marker {} is at frame {}, after the end of the sequence ({} frames)",
+            marker.name, marker.frame, len
+        )
+        .into());
+    }
+    debug!("Generating {} frames", len);
+    let blanking = Blanking {
+        lead_in: gen.lead_in,
+        lead_out: gen.lead_out,
+        blank_first: gen.blank_first,
+    };
+    let neighbours = NeighbourGraph::knn(&coords, NEIGHBOUR_COUNT);
+    let bounds = Bounds::of(&coords);
+    let visibility = visibility::estimate(&coords);
+    let mut filters = gen.filters.build(&coords, opt.calibration.as_ref(), None);
+    let mut correction = gen.correction.build();
+
+    if gen.preview {
+        let mut writer = CsvWriter::new(io::stdout(), coords.len())?;
+        let mut colors = vec![(0.0, 0.0, 0.0); coords.len()];
+        let mut filtered = Vec::with_capacity(coords.len());
+        let mut state = EffectState {
+            previous: None,
+            correction,
+        };
+        let mut rgb = Vec::with_capacity(coords.len());
+        let frame_time = Duration::from_secs_f32(1.0 / opt.fps);
+        let mut next_frame = Instant::now();
+        let mut debugger = gen.snapshot_every.map(|every| Debugger::start(every, len));
+        let mut render = |frame: usize, state: &mut EffectState, rgb: &mut Vec<Rgb>| {
+            // Each loop starts afresh, as the start of a generated file would
+            if frame == 0 {
+                state.previous = None;
+            }
+            let ctx = EffectContext {
+                coords: &coords,
+                bounds,
+                frame,
+                total_frames: len,
+                fps: opt.fps,
+                seed: opt.seed,
+                previous: state.previous.as_deref(),
+                neighbours: &neighbours,
+                visibility: &visibility,
+                params: &params,
+                audio: audio.as_ref(),
+            };
+            effect.render(&ctx, &mut colors);
+            filtered.clear();
+            filtered.extend_from_slice(&colors);
+            for filter in &mut filters {
+                filter.apply(&ctx, &mut filtered);
+            }
+            state.correction.apply(&filtered, rgb);
+            state.previous = Some(colors.clone());
+        };
+        info!("Previewing {} frames at {} fps", len, opt.fps);
+        let mut frame = 0;
+        loop {
+            if let Some(debugger) = &mut debugger {
+                debugger.snapshot(frame, &state);
+                let polled = Instant::now();
+                if let Some(target) = debugger.poll(frame) {
+                    debugger.seek(frame, target, &mut state, |frame, state| {
+                        render(frame, state, &mut rgb)
+                    });
+                    frame = target;
+                }
+                // Carry on at the frame rate from here, rather than catching up after a pause
+                if polled.elapsed() > frame_time {
+                    next_frame = Instant::now();
+                }
+            }
+            render(frame, &mut state, &mut rgb);
+            // The player closing the pipe ends the preview
+            match writer.write_frame(&rgb).and_then(|()| writer.flush()) {
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+                result => result?,
+            }
+            frame = (frame + 1) % len.max(1);
+
+            next_frame += frame_time;
+            let now = Instant::now();
+            if next_frame > now {
+                thread::sleep(next_frame - now);
+            } else if now - next_frame > frame_time {
+                debug!("Rendering is slower than {} fps", opt.fps);
+                next_frame = now;
+            }
+        }
+    }
+
+    let checkpoint_path = match (&gen.output, gen.checkpoint.is_some() || gen.resume) {
+        (Some(output), true) => Some(Checkpoint::path_for(output)),
+        (None, true) => return Err("--checkpoint and --resume require --output".into()),
+        (_, false) => None,
+    };
+    if checkpoint_path.is_some() && gen.format == SequenceFormat::Fseq {
+        return Err("--checkpoint and --resume don't work with fseq files, which are only written at the end".into());
+    }
+    if checkpoint_path.is_some() && effect.stateful() {
+        return Err(
+            "--checkpoint and --resume don't work with --hold or --led-offset, which carry state \
+             from frame to frame that checkpoints don't save"
+                .into(),
+        );
+    }
+    let settings = match &checkpoint_path {
+        Some(_) => Some(render_settings(opt, gen, info, &params, len)?),
+        None => None,
+    };
+
+    let mut start_frame = 0;
+    let mut output_len = 0;
+    let mut previous: Option<Vec<Color>> = None;
+    let output: Box<dyn Write> = match &gen.output {
+        None => Box::new(io::stdout()),
+        Some(path) if gen.resume => {
+            let checkpoint = Checkpoint::load(checkpoint_path.as_ref().unwrap())?;
+            checkpoint.check_matches(settings.as_ref().unwrap())?;
+            let mut file = OpenOptions::new().write(true).open(path)?;
+            file.set_len(checkpoint.output_len)?;
+            file.seek(SeekFrom::End(0))?;
+            info!("Resuming from frame {}", checkpoint.next_frame);
+            start_frame = checkpoint.next_frame;
+            output_len = checkpoint.output_len;
+            previous = checkpoint.previous_frame;
+            Box::new(file)
+        }
+        Some(path) => Box::new(File::create(path)?),
+    };
+    let output = CountingWriter::new(output, output_len);
+    let bytes_written = output.counter();
+    let writer: Box<dyn SequenceWriter> = match (gen.format, start_frame) {
+        (SequenceFormat::Csv, 0) => Box::new(CsvWriter::new(output, coords.len())?),
+        (SequenceFormat::Csv, _) => Box::new(CsvWriter::resume(output, gen.lead_in + start_frame)),
+        (SequenceFormat::Delta, 0) => Box::new(DeltaWriter::new(output, coords.len())?),
+        (SequenceFormat::Delta, _) => Box::new(DeltaWriter::resume(output)),
+        (SequenceFormat::Fseq, _) => Box::new(FseqWriter::new(output, coords.len(), opt.fps)),
+    };
+    let mut writer = if start_frame == 0 {
+        BlankingWriter::new(writer, blanking, coords.len())
+    } else {
+        BlankingWriter::resume(writer, blanking, coords.len())
+    };
+
+    // Built in effects which don't look back at the previous frame are rendered in parallel,
+    // and their frames filtered and written in order as they come
+    let parallel = match script::path(&gen.effect) {
+        None if !info.uses_previous => Some(info.render),
+        _ => None,
+    };
+    let batch_len = PARALLEL_CHUNK * rayon::current_num_threads();
+    let mut rendered = VecDeque::new();
+
+    let progress = progress_bar(opt, len);
+    progress.set_position(start_frame as u64);
+    let led_count = coords.len();
+    let mut colors = vec![(0.0, 0.0, 0.0); led_count];
+    let mut filtered = Vec::with_capacity(led_count);
+    let mut rgb = Vec::with_capacity(led_count);
+    let mut stats = StatsCollector::new(opt.fps);
+    let mut thumbnail = Thumbnail::new(len);
+    for frame in start_frame..len {
+        let ctx = EffectContext {
+            coords: &coords,
+            bounds,
+            frame,
+            total_frames: len,
+            fps: opt.fps,
+            seed: opt.seed,
+            previous: previous.as_deref(),
+            neighbours: &neighbours,
+            visibility: &visibility,
+            params: &params,
+            audio: audio.as_ref(),
+        };
+        match parallel {
+            Some(render) => {
+                if rendered.is_empty() {
+                    rendered =
+                        render_batch(render, &gen.meta, &ctx, frame..len.min(frame + batch_len));
+                }
+                colors = rendered.pop_front().unwrap();
+            }
+            None => effect.render(&ctx, &mut colors),
+        }
+        filtered.clear();
+        filtered.extend_from_slice(&colors);
+        for filter in &mut filters {
+            filter.apply(&ctx, &mut filtered);
+        }
+        stats.add_frame(blanking.lead_in + frame, &filtered);
+        let limited = correction.apply(&filtered, &mut rgb);
+        stats.add_output(blanking.lead_in + frame, &rgb, limited);
+        writer.write_frame(&rgb)?;
+        thumbnail.add(&rgb);
+        // Keep this frame for the next one, and render the next into the old buffer
+        match &mut previous {
+            Some(previous) => mem::swap(previous, &mut colors),
+            None => previous = Some(mem::replace(&mut colors, vec![(0.0, 0.0, 0.0); led_count])),
+        }
+        progress.inc(1);
+
+        if let (Some(every), Some(path), Some(settings)) =
+            (gen.checkpoint, &checkpoint_path, &settings)
+        {
+            if (frame + 1) % every == 0 {
+                writer.flush()?;
+                Checkpoint {
+                    settings: settings.clone(),
+                    next_frame: frame + 1,
+                    output_len: bytes_written.get(),
+                    previous_frame: previous.clone(),
+                }
+                .save(path)?;
+            }
+        }
+    }
+    writer.finish()?;
+    progress.finish_and_clear();
+
+    drop(writer);
+
+    if let Some(output) = &gen.output {
+        if let Some(mode) = opt.seal {
+            seal::seal_file(output, mode)?;
+        }
+        let metadata = SequenceMetadata::write_sidecar(
+            output,
+            gen.format,
+            blanking.total_frames(len),
+            coords.len(),
+            Some(metadata::checksum(&fs::read(&opt.coords_path)?)),
+            // Markers count effect frames, so skip over the lead in
+            gen.markers
+                .iter()
+                .map(|marker| Marker {
+                    frame: marker.frame + blanking.lead_in,
+                    ..marker.clone()
+                })
+                .collect(),
+            vec![authorship.credit(script::credit_name(&gen.effect), blanking.lead_in, len)],
+        )?;
+        thumbnail.finish(
+            output,
+            &coords,
+            &metadata,
+            opt.fps,
+            !opt.no_thumbnail && opt.seal != Some(SealMode::Encrypt),
+        )?;
+    }
+    if let Some(path) = &checkpoint_path {
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+
+    let report = stats.finish(
+        vec![EffectStats {
+            name: gen.effect.clone(),
+            start_frame: blanking.lead_in,
+            frames: len,
+            duration_secs: len as f32 / opt.fps,
+        }],
+        blanking.total_frames(len),
+        opt.seed,
+        gen.output.as_deref(),
+    );
+    gen.stats.emit(&report, opt.quiet)
+}
@@ -0,0 +1,96 @@
+use xmas_tree_common::color::{hsv_to_rgb, rgb_to_hsv};
+
+use crate::{
+    effects::{Color, EffectContext},
+    filters::PostFilter,
+    lanes::{self, Planar},
+};
+
+/// A color grade applied to every LED, for retinting a whole show.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Grade {
+    /// Hue rotation in degrees.
+    pub hue_shift: f32,
+    /// Negative values cool the image towards blue, positive values warm it towards orange.
+    pub temperature: f32,
+    /// Saturation boost which mostly affects less saturated colors.
+    pub vibrance: f32,
+    pub lift: f32,
+    pub gamma: f32,
+    pub gain: f32,
+}
+
+impl Default for Grade {
+    fn default() -> Self {
+        Self {
+            hue_shift: 0.0,
+            temperature: 0.0,
+            vibrance: 0.0,
+            lift: 0.0,
+            gamma: 1.0,
+            gain: 1.0,
+        }
+    }
+}
+
+impl Grade {
+    fn adjusts_hsv(&self) -> bool {
+        self.hue_shift != 0.0 || self.vibrance != 0.0
+    }
+
+    fn adjust_hsv(&self, color: Color) -> Color {
+        let (mut hue, mut saturation, value) = rgb_to_hsv(color);
+        hue += self.hue_shift / 360.0;
+        saturation = (saturation * (1.0 + self.vibrance * (1.0 - saturation))).clamp(0.0, 1.0);
+        hsv_to_rgb(hue, saturation, value)
+    }
+
+    /// Lift, gain and gamma, which treat each channel the same, after scaling by `scale`.
+    fn apply_channel(&self, values: &mut [f32], scale: f32) {
+        let Self { lift, gain, .. } = *self;
+        let exponent = 1.0 / self.gamma;
+        let linear = move |v: f32| {
+            let v = v * scale;
+            ((v + lift * (1.0 - v)) * gain).max(0.0)
+        };
+        if exponent == 1.0 {
+            lanes::map(values, linear);
+        } else {
+            lanes::map(values, |v| linear(v).powf(exponent));
+        }
+    }
+}
+
+/// Applies a grade a channel at a time. Colors only go through HSV when the grade changes hue or
+/// saturation.
+pub struct GradeFilter {
+    pub grade: Grade,
+    planar: Planar,
+}
+
+impl GradeFilter {
+    pub fn new(grade: Grade) -> Self {
+        Self {
+            grade,
+            planar: Planar::default(),
+        }
+    }
+}
+
+impl PostFilter for GradeFilter {
+    fn apply(&mut self, _ctx: &EffectContext, frame: &mut Vec<Color>) {
+        let grade = self.grade;
+        if grade.adjusts_hsv() {
+            self.planar
+                .load_with(frame, |color| grade.adjust_hsv(color));
+        } else {
+            self.planar.load(frame);
+        }
+        let warmth = grade.temperature * 0.2;
+        let [r, g, b] = self.planar.channels_mut();
+        grade.apply_channel(r, 1.0 + warmth);
+        grade.apply_channel(g, 1.0);
+        grade.apply_channel(b, 1.0 - warmth);
+        self.planar.store(frame);
+    }
+}
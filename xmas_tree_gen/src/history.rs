@@ -0,0 +1,187 @@
+//! Running totals kept by `live` across every run, to see how long the tree has really been on,
+//! what it showed, and whether an output (usually one over WiFi) keeps failing. They're saved
+//! every so often as JSON, and the `stats` command summarises them.
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use xmas_tree_common::output::SinkStats;
+
+use crate::report::Report;
+
+/// How often the totals are saved, so no more than this is lost if the process is killed.
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ShowTotals {
+    /// Times the show was switched to.
+    pub plays: u64,
+    pub seconds: f64,
+    /// Time spent with an effect cut back to keep up with the frame rate.
+    #[serde(default)]
+    pub degraded_seconds: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OutputTotals {
+    pub frames: u64,
+    pub errors: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct History {
+    pub runs: u64,
+    pub runtime_secs: f64,
+    /// When the first and latest runs started, in RFC 3339.
+    pub first_run: Option<String>,
+    pub last_run: Option<String>,
+    pub shows: BTreeMap<String, ShowTotals>,
+    pub outputs: BTreeMap<String, OutputTotals>,
+}
+
+impl History {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        match fs::read_to_string(path) {
+            Ok(json) => Ok(serde_json::from_str(&json)
+                .map_err(|e| format!("Cannot read history from {}: {}", path.display(), e))?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("Cannot read {}: {}", path.display(), e).into()),
+        }
+    }
+}
+
+/// Records one run of `live` on top of the totals from earlier runs.
+pub struct HistoryLog {
+    path: PathBuf,
+    /// The totals as they were before this run.
+    before: History,
+    started: Instant,
+    shows: BTreeMap<String, ShowTotals>,
+    show: Option<String>,
+    /// Not set until the first frame, which is saved straight away to record the run.
+    last_saved: Option<Instant>,
+}
+
+impl HistoryLog {
+    pub fn start(path: PathBuf) -> Result<Self, Box<dyn Error>> {
+        let mut before = History::load(&path)?;
+        let now = Local::now().to_rfc3339();
+        before.runs += 1;
+        before.first_run.get_or_insert_with(|| now.clone());
+        before.last_run = Some(now);
+        Ok(Self {
+            path,
+            before,
+            started: Instant::now(),
+            shows: BTreeMap::new(),
+            show: None,
+            last_saved: None,
+        })
+    }
+
+    /// Records a frame of `show`, and whether it was degraded, saving the totals if it's time to.
+    pub fn frame<'a>(
+        &mut self,
+        show: &str,
+        fps: f32,
+        degraded: bool,
+        outputs: impl Iterator<Item = (&'a str, SinkStats)>,
+    ) -> Result<(), Box<dyn Error>> {
+        let totals = self.shows.entry(show.into()).or_default();
+        if self.show.as_deref() != Some(show) {
+            totals.plays += 1;
+            self.show = Some(show.into());
+        }
+        totals.seconds += 1.0 / fps as f64;
+        if degraded {
+            totals.degraded_seconds += 1.0 / fps as f64;
+        }
+        if self
+            .last_saved
+            .is_none_or(|saved| saved.elapsed() >= SAVE_INTERVAL)
+        {
+            self.save(outputs)?;
+        }
+        Ok(())
+    }
+
+    pub fn save<'a>(
+        &mut self,
+        outputs: impl Iterator<Item = (&'a str, SinkStats)>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.last_saved = Some(Instant::now());
+        let mut history = self.before.clone();
+        history.runtime_secs += self.started.elapsed().as_secs_f64();
+        for (name, run) in &self.shows {
+            let totals = history.shows.entry(name.clone()).or_default();
+            totals.plays += run.plays;
+            totals.seconds += run.seconds;
+            totals.degraded_seconds += run.degraded_seconds;
+        }
+        for (name, stats) in outputs {
+            let totals = history.outputs.entry(name.into()).or_default();
+            totals.frames += stats.frames;
+            totals.errors += stats.errors;
+        }
+        // Write to a temporary file first so a crash mid-save leaves the old totals intact
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(&history)?)
+            .map_err(|e| format!("Cannot write {}: {}", tmp_path.display(), e))?;
+        fs::rename(&tmp_path, &self.path)
+            .map_err(|e| format!("Cannot write {}: {}", self.path.display(), e))?;
+        Ok(())
+    }
+}
+
+impl Report for History {
+    fn write_text(&self, out: &mut dyn io::Write) -> io::Result<()> {
+        writeln!(out, "Runs:              {}", self.runs)?;
+        writeln!(
+            out,
+            "Runtime:           {:.1} hours",
+            self.runtime_secs / 3600.0
+        )?;
+        if let (Some(first), Some(last)) = (&self.first_run, &self.last_run) {
+            writeln!(out, "First run:         {}", first)?;
+            writeln!(out, "Latest run:        {}", last)?;
+        }
+        let mut shows: Vec<_> = self.shows.iter().collect();
+        shows.sort_by(|a, b| b.1.seconds.total_cmp(&a.1.seconds));
+        for (name, totals) in shows {
+            write!(
+                out,
+                "Show:              {} ({} plays, {:.1} hours",
+                name,
+                totals.plays,
+                totals.seconds / 3600.0
+            )?;
+            if totals.degraded_seconds > 0.0 {
+                write!(
+                    out,
+                    ", {:.1}% degraded",
+                    totals.degraded_seconds / totals.seconds.max(f64::EPSILON) * 100.0
+                )?;
+            }
+            writeln!(out, ")")?;
+        }
+        for (name, totals) in &self.outputs {
+            let rate = totals.errors as f64 / (totals.frames + totals.errors).max(1) as f64;
+            writeln!(
+                out,
+                "Output:            {} ({} frames, {} errors, {:.2}%)",
+                name,
+                totals.frames,
+                totals.errors,
+                rate * 100.0
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+//! Small Rhai scripts run on a span of a composed show's frames, for one-off tweaks such as a
+//! flash on a drum hit, which would otherwise mean editing the rendered sequence by hand. Each
+//! frame of its span, a hook is run with `frame` (counted from the start of the show), `time` in
+//! seconds, `progress` from 0 at the start of the span to 1 at its end, and `coords`, and tells
+//! the compositor what to change by calling:
+//!
+//! - `fill(color)` to set every LED to `color`, or `fill(color, amount)` to blend towards it
+//! - `dim(factor)` to scale every LED's brightness
+//! - `set(led, color)` to set one LED
+//! - `dim_layer(factor)` to scale the opacity of the layer of each effect playing
+//!
+//! Changes to the frame apply after any transition and captions, in the order they were made.
+
+use std::{cell::RefCell, error::Error, path::Path, rc::Rc};
+
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Scope, AST, FLOAT, INT};
+use tracing::error;
+
+use crate::{
+    effects::{mix, Color, Coord},
+    script::{self, SandboxOpt},
+};
+
+/// A change a hook asks the compositor for.
+#[derive(Debug, Clone, Copy)]
+enum Command {
+    Fill(Color, f32),
+    Dim(f32),
+    Set(usize, Color),
+    DimLayer(f32),
+}
+
+pub struct Hook {
+    /// Shown in errors, e.g. `Hook 2`.
+    pub name: String,
+    pub start: usize,
+    pub len: usize,
+    pub script: String,
+}
+
+struct Compiled {
+    hook: Hook,
+    ast: AST,
+    /// Only the first error is reported, rather than one for every frame.
+    failed: bool,
+}
+
+/// What the hooks playing on one frame asked for.
+#[derive(Default)]
+pub struct Tweaks {
+    commands: Vec<Command>,
+}
+
+impl Tweaks {
+    /// How much the layers' opacity is scaled by.
+    pub fn layer_opacity(&self) -> f32 {
+        self.commands
+            .iter()
+            .map(|command| match command {
+                Command::DimLayer(factor) => *factor,
+                _ => 1.0,
+            })
+            .product()
+    }
+
+    /// Makes the changes to the finished frame.
+    pub fn apply(&self, colors: &mut [Color]) {
+        for command in &self.commands {
+            match *command {
+                Command::Fill(color, amount) => {
+                    for c in colors.iter_mut() {
+                        *c = mix(*c, color, amount);
+                    }
+                }
+                Command::Dim(factor) => {
+                    for c in colors.iter_mut() {
+                        *c = (c.0 * factor, c.1 * factor, c.2 * factor);
+                    }
+                }
+                Command::Set(led, color) => {
+                    if let Some(c) = colors.get_mut(led) {
+                        *c = color;
+                    }
+                }
+                Command::DimLayer(_) => {}
+            }
+        }
+    }
+}
+
+pub struct Hooks {
+    engine: Engine,
+    sandbox: SandboxOpt,
+    hooks: Vec<Compiled>,
+    /// Where the functions hooks call record what they asked for.
+    commands: Rc<RefCell<Vec<Command>>>,
+    coords: Array,
+}
+
+impl Hooks {
+    /// Compiles the hooks, which may import modules from `dir` if the sandbox allows.
+    pub fn compile(
+        hooks: Vec<Hook>,
+        sandbox: &SandboxOpt,
+        dir: &Path,
+        coords: &[Coord],
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut engine = script::engine(sandbox, dir);
+        let commands = Rc::new(RefCell::new(Vec::new()));
+        let color =
+            |value: Array| script::to_color(value.into()).map_err(Box::<EvalAltResult>::from);
+        let record = |commands: &Rc<RefCell<Vec<Command>>>| {
+            let commands = commands.clone();
+            move |command| commands.borrow_mut().push(command)
+        };
+        let push = record(&commands);
+        engine.register_fn(
+            "fill",
+            move |value: Array| -> Result<(), Box<EvalAltResult>> {
+                push(Command::Fill(color(value)?, 1.0));
+                Ok(())
+            },
+        );
+        let push = record(&commands);
+        engine.register_fn(
+            "fill",
+            move |value: Array, amount: FLOAT| -> Result<(), Box<EvalAltResult>> {
+                push(Command::Fill(
+                    color(value)?,
+                    (amount as f32).clamp(0.0, 1.0),
+                ));
+                Ok(())
+            },
+        );
+        let push = record(&commands);
+        engine.register_fn("dim", move |factor: FLOAT| {
+            push(Command::Dim((factor as f32).max(0.0)))
+        });
+        let push = record(&commands);
+        engine.register_fn(
+            "set",
+            move |led: INT, value: Array| -> Result<(), Box<EvalAltResult>> {
+                push(Command::Set(led.max(0) as usize, color(value)?));
+                Ok(())
+            },
+        );
+        let push = record(&commands);
+        engine.register_fn("dim_layer", move |factor: FLOAT| {
+            push(Command::DimLayer((factor as f32).max(0.0)))
+        });
+
+        let hooks = hooks
+            .into_iter()
+            .map(|hook| {
+                let ast = engine
+                    .compile(&hook.script)
+                    .map_err(|e| format!("Error in {}: {}", hook.name, e))?;
+                Ok(Compiled {
+                    hook,
+                    ast,
+                    failed: false,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self {
+            engine,
+            sandbox: sandbox.clone(),
+            hooks,
+            commands,
+            coords: coords
+                .iter()
+                .map(|&coord| script::to_array(coord).into())
+                .collect(),
+        })
+    }
+
+    /// Runs the hooks playing on `frame`. A hook which fails changes nothing.
+    pub fn run(&mut self, frame: usize, fps: f32) -> Tweaks {
+        let mut tweaks = Tweaks::default();
+        let Self {
+            engine,
+            sandbox,
+            hooks,
+            commands,
+            coords,
+        } = self;
+        for compiled in hooks.iter_mut() {
+            let hook = &compiled.hook;
+            if !(hook.start..hook.start + hook.len).contains(&frame) {
+                continue;
+            }
+            let progress = match hook.len {
+                1 => 1.0,
+                len => (frame - hook.start) as FLOAT / (len - 1) as FLOAT,
+            };
+            let mut scope = Scope::new();
+            scope
+                .push_constant("frame", frame as INT)
+                .push_constant("time", frame as FLOAT / fps as FLOAT)
+                .push_constant("progress", progress)
+                .push_constant("coords", Dynamic::from_array(coords.clone()));
+            commands.borrow_mut().clear();
+            match engine.run_ast_with_scope(&mut scope, &compiled.ast) {
+                Ok(()) => tweaks.commands.append(&mut commands.borrow_mut()),
+                Err(e) if !compiled.failed => {
+                    error!(
+                        "{} failed at frame {}: {}",
+                        hook.name,
+                        frame,
+                        sandbox.describe(&e)
+                    );
+                    compiled.failed = true;
+                }
+                Err(_) => {}
+            }
+        }
+        tweaks
+    }
+}
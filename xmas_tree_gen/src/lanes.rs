@@ -0,0 +1,102 @@
+//! Filter math over frames split into one contiguous slice per channel. Values are processed a
+//! fixed number at a time, which the compiler turns into SIMD instructions without needing the
+//! nightly-only `std::simd`.
+
+use std::convert::TryInto;
+
+use xmas_tree_common::palette::Palette;
+
+use crate::effects::Color;
+
+/// Values processed together; eight `f32`s fill an AVX register.
+pub const LANES: usize = 8;
+
+/// A frame stored as separate red, green and blue slices. Kept by filters between frames so
+/// loading doesn't allocate.
+#[derive(Debug, Default)]
+pub struct Planar {
+    pub r: Vec<f32>,
+    pub g: Vec<f32>,
+    pub b: Vec<f32>,
+}
+
+impl Planar {
+    pub fn load(&mut self, frame: &[Color]) {
+        self.load_with(frame, |color| color);
+    }
+
+    /// Loads `f(color)` for each color of a frame.
+    pub fn load_with(&mut self, frame: &[Color], f: impl Fn(Color) -> Color) {
+        self.r.resize(frame.len(), 0.0);
+        self.g.resize(frame.len(), 0.0);
+        self.b.resize(frame.len(), 0.0);
+        for (i, &color) in frame.iter().enumerate() {
+            let (r, g, b) = f(color);
+            self.r[i] = r;
+            self.g[i] = g;
+            self.b[i] = b;
+        }
+    }
+
+    pub fn store(&self, frame: &mut [Color]) {
+        for (((color, &r), &g), &b) in frame.iter_mut().zip(&self.r).zip(&self.g).zip(&self.b) {
+            *color = (r, g, b);
+        }
+    }
+
+    pub fn channels_mut(&mut self) -> [&mut [f32]; 3] {
+        [&mut self.r, &mut self.g, &mut self.b]
+    }
+}
+
+/// Replaces every value with `f(value)`.
+#[inline]
+pub fn map(values: &mut [f32], f: impl Fn(f32) -> f32) {
+    let mut chunks = values.chunks_exact_mut(LANES);
+    for chunk in &mut chunks {
+        let chunk: &mut [f32; LANES] = chunk.try_into().unwrap();
+        for v in chunk {
+            *v = f(*v);
+        }
+    }
+    for v in chunks.into_remainder() {
+        *v = f(*v);
+    }
+}
+
+/// Replaces every LED with its nearest palette entry. Matches `Palette::nearest`, including
+/// picking the earlier entry on ties.
+pub fn quantize(planar: &mut Planar, palette: &Palette) {
+    let len = planar.r.len();
+    let whole = len - len % LANES;
+    for start in (0..whole).step_by(LANES) {
+        let end = start + LANES;
+        let r: &mut [f32; LANES] = (&mut planar.r[start..end]).try_into().unwrap();
+        let g: &mut [f32; LANES] = (&mut planar.g[start..end]).try_into().unwrap();
+        let b: &mut [f32; LANES] = (&mut planar.b[start..end]).try_into().unwrap();
+
+        let mut best_distance = [f32::INFINITY; LANES];
+        let mut best = [palette.colors[0]; LANES];
+        for &entry in &palette.colors {
+            for i in 0..LANES {
+                let distance =
+                    (r[i] - entry.0).powi(2) + (g[i] - entry.1).powi(2) + (b[i] - entry.2).powi(2);
+                if distance < best_distance[i] {
+                    best_distance[i] = distance;
+                    best[i] = entry;
+                }
+            }
+        }
+        for i in 0..LANES {
+            r[i] = best[i].0;
+            g[i] = best[i].1;
+            b[i] = best[i].2;
+        }
+    }
+    for i in whole..len {
+        let nearest = palette.nearest((planar.r[i], planar.g[i], planar.b[i]));
+        planar.r[i] = nearest.0;
+        planar.g[i] = nearest.1;
+        planar.b[i] = nearest.2;
+    }
+}
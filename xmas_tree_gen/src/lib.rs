@@ -0,0 +1,694 @@
+//! The commands of `xmas_tree_gen`, run by its binary. [`stress`] is public as well, for the
+//! benchmarks in `benches/`, and so is [`tweak`], for the player's parameter panel.
+
+use std::{
+    error::Error,
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+    process,
+    time::Duration,
+};
+
+use accessibility::ColorVision;
+use calibrate::CalibrateCommand;
+use compose::ComposeOpt;
+use generate::GenerateOpt;
+use indicatif::{ProgressBar, ProgressStyle};
+use live::LiveOpt;
+use report::OutputFormat;
+use structopt::{clap::ArgMatches, StructOpt};
+use tracing::{info, warn, Level};
+use xmas_tree_common::{
+    calibration::Calibration,
+    coords::{Handedness, Layout, LoadOptions, Units, UpAxis},
+    hardware::HardwareProfile,
+    project::Project,
+    seal::{self, SealMode},
+    sequence::SequenceFormat,
+};
+
+mod accessibility;
+mod advent;
+mod align;
+mod analyze;
+mod audio;
+mod bench;
+mod budget;
+mod calibrate;
+mod captions;
+mod capture;
+mod checkpoint;
+mod compose;
+mod convert;
+mod coords;
+mod correction;
+mod debugger;
+mod diff;
+mod docs;
+mod effects;
+mod export;
+mod filters;
+mod generate;
+mod grading;
+mod history;
+mod hooks;
+mod lanes;
+mod light_sensor;
+mod live;
+mod mask;
+mod media;
+mod memo;
+mod meta;
+mod neighbours;
+mod optimize;
+mod overlay;
+mod params;
+mod path;
+mod playback;
+mod power;
+mod report;
+mod review;
+mod rotation;
+mod safe;
+mod script;
+mod self_test;
+mod share;
+mod stats;
+pub mod stress;
+mod text;
+mod thumbnail;
+pub mod tweak;
+mod validate;
+mod visibility;
+mod votes;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "xmas_tree_gen",
+    about = "Generates christmas tree light sequences."
+)]
+struct Opt {
+    /// Project file (`.xtree`) with the coordinates, calibration, outputs, palettes and playlist
+    /// to use when they aren't given as options.
+    #[structopt(long = "project", parse(from_os_str), global = true)]
+    project_path: Option<PathBuf>,
+    #[structopt(
+        long = "coords",
+        parse(from_os_str),
+        default_value = "coords/coords_2021.csv",
+        global = true
+    )]
+    coords_path: PathBuf,
+    /// Which axis of the coordinates file points up the tree: z, y or auto.
+    #[structopt(long, alias = "up", default_value = "auto", global = true)]
+    up_axis: UpAxis,
+    /// Handedness of the coordinates file: right or left.
+    #[structopt(long, default_value = "right", global = true)]
+    handedness: Handedness,
+    /// Units of the coordinates file: gift, m, cm or auto. Scans are rescaled to GIFT units.
+    #[structopt(long, default_value = "auto", global = true)]
+    units: Units,
+    /// Move LEDs whose captured position is clearly out of line with their neighbours on the
+    /// string back between them.
+    #[structopt(long, global = true)]
+    repair_outliers: bool,
+    /// Place LEDs the scan didn't find (left at 0,0,0) along the string, between the nearest
+    /// found LEDs either side.
+    #[structopt(long, global = true)]
+    fill_missing: bool,
+    #[structopt(long, default_value = "34.7", global = true)]
+    fps: f32,
+    #[structopt(long, default_value = "42", global = true)]
+    seed: u64,
+    /// JSON hardware profile, used to warn when sequences play faster than the LEDs can refresh.
+    #[structopt(long, parse(from_os_str), global = true)]
+    hardware: Option<PathBuf>,
+    /// Only log warnings and errors, and hide the progress bar.
+    #[structopt(short, long, global = true)]
+    quiet: bool,
+    /// Seal the sequences written, with the project's key or `XMAS_TREE_KEY`: `sign` so changes
+    /// are detected, or `encrypt` so they can only be played with the key too.
+    #[structopt(long, global = true)]
+    seal: Option<SealMode>,
+    /// Don't write a thumbnail picture next to each sequence. Sequences are still listed in
+    /// their directory's `sequences.jsonl` manifest.
+    #[structopt(long, global = true)]
+    no_thumbnail: bool,
+    /// Log more detail (repeat for even more).
+    #[structopt(short, long, parse(from_occurrences), global = true)]
+    verbose: u8,
+    #[structopt(flatten)]
+    sandbox: script::SandboxOpt,
+    #[structopt(subcommand)]
+    command: Command,
+    #[structopt(skip)]
+    project: Project,
+    /// The project's calibration, which filters apply unless given `--calibration`.
+    #[structopt(skip)]
+    calibration: Option<Calibration>,
+}
+
+// Parsed once at startup, so the size of the generate options doesn't matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Generates a sequence from a single effect.
+    Generate(GenerateOpt),
+    /// Generates a sequence from an effect written as a Rhai script, e.g. `script my_effect.rhai`.
+    /// Takes the same options as `generate`; with `--watch` the script reloads when it changes.
+    Script(GenerateOpt),
+    /// Renders a playlist of effects into one sequence, with transitions and blended layers.
+    Compose(ComposeOpt),
+    /// Runs an effect in real time, sending frames to outputs such as a WLED controller.
+    Live(LiveOpt),
+    /// Reports statistics about an existing sequence file.
+    Analyze {
+        #[structopt(parse(from_os_str))]
+        sequence_path: PathBuf,
+        /// Also measure how much of each frame's contrast survives this color vision
+        /// (deuteranopia or protanopia), listing the frames which rely on telling red from green.
+        #[structopt(long)]
+        color_vision: Option<ColorVision>,
+        #[structopt(long, default_value = "text")]
+        format: OutputFormat,
+    },
+    /// Lines a sequence up with its music, by finding the delay and speed change which put the
+    /// most of its sudden changes on notes and hits in the audio.
+    Align {
+        #[structopt(parse(from_os_str))]
+        sequence_path: PathBuf,
+        /// The music, as a WAV file.
+        #[structopt(parse(from_os_str))]
+        audio: PathBuf,
+        /// Write the aligned sequence here, rather than only suggesting the change.
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// Format of the aligned sequence.
+        #[structopt(long, default_value = "csv")]
+        sequence_format: SequenceFormat,
+        /// Largest delay to try either way, in seconds.
+        #[structopt(long, default_value = "2")]
+        max_offset: f32,
+        /// Largest speed change to try either way, e.g. 0.02 for 2% faster or slower.
+        #[structopt(long, default_value = "0.02")]
+        max_speed: f32,
+        #[structopt(long, default_value = "text")]
+        format: OutputFormat,
+    },
+    /// Rewrites a sequence in the delta format, collapsing repeated and near-identical frames.
+    Optimize {
+        #[structopt(parse(from_os_str))]
+        sequence_path: PathBuf,
+        #[structopt(short, long, parse(from_os_str))]
+        output: PathBuf,
+        /// Largest per-channel change (0-255) which is treated as no change.
+        #[structopt(long, default_value = "0")]
+        tolerance: u8,
+        #[structopt(long, default_value = "text")]
+        format: OutputFormat,
+    },
+    /// Converts a sequence, such as a community GIFT CSV, to the canonical CSV, delta or fseq
+    /// format.
+    Convert {
+        #[structopt(parse(from_os_str))]
+        sequence_path: PathBuf,
+        #[structopt(short, long, parse(from_os_str))]
+        output: PathBuf,
+        #[structopt(long, default_value = "csv")]
+        format: SequenceFormat,
+    },
+    /// Compares two sequences frame by frame, exiting with status 1 if they differ.
+    Diff {
+        #[structopt(parse(from_os_str))]
+        a: PathBuf,
+        #[structopt(parse(from_os_str))]
+        b: PathBuf,
+        /// Write the error of every LED in every frame to this CSV file.
+        #[structopt(long, parse(from_os_str))]
+        heatmap: Option<PathBuf>,
+        #[structopt(long, default_value = "text")]
+        format: OutputFormat,
+    },
+    /// Checks a sequence CSV against the rules for GIFT submissions and the coordinate file,
+    /// exiting with status 1 if it would be rejected.
+    Validate {
+        #[structopt(parse(from_os_str))]
+        sequence_path: PathBuf,
+        /// Warn when the whole tree flashes more than this many times a second.
+        #[structopt(long, default_value = "3")]
+        max_flash_hz: f32,
+        /// Fail when the sequence has more frames than this.
+        #[structopt(long)]
+        max_frames: Option<usize>,
+        #[structopt(long, default_value = "text")]
+        format: OutputFormat,
+    },
+    /// Lists the review notes made on a sequence in the player, with their times.
+    Notes {
+        #[structopt(parse(from_os_str))]
+        sequence_path: PathBuf,
+        #[structopt(long, default_value = "text")]
+        format: OutputFormat,
+    },
+    /// Summarises the running totals kept by `live --history`.
+    Stats {
+        #[structopt(parse(from_os_str))]
+        history_path: PathBuf,
+        #[structopt(long, default_value = "text")]
+        format: OutputFormat,
+    },
+    /// Inspects the coordinate file.
+    Coords(CoordsCommand),
+    /// Measures how each LED's brightness and color differ from the rest, using a camera.
+    Calibrate(CalibrateCommand),
+    /// Writes a sequence in a format accepted elsewhere.
+    Export(ExportCommand),
+    /// Times how long each effect takes to render, as a baseline for performance work.
+    Bench {
+        /// Effects to time [default: all of them].
+        effects: Vec<String>,
+        #[structopt(long, default_value = "200")]
+        frames: usize,
+        #[structopt(long, default_value = "text")]
+        format: OutputFormat,
+    },
+    /// Writes Markdown documentation for every effect and its parameters.
+    Docs {
+        /// Write the documentation to this file instead of stdout.
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// Directory the effect preview thumbnails are linked from.
+        #[structopt(long, parse(from_os_str), default_value = "docs/previews")]
+        previews: PathBuf,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum CoordsCommand {
+    /// Reports bounds, duplicate and missing LEDs in the coordinate file.
+    Check {
+        #[structopt(long, default_value = "text")]
+        format: OutputFormat,
+    },
+    /// Estimates how densely packed the LEDs are around each one, overall and by height, to
+    /// see whether `--density-balance` is needed.
+    Density {
+        #[structopt(long, default_value = "text")]
+        format: OutputFormat,
+    },
+    /// Estimates how visible each LED is from outside the tree, listing those hidden inside the
+    /// branches.
+    Visibility {
+        #[structopt(long, default_value = "text")]
+        format: OutputFormat,
+    },
+    /// Writes the nearest neighbour graph and spatial tour used by effects, as CSV or JSON.
+    Graph {
+        /// Number of neighbours per LED [default: the number used by spatial filters].
+        #[structopt(short)]
+        k: Option<usize>,
+        #[structopt(long, default_value = "csv")]
+        format: coords::GraphFormat,
+        /// Write the graph to this file instead of stdout.
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Writes the coordinates with the LEDs the scan didn't find placed between their neighbours,
+    /// and those LEDs listed in a comment at the top, so their positions can be adjusted by hand.
+    Fill {
+        /// Write the coordinates to this file instead of stdout.
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Writes a synthetic coordinate file for stress testing: dense-cone or irregular-scan.
+    Make {
+        layout: coords::Layout,
+        /// Number of LEDs [default: 10000 for dense-cone, 500 for irregular-scan].
+        #[structopt(short = "n", long)]
+        count: Option<usize>,
+        /// Write the coordinates to this file instead of stdout.
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum ExportCommand {
+    /// Writes a CSV for submission to the GIFT community repository.
+    Gift {
+        #[structopt(parse(from_os_str))]
+        sequence_path: PathBuf,
+        #[structopt(short, long, parse(from_os_str))]
+        output: PathBuf,
+    },
+}
+
+/// Runs the command given on the command line.
+pub fn run() -> Result<(), Box<dyn Error>> {
+    let matches = Opt::clap().get_matches();
+    let mut opt = Opt::from_clap(&matches);
+    init_logging(&opt);
+    if let Some(path) = &opt.project_path {
+        let project = Project::load(path)?;
+        apply_project(&mut opt, &matches, project)?;
+    }
+    // Better to find out now than after rendering the whole show
+    if opt.seal.is_some() && seal::key().is_none() {
+        return Err(format!(
+            "--seal needs a key, set in the project or {}",
+            seal::KEY_VARIABLE
+        )
+        .into());
+    }
+    match &opt.command {
+        Command::Generate(gen) => generate::generate(&opt, gen),
+        Command::Script(gen) if script::path(&gen.effect).is_none() => {
+            Err(format!("{} is not a .rhai script", gen.effect).into())
+        }
+        Command::Script(gen) => generate::generate(&opt, gen),
+        Command::Compose(compose) => compose::compose(&opt, compose),
+        Command::Live(live) => live::live(&opt, live),
+        Command::Analyze {
+            sequence_path,
+            color_vision,
+            format,
+        } => {
+            let sequence = xmas_tree_common::sequence::read(sequence_path)?;
+            let hardware = load_hardware(&opt)?;
+            report::emit(
+                &analyze::analyze(&sequence, opt.fps, hardware.as_ref(), *color_vision),
+                *format,
+            )
+        }
+        Command::Align {
+            sequence_path,
+            audio,
+            output,
+            sequence_format,
+            max_offset,
+            max_speed,
+            format,
+        } => report::emit(
+            &align::align(
+                sequence_path,
+                audio,
+                opt.fps,
+                *max_offset,
+                *max_speed,
+                output.as_deref(),
+                *sequence_format,
+                opt.seal,
+            )?,
+            *format,
+        ),
+        Command::Optimize {
+            sequence_path,
+            output,
+            tolerance,
+            format,
+        } => report::emit(
+            &optimize::optimize(sequence_path, output, *tolerance, opt.seal)?,
+            *format,
+        ),
+        Command::Convert {
+            sequence_path,
+            output,
+            format,
+        } => convert::convert(sequence_path, output, *format, opt.fps, opt.seal),
+        Command::Diff {
+            a,
+            b,
+            heatmap,
+            format,
+        } => {
+            let a = xmas_tree_common::sequence::read(a)?;
+            let b = xmas_tree_common::sequence::read(b)?;
+            let report = diff::diff(&a, &b, heatmap.as_deref())?;
+            report::emit(&report, *format)?;
+            if !report.identical {
+                process::exit(1);
+            }
+            Ok(())
+        }
+        Command::Validate {
+            sequence_path,
+            max_flash_hz,
+            max_frames,
+            format,
+        } => {
+            let coords = load_coords(&opt)?;
+            let report = validate::validate(
+                sequence_path,
+                coords.len(),
+                opt.fps,
+                *max_flash_hz,
+                *max_frames,
+            )?;
+            report::emit(&report, *format)?;
+            if !report.is_valid() {
+                process::exit(1);
+            }
+            Ok(())
+        }
+        Command::Notes {
+            sequence_path,
+            format,
+        } => report::emit(&review::notes(sequence_path, opt.fps)?, *format),
+        Command::Stats {
+            history_path,
+            format,
+        } => {
+            if !history_path.exists() {
+                return Err(format!("No history at {}", history_path.display()).into());
+            }
+            report::emit(&history::History::load(history_path)?, *format)
+        }
+        Command::Coords(CoordsCommand::Check { format }) => {
+            let layout = load_layout(&opt, &opt.coords_path)?;
+            report::emit(&coords::check(&layout), *format)
+        }
+        Command::Coords(CoordsCommand::Density { format }) => {
+            let coords = load_coords(&opt)?;
+            report::emit(&coords::density(&coords), *format)
+        }
+        Command::Coords(CoordsCommand::Visibility { format }) => {
+            let coords = load_coords(&opt)?;
+            report::emit(&coords::visibility(&coords), *format)
+        }
+        Command::Coords(CoordsCommand::Graph { k, format, output }) => {
+            let coords = load_coords(&opt)?;
+            let mut out: Box<dyn io::Write> = match output {
+                Some(path) => Box::new(File::create(path)?),
+                None => Box::new(io::stdout()),
+            };
+            let k = k.unwrap_or(neighbours::NEIGHBOUR_COUNT);
+            coords::write_graph(&mut out, &coords, k, *format)
+        }
+        Command::Coords(CoordsCommand::Fill { output }) => {
+            let layout = xmas_tree_common::coords::load(
+                &opt.coords_path,
+                &LoadOptions {
+                    fill_missing: true,
+                    ..load_options(&opt)
+                },
+            )?;
+            let mut out: Box<dyn io::Write> = match output {
+                Some(path) => Box::new(File::create(path)?),
+                None => Box::new(io::stdout()),
+            };
+            coords::write_filled(&mut out, &layout)
+        }
+        Command::Coords(CoordsCommand::Make {
+            layout,
+            count,
+            output,
+        }) => {
+            let mut out: Box<dyn io::Write> = match output {
+                Some(path) => Box::new(File::create(path)?),
+                None => Box::new(io::stdout()),
+            };
+            let count = count.unwrap_or_else(|| layout.default_count());
+            coords::write_coords(&mut out, &coords::make_layout(*layout, count, opt.seed))
+        }
+        Command::Calibrate(CalibrateCommand::Capture {
+            output,
+            camera,
+            dir,
+            settle,
+        }) => {
+            let coords = load_coords(&opt)?;
+            calibrate::capture(
+                coords.len(),
+                output,
+                camera,
+                dir,
+                Duration::from_millis(*settle),
+            )
+        }
+        Command::Calibrate(CalibrateCommand::Analyze {
+            dirs,
+            output,
+            percentile,
+        }) => {
+            let coords = load_coords(&opt)?;
+            calibrate::analyze(coords.len(), dirs, output, *percentile)
+        }
+        Command::Export(ExportCommand::Gift {
+            sequence_path,
+            output,
+        }) => {
+            let coords = load_coords(&opt)?;
+            export::gift(sequence_path, output, coords.len())
+        }
+        Command::Bench {
+            effects,
+            frames,
+            format,
+        } => {
+            let coords = load_coords(&opt)?;
+            report::emit(&bench::bench(&opt, &coords, effects, *frames)?, *format)
+        }
+        Command::Docs { output, previews } => {
+            let mut out: Box<dyn io::Write> = match output {
+                Some(path) => Box::new(File::create(path)?),
+                None => Box::new(io::stdout()),
+            };
+            Ok(docs::write_markdown(&mut out, previews)?)
+        }
+    }
+}
+
+fn init_logging(opt: &Opt) {
+    let level = if opt.quiet {
+        Level::WARN
+    } else {
+        match opt.verbose {
+            0 => Level::INFO,
+            1 => Level::DEBUG,
+            _ => Level::TRACE,
+        }
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+/// Takes the options which weren't given on the command line from the project.
+fn apply_project(
+    opt: &mut Opt,
+    matches: &ArgMatches,
+    project: Project,
+) -> Result<(), Box<dyn Error>> {
+    let unset = |name| matches.occurrences_of(name) == 0;
+    if let Some(key) = &project.key {
+        seal::set_key(key);
+    }
+    if let Some(path) = project.coords.clone().filter(|_| unset("coords_path")) {
+        opt.coords_path = path;
+    }
+    if let Some(up_axis) = project.up_axis.filter(|_| unset("up_axis")) {
+        opt.up_axis = up_axis;
+    }
+    if let Some(handedness) = project.handedness.filter(|_| unset("handedness")) {
+        opt.handedness = handedness;
+    }
+    if let Some(units) = project.units.filter(|_| unset("units")) {
+        opt.units = units;
+    }
+    opt.repair_outliers |= project.repair_outliers;
+    opt.fill_missing |= project.fill_missing;
+    if let Some(fps) = project.fps.filter(|_| unset("fps")) {
+        opt.fps = fps;
+    }
+    if opt.hardware.is_none() {
+        opt.hardware = project.hardware.clone();
+    }
+    opt.calibration = project
+        .calibration
+        .as_deref()
+        .map(Calibration::load)
+        .transpose()?;
+    opt.project = project;
+    Ok(())
+}
+
+fn progress_bar(opt: &Opt, len: usize) -> ProgressBar {
+    if opt.quiet {
+        return ProgressBar::hidden();
+    }
+    let progress = ProgressBar::new(len as u64);
+    progress.set_style(
+        ProgressStyle::default_bar()
+            .template("{bar:40} {pos}/{len} frames [{elapsed_precise} < {eta}] {per_sec}"),
+    );
+    progress
+}
+
+fn load_coords(opt: &Opt) -> Result<Vec<effects::Coord>, Box<dyn Error>> {
+    load_coords_from(opt, &opt.coords_path)
+}
+
+/// Reads a coordinates file other than the tree's, such as the one a sequence was made for, in
+/// the same way.
+fn load_coords_from(opt: &Opt, path: &Path) -> Result<Vec<effects::Coord>, Box<dyn Error>> {
+    Ok(load_layout(opt, path)?.coords)
+}
+
+fn load_options(opt: &Opt) -> LoadOptions {
+    LoadOptions {
+        up_axis: opt.up_axis,
+        handedness: opt.handedness,
+        units: opt.units,
+        repair_outliers: opt.repair_outliers,
+        fill_missing: opt.fill_missing,
+    }
+}
+
+/// Reads a coordinates file along with what was worked out and fixed while reading it.
+fn load_layout(opt: &Opt, path: &Path) -> Result<Layout, Box<dyn Error>> {
+    let layout = xmas_tree_common::coords::load(path, &load_options(opt))?;
+    if opt.up_axis == UpAxis::Auto && layout.up_axis != UpAxis::Z {
+        warn!(
+            "Coordinates look {}-up, use --up-axis to override",
+            layout.up_axis
+        );
+    }
+    if opt.units == Units::Auto && layout.units != Units::Gift {
+        info!(
+            "Rescaling coordinates from {}, use --units to override",
+            layout.units
+        );
+    }
+    if layout.flat {
+        info!("Read a flat layout, with y up");
+    }
+    if !layout.repaired.is_empty() {
+        warn!("Repaired the positions of LEDs {:?}", layout.repaired);
+    }
+    if !layout.filled.is_empty() {
+        info!(
+            "Placed LEDs {:?}, which weren't found, between their neighbours",
+            layout.filled
+        );
+    } else if !layout.missing.is_empty() {
+        warn!(
+            "LEDs {:?} weren't found, use --fill-missing to place them between their neighbours",
+            layout.missing
+        );
+    }
+    Ok(layout)
+}
+
+/// Loads the hardware profile, if any, warning if the frame rate is too high for it.
+fn load_hardware(opt: &Opt) -> Result<Option<HardwareProfile>, Box<dyn Error>> {
+    let profile = match &opt.hardware {
+        Some(path) => HardwareProfile::load(path)?,
+        None => return Ok(None),
+    };
+    if let Some(warning) = profile.check_fps(opt.fps) {
+        warn!("{}", warning);
+    }
+    Ok(Some(profile))
+}
@@ -0,0 +1,273 @@
+//! Follows the brightness of the room with an ambient light sensor, so `live` can dim the tree at
+//! night and turn it up in daylight. The sensor is read every couple of seconds on a thread of
+//! its own, since a slow network sensor mustn't hold up frames, and the brightness eases towards
+//! what each reading calls for rather than jumping when a light is switched on.
+//!
+//! Light is perceived on a log scale, so brightness rises evenly with each doubling of the lux
+//! between `--light-dark` and `--light-bright`.
+
+use std::{
+    error::Error,
+    fs,
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use structopt::StructOpt;
+use tracing::{debug, info, warn};
+
+/// How often the sensor is read.
+const READ_INTERVAL: Duration = Duration::from_secs(2);
+const HTTP_TIMEOUT: Duration = Duration::from_secs(2);
+/// Where Linux lists industrial I/O devices, which light sensors with a kernel driver are.
+const IIO_DEVICES: &str = "/sys/bus/iio/devices";
+
+/// Where readings come from: `http://HOST[:PORT]/PATH`, `iio`, `iio:DEVICE` or a file.
+#[derive(Debug, Clone)]
+pub enum SensorSource {
+    /// A URL giving the lux as a number, or JSON with a `lux` field.
+    Http { host: String, path: String },
+    /// A light sensor's IIO device directory, or the first one found.
+    Iio(Option<PathBuf>),
+    /// A file holding the lux, kept up to date by another program.
+    File(PathBuf),
+}
+
+impl FromStr for SensorSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(rest) = s.strip_prefix("http://") {
+            let (host, path) = match rest.find('/') {
+                Some(slash) => rest.split_at(slash),
+                None => (rest, "/"),
+            };
+            if host.is_empty() {
+                return Err(format!("No host in light sensor URL: {}", s));
+            }
+            return Ok(Self::Http {
+                host: host.into(),
+                path: path.into(),
+            });
+        }
+        match s.split_once(':') {
+            None if s == "iio" => Ok(Self::Iio(None)),
+            Some(("iio", device)) => Ok(Self::Iio(Some(Path::new(IIO_DEVICES).join(device)))),
+            _ => Ok(Self::File(s.into())),
+        }
+    }
+}
+
+/// Reads a lux value, written either as a plain number or as JSON with a `lux` or `illuminance`
+/// field.
+fn parse_lux(text: &str) -> Result<f32, Box<dyn Error>> {
+    let text = text.trim();
+    if let Ok(lux) = text.parse() {
+        return Ok(lux);
+    }
+    let value: serde_json::Value =
+        serde_json::from_str(text).map_err(|_| format!("Expected a lux value, got {:?}", text))?;
+    ["lux", "illuminance"]
+        .iter()
+        .find_map(|field| value.get(field)?.as_f64())
+        .map(|lux| lux as f32)
+        .ok_or_else(|| format!("No lux field in {}", text).into())
+}
+
+/// The lux from an IIO device, which either gives it directly or as a raw count and scale.
+fn read_iio(device: &Path) -> Result<f32, Box<dyn Error>> {
+    let read = |name: &str| -> Option<f32> {
+        fs::read_to_string(device.join(name))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    };
+    if let Some(lux) = read("in_illuminance_input").or_else(|| read("in_illuminance0_input")) {
+        return Ok(lux);
+    }
+    let raw = read("in_illuminance_raw")
+        .or_else(|| read("in_illuminance0_raw"))
+        .ok_or_else(|| format!("{} isn't a light sensor", device.display()))?;
+    let scale = read("in_illuminance_scale")
+        .or_else(|| read("in_illuminance0_scale"))
+        .unwrap_or(1.0);
+    let offset = read("in_illuminance_offset").unwrap_or(0.0);
+    Ok((raw + offset) * scale)
+}
+
+/// The first IIO device which measures light.
+fn find_iio() -> Result<PathBuf, Box<dyn Error>> {
+    let mut devices: Vec<PathBuf> = fs::read_dir(IIO_DEVICES)
+        .map_err(|e| format!("Cannot list {}: {}", IIO_DEVICES, e))?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .collect();
+    devices.sort();
+    devices
+        .into_iter()
+        .find(|device| read_iio(device).is_ok())
+        .ok_or_else(|| {
+            "No light sensor found, is its kernel driver loaded (e.g. a device tree overlay for \
+             an I2C sensor)?"
+                .into()
+        })
+}
+
+/// The body of a plain HTTP GET, which sensors on the local network answer.
+pub(crate) fn get(host: &str, path: &str) -> Result<String, Box<dyn Error>> {
+    let addr = if host.contains(':') {
+        host.to_socket_addrs()
+    } else {
+        (host, 80).to_socket_addrs()
+    }?
+    .next()
+    .ok_or_else(|| format!("Cannot resolve {}", host))?;
+    let mut stream = TcpStream::connect_timeout(&addr, HTTP_TIMEOUT)?;
+    stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+    write!(
+        stream,
+        "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, host
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or("Malformed HTTP response")?;
+    let status = head.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(format!("{} replied {}", host, status).into());
+    }
+    Ok(body.into())
+}
+
+impl SensorSource {
+    fn read_lux(&mut self) -> Result<f32, Box<dyn Error>> {
+        match self {
+            Self::Http { host, path } => parse_lux(&get(host, path)?),
+            Self::Iio(device) => {
+                let device = match device {
+                    Some(device) => device,
+                    None => device.insert(find_iio()?),
+                };
+                read_iio(device)
+            }
+            Self::File(path) => parse_lux(
+                &fs::read_to_string(&*path)
+                    .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?,
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct LightSensorOpt {
+    /// Dim the outputs in a dark room and turn them up in daylight, following an ambient light
+    /// sensor: `http://HOST/PATH` giving the lux as a number (or JSON with a `lux` field), `iio`
+    /// for the first light sensor with a kernel driver (such as a BH1750 or TSL2561 on I2C),
+    /// `iio:DEVICE` for a particular one, or a file another program keeps the lux in.
+    #[structopt(long)]
+    light_sensor: Option<SensorSource>,
+    /// Lux at and below which the outputs are at their dimmest.
+    #[structopt(long, default_value = "2")]
+    light_dark: f32,
+    /// Lux at and above which the outputs are at full brightness.
+    #[structopt(long, default_value = "400")]
+    light_bright: f32,
+    /// Brightness of the outputs in the dark, from 0 to 1.
+    #[structopt(long, default_value = "0.25")]
+    light_min: f32,
+    /// Seconds the brightness takes to follow most of a change in the room, so headlights
+    /// passing or a lamp switched on don't make the tree jump.
+    #[structopt(long, default_value = "20")]
+    light_smoothing: f32,
+}
+
+impl LightSensorOpt {
+    /// Checks the options make sense, and that the sensor can be read now.
+    pub fn start(&self) -> Result<Option<LightSensor>, Box<dyn Error>> {
+        let mut source = match &self.light_sensor {
+            Some(source) => source.clone(),
+            None => return Ok(None),
+        };
+        if !(self.light_dark > 0.0 && self.light_dark < self.light_bright) {
+            return Err("--light-dark must be above 0 and below --light-bright".into());
+        }
+        if !(0.0..=1.0).contains(&self.light_min) {
+            return Err("--light-min must be between 0 and 1".into());
+        }
+        // Reading once up front means a dark room doesn't start with a flash of full brightness
+        let first = source
+            .read_lux()
+            .map_err(|e| format!("Cannot read the light sensor: {}", e))?;
+        let level = self.level_for(first);
+        info!(
+            "Light sensor reads {:.1} lux, starting at {:.0}% brightness",
+            first,
+            level * 100.0
+        );
+        let reading = Arc::new(Mutex::new(first));
+        let shared = reading.clone();
+        thread::spawn(move || {
+            let mut failing = false;
+            loop {
+                thread::sleep(READ_INTERVAL);
+                match source.read_lux() {
+                    Ok(lux) => {
+                        if failing {
+                            info!("Light sensor is back, reading {:.1} lux", lux);
+                            failing = false;
+                        }
+                        debug!("Light sensor reads {:.1} lux", lux);
+                        *shared.lock().unwrap() = lux;
+                    }
+                    // Brightness holds at the last reading until the sensor comes back
+                    Err(e) if !failing => {
+                        warn!("Cannot read the light sensor: {}", e);
+                        failing = true;
+                    }
+                    Err(e) => debug!("Cannot read the light sensor: {}", e),
+                }
+            }
+        });
+        Ok(Some(LightSensor {
+            opt: self.clone(),
+            reading,
+            level,
+            updated: Instant::now(),
+        }))
+    }
+
+    /// The brightness a room this bright calls for.
+    fn level_for(&self, lux: f32) -> f32 {
+        let (dark, bright) = (self.light_dark.ln(), self.light_bright.ln());
+        let t = ((lux.max(f32::MIN_POSITIVE).ln() - dark) / (bright - dark)).clamp(0.0, 1.0);
+        self.light_min + (1.0 - self.light_min) * t
+    }
+}
+
+pub struct LightSensor {
+    opt: LightSensorOpt,
+    reading: Arc<Mutex<f32>>,
+    level: f32,
+    updated: Instant,
+}
+
+impl LightSensor {
+    /// The brightness to send at now, from 0 to 1, easing towards what the latest reading calls
+    /// for.
+    pub fn level(&mut self) -> f32 {
+        let now = Instant::now();
+        let elapsed = (now - self.updated).as_secs_f32();
+        self.updated = now;
+        let target = self.opt.level_for(*self.reading.lock().unwrap());
+        let t = 1.0 - (-elapsed / self.opt.light_smoothing.max(f32::EPSILON)).exp();
+        self.level += (target - self.level) * t;
+        self.level
+    }
+}
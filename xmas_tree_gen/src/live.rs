@@ -0,0 +1,903 @@
+use std::{
+    error::Error,
+    fs, mem,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use chrono::{Local, NaiveDate, Timelike};
+use structopt::StructOpt;
+use tracing::{debug, info, warn};
+use xmas_tree_common::{
+    color::mix_hue,
+    output::{self, OutputGroup, OutputSink, Watchdog},
+    sequence::Rgb,
+};
+
+use crate::{
+    advent::{self, AdventState, Calendar, Entry},
+    budget::{Budget, BudgetOpt, Degradation},
+    effects::{self, Bounds, Color, Coord, Effect, EffectContext, EffectInfo},
+    filters::{FilterOpt, PostFilter},
+    generate::{to_rgb, Length},
+    history::HistoryLog,
+    light_sensor::{LightSensor, LightSensorOpt},
+    load_coords, load_hardware,
+    meta::MetaOpt,
+    neighbours::{NeighbourGraph, NEIGHBOUR_COUNT},
+    overlay::Overlays,
+    params::{ParamArg, Params},
+    playback::Playback,
+    power::{self, PowerMode, PowerOpt},
+    safe,
+    script::{self, ScriptEffect},
+    self_test,
+    share::ShareServer,
+    visibility,
+    votes::Votes,
+    Opt,
+};
+
+#[derive(Debug, StructOpt)]
+pub struct LiveOpt {
+    /// Effect to run, or a `.rhai` script, which is reloaded whenever it's saved. Not needed
+    /// with --ambient or --advent.
+    #[structopt(required_unless_one = &["ambient", "advent"])]
+    effect: Option<String>,
+    /// Sets an effect parameter, as NAME=VALUE.
+    #[structopt(long = "param", number_of_values = 1)]
+    params: Vec<ParamArg>,
+    /// Where to send frames, e.g. `wled://192.168.1.50`, `ddp://192.168.1.50`,
+    /// `e131://?universe=1&order=grb` (multicast), `artnet://192.168.1.255`,
+    /// `opc://localhost`, `serial:///dev/ttyUSB0`, `gpio://` (a Raspberry Pi's SPI pins, in a
+    /// build with the `gpio` feature), `preview://` (the player's `ddp://` window on this machine)
+    /// or `file://session.csv`. Repeat to send to several outputs [default: the project's
+    /// outputs]. Add `mirror=HOST` to an output to watch what it's sent, after its color
+    /// pipeline, in `xmas_tree_player ddp://` on HOST.
+    #[structopt(long = "output", number_of_values = 1)]
+    outputs: Vec<String>,
+    /// Frames before the effect loops, or `auto[:CYCLES]` to use its natural cycle.
+    #[structopt(long, default_value = "auto")]
+    len: Length,
+    /// Sets a parameter from the contents of a file whenever it changes, as NAME=PATH, so live
+    /// data (e.g. a tracker's longitude, written by another program) can drive the effect.
+    #[structopt(long = "feed", number_of_values = 1)]
+    feeds: Vec<ParamArg>,
+    /// Follow the time of day with built-in themes instead of running a single effect.
+    #[structopt(long)]
+    ambient: bool,
+    /// Run an advent calendar, unlocking the next entry of this file each day of December with a
+    /// reveal the first time it is shown.
+    #[structopt(long, parse(from_os_str), conflicts_with = "ambient")]
+    advent: Option<PathBuf>,
+    /// Seconds to fade over when the advent calendar moves on to the next day's entry, rather
+    /// than cutting straight to it.
+    #[structopt(long, default_value = "3")]
+    crossfade: f32,
+    /// Where the advent calendar remembers the last day it revealed.
+    #[structopt(long, parse(from_os_str), default_value = "advent_state.txt")]
+    advent_state: PathBuf,
+    /// Show this day of the advent calendar instead of today's, without recording it.
+    #[structopt(long)]
+    advent_day: Option<u32>,
+    /// Fade hardware outputs to black if no frame has been sent for this many milliseconds.
+    #[structopt(long, default_value = "2000")]
+    output_timeout: u64,
+    /// Serve the coordinates, what's showing and a stream of frames over HTTP at this address,
+    /// e.g. `0.0.0.0:8080`, so the tree can be watched from elsewhere.
+    #[structopt(long)]
+    serve: Option<String>,
+    /// Frames per second streamed to viewers with --serve.
+    #[structopt(long, default_value = "10")]
+    serve_fps: f32,
+    /// Lets viewers with this token take over the transport controls and pause the show.
+    #[structopt(long, requires = "serve")]
+    serve_token: Option<String>,
+    /// Let other programs draw layers over the show through a Unix socket at this path (see
+    /// the `overlay` module for the commands).
+    #[structopt(long, parse(from_os_str))]
+    overlay_socket: Option<PathBuf>,
+    /// Let viewers like what's showing with --serve, keeping each show's likes and time shown
+    /// in this JSON file across the season.
+    #[structopt(long, parse(from_os_str), requires = "serve")]
+    votes: Option<PathBuf>,
+    /// Fall back to a gentle built-in twinkle, rather than stopping or going dark, when the
+    /// coordinates, effect or advent calendar entry can't be loaded.
+    #[structopt(long)]
+    safe_mode: bool,
+    /// LEDs to light in safe mode when the coordinates can't be read [default: every pixel of
+    /// the hardware profile].
+    #[structopt(long, requires = "safe-mode")]
+    safe_mode_leds: Option<usize>,
+    /// Light the tree red, green and blue, then each port or universe in turn, before the show
+    /// starts, logging any output which fails.
+    #[structopt(long)]
+    self_test: bool,
+    /// Keep running totals of time running, what was shown and output errors across runs in
+    /// this JSON file, for the `stats` command.
+    #[structopt(long, parse(from_os_str))]
+    history: Option<PathBuf>,
+    #[structopt(flatten)]
+    light: LightSensorOpt,
+    #[structopt(flatten)]
+    power: PowerOpt,
+    #[structopt(flatten)]
+    budget: BudgetOpt,
+    #[structopt(flatten)]
+    meta: MetaOpt,
+    #[structopt(flatten)]
+    filters: FilterOpt,
+}
+
+/// How often feed files are checked for new values.
+const FEED_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A parameter kept up to date from a file.
+struct Feed {
+    name: String,
+    path: String,
+    last: Option<String>,
+}
+
+impl Feed {
+    /// Returns the file's contents if they have changed since last time.
+    fn poll(&mut self) -> Option<String> {
+        let value = match fs::read_to_string(&self.path) {
+            Ok(value) => value.trim().to_string(),
+            Err(e) => {
+                debug!("Cannot read feed {}: {}", self.path, e);
+                return None;
+            }
+        };
+        if self.last.as_ref() == Some(&value) {
+            return None;
+        }
+        self.last = Some(value.clone());
+        Some(value)
+    }
+}
+
+/// How long hardware outputs take to fade out once frames stop arriving.
+const WATCHDOG_FADE: Duration = Duration::from_secs(1);
+
+/// How often each output's traffic is logged.
+const STATS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often a script being run is checked for changes.
+const SCRIPT_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// A script being run, reloaded whenever it's saved.
+struct ScriptWatch {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    next_check: Instant,
+    /// Why the latest save couldn't be loaded, while the last version which could keeps running.
+    error: Option<String>,
+}
+
+/// An effect rendering one frame at a time, looping every `len` frames.
+struct Runner {
+    info: &'static EffectInfo,
+    bounds: Bounds,
+    visibility: Vec<f32>,
+    params: Params,
+    effect: Box<dyn Effect>,
+    filters: Vec<Box<dyn PostFilter>>,
+    len: usize,
+    frame: usize,
+    colors: Vec<Color>,
+    previous: Option<Vec<Color>>,
+    budget: Budget,
+    /// The effect's detail parameter and the value asked for, before any lowering to keep up.
+    detail: Option<(&'static str, usize)>,
+    /// Share of its detail the battery can afford, with `--battery`.
+    power: f32,
+    /// The last frame shown after the filters, repeated when rendering at half rate.
+    filtered: Vec<Color>,
+    script: Option<ScriptWatch>,
+}
+
+impl Runner {
+    fn new(
+        name: &str,
+        args: &[ParamArg],
+        len: Length,
+        live: &LiveOpt,
+        opt: &Opt,
+        coords: &[Coord],
+    ) -> Result<Self, Box<dyn Error>> {
+        let (info, effect, script): (_, Box<dyn Effect>, _) = match script::path(name) {
+            Some(path) => {
+                let script = ScriptEffect::load(path, &opt.sandbox)?;
+                let watch = ScriptWatch {
+                    path: path.into(),
+                    modified: modified(path),
+                    next_check: Instant::now() + SCRIPT_CHECK_INTERVAL,
+                    error: None,
+                };
+                (&script::INFO, Box::new(script), Some(watch))
+            }
+            None => {
+                let info =
+                    effects::lookup(name).ok_or_else(|| format!("Unknown effect: {}", name))?;
+                (info, Box::new(info.render), None)
+            }
+        };
+        let params = Params::resolve(info, args, &opt.project.palettes, opt.seed, 0)?;
+        let len = len.resolve(info, coords, &params)?.max(1);
+        Ok(Self {
+            info,
+            bounds: Bounds::of(coords),
+            visibility: visibility::estimate(coords),
+            effect: live.meta.wrap(effect),
+            filters: live.filters.build(coords, opt.calibration.as_ref(), None),
+            len,
+            frame: 0,
+            colors: vec![(0.0, 0.0, 0.0); coords.len()],
+            previous: None,
+            budget: Budget::new(&live.budget, info.name, opt.fps),
+            detail: info.detail.map(|name| (name, params.int(name))),
+            power: 1.0,
+            filtered: Vec::with_capacity(coords.len()),
+            script,
+            params,
+        })
+    }
+
+    /// Reloads the script being run if it has been saved since it was loaded. The frame counter
+    /// and previous frame carry on, so the show picks up where the old version left off. A
+    /// version which doesn't load is reported, and the last one which did keeps running.
+    fn reload_script(&mut self, live: &LiveOpt, opt: &Opt) {
+        let watch = match &mut self.script {
+            Some(watch) if Instant::now() >= watch.next_check => watch,
+            _ => return,
+        };
+        watch.next_check = Instant::now() + SCRIPT_CHECK_INTERVAL;
+        let modified = modified(&watch.path);
+        if modified == watch.modified {
+            return;
+        }
+        watch.modified = modified;
+        match ScriptEffect::load(&watch.path, &opt.sandbox) {
+            Ok(script) => {
+                info!("Reloaded {}", watch.path.display());
+                self.effect = live.meta.wrap(Box::new(script));
+                watch.error = None;
+            }
+            Err(e) => {
+                warn!("{}, still running the last version which loaded", e);
+                watch.error = Some(e.to_string());
+            }
+        }
+    }
+
+    /// Why the script being run couldn't be reloaded, if it couldn't.
+    fn script_error(&self) -> Option<&str> {
+        self.script.as_ref()?.error.as_deref()
+    }
+
+    /// Sets a parameter from a feed, which becomes the detail asked for if it's the detail one.
+    fn set_param(&mut self, name: &str, value: String) -> Result<(), String> {
+        self.params.set(self.info, name, value)?;
+        if let Some((detail, full)) = &mut self.detail {
+            if *detail == name {
+                *full = self.params.int(detail);
+            }
+        }
+        Ok(())
+    }
+
+    fn render(&mut self, opt: &Opt, coords: &[Coord], neighbours: &NeighbourGraph) -> &[Color] {
+        let level = self.budget.level();
+        if level >= Degradation::HalfRate && self.frame % 2 == 1 && !self.filtered.is_empty() {
+            self.budget.repeated();
+            self.frame = (self.frame + 1) % self.len;
+            return &self.filtered;
+        }
+        if let Some((name, full)) = self.detail {
+            let wanted = if level >= Degradation::LessDetail {
+                (full / 2).max(1)
+            } else {
+                full
+            };
+            let wanted = ((wanted as f32 * self.power).round() as usize).max(1);
+            if self.params.int(name) != wanted {
+                if let Err(e) = self.params.set(self.info, name, wanted.to_string()) {
+                    warn!("Cannot change the detail of {}: {}", self.info.name, e);
+                }
+            }
+        }
+        let start = Instant::now();
+        let ctx = EffectContext {
+            coords,
+            bounds: self.bounds,
+            frame: self.frame,
+            total_frames: self.len,
+            fps: opt.fps,
+            seed: opt.seed,
+            previous: self.previous.as_deref(),
+            neighbours,
+            visibility: &self.visibility,
+            params: &self.params,
+            audio: None,
+        };
+        self.effect.render(&ctx, &mut self.colors);
+        self.filtered.clear();
+        self.filtered.extend_from_slice(&self.colors);
+        for filter in &mut self.filters {
+            if level >= Degradation::SkipOptionalFilters && filter.optional() {
+                continue;
+            }
+            filter.apply(&ctx, &mut self.filtered);
+        }
+        self.budget.record(start.elapsed());
+        match &mut self.previous {
+            Some(previous) => mem::swap(previous, &mut self.colors),
+            None => self.previous = Some(self.colors.clone()),
+        }
+        self.frame = (self.frame + 1) % self.len;
+        &self.filtered
+    }
+}
+
+/// What the advent calendar is currently showing.
+enum Show {
+    Effect(Box<Runner>),
+    Sequence(Playback),
+}
+
+impl Show {
+    fn open(
+        entry: &Entry,
+        live: &LiveOpt,
+        opt: &Opt,
+        coords: &[Coord],
+    ) -> Result<Self, Box<dyn Error>> {
+        match entry {
+            Entry::Effect { name, params, len } => Ok(Self::Effect(Box::new(Runner::new(
+                name, params, *len, live, opt, coords,
+            )?))),
+            Entry::Sequence {
+                path,
+                coords: source,
+                fps,
+            } => Ok(Self::Sequence(Playback::open(
+                path,
+                source.as_deref(),
+                *fps,
+                opt,
+                coords,
+            )?)),
+        }
+    }
+
+    fn render(&mut self, opt: &Opt, coords: &[Coord], neighbours: &NeighbourGraph) -> &[Color] {
+        match self {
+            Self::Effect(runner) => runner.render(opt, coords, neighbours),
+            Self::Sequence(playback) => playback.render(),
+        }
+    }
+}
+
+/// Runs `--advent`, switching to the next entry of the calendar at midnight.
+struct Advent {
+    calendar: Calendar,
+    state: AdventState,
+    date: Option<NaiveDate>,
+    show: Option<Show>,
+    /// Yesterday's show, while it fades out, and how many frames it has been fading for.
+    fading: Option<(Show, usize)>,
+    /// Frames taken to fade from one day's show to the next.
+    crossfade_frames: usize,
+    /// Frames into the reveal animation, while it is playing.
+    reveal_frame: Option<usize>,
+    /// Why today's entry couldn't be opened.
+    failure: Option<String>,
+    /// The frame being shown, with the reveal and crossfade applied.
+    frame: Vec<Color>,
+}
+
+impl Advent {
+    /// Opens the entry for a new date.
+    fn open(
+        &mut self,
+        date: NaiveDate,
+        live: &LiveOpt,
+        opt: &Opt,
+        coords: &[Coord],
+    ) -> Result<(), Box<dyn Error>> {
+        self.date = Some(date);
+        self.fading = self
+            .show
+            .take()
+            .filter(|_| self.crossfade_frames > 0)
+            .map(|show| (show, 0));
+        self.reveal_frame = None;
+        let day = match live.advent_day.or_else(|| advent::day_of_advent(date)) {
+            Some(day) => day,
+            None => {
+                info!("The advent calendar is closed until December");
+                return Ok(());
+            }
+        };
+        info!("Showing day {} of the advent calendar", day);
+        self.show = Some(Show::open(self.calendar.entry(day), live, opt, coords)?);
+        // Previews always reveal, and don't count as the day's first showing
+        if live.advent_day.is_some() {
+            self.reveal_frame = Some(0);
+        } else if self.state.is_first_showing(date)? {
+            self.reveal_frame = Some(0);
+            self.state.record(date)?;
+        }
+        Ok(())
+    }
+
+    fn render(&mut self, opt: &Opt, coords: &[Coord], neighbours: &NeighbourGraph) -> &[Color] {
+        self.frame.clear();
+        match &mut self.show {
+            Some(show) => self
+                .frame
+                .extend_from_slice(show.render(opt, coords, neighbours)),
+            None => self.frame.resize(coords.len(), (0.0, 0.0, 0.0)),
+        }
+        if let Some(reveal_frame) = self.reveal_frame {
+            let progress = reveal_frame as f32 / (advent::REVEAL_SECONDS * opt.fps);
+            advent::reveal(coords, progress.min(1.0), &mut self.frame);
+            self.reveal_frame = if progress < 1.0 {
+                Some(reveal_frame + 1)
+            } else {
+                None
+            };
+        }
+        if let Some((old, fade_frame)) = &mut self.fading {
+            let t = *fade_frame as f32 / self.crossfade_frames as f32;
+            let old = old.render(opt, coords, neighbours);
+            for (color, &old) in self.frame.iter_mut().zip(old) {
+                *color = effects::mix(old, *color, t);
+            }
+            *fade_frame += 1;
+            if *fade_frame >= self.crossfade_frames {
+                self.fading = None;
+            }
+        }
+        &self.frame
+    }
+}
+
+/// A look for part of the day, used by `--ambient`.
+struct Theme {
+    name: &'static str,
+    /// Hour of the day the theme starts at.
+    start: u32,
+    effect: &'static str,
+    params: &'static [&'static str],
+    len: &'static str,
+    brightness: f32,
+}
+
+const THEMES: &[Theme] = &[
+    Theme {
+        name: "night",
+        start: 0,
+        effect: "twinkle",
+        params: &["phases=12"],
+        len: "3000",
+        brightness: 0.1,
+    },
+    Theme {
+        name: "morning",
+        start: 7,
+        effect: "barber-pole",
+        params: &["palette=ice", "softness=0.2"],
+        len: "auto",
+        brightness: 0.5,
+    },
+    Theme {
+        name: "day",
+        start: 10,
+        effect: "shells",
+        params: &["palette=christmas", "softness=0.05"],
+        len: "auto",
+        brightness: 0.7,
+    },
+    Theme {
+        name: "evening",
+        start: 16,
+        effect: "ornaments",
+        params: &["background=#301000"],
+        len: "auto",
+        brightness: 1.0,
+    },
+    Theme {
+        name: "late",
+        start: 22,
+        effect: "fall-down",
+        params: &["palette=warm-white", "softness=0.2"],
+        len: "auto",
+        brightness: 0.3,
+    },
+];
+
+/// How long one theme takes to blend into the next.
+const THEME_CROSSFADE_MINUTES: f32 = 10.0;
+
+/// The theme for a time of day in minutes, along with the previous theme and how far through
+/// blending from it this is, if they are still being blended.
+fn theme_at(minutes: f32) -> (usize, Option<(usize, f32)>) {
+    let current = THEMES
+        .iter()
+        .rposition(|theme| theme.start as f32 * 60.0 <= minutes)
+        .unwrap_or(THEMES.len() - 1);
+    let since = minutes - THEMES[current].start as f32 * 60.0;
+    let previous = (current + THEMES.len() - 1) % THEMES.len();
+    if since < THEME_CROSSFADE_MINUTES {
+        (current, Some((previous, since / THEME_CROSSFADE_MINUTES)))
+    } else {
+        (current, None)
+    }
+}
+
+fn scale(frame: &mut [Color], factor: f32) {
+    for color in frame {
+        *color = (color.0 * factor, color.1 * factor, color.2 * factor);
+    }
+}
+
+fn open_outputs(live: &LiveOpt, opt: &Opt) -> Result<OutputGroup, Box<dyn Error>> {
+    let urls = if live.outputs.is_empty() {
+        &opt.project.outputs
+    } else {
+        &live.outputs
+    };
+    if urls.is_empty() {
+        return Err("No outputs given, use --output or a project with outputs".into());
+    }
+    let mut group = OutputGroup::default();
+    for url in urls {
+        let sink = output::open(url)?;
+        // Recordings shouldn't end with a fade out
+        let sink = if url.starts_with("file://") {
+            sink
+        } else {
+            Box::new(Watchdog::new(
+                sink,
+                Duration::from_millis(live.output_timeout),
+                WATCHDOG_FADE,
+            ))
+        };
+        group.add(url.clone(), sink);
+    }
+    Ok(group)
+}
+
+/// Records why safe mode has to show its fallback, or passes the error on without
+/// `--safe-mode`.
+fn fall_back(
+    live: &LiveOpt,
+    failure: &mut Option<String>,
+    e: Box<dyn Error>,
+) -> Result<(), Box<dyn Error>> {
+    if !live.safe_mode {
+        return Err(e);
+    }
+    warn!("Falling back to safe mode: {}", e);
+    failure.get_or_insert(e.to_string());
+    Ok(())
+}
+
+/// The effects to run: every theme with `--ambient`, none with `--advent` (which opens its own
+/// each day), or else the one asked for.
+fn open_runners(
+    live: &LiveOpt,
+    opt: &Opt,
+    coords: &[Coord],
+) -> Result<Vec<Runner>, Box<dyn Error>> {
+    if live.ambient {
+        THEMES
+            .iter()
+            .map(|theme| {
+                let args = theme
+                    .params
+                    .iter()
+                    .map(|arg| arg.parse())
+                    .collect::<Result<Vec<ParamArg>, _>>()?;
+                Runner::new(theme.effect, &args, theme.len.parse()?, live, opt, coords)
+            })
+            .collect()
+    } else if live.advent.is_some() {
+        Ok(Vec::new())
+    } else {
+        Ok(vec![Runner::new(
+            live.effect.as_deref().unwrap(),
+            &live.params,
+            live.len,
+            live,
+            opt,
+            coords,
+        )?])
+    }
+}
+
+/// Renders an effect in real time and sends it to the outputs, until interrupted.
+pub fn live(opt: &Opt, live: &LiveOpt) -> Result<(), Box<dyn Error>> {
+    // In safe mode, why the fallback is showing instead of what was asked for
+    let mut failure = None;
+    let hardware = match load_hardware(opt) {
+        Ok(hardware) => hardware,
+        Err(e) => {
+            fall_back(live, &mut failure, e)?;
+            None
+        }
+    };
+    let coords = match load_coords(opt) {
+        Ok(coords) => coords,
+        Err(e) => {
+            let count = live.safe_mode_leds.or_else(|| {
+                let hardware = hardware.as_ref()?;
+                Some(hardware.pixels_per_port * hardware.ports)
+            });
+            fall_back(live, &mut failure, e)?;
+            let count = count.ok_or(
+                "Safe mode needs --safe-mode-leds or a hardware profile to light LEDs without \
+                 coordinates",
+            )?;
+            vec![(0.0, 0.0, 0.0); count]
+        }
+    };
+    let neighbours = NeighbourGraph::knn(&coords, NEIGHBOUR_COUNT);
+    let mut outputs = open_outputs(live, opt)?;
+    if live.self_test {
+        self_test::run(&mut outputs, coords.len(), opt.fps, hardware.as_ref());
+    }
+    let mut runners = Vec::new();
+    let mut advent = None;
+    if failure.is_none() {
+        match open_runners(live, opt, &coords) {
+            Ok(opened) => runners = opened,
+            Err(e) => fall_back(live, &mut failure, e)?,
+        }
+        if let Some(path) = &live.advent {
+            match Calendar::load(path) {
+                Ok(calendar) => {
+                    advent = Some(Advent {
+                        calendar,
+                        state: AdventState::new(live.advent_state.clone()),
+                        date: None,
+                        show: None,
+                        fading: None,
+                        crossfade_frames: (live.crossfade * opt.fps).round() as usize,
+                        reveal_frame: None,
+                        failure: None,
+                        frame: Vec::with_capacity(coords.len()),
+                    })
+                }
+                Err(e) => fall_back(live, &mut failure, e)?,
+            }
+        }
+    }
+    for runner in &runners {
+        debug!(
+            "Running {} with {} frames per loop",
+            runner.info.name, runner.len
+        );
+    }
+
+    let mut feeds: Vec<Feed> = live
+        .feeds
+        .iter()
+        .map(|feed| Feed {
+            name: feed.name.clone(),
+            path: feed.value.clone(),
+            last: None,
+        })
+        .collect();
+    let share = match &live.serve {
+        Some(addr) => Some(ShareServer::start(
+            addr,
+            &coords,
+            opt.fps,
+            live.serve_fps,
+            live.serve_token.clone(),
+            live.votes.clone().map(Votes::load).transpose()?,
+        )?),
+        None => None,
+    };
+    let overlays = live
+        .overlay_socket
+        .as_deref()
+        .map(|path| Overlays::start(path, coords.len()))
+        .transpose()?;
+    let mut history = live.history.clone().map(HistoryLog::start).transpose()?;
+    let mut light_sensor = live.light.start()?;
+    let mut battery = live.power.start()?;
+    let mut next_feed = Instant::now();
+    let mut next_stats = Instant::now() + STATS_INTERVAL;
+
+    let frame_time = Duration::from_secs_f32(1.0 / opt.fps);
+    let mut next_frame = Instant::now();
+    let mut shown_theme = None;
+    let mut frame: Vec<Color> = Vec::with_capacity(coords.len());
+    let mut rgb: Vec<Rgb> = Vec::new();
+    let mut dimmed: Vec<Rgb> = Vec::new();
+    let mut frame_index = 0;
+    loop {
+        if Instant::now() >= next_feed {
+            next_feed += FEED_INTERVAL;
+            for feed in &mut feeds {
+                if let Some(value) = feed.poll() {
+                    let advent_runner = advent.as_mut().and_then(|advent| match &mut advent.show {
+                        Some(Show::Effect(runner)) => Some(&mut **runner),
+                        _ => None,
+                    });
+                    for runner in runners.iter_mut().chain(advent_runner) {
+                        match runner.set_param(&feed.name, value.clone()) {
+                            Ok(()) => debug!("Set {} to {}", feed.name, value),
+                            Err(e) => warn!("Ignoring feed {}: {}", feed.path, e),
+                        }
+                    }
+                }
+            }
+        }
+        let power = battery
+            .as_mut()
+            .map_or(PowerMode::Level(1.0), |battery| battery.mode());
+        if let PowerMode::Level(level) = power {
+            let advent_runner = advent.as_mut().and_then(|advent| match &mut advent.show {
+                Some(Show::Effect(runner)) => Some(&mut **runner),
+                _ => None,
+            });
+            for runner in runners.iter_mut().chain(advent_runner) {
+                runner.power = level;
+            }
+        }
+        // While paused, keep sending the frame shown last, so outputs don't time out
+        let paused = share.as_ref().is_some_and(ShareServer::paused) && !rgb.is_empty();
+        if !paused {
+            if failure.is_some() {
+                frame = safe::twinkle(frame_index, opt.fps, coords.len());
+            } else if power == PowerMode::Sparse {
+                frame = power::sparse_twinkle(frame_index, opt.fps, coords.len());
+            } else if let Some(advent) = &mut advent {
+                let today = Local::now().date_naive();
+                if advent.date != Some(today) {
+                    let first = advent.date.is_none();
+                    advent.failure = None;
+                    if let Err(e) = advent.open(today, live, opt, &coords) {
+                        // A bad entry shouldn't take down the rest of the season
+                        if first && !live.safe_mode {
+                            return Err(e);
+                        }
+                        warn!("Cannot show today's advent calendar entry: {}", e);
+                        advent.failure = Some(e.to_string());
+                    }
+                }
+                match &advent.failure {
+                    Some(_) if live.safe_mode => {
+                        frame = safe::twinkle(frame_index, opt.fps, coords.len());
+                    }
+                    _ => {
+                        frame.clear();
+                        frame.extend_from_slice(advent.render(opt, &coords, &neighbours));
+                    }
+                }
+            } else if live.ambient {
+                let now = Local::now();
+                let minutes =
+                    now.hour() as f32 * 60.0 + now.minute() as f32 + now.second() as f32 / 60.0;
+                let (current, blend) = theme_at(minutes);
+                if shown_theme != Some(current) {
+                    info!("Switching to the {} theme", THEMES[current].name);
+                    shown_theme = Some(current);
+                }
+                frame.clear();
+                frame.extend_from_slice(runners[current].render(opt, &coords, &neighbours));
+                scale(&mut frame, THEMES[current].brightness);
+                if let Some((previous, t)) = blend {
+                    let brightness = THEMES[previous].brightness;
+                    let old = runners[previous].render(opt, &coords, &neighbours);
+                    for (color, &(r, g, b)) in frame.iter_mut().zip(old) {
+                        let old = (r * brightness, g * brightness, b * brightness);
+                        *color = mix_hue(old, *color, t);
+                    }
+                }
+            } else {
+                frame.clear();
+                frame.extend_from_slice(runners[0].render(opt, &coords, &neighbours));
+            }
+            if let Some(overlays) = &overlays {
+                overlays.composite(&mut frame);
+            }
+            rgb.clear();
+            rgb.extend(frame.iter().map(|&color| to_rgb(color)));
+        }
+        // Only the tree is dimmed for the room and the battery, not what viewers of --serve see
+        let mut level = light_sensor.as_mut().map_or(1.0, LightSensor::level);
+        if let PowerMode::Level(power) = power {
+            level *= power;
+        }
+        let sent = if level < 1.0 {
+            dimmed.clear();
+            dimmed.extend(
+                rgb.iter()
+                    .map(|rgb| rgb.map(|v| (v as f32 * level).round() as u8)),
+            );
+            &dimmed
+        } else {
+            &rgb
+        };
+        if let Err(e) = outputs.send_frame(sent) {
+            warn!("Failed to send frame: {}", e);
+        }
+        let advent_failure = advent
+            .as_ref()
+            .and_then(|advent| advent.failure.as_deref())
+            .filter(|_| live.safe_mode);
+        let safe_failure = failure.as_deref().or(advent_failure);
+        let show = match (&advent, shown_theme) {
+            _ if safe_failure.is_some() => "safe mode".into(),
+            _ if power == PowerMode::Sparse => "battery saving".into(),
+            (Some(advent), _) => match advent.date {
+                Some(date) => format!("advent calendar, {}", date),
+                None => "advent calendar".into(),
+            },
+            (None, Some(theme)) => format!("{} theme", THEMES[theme].name),
+            (None, None) => runners[0].info.name.into(),
+        };
+        if let Some(share) = &share {
+            share.set_show(&show);
+            share.set_failure(safe_failure);
+            share.send_frame(&rgb);
+        }
+        let advent_runner = advent.as_mut().and_then(|advent| match &mut advent.show {
+            Some(Show::Effect(runner)) => Some(&mut **runner),
+            _ => None,
+        });
+        let mut degraded = false;
+        let mut script_error = None;
+        for runner in runners.iter_mut().chain(advent_runner) {
+            degraded |= runner.budget.take_degraded();
+            runner.reload_script(live, opt);
+            script_error = script_error.or_else(|| runner.script_error().map(String::from));
+        }
+        if let Some(share) = &share {
+            share.set_script_error(script_error.as_deref());
+        }
+        if let Some(history) = &mut history {
+            if let Err(e) = history.frame(&show, opt.fps, degraded, outputs.stats_by_sink()) {
+                warn!("Cannot save history: {}", e);
+            }
+        }
+        if Instant::now() >= next_stats {
+            next_stats += STATS_INTERVAL;
+            for (name, stats) in outputs.stats_by_sink() {
+                debug!(
+                    "{}: {} frames, {} packets, {} bytes, {} errors",
+                    name, stats.frames, stats.packets, stats.bytes, stats.errors
+                );
+            }
+            let advent_runner = advent.as_ref().and_then(|advent| match &advent.show {
+                Some(Show::Effect(runner)) => Some(&**runner),
+                _ => None,
+            });
+            for runner in runners.iter().chain(advent_runner) {
+                runner.budget.log_stats();
+            }
+        }
+
+        frame_index += 1;
+        next_frame += frame_time;
+        let now = Instant::now();
+        if next_frame > now {
+            thread::sleep(next_frame - now);
+        } else if now - next_frame > frame_time {
+            // Fell behind, so drop frames rather than rushing to catch up
+            debug!("Rendering is slower than {} fps", opt.fps);
+            next_frame = now;
+        }
+    }
+}
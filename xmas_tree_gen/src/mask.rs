@@ -0,0 +1,456 @@
+//! Regions of the tree described by expressions over LED coordinates, such as
+//! `z > 0.8 * max_z`, `abs(theta) < 0.5` or `sphere(0, 0, 1.5, 0.4) or z < 0.2`.
+//!
+//! Variables: `x`, `y`, `z`, `r` (distance from the trunk), `theta` (angle around the trunk),
+//! `index`, and the tree bounds `min_x`, `max_x`, `min_y`, `max_y`, `min_z`, `max_z`, `max_r`.
+//! Functions: `abs`, `sqrt`, `sin`, `cos`, `min`, `max`, `sphere(cx, cy, cz, radius)` and
+//! `box(x0, y0, z0, x1, y1, z1)`. Comparisons produce 1 or 0 and can be combined with
+//! `and`, `or` and `not`. An LED is inside the mask when the expression is non-zero.
+
+use std::{fmt, str::FromStr};
+
+use crate::effects::Coord;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    Num(f32),
+    Ident(usize, usize),
+    Op(&'static str),
+    LParen,
+    RParen,
+    Comma,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Func {
+    Abs,
+    Sqrt,
+    Sin,
+    Cos,
+    Min,
+    Max,
+    Sphere,
+    Box,
+}
+
+impl Func {
+    fn parse(name: &str) -> Option<(Self, usize)> {
+        Some(match name {
+            "abs" => (Self::Abs, 1),
+            "sqrt" => (Self::Sqrt, 1),
+            "sin" => (Self::Sin, 1),
+            "cos" => (Self::Cos, 1),
+            "min" => (Self::Min, 2),
+            "max" => (Self::Max, 2),
+            "sphere" => (Self::Sphere, 4),
+            "box" => (Self::Box, 6),
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Var {
+    X,
+    Y,
+    Z,
+    R,
+    Theta,
+    Index,
+    MinX,
+    MaxX,
+    MinY,
+    MaxY,
+    MinZ,
+    MaxZ,
+    MaxR,
+}
+
+impl Var {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "x" => Self::X,
+            "y" => Self::Y,
+            "z" => Self::Z,
+            "r" => Self::R,
+            "theta" => Self::Theta,
+            "index" => Self::Index,
+            "min_x" => Self::MinX,
+            "max_x" => Self::MaxX,
+            "min_y" => Self::MinY,
+            "max_y" => Self::MaxY,
+            "min_z" => Self::MinZ,
+            "max_z" => Self::MaxZ,
+            "max_r" => Self::MaxR,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Num(f32),
+    Var(Var),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Call(Func, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaskError(String);
+
+impl fmt::Display for MaskError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for MaskError {}
+
+fn err<T>(msg: impl Into<String>) -> Result<T, MaskError> {
+    Err(MaskError(msg.into()))
+}
+
+fn tokenize(src: &str) -> Result<Vec<Token>, MaskError> {
+    const OPS: &[&str] = &[
+        "<=", ">=", "==", "!=", "&&", "||", "<", ">", "+", "-", "*", "/", "!",
+    ];
+    let bytes = src.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    'outer: while i < bytes.len() {
+        let c = bytes[i] as char;
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || c == '.' {
+            let start = i;
+            while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'.') {
+                i += 1;
+            }
+            match src[start..i].parse() {
+                Ok(v) => tokens.push(Token::Num(v)),
+                Err(_) => return err(format!("bad number '{}'", &src[start..i])),
+            }
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(start, i));
+        } else if c == '(' {
+            tokens.push(Token::LParen);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(Token::RParen);
+            i += 1;
+        } else if c == ',' {
+            tokens.push(Token::Comma);
+            i += 1;
+        } else {
+            for op in OPS {
+                if src[i..].starts_with(op) {
+                    tokens.push(Token::Op(op));
+                    i += op.len();
+                    continue 'outer;
+                }
+            }
+            return err(format!("unexpected character '{}'", c));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek();
+        self.pos += 1;
+        token
+    }
+
+    fn ident(&self, token: Token) -> Option<&'a str> {
+        match token {
+            Token::Ident(start, end) => Some(&self.src[start..end]),
+            _ => None,
+        }
+    }
+
+    fn binary_op(&self, token: Option<Token>) -> Option<BinOp> {
+        let token = token?;
+        if let Some(word) = self.ident(token) {
+            return match word {
+                "and" => Some(BinOp::And),
+                "or" => Some(BinOp::Or),
+                _ => None,
+            };
+        }
+        Some(match token {
+            Token::Op("+") => BinOp::Add,
+            Token::Op("-") => BinOp::Sub,
+            Token::Op("*") => BinOp::Mul,
+            Token::Op("/") => BinOp::Div,
+            Token::Op("<") => BinOp::Lt,
+            Token::Op("<=") => BinOp::Le,
+            Token::Op(">") => BinOp::Gt,
+            Token::Op(">=") => BinOp::Ge,
+            Token::Op("==") => BinOp::Eq,
+            Token::Op("!=") => BinOp::Ne,
+            Token::Op("&&") => BinOp::And,
+            Token::Op("||") => BinOp::Or,
+            _ => return None,
+        })
+    }
+
+    fn precedence(op: BinOp) -> u8 {
+        match op {
+            BinOp::Or => 1,
+            BinOp::And => 2,
+            BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge | BinOp::Eq | BinOp::Ne => 3,
+            BinOp::Add | BinOp::Sub => 4,
+            BinOp::Mul | BinOp::Div => 5,
+        }
+    }
+
+    fn expr(&mut self, min_precedence: u8) -> Result<Expr, MaskError> {
+        let mut lhs = self.unary()?;
+        while let Some(op) = self.binary_op(self.peek()) {
+            let precedence = Self::precedence(op);
+            if precedence < min_precedence {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.expr(precedence + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, MaskError> {
+        match self.peek() {
+            Some(Token::Op("-")) => {
+                self.pos += 1;
+                Ok(Expr::Neg(Box::new(self.unary()?)))
+            }
+            Some(Token::Op("!")) => {
+                self.pos += 1;
+                Ok(Expr::Not(Box::new(self.unary()?)))
+            }
+            Some(token) if self.ident(token) == Some("not") => {
+                // `not` binds looser than comparisons, so `not z > 1` means `not (z > 1)`
+                self.pos += 1;
+                Ok(Expr::Not(Box::new(self.expr(3)?)))
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, MaskError> {
+        match self.next() {
+            Some(Token::Num(v)) => Ok(Expr::Num(v)),
+            Some(Token::LParen) => {
+                let inner = self.expr(0)?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(inner),
+                    _ => err("expected ')'"),
+                }
+            }
+            Some(token @ Token::Ident(..)) => {
+                let name = self.ident(token).unwrap();
+                if self.peek() == Some(Token::LParen) {
+                    self.pos += 1;
+                    let (func, arity) = Func::parse(name)
+                        .ok_or_else(|| MaskError(format!("unknown function '{}'", name)))?;
+                    let mut args = Vec::new();
+                    if self.peek() != Some(Token::RParen) {
+                        loop {
+                            args.push(self.expr(0)?);
+                            match self.next() {
+                                Some(Token::Comma) => continue,
+                                Some(Token::RParen) => break,
+                                _ => return err("expected ',' or ')'"),
+                            }
+                        }
+                    } else {
+                        self.pos += 1;
+                    }
+                    if args.len() != arity {
+                        return err(format!("{} takes {} arguments", name, arity));
+                    }
+                    Ok(Expr::Call(func, args))
+                } else {
+                    Var::parse(name)
+                        .map(Expr::Var)
+                        .ok_or_else(|| MaskError(format!("unknown variable '{}'", name)))
+                }
+            }
+            Some(token) => err(format!("unexpected {:?}", token)),
+            None => err("unexpected end of expression"),
+        }
+    }
+}
+
+/// Values available to a mask expression for one LED.
+struct Env {
+    coord: Coord,
+    index: usize,
+    min: Coord,
+    max: Coord,
+    max_r: f32,
+}
+
+fn truth(b: bool) -> f32 {
+    if b {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+impl Expr {
+    fn eval(&self, env: &Env) -> f32 {
+        let (x, y, z) = env.coord;
+        match self {
+            Expr::Num(v) => *v,
+            Expr::Var(var) => match var {
+                Var::X => x,
+                Var::Y => y,
+                Var::Z => z,
+                Var::R => (x * x + y * y).sqrt(),
+                Var::Theta => f32::atan2(y, x),
+                Var::Index => env.index as f32,
+                Var::MinX => env.min.0,
+                Var::MaxX => env.max.0,
+                Var::MinY => env.min.1,
+                Var::MaxY => env.max.1,
+                Var::MinZ => env.min.2,
+                Var::MaxZ => env.max.2,
+                Var::MaxR => env.max_r,
+            },
+            Expr::Neg(inner) => -inner.eval(env),
+            Expr::Not(inner) => truth(inner.eval(env) == 0.0),
+            Expr::Binary(op, lhs, rhs) => {
+                let (a, b) = (lhs.eval(env), rhs.eval(env));
+                match op {
+                    BinOp::Add => a + b,
+                    BinOp::Sub => a - b,
+                    BinOp::Mul => a * b,
+                    BinOp::Div => a / b,
+                    BinOp::Lt => truth(a < b),
+                    BinOp::Le => truth(a <= b),
+                    BinOp::Gt => truth(a > b),
+                    BinOp::Ge => truth(a >= b),
+                    BinOp::Eq => truth(a == b),
+                    BinOp::Ne => truth(a != b),
+                    BinOp::And => truth(a != 0.0 && b != 0.0),
+                    BinOp::Or => truth(a != 0.0 || b != 0.0),
+                }
+            }
+            Expr::Call(func, args) => {
+                let arg = |i: usize| args[i].eval(env);
+                match func {
+                    Func::Abs => arg(0).abs(),
+                    Func::Sqrt => arg(0).sqrt(),
+                    Func::Sin => arg(0).sin(),
+                    Func::Cos => arg(0).cos(),
+                    Func::Min => arg(0).min(arg(1)),
+                    Func::Max => arg(0).max(arg(1)),
+                    Func::Sphere => {
+                        let d2 = (x - arg(0)).powi(2) + (y - arg(1)).powi(2) + (z - arg(2)).powi(2);
+                        truth(d2 <= arg(3).powi(2))
+                    }
+                    Func::Box => truth(
+                        (arg(0)..=arg(3)).contains(&x)
+                            && (arg(1)..=arg(4)).contains(&y)
+                            && (arg(2)..=arg(5)).contains(&z),
+                    ),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mask {
+    source: String,
+    expr: Expr,
+}
+
+impl FromStr for Mask {
+    type Err = MaskError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            src: s,
+            tokens: tokenize(s)?,
+            pos: 0,
+        };
+        let expr = parser.expr(0)?;
+        if let Some(token) = parser.peek() {
+            return err(format!("unexpected {:?} after expression", token));
+        }
+        Ok(Self {
+            source: s.into(),
+            expr,
+        })
+    }
+}
+
+impl fmt::Display for Mask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Mask {
+    /// Works out which LEDs are inside the mask.
+    pub fn evaluate(&self, coords: &[Coord]) -> Vec<bool> {
+        let mut min = (f32::INFINITY, f32::INFINITY, f32::INFINITY);
+        let mut max = (f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY);
+        let mut max_r: f32 = 0.0;
+        for &(x, y, z) in coords {
+            min = (min.0.min(x), min.1.min(y), min.2.min(z));
+            max = (max.0.max(x), max.1.max(y), max.2.max(z));
+            max_r = max_r.max((x * x + y * y).sqrt());
+        }
+        coords
+            .iter()
+            .enumerate()
+            .map(|(index, &coord)| {
+                let env = Env {
+                    coord,
+                    index,
+                    min,
+                    max,
+                    max_r,
+                };
+                self.expr.eval(&env) != 0.0
+            })
+            .collect()
+    }
+}
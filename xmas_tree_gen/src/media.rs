@@ -0,0 +1,130 @@
+//! Images projected onto the tree by the `image-wrap` and `video` effects. Effects render each
+//! frame from scratch, so files are decoded once and kept. They're shrunk as they're loaded,
+//! since a tree has far fewer LEDs than a photo has pixels, and averaging pixels down also stops
+//! fine detail flickering as it moves between LEDs.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use image::{imageops::FilterType, RgbImage};
+
+use crate::effects::Color;
+
+/// The most pixels an image keeps along its longer side.
+const MAX_SIZE: u32 = 96;
+
+pub struct Texture {
+    image: RgbImage,
+}
+
+impl Texture {
+    /// The color at (`u`, `v`), from (0, 0) at the top left to (1, 1) at the bottom right,
+    /// blended between the nearest pixels. Positions past the edges take the color at the edge.
+    pub fn sample(&self, u: f32, v: f32) -> Color {
+        self.sample_at(u.clamp(0.0, 1.0), v, false)
+    }
+
+    /// As [`sample`](Self::sample), but `u` wraps around so the texture can go all the way
+    /// around the tree, with its left and right edges meeting.
+    pub fn sample_wrapped(&self, u: f32, v: f32) -> Color {
+        self.sample_at(u.rem_euclid(1.0), v, true)
+    }
+
+    fn sample_at(&self, u: f32, v: f32, wrap: bool) -> Color {
+        let (width, height) = self.image.dimensions();
+        let edge = |n: u32| (n - 1) as f32;
+        let mut x = u * width as f32 - 0.5;
+        if !wrap {
+            x = x.clamp(0.0, edge(width));
+        }
+        let y = (v.clamp(0.0, 1.0) * height as f32 - 0.5).clamp(0.0, edge(height));
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let pixel = |dx: i64, dy: u32| {
+            let px = if wrap {
+                (x0 as i64 + dx).rem_euclid(width as i64) as u32
+            } else {
+                (x0 as u32 + dx as u32).min(width - 1)
+            };
+            let py = (y0 as u32 + dy).min(height - 1);
+            self.image.get_pixel(px, py).0.map(|v| v as f32 / 255.0)
+        };
+        let [a, b, c, d] = [pixel(0, 0), pixel(1, 0), pixel(0, 1), pixel(1, 1)];
+        let channel = |i: usize| {
+            let top = a[i] + (b[i] - a[i]) * fx;
+            let bottom = c[i] + (d[i] - c[i]) * fx;
+            top + (bottom - top) * fy
+        };
+        (channel(0), channel(1), channel(2))
+    }
+}
+
+fn decode(path: &Path) -> Result<Texture, String> {
+    let image = image::open(path)
+        .map_err(|e| format!("Cannot read image {}: {}", path.display(), e))?
+        .into_rgb8();
+    let (width, height) = image.dimensions();
+    let image = if width.max(height) > MAX_SIZE {
+        let scale = MAX_SIZE as f32 / width.max(height) as f32;
+        let size = |n: u32| ((n as f32 * scale).round() as u32).max(1);
+        image::imageops::resize(&image, size(width), size(height), FilterType::Triangle)
+    } else {
+        image
+    };
+    Ok(Texture { image })
+}
+
+/// Loads an image, or gives the copy loaded before.
+pub fn image(path: &Path) -> Result<Arc<Texture>, String> {
+    static IMAGES: OnceLock<Mutex<HashMap<PathBuf, Arc<Texture>>>> = OnceLock::new();
+    let images = IMAGES.get_or_init(Default::default);
+    if let Some(texture) = images.lock().unwrap().get(path) {
+        return Ok(texture.clone());
+    }
+    // Decode without holding the lock, so frames rendering in parallel don't wait on each other
+    let texture = Arc::new(decode(path)?);
+    images
+        .lock()
+        .unwrap()
+        .insert(path.to_owned(), texture.clone());
+    Ok(texture)
+}
+
+/// The images in a directory, in name order, as the frames of a video.
+pub fn frames(dir: &Path) -> Result<Arc<Vec<PathBuf>>, String> {
+    static FRAMES: OnceLock<Mutex<HashMap<PathBuf, Arc<Vec<PathBuf>>>>> = OnceLock::new();
+    let cache = FRAMES.get_or_init(Default::default);
+    if let Some(frames) = cache.lock().unwrap().get(dir) {
+        return Ok(frames.clone());
+    }
+    let mut frames: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|e| format!("Cannot read frames from {}: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+        })
+        .collect();
+    if frames.is_empty() {
+        return Err(format!("{} has no PNG images", dir.display()));
+    }
+    frames.sort();
+    let frames = Arc::new(frames);
+    cache.lock().unwrap().insert(dir.to_owned(), frames.clone());
+    Ok(frames)
+}
+
+/// Checks an image can be read.
+pub fn validate_image(path: &str) -> Result<(), String> {
+    image(Path::new(path)).map(|_| ())
+}
+
+/// Checks a directory has frames, and that the first of them can be read.
+pub fn validate_frames(dir: &str) -> Result<(), String> {
+    let frames = frames(Path::new(dir))?;
+    image(&frames[0]).map(|_| ())
+}
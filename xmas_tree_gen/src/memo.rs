@@ -0,0 +1,54 @@
+//! Keeps what effects work out from their coordinates and parameters, such as a path through the
+//! LEDs or a shape fitted to them. Effects are plain functions which render each frame from
+//! scratch, so like the images in `media` anything costly is worked out the first time it's
+//! needed and found again on later frames by what it was worked out from.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use crate::effects::Coord;
+
+/// Values a memo keeps before starting again, since parameters fed to `live` can change all
+/// season and each new value is a new key.
+const MAX_ENTRIES: usize = 16;
+
+pub struct Memo<K, V> {
+    entries: OnceLock<Mutex<HashMap<K, Arc<V>>>>,
+}
+
+impl<K: Eq + Hash, V> Memo<K, V> {
+    pub const fn new() -> Self {
+        Self {
+            entries: OnceLock::new(),
+        }
+    }
+
+    /// The value for `key`, made with `make` the first time it's asked for.
+    pub fn get(&self, key: K, make: impl FnOnce() -> V) -> Arc<V> {
+        let entries = self.entries.get_or_init(Default::default);
+        if let Some(value) = entries.lock().unwrap().get(&key) {
+            return value.clone();
+        }
+        // Make it without holding the lock, so frames rendering in parallel don't wait on each
+        // other
+        let value = Arc::new(make());
+        let mut entries = entries.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES {
+            entries.clear();
+        }
+        entries.insert(key, value.clone());
+        value
+    }
+}
+
+/// A key for a set of coordinates, which changes if any of them move.
+pub fn coords_key(coords: &[Coord]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for &(x, y, z) in coords {
+        (x.to_bits(), y.to_bits(), z.to_bits()).hash(&mut hasher);
+    }
+    hasher.finish()
+}
@@ -0,0 +1,335 @@
+//! Meta-effects, which wrap another effect to change how it behaves.
+
+use std::{collections::HashMap, f32::consts::PI, str::FromStr};
+
+use rand::{prelude::StdRng, Rng, SeedableRng};
+use structopt::StructOpt;
+
+use crate::effects::{Bounds, Color, Coord, Effect, EffectContext};
+
+#[derive(Debug, StructOpt)]
+pub struct MetaOpt {
+    /// Make the effect symmetric: `kaleidoscope:N` mirrors it N-fold around the trunk,
+    /// `mirror-z` reflects the bottom half onto the top. May be given more than once.
+    #[structopt(long, number_of_values = 1)]
+    symmetry: Vec<Symmetry>,
+    /// Remap the effect's time: `ease-in`, `ease-out`, `ease-in-out` over the whole sequence,
+    /// or `oscillate:PERIOD:DEPTH` to speed up and slow down every PERIOD frames.
+    #[structopt(long)]
+    time_warp: Option<TimeWarp>,
+    /// Freeze the effect for FRAMES frames after every SECONDS seconds of playback, given as
+    /// `SECONDS:FRAMES`.
+    #[structopt(long)]
+    hold: Option<Hold>,
+    /// Shift each LED's timing by a random (seeded) number of frames, up to this many, to
+    /// break up lock-step effects.
+    #[structopt(long)]
+    led_offset: Option<usize>,
+}
+
+impl MetaOpt {
+    pub fn wrap(&self, mut effect: Box<dyn Effect>) -> Box<dyn Effect> {
+        for &symmetry in &self.symmetry {
+            effect = Box::new(Symmetric {
+                inner: effect,
+                symmetry,
+                coords: None,
+            });
+        }
+        if let Some(warp) = self.time_warp {
+            effect = Box::new(TimeWarped {
+                inner: effect,
+                warp,
+            });
+        }
+        if let Some(hold) = self.hold {
+            effect = Box::new(Held {
+                inner: effect,
+                hold,
+                last_frame: None,
+                last: Vec::new(),
+            });
+        }
+        if let Some(max_offset) = self.led_offset {
+            effect = Box::new(LedOffset {
+                inner: effect,
+                max_offset,
+                offsets: None,
+                renders: HashMap::new(),
+                spare: Vec::new(),
+            });
+        }
+        effect
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Symmetry {
+    Kaleidoscope(u32),
+    MirrorZ,
+}
+
+impl FromStr for Symmetry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("kaleidoscope", n)) => match n.parse() {
+                Ok(n) if n > 0 => Ok(Self::Kaleidoscope(n)),
+                _ => Err(format!("Invalid kaleidoscope count: {}", n)),
+            },
+            None if s == "mirror-z" => Ok(Self::MirrorZ),
+            _ => Err(format!("Unknown symmetry: {}", s)),
+        }
+    }
+}
+
+impl Symmetry {
+    /// Maps every LED into the symmetry's fundamental region, so LEDs which should match
+    /// end up with the same coordinates.
+    fn transform(self, coords: &[Coord]) -> Vec<Coord> {
+        match self {
+            Self::Kaleidoscope(n) => {
+                let wedge = 2.0 * PI / n as f32;
+                coords
+                    .iter()
+                    .map(|&(x, y, z)| {
+                        let r = (x * x + y * y).sqrt();
+                        let mut angle = f32::atan2(x, y).rem_euclid(wedge);
+                        if angle > wedge * 0.5 {
+                            angle = wedge - angle;
+                        }
+                        let (sin, cos) = angle.sin_cos();
+                        (r * sin, r * cos, z)
+                    })
+                    .collect()
+            }
+            Self::MirrorZ => {
+                let (min_z, max_z) = coords
+                    .iter()
+                    .fold((f32::INFINITY, f32::NEG_INFINITY), |(min_z, max_z), c| {
+                        (min_z.min(c.2), max_z.max(c.2))
+                    });
+                let mid_z = (min_z + max_z) * 0.5;
+                coords
+                    .iter()
+                    .map(|&(x, y, z)| (x, y, mid_z - (z - mid_z).abs()))
+                    .collect()
+            }
+        }
+    }
+}
+
+pub struct Symmetric {
+    inner: Box<dyn Effect>,
+    symmetry: Symmetry,
+    coords: Option<(Vec<Coord>, Bounds)>,
+}
+
+impl Effect for Symmetric {
+    fn render(&mut self, ctx: &EffectContext, out: &mut [Color]) {
+        let symmetry = self.symmetry;
+        let (coords, bounds) = self.coords.get_or_insert_with(|| {
+            let coords = symmetry.transform(ctx.coords);
+            let bounds = Bounds::of(&coords);
+            (coords, bounds)
+        });
+        let bounds = *bounds;
+        self.inner.render(
+            &EffectContext {
+                coords,
+                bounds,
+                ..*ctx
+            },
+            out,
+        )
+    }
+
+    fn stateful(&self) -> bool {
+        self.inner.stateful()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeWarp {
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    Oscillate { period: f32, depth: f32 },
+}
+
+impl FromStr for TimeWarp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<_> = s.split(':').collect();
+        match parts[..] {
+            ["ease-in"] => Ok(Self::EaseIn),
+            ["ease-out"] => Ok(Self::EaseOut),
+            ["ease-in-out"] => Ok(Self::EaseInOut),
+            ["oscillate", period, depth] => {
+                let period = period
+                    .parse()
+                    .map_err(|_| format!("Invalid oscillation period: {}", period))?;
+                let depth: f32 = depth
+                    .parse()
+                    .map_err(|_| format!("Invalid oscillation depth: {}", depth))?;
+                if !(0.0..=1.0).contains(&depth) {
+                    return Err("Oscillation depth must be between 0 and 1".into());
+                }
+                Ok(Self::Oscillate { period, depth })
+            }
+            _ => Err(format!("Unknown time warp: {}", s)),
+        }
+    }
+}
+
+impl TimeWarp {
+    /// Maps a frame to the (fractional) frame the inner effect should render.
+    fn apply(self, frame: f32, total_frames: f32) -> f32 {
+        let t = frame / total_frames;
+        match self {
+            Self::EaseIn => t * t * total_frames,
+            Self::EaseOut => (1.0 - (1.0 - t) * (1.0 - t)) * total_frames,
+            Self::EaseInOut => t * t * (3.0 - 2.0 * t) * total_frames,
+            // Integral of a speed of `1 + depth * sin(2 pi frame / period)`
+            Self::Oscillate { period, depth } => {
+                let phase = 2.0 * PI * frame / period;
+                frame + depth * period / (2.0 * PI) * (1.0 - phase.cos())
+            }
+        }
+    }
+}
+
+pub struct TimeWarped {
+    inner: Box<dyn Effect>,
+    warp: TimeWarp,
+}
+
+impl Effect for TimeWarped {
+    fn render(&mut self, ctx: &EffectContext, out: &mut [Color]) {
+        let total_frames = ctx.total_frames as f32;
+        let frame = self
+            .warp
+            .apply(ctx.frame as f32, total_frames)
+            .round()
+            .rem_euclid(total_frames) as usize;
+        self.inner.render(&EffectContext { frame, ..*ctx }, out)
+    }
+
+    fn stateful(&self) -> bool {
+        self.inner.stateful()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hold {
+    every_secs: f32,
+    frames: usize,
+}
+
+impl FromStr for Hold {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (every_secs, frames) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Expected SECONDS:FRAMES, got {}", s))?;
+        Ok(Self {
+            every_secs: every_secs
+                .parse()
+                .map_err(|_| format!("Invalid hold interval: {}", every_secs))?,
+            frames: frames
+                .parse()
+                .map_err(|_| format!("Invalid hold length: {}", frames))?,
+        })
+    }
+}
+
+pub struct Held {
+    inner: Box<dyn Effect>,
+    hold: Hold,
+    last_frame: Option<usize>,
+    last: Vec<Color>,
+}
+
+impl Effect for Held {
+    fn render(&mut self, ctx: &EffectContext, out: &mut [Color]) {
+        // The inner effect plays for `play_frames`, then its last frame is held. Its time is
+        // paused while holding, so it carries on where it left off afterwards.
+        let play_frames = ((self.hold.every_secs * ctx.fps).round() as usize).max(1);
+        let cycle_frames = play_frames + self.hold.frames;
+        let cycle = ctx.frame / cycle_frames;
+        let frame = cycle * play_frames + (ctx.frame % cycle_frames).min(play_frames - 1);
+
+        if self.last_frame == Some(frame) {
+            out.copy_from_slice(&self.last);
+        } else {
+            self.inner.render(&EffectContext { frame, ..*ctx }, out);
+            self.last_frame = Some(frame);
+            self.last.clear();
+            self.last.extend_from_slice(out);
+        }
+    }
+
+    /// The held frame was rendered with the context of the frame the hold started on.
+    fn stateful(&self) -> bool {
+        true
+    }
+}
+
+pub struct LedOffset {
+    inner: Box<dyn Effect>,
+    max_offset: usize,
+    offsets: Option<Vec<usize>>,
+    /// Recent renders of the inner effect by frame, since consecutive frames need mostly the
+    /// same ones.
+    renders: HashMap<usize, Vec<Color>>,
+    /// Buffers of renders which are no longer needed, to reuse.
+    spare: Vec<Vec<Color>>,
+}
+
+impl Effect for LedOffset {
+    fn render(&mut self, ctx: &EffectContext, out: &mut [Color]) {
+        let max_offset = self.max_offset;
+        let renders = &mut self.renders;
+        let spare = &mut self.spare;
+        let offsets = self.offsets.get_or_insert_with(|| {
+            let mut rng = StdRng::seed_from_u64(ctx.seed);
+            (0..ctx.coords.len())
+                .map(|_| rng.gen_range(0..=max_offset))
+                .collect()
+        });
+
+        let frame_for = |offset: usize| (ctx.frame + offset) % ctx.total_frames;
+        let stale: Vec<usize> = renders
+            .keys()
+            .copied()
+            .filter(|&frame| !(0..=max_offset).any(|offset| frame_for(offset) == frame))
+            .collect();
+        for frame in stale {
+            spare.extend(renders.remove(&frame));
+        }
+        for offset in 0..=max_offset {
+            let frame = frame_for(offset);
+            let inner = &mut self.inner;
+            renders.entry(frame).or_insert_with(|| {
+                let mut colors = spare
+                    .pop()
+                    .unwrap_or_else(|| vec![(0.0, 0.0, 0.0); ctx.coords.len()]);
+                inner.render(&EffectContext { frame, ..*ctx }, &mut colors);
+                colors
+            });
+        }
+
+        for (i, (color, &offset)) in out.iter_mut().zip(offsets.iter()).enumerate() {
+            *color = renders[&frame_for(offset)][i];
+        }
+    }
+
+    /// Renders are kept for later frames, made with the previous frame of the one they were
+    /// first needed for.
+    fn stateful(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,129 @@
+use std::f32::consts::PI;
+
+use crate::effects::{Color, Coord};
+
+/// Number of neighbours each LED is connected to for spatial filters.
+pub const NEIGHBOUR_COUNT: usize = 6;
+
+/// The k nearest neighbours of every LED, used for spatial filters and effects.
+pub struct NeighbourGraph {
+    /// For each LED, its neighbours' indices and distances, nearest first.
+    pub neighbours: Vec<Vec<(usize, f32)>>,
+}
+
+fn distance(a: Coord, b: Coord) -> f32 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2) + (a.2 - b.2).powi(2)).sqrt()
+}
+
+/// An order to visit every LED in which consecutive LEDs are close together, found greedily by
+/// always stepping to the nearest unvisited LED, starting from the lowest one.
+pub fn spatial_tour(coords: &[Coord]) -> Vec<usize> {
+    let mut remaining: Vec<usize> = (0..coords.len()).collect();
+    let mut tour = Vec::with_capacity(coords.len());
+    let mut next = remaining
+        .iter()
+        .enumerate()
+        .min_by(|a, b| coords[*a.1].2.total_cmp(&coords[*b.1].2))
+        .map(|(pos, _)| pos);
+    while let Some(pos) = next {
+        let current = remaining.swap_remove(pos);
+        tour.push(current);
+        next = remaining
+            .iter()
+            .enumerate()
+            .min_by(|a, b| {
+                distance(coords[current], coords[*a.1])
+                    .total_cmp(&distance(coords[current], coords[*b.1]))
+            })
+            .map(|(pos, _)| pos);
+    }
+    tour
+}
+
+impl NeighbourGraph {
+    pub fn knn(coords: &[Coord], k: usize) -> Self {
+        let neighbours = coords
+            .iter()
+            .enumerate()
+            .map(|(i, &a)| {
+                let mut distances: Vec<_> = coords
+                    .iter()
+                    .enumerate()
+                    .filter(|&(j, _)| j != i)
+                    .map(|(j, &b)| (j, distance(a, b)))
+                    .collect();
+                if distances.len() > k {
+                    distances.select_nth_unstable_by(k, |x, y| x.1.total_cmp(&y.1));
+                    distances.truncate(k);
+                }
+                distances.sort_by(|x, y| x.1.total_cmp(&y.1));
+                distances
+            })
+            .collect();
+        Self { neighbours }
+    }
+
+    /// Mixes each LED with the distance-weighted average of its neighbours. A `strength` of 0
+    /// leaves the frame unchanged, 1 replaces each LED entirely with its neighbourhood.
+    pub fn blur(&self, frame: &[Color], strength: f32) -> Vec<Color> {
+        frame
+            .iter()
+            .zip(&self.neighbours)
+            .map(|(&color, neighbours)| {
+                let mean_distance =
+                    neighbours.iter().map(|n| n.1).sum::<f32>() / neighbours.len().max(1) as f32;
+                let mut total = (0.0, 0.0, 0.0);
+                let mut total_weight = 0.0;
+                for &(j, d) in neighbours {
+                    let weight = 1.0 / (1.0 + d / mean_distance.max(f32::EPSILON));
+                    let c = frame[j];
+                    total = (
+                        total.0 + c.0 * weight,
+                        total.1 + c.1 * weight,
+                        total.2 + c.2 * weight,
+                    );
+                    total_weight += weight;
+                }
+                if total_weight == 0.0 {
+                    return color;
+                }
+                let keep = 1.0 - strength;
+                let mix = strength / total_weight;
+                (
+                    color.0 * keep + total.0 * mix,
+                    color.1 * keep + total.1 * mix,
+                    color.2 * keep + total.2 * mix,
+                )
+            })
+            .collect()
+    }
+
+    /// Estimates how many LEDs there are per unit volume around each LED, from the sphere
+    /// reaching its furthest neighbour.
+    pub fn density(&self) -> Vec<f32> {
+        self.neighbours
+            .iter()
+            .map(|neighbours| {
+                let radius = neighbours.last().map_or(0.0, |n| n.1).max(f32::EPSILON);
+                neighbours.len() as f32 / (4.0 / 3.0 * PI * radius.powi(3))
+            })
+            .collect()
+    }
+}
+
+/// Brightness scales which even out LED density: each LED is dimmed by how much denser its
+/// neighbourhood is than the sparse parts of the tree, raised to `strength` (0 = unchanged, 1 =
+/// the same light per volume everywhere). Nothing is brightened, so the sparsest tenth of LEDs
+/// keep their full brightness.
+pub fn density_gains(density: &[f32], strength: f32) -> Vec<f32> {
+    let mut sorted = density.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let sparse = match sorted.get(sorted.len() / 10) {
+        Some(&sparse) => sparse,
+        None => return Vec::new(),
+    };
+    density
+        .iter()
+        .map(|&d| (sparse / d).powf(strength).min(1.0))
+        .collect()
+}
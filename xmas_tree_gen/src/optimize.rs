@@ -0,0 +1,103 @@
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::Path,
+};
+
+use serde::Serialize;
+use xmas_tree_common::{
+    delta_format::DeltaWriter,
+    metadata::SequenceMetadata,
+    seal::{self, SealMode},
+    sequence::{self, Rgb, SequenceFormat, SequenceWriter},
+};
+
+use crate::report::Report;
+
+#[derive(Debug, Serialize)]
+pub struct OptimizeReport {
+    pub frames: usize,
+    pub repeated_frames: usize,
+    pub quantized_leds: usize,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+}
+
+/// Rewrites a sequence in the delta format. LEDs which changed by no more than `tolerance` in
+/// every channel keep their previous value, so near-identical frames collapse into repeats.
+pub fn optimize(
+    input: &Path,
+    output: &Path,
+    tolerance: u8,
+    seal: Option<SealMode>,
+) -> Result<OptimizeReport, Box<dyn Error>> {
+    let sequence = sequence::read(input)?;
+    let (coords_hash, markers, credits) = match SequenceMetadata::load(input)? {
+        Some(metadata) => (metadata.coords_hash, metadata.markers, metadata.credits),
+        None => (None, Vec::new(), Vec::new()),
+    };
+    let mut writer = DeltaWriter::new(BufWriter::new(File::create(output)?), sequence.led_count)?;
+
+    let frames = sequence.frames.len();
+    let mut previous: Option<Vec<Rgb>> = None;
+    let mut repeated_frames = 0;
+    let mut quantized_leds = 0;
+    for mut frame in sequence.frames {
+        if let Some(previous) = &previous {
+            for (rgb, prev) in frame.iter_mut().zip(previous) {
+                let close = rgb
+                    .iter()
+                    .zip(prev)
+                    .all(|(&a, &b)| (a as i16 - b as i16).abs() <= tolerance as i16);
+                if close && rgb != prev {
+                    *rgb = *prev;
+                    quantized_leds += 1;
+                }
+            }
+            if &frame == previous {
+                repeated_frames += 1;
+            }
+        }
+        writer.write_frame(&frame)?;
+        previous = Some(frame);
+    }
+    writer.flush()?;
+    drop(writer);
+    if let Some(mode) = seal {
+        seal::seal_file(output, mode)?;
+    }
+    SequenceMetadata::write_sidecar(
+        output,
+        SequenceFormat::Delta,
+        frames,
+        sequence.led_count,
+        coords_hash,
+        markers,
+        credits,
+    )?;
+
+    Ok(OptimizeReport {
+        frames,
+        repeated_frames,
+        quantized_leds,
+        input_bytes: fs::metadata(input)?.len(),
+        output_bytes: fs::metadata(output)?.len(),
+    })
+}
+
+impl Report for OptimizeReport {
+    fn write_text(&self, out: &mut dyn io::Write) -> io::Result<()> {
+        writeln!(out, "Frames:          {}", self.frames)?;
+        writeln!(out, "Repeated frames: {}", self.repeated_frames)?;
+        writeln!(out, "Quantized LEDs:  {}", self.quantized_leds)?;
+        writeln!(
+            out,
+            "Size:            {} -> {} bytes ({:.1}% smaller)",
+            self.input_bytes,
+            self.output_bytes,
+            100.0 * (1.0 - self.output_bytes as f64 / self.input_bytes.max(1) as f64)
+        )?;
+        Ok(())
+    }
+}
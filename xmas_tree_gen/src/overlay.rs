@@ -0,0 +1,388 @@
+//! Lets other programs draw over what `live` is showing, through a Unix socket, so a one-off
+//! script (a doorbell flash, a countdown, a score from a game) doesn't mean changing the show.
+//! Each connection sends commands, one per line, and gets back `ok` or `error: MESSAGE` for each:
+//!
+//! - `layer NAME [priority=N] [blend=MODE] [opacity=F]` sets up a layer. Layers with higher
+//!   priorities are drawn on top (default 0), and `blend` is `over` (the default), `add` or
+//!   `multiply`.
+//! - `frame NAME HEX` sets what a layer shows, as six hex digits per LED like the `/frames` stream
+//!   of `--serve`, or eight to give each LED an opacity as well, so the show can be left showing
+//!   through. A layer which hasn't been set up is created with the defaults.
+//! - `clear NAME` removes a layer.
+//!
+//! A layer keeps showing its last frame until it's cleared or the connection which made it
+//! closes, so a script which dies doesn't leave its overlay behind. A line too long to be any
+//! command, longer than a frame with an opacity for every LED, closes the connection.
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    io::{BufRead, Read, Write},
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use tracing::{debug, info};
+
+use crate::effects::{mix, Color};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Blend {
+    Over,
+    Add,
+    Multiply,
+}
+
+impl FromStr for Blend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "over" => Ok(Self::Over),
+            "add" => Ok(Self::Add),
+            "multiply" => Ok(Self::Multiply),
+            _ => Err(format!(
+                "Unknown blend {}, expected over, add or multiply",
+                s
+            )),
+        }
+    }
+}
+
+struct Layer {
+    /// The connection which made it.
+    owner: u64,
+    priority: i32,
+    blend: Blend,
+    opacity: f32,
+    /// Each LED's color and opacity.
+    pixels: Vec<(Color, f32)>,
+}
+
+impl Layer {
+    fn new(owner: u64) -> Self {
+        Self {
+            owner,
+            priority: 0,
+            blend: Blend::Over,
+            opacity: 1.0,
+            pixels: Vec::new(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Layers {
+    layers: BTreeMap<String, Layer>,
+}
+
+impl Layers {
+    /// Runs one command from a connection.
+    fn command(&mut self, owner: u64, line: &str, led_count: usize) -> Result<(), String> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let name = words
+            .next()
+            .ok_or_else(|| format!("{} needs a layer name", command))?;
+        match command {
+            "layer" => {
+                let layer = self.claim(owner, name)?;
+                for word in words {
+                    let (key, value) = word
+                        .split_once('=')
+                        .ok_or_else(|| format!("Expected KEY=VALUE, got {}", word))?;
+                    let invalid = || format!("Invalid {}: {}", key, value);
+                    match key {
+                        "priority" => layer.priority = value.parse().map_err(|_| invalid())?,
+                        "blend" => layer.blend = value.parse()?,
+                        "opacity" => {
+                            layer.opacity =
+                                value.parse::<f32>().map_err(|_| invalid())?.clamp(0.0, 1.0)
+                        }
+                        other => return Err(format!("Unknown layer option: {}", other)),
+                    }
+                }
+            }
+            "frame" => {
+                let hex = words.next().unwrap_or_default();
+                let pixels = parse_pixels(hex, led_count)?;
+                self.claim(owner, name)?.pixels = pixels;
+            }
+            "clear" => match self.layers.get(name) {
+                Some(layer) if layer.owner != owner => {
+                    return Err(format!("{} belongs to another connection", name))
+                }
+                Some(_) => {
+                    self.layers.remove(name);
+                    info!("Removed overlay {}", name);
+                }
+                None => return Err(format!("No layer called {}", name)),
+            },
+            other => return Err(format!("Unknown command: {}", other)),
+        }
+        Ok(())
+    }
+
+    /// The layer called `name`, made for `owner` if there isn't one yet. Layers can only be
+    /// changed by the connection which made them.
+    fn claim(&mut self, owner: u64, name: &str) -> Result<&mut Layer, String> {
+        let layer = self.layers.entry(name.to_string()).or_insert_with(|| {
+            info!("Added overlay {}", name);
+            Layer::new(owner)
+        });
+        if layer.owner != owner {
+            return Err(format!("{} belongs to another connection", name));
+        }
+        Ok(layer)
+    }
+
+    fn disconnect(&mut self, owner: u64) {
+        self.layers.retain(|name, layer| {
+            if layer.owner == owner {
+                info!("Removed overlay {}", name);
+            }
+            layer.owner != owner
+        });
+    }
+}
+
+/// Reads a frame of `RRGGBB` or `RRGGBBAA` per LED.
+fn parse_pixels(hex: &str, led_count: usize) -> Result<Vec<(Color, f32)>, String> {
+    let digits = match hex.len() {
+        len if len == led_count * 6 => 6,
+        len if len == led_count * 8 => 8,
+        len => {
+            return Err(format!(
+                "Expected 6 or 8 hex digits for each of {} LEDs, got {} digits",
+                led_count, len
+            ))
+        }
+    };
+    let byte = |i: usize| {
+        hex.get(i..i + 2)
+            .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+            .map(|v| v as f32 / 255.0)
+            .ok_or_else(|| format!("Invalid hex at digit {}", i))
+    };
+    (0..led_count)
+        .map(|led| {
+            let at = led * digits;
+            let color = (byte(at)?, byte(at + 2)?, byte(at + 4)?);
+            let alpha = if digits == 8 { byte(at + 6)? } else { 1.0 };
+            Ok((color, alpha))
+        })
+        .collect()
+}
+
+/// Room on a line for the command and layer name, on top of a frame's hex digits.
+const COMMAND_OVERHEAD: usize = 256;
+
+/// Runs a connection's commands until it closes, then removes the layers it made. Lines longer
+/// than the longest frame could be end the connection, rather than being buffered.
+#[cfg_attr(not(unix), allow(dead_code))]
+fn serve(
+    layers: &Mutex<Layers>,
+    owner: u64,
+    mut reader: impl BufRead,
+    mut writer: impl Write,
+    led_count: usize,
+) {
+    let max_line = (led_count * 8 + COMMAND_OVERHEAD) as u64;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match reader.by_ref().take(max_line).read_line(&mut line) {
+            Ok(0) => break,
+            Ok(len) if len as u64 == max_line && !line.ends_with('\n') => {
+                let _ = writeln!(writer, "error: Line longer than {} bytes", max_line);
+                break;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                debug!("Overlay connection failed: {}", e);
+                break;
+            }
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = layers.lock().unwrap().command(owner, &line, led_count);
+        let reply = match result {
+            Ok(()) => writeln!(writer, "ok"),
+            Err(e) => writeln!(writer, "error: {}", e),
+        };
+        if reply.is_err() {
+            break;
+        }
+    }
+    layers.lock().unwrap().disconnect(owner);
+}
+
+pub struct Overlays {
+    layers: Arc<Mutex<Layers>>,
+}
+
+impl Overlays {
+    /// Listens on a Unix socket at `path`, replacing any left by an earlier run.
+    #[cfg(unix)]
+    pub fn start(path: &Path, led_count: usize) -> Result<Self, Box<dyn Error>> {
+        use std::{
+            fs,
+            io::BufReader,
+            os::unix::{fs::FileTypeExt, net::UnixListener},
+            thread,
+        };
+
+        if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)
+            .map_err(|e| format!("Cannot listen on {}: {}", path.display(), e))?;
+        let layers = Arc::new(Mutex::new(Layers::default()));
+        let shared = layers.clone();
+        thread::spawn(move || {
+            for (owner, stream) in (0..).zip(listener.incoming().flatten()) {
+                let layers = shared.clone();
+                thread::spawn(move || {
+                    if let Ok(writer) = stream.try_clone() {
+                        serve(&layers, owner, BufReader::new(stream), writer, led_count);
+                    }
+                });
+            }
+        });
+        info!("Taking overlays on {}", path.display());
+        Ok(Self { layers })
+    }
+
+    #[cfg(not(unix))]
+    pub fn start(path: &Path, _led_count: usize) -> Result<Self, Box<dyn Error>> {
+        Err(format!(
+            "Cannot listen on {}: overlays need Unix sockets",
+            path.display()
+        )
+        .into())
+    }
+
+    /// Draws every layer over `frame`, lowest priority first.
+    pub fn composite(&self, frame: &mut [Color]) {
+        let layers = self.layers.lock().unwrap();
+        let mut order: Vec<&Layer> = layers.layers.values().collect();
+        order.sort_by_key(|layer| layer.priority);
+        for layer in order {
+            for (base, &(color, alpha)) in frame.iter_mut().zip(&layer.pixels) {
+                let amount = alpha * layer.opacity;
+                *base = match layer.blend {
+                    Blend::Over => mix(*base, color, amount),
+                    Blend::Add => (
+                        base.0 + color.0 * amount,
+                        base.1 + color.1 * amount,
+                        base.2 + color.2 * amount,
+                    ),
+                    Blend::Multiply => mix(
+                        *base,
+                        (base.0 * color.0, base.1 * color.1, base.2 * color.2),
+                        amount,
+                    ),
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overlays(layers: Layers) -> Overlays {
+        Overlays {
+            layers: Arc::new(Mutex::new(layers)),
+        }
+    }
+
+    #[test]
+    fn layers_belong_to_their_connection() {
+        let mut layers = Layers::default();
+        layers.command(1, "frame doorbell ff0000", 1).unwrap();
+        for command in [
+            "layer doorbell priority=1",
+            "frame doorbell 00ff00",
+            "clear doorbell",
+        ] {
+            assert_eq!(
+                layers.command(2, command, 1),
+                Err("doorbell belongs to another connection".into())
+            );
+        }
+        layers.command(1, "clear doorbell", 1).unwrap();
+        layers.command(2, "frame doorbell 00ff00", 1).unwrap();
+    }
+
+    #[test]
+    fn higher_priorities_drawn_on_top() {
+        let mut layers = Layers::default();
+        // Named so the top layer comes first by name
+        layers.command(1, "layer a priority=2", 1).unwrap();
+        layers.command(1, "frame a ff0000", 1).unwrap();
+        layers.command(1, "frame b 0000ff", 1).unwrap();
+        let mut frame = vec![(0.0, 1.0, 0.0)];
+        overlays(layers).composite(&mut frame);
+        assert_eq!(frame, [(1.0, 0.0, 0.0)]);
+    }
+
+    #[test]
+    fn hex_digits() {
+        assert_eq!(
+            parse_pixels("ff000000ff00", 2),
+            Ok(vec![((1.0, 0.0, 0.0), 1.0), ((0.0, 1.0, 0.0), 1.0)])
+        );
+        assert_eq!(
+            parse_pixels("ff0000ff0000ff00", 2),
+            Ok(vec![((1.0, 0.0, 0.0), 1.0), ((0.0, 0.0, 1.0), 0.0)])
+        );
+        assert_eq!(
+            parse_pixels("ff0000", 2),
+            Err("Expected 6 or 8 hex digits for each of 2 LEDs, got 6 digits".into())
+        );
+    }
+
+    #[test]
+    fn disconnect_removes_layers() {
+        let layers = Mutex::new(Layers::default());
+        layers
+            .lock()
+            .unwrap()
+            .command(2, "frame other ff0000", 1)
+            .unwrap();
+        let mut replies = Vec::new();
+        serve(
+            &layers,
+            1,
+            &b"frame doorbell ff0000\nframe other 00ff00\n"[..],
+            &mut replies,
+            1,
+        );
+        assert_eq!(
+            String::from_utf8(replies).unwrap(),
+            "ok\nerror: other belongs to another connection\n"
+        );
+        let names: Vec<_> = layers.lock().unwrap().layers.keys().cloned().collect();
+        assert_eq!(names, ["other"]);
+    }
+
+    #[test]
+    fn long_lines_end_the_connection() {
+        let layers = Mutex::new(Layers::default());
+        let mut replies = Vec::new();
+        let line = format!(
+            "frame doorbell {}\nframe doorbell ff0000\n",
+            "0".repeat(300)
+        );
+        serve(&layers, 1, line.as_bytes(), &mut replies, 1);
+        assert_eq!(
+            String::from_utf8(replies).unwrap(),
+            "error: Line longer than 264 bytes\n"
+        );
+        assert!(layers.lock().unwrap().layers.is_empty());
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use rand::{prelude::StdRng, Rng, SeedableRng};
+use tracing::debug;
+use xmas_tree_common::palette::Palette;
+
+use crate::effects::EffectInfo;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ParamKind {
+    Float,
+    Int,
+    /// Comma separated integers.
+    IntList,
+    /// A palette, or `@PATH` to read one from a palette file (or `@NAME` for one of the
+    /// project's).
+    Palette,
+    /// A palette, or empty to let the effect pick its own colors.
+    OptionalPalette,
+    /// Free-form text checked by the given function, or `@PATH` to read it from a file.
+    Script(fn(&str) -> Result<(), String>),
+    /// The path of a file or directory, checked by the given function.
+    Path(fn(&str) -> Result<(), String>),
+    /// One of a fixed set of names.
+    Choice(&'static [&'static str]),
+}
+
+impl fmt::Display for ParamKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Float => f.write_str("number"),
+            Self::Int => f.write_str("integer"),
+            Self::IntList => f.write_str("list of integers"),
+            Self::Palette => f.write_str("palette"),
+            Self::OptionalPalette => f.write_str("palette (optional)"),
+            Self::Script(_) => f.write_str("script or @file"),
+            Self::Path(_) => f.write_str("path"),
+            Self::Choice(choices) => write!(f, "one of {}", choices.join(", ")),
+        }
+    }
+}
+
+impl ParamKind {
+    fn check(&self, value: &str) -> Result<(), String> {
+        match self {
+            Self::Script(validate) if !value.is_empty() => return validate(value),
+            Self::Script(_) => return Ok(()),
+            Self::Path(validate) => return validate(value),
+            _ => {}
+        }
+        let valid = match self {
+            Self::Float => value.parse::<f32>().is_ok(),
+            Self::Int => value.parse::<usize>().is_ok(),
+            Self::IntList => value.split(',').all(|v| v.trim().parse::<usize>().is_ok()),
+            Self::Palette => value.parse::<Palette>().is_ok(),
+            Self::OptionalPalette => value.is_empty() || value.parse::<Palette>().is_ok(),
+            Self::Choice(choices) => choices.contains(&value),
+            Self::Script(_) | Self::Path(_) => unreachable!(),
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(format!("expected {}, got {}", self, value))
+        }
+    }
+}
+
+fn is_shell_safe(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "=,.-_#:@/".contains(c)
+}
+
+/// How far `--variation` can move a parameter, as a fraction of its safe range.
+const VARIATION_AMOUNT: f32 = 0.2;
+
+/// Describes a tunable parameter of an effect.
+pub struct ParamInfo {
+    pub name: &'static str,
+    pub kind: ParamKind,
+    /// Range a numeric parameter can safely be moved within by `--variation`.
+    pub range: Option<(f32, f32)>,
+    pub default: &'static str,
+    pub description: &'static str,
+}
+
+/// A `name=value` parameter override from the command line.
+#[derive(Debug, Clone)]
+pub struct ParamArg {
+    pub name: String,
+    pub value: String,
+}
+
+impl FromStr for ParamArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Expected NAME=VALUE, got {}", s))?;
+        Ok(Self {
+            name: name.trim().into(),
+            value: value.trim().into(),
+        })
+    }
+}
+
+/// The parameter values for one effect, with defaults filled in. Values are checked against
+/// their declared kind up front, so effects can read them without handling errors.
+#[derive(Debug, Clone, Default)]
+pub struct Params {
+    values: HashMap<&'static str, String>,
+}
+
+impl Params {
+    /// Fills in defaults and applies the overrides. If `variation` is non-zero, parameters which
+    /// aren't overridden are nudged within their safe range, differently for each variation.
+    /// `palettes` are the project's palette files, by name.
+    pub fn resolve(
+        info: &EffectInfo,
+        args: &[ParamArg],
+        palettes: &HashMap<String, PathBuf>,
+        seed: u64,
+        variation: u64,
+    ) -> Result<Self, String> {
+        let mut rng = StdRng::seed_from_u64(seed ^ variation.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        let mut values = HashMap::new();
+        for param in info.params {
+            let value = match param.range {
+                Some((min, max)) if variation != 0 => {
+                    let default: f32 = param.default.parse().unwrap();
+                    let offset = rng.gen_range(-1.0..=1.0) * VARIATION_AMOUNT * (max - min);
+                    let value = (default + offset).clamp(min, max);
+                    let value = match param.kind {
+                        ParamKind::Int => value.round().to_string(),
+                        _ => value.to_string(),
+                    };
+                    debug!("Varied parameter {} to {}", param.name, value);
+                    value
+                }
+                _ => param.default.to_string(),
+            };
+            values.insert(param.name, value);
+        }
+        let mut params = Self { values };
+        for arg in args {
+            let value = match arg.value.strip_prefix('@') {
+                Some(path) if Self::reads_files(info, &arg.name) => {
+                    let path = match palettes.get(path) {
+                        Some(palette) if Self::is_palette(info, &arg.name) => palette,
+                        _ => Path::new(path),
+                    };
+                    fs::read_to_string(path).map_err(|e| {
+                        format!(
+                            "Cannot read parameter {} from {}: {}",
+                            arg.name,
+                            path.display(),
+                            e
+                        )
+                    })?
+                }
+                _ => arg.value.clone(),
+            };
+            params.set(info, &arg.name, value)?;
+        }
+        Ok(params)
+    }
+
+    fn is_palette(info: &EffectInfo, name: &str) -> bool {
+        info.params.iter().any(|param| {
+            param.name == name
+                && matches!(param.kind, ParamKind::Palette | ParamKind::OptionalPalette)
+        })
+    }
+
+    fn reads_files(info: &EffectInfo, name: &str) -> bool {
+        info.params.iter().any(|param| {
+            param.name == name
+                && matches!(
+                    param.kind,
+                    ParamKind::Script(_) | ParamKind::Palette | ParamKind::OptionalPalette
+                )
+        })
+    }
+
+    /// Changes a parameter, checking the new value against its declared kind.
+    pub fn set(&mut self, info: &EffectInfo, name: &str, value: String) -> Result<(), String> {
+        let param = info
+            .params
+            .iter()
+            .find(|param| param.name == name)
+            .ok_or_else(|| format!("Effect {} has no parameter {}", info.name, name))?;
+        param
+            .kind
+            .check(&value)
+            .map_err(|e| format!("Invalid value for parameter {}: {}", param.name, e))?;
+        self.values.insert(param.name, value);
+        Ok(())
+    }
+
+    /// The `--param` flags which reproduce these values, skipping any left at their default.
+    pub fn cli_flags(&self, info: &EffectInfo) -> String {
+        info.params
+            .iter()
+            .filter_map(|param| {
+                let value = self.raw(param.name);
+                if value == param.default {
+                    return None;
+                }
+                let flag = format!("{}={}", param.name, value);
+                Some(if flag.chars().all(is_shell_safe) {
+                    format!("--param {}", flag)
+                } else {
+                    format!("--param '{}'", flag.replace('\'', "'\\''"))
+                })
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// A parameter's value as it was given, whatever its kind.
+    pub fn raw(&self, name: &str) -> &str {
+        self.values
+            .get(name)
+            .unwrap_or_else(|| panic!("Effect parameter {} is not declared", name))
+    }
+
+    pub fn float(&self, name: &str) -> f32 {
+        self.raw(name).parse().unwrap()
+    }
+
+    pub fn int(&self, name: &str) -> usize {
+        self.raw(name).parse().unwrap()
+    }
+
+    pub fn int_list(&self, name: &str) -> Vec<usize> {
+        self.raw(name)
+            .split(',')
+            .map(|v| v.trim().parse().unwrap())
+            .collect()
+    }
+
+    pub fn palette(&self, name: &str) -> Palette {
+        self.raw(name).parse().unwrap()
+    }
+
+    pub fn optional_palette(&self, name: &str) -> Option<Palette> {
+        match self.raw(name) {
+            "" => None,
+            value => Some(value.parse().unwrap()),
+        }
+    }
+
+    pub fn script(&self, name: &str) -> &str {
+        self.raw(name)
+    }
+
+    pub fn path(&self, name: &str) -> &Path {
+        Path::new(self.raw(name))
+    }
+
+    pub fn choice(&self, name: &str) -> &str {
+        self.raw(name)
+    }
+}
@@ -0,0 +1,149 @@
+use std::{f32::consts::PI, str::FromStr};
+
+use xmas_tree_common::geometry::{Cone, Surface};
+
+use crate::effects::Coord;
+
+/// One point of a path: either a position, or an LED to pass through.
+#[derive(Debug, Clone, Copy)]
+pub enum Waypoint {
+    Position(Coord),
+    Led(usize),
+}
+
+impl FromStr for Waypoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |v: &str| {
+            v.trim()
+                .parse::<f32>()
+                .map_err(|_| format!("Invalid path coordinate: {}", v))
+        };
+        let parts: Vec<_> = s.split(',').collect();
+        match parts[..] {
+            [led] => led
+                .trim()
+                .parse()
+                .map(Self::Led)
+                .map_err(|_| format!("Invalid path point: {}", s)),
+            [x, y, z] => Ok(Self::Position((parse(x)?, parse(y)?, parse(z)?))),
+            _ => Err(format!("Invalid path point: {}", s)),
+        }
+    }
+}
+
+/// A path through the tree, written as points separated by `;` or newlines. Each point is either
+/// `X,Y,Z` or the index of an LED to pass through.
+#[derive(Debug, Clone)]
+pub struct PathScript {
+    pub waypoints: Vec<Waypoint>,
+}
+
+impl FromStr for PathScript {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let waypoints = s
+            .split([';', '\n'])
+            .map(str::trim)
+            .filter(|point| !point.is_empty() && !point.starts_with('#'))
+            .map(str::parse)
+            .collect::<Result<Vec<Waypoint>, _>>()?;
+        if waypoints.len() < 2 {
+            return Err("A path needs at least two points".into());
+        }
+        Ok(Self { waypoints })
+    }
+}
+
+pub fn validate(s: &str) -> Result<(), String> {
+    s.parse::<PathScript>().map(|_| ())
+}
+
+/// Straight segments joining a list of points, measured by distance along them.
+#[derive(Debug, Clone)]
+pub struct Polyline {
+    points: Vec<Coord>,
+    /// Distance along the path to each point.
+    distances: Vec<f32>,
+}
+
+fn distance(a: Coord, b: Coord) -> f32 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2) + (a.2 - b.2).powi(2)).sqrt()
+}
+
+impl Polyline {
+    pub fn new(points: Vec<Coord>) -> Self {
+        let mut distances = Vec::with_capacity(points.len());
+        let mut total = 0.0;
+        for (i, &point) in points.iter().enumerate() {
+            if i > 0 {
+                total += distance(points[i - 1], point);
+            }
+            distances.push(total);
+        }
+        Self { points, distances }
+    }
+
+    /// Resolves a script's waypoints against the LED positions, skipping LEDs which don't exist.
+    pub fn from_script(script: &PathScript, coords: &[Coord]) -> Self {
+        let points = script
+            .waypoints
+            .iter()
+            .filter_map(|waypoint| match *waypoint {
+                Waypoint::Position(position) => Some(position),
+                Waypoint::Led(index) => coords.get(index).copied(),
+            })
+            .collect();
+        Self::new(points)
+    }
+
+    /// A garland winding up the tree from the bottom, following the outside of the foliage.
+    pub fn spiral(coords: &[Coord], turns: f32) -> Self {
+        const STEPS_PER_TURN: usize = 32;
+        // Garlands sit a little way into the branches rather than on their tips
+        const DEPTH: f32 = 0.8;
+        let cone = Cone::fit(coords);
+        let (bottom, top) = (cone.axis.bottom, cone.axis.top);
+        let steps = (turns * STEPS_PER_TURN as f32).ceil() as usize;
+        let points = (0..=steps)
+            .map(|i| {
+                let t = i as f32 / steps as f32;
+                let angle = t * turns * PI * 2.0;
+                let height = bottom + (top - bottom) * t;
+                let radius = cone.radius_at(height) * DEPTH;
+                let (x, y, z) = cone.axis.point_at(height);
+                (x + radius * angle.cos(), y + radius * angle.sin(), z)
+            })
+            .collect();
+        Self::new(points)
+    }
+
+    pub fn length(&self) -> f32 {
+        self.distances.last().copied().unwrap_or(0.0)
+    }
+
+    /// The closest point on the path to `coord`, as its distance along the path and its
+    /// distance from `coord`.
+    pub fn project(&self, coord: Coord) -> (f32, f32) {
+        let mut best = (0.0, f32::INFINITY);
+        for (i, pair) in self.points.windows(2).enumerate() {
+            let (a, b) = (pair[0], pair[1]);
+            let ab = (b.0 - a.0, b.1 - a.1, b.2 - a.2);
+            let ac = (coord.0 - a.0, coord.1 - a.1, coord.2 - a.2);
+            let len_sq = ab.0 * ab.0 + ab.1 * ab.1 + ab.2 * ab.2;
+            let t = if len_sq > 0.0 {
+                ((ab.0 * ac.0 + ab.1 * ac.1 + ab.2 * ac.2) / len_sq).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let closest = (a.0 + ab.0 * t, a.1 + ab.1 * t, a.2 + ab.2 * t);
+            let d = distance(coord, closest);
+            if d < best.1 {
+                best = (self.distances[i] + len_sq.sqrt() * t, d);
+            }
+        }
+        best
+    }
+}
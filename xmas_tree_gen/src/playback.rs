@@ -0,0 +1,222 @@
+//! Plays a pre-rendered sequence live, on a tree it may not have been made for. Sequences made at
+//! another frame rate are resampled, blending between neighbouring frames, and sequences made for
+//! other coordinates are remapped so each of the tree's LEDs shows the nearest LED of the
+//! sequence's layout.
+
+use std::{error::Error, fs, path::Path};
+
+use tracing::{info, warn};
+use xmas_tree_common::{
+    manifest,
+    metadata::{self, SequenceMetadata},
+    sequence,
+};
+
+use crate::{
+    effects::{mix, Bounds, Color, Coord},
+    load_coords_from, Opt,
+};
+
+pub struct Playback {
+    frames: Vec<Vec<Color>>,
+    /// Where playback has got to, in the sequence's own frames.
+    position: f32,
+    /// Sequence frames per frame shown.
+    step: f32,
+    /// The frame being shown.
+    colors: Vec<Color>,
+}
+
+impl Playback {
+    /// Opens a sequence to play on a tree with `coords`. `source_coords` is the layout it was
+    /// made for, if not the tree's, and `fps` its rate, if not recorded in its manifest.
+    pub fn open(
+        path: &Path,
+        source_coords: Option<&Path>,
+        fps: Option<f32>,
+        opt: &Opt,
+        coords: &[Coord],
+    ) -> Result<Self, Box<dyn Error>> {
+        let sequence =
+            sequence::read(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        if sequence.frames.is_empty() {
+            return Err(format!("{} has no frames", path.display()).into());
+        }
+        let leds = remap(path, sequence.led_count, source_coords, opt, coords)?;
+
+        let fps = match fps {
+            Some(fps) => fps,
+            None => recorded_fps(path)?.unwrap_or(opt.fps),
+        };
+        if fps <= 0.0 {
+            return Err(format!("Invalid fps for {}: {}", path.display(), fps).into());
+        }
+        if (fps - opt.fps).abs() > 0.01 {
+            info!(
+                "Resampling {} from {} to {} fps",
+                path.display(),
+                fps,
+                opt.fps
+            );
+        }
+
+        let channel = |v: u8| v as f32 / 255.0;
+        let frames = sequence
+            .frames
+            .into_iter()
+            .map(|frame| {
+                leds.iter()
+                    .map(|&led| {
+                        let [r, g, b] = frame[led];
+                        (channel(r), channel(g), channel(b))
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(Self {
+            frames,
+            position: 0.0,
+            step: fps / opt.fps,
+            colors: Vec::with_capacity(coords.len()),
+        })
+    }
+
+    pub fn render(&mut self) -> &[Color] {
+        let count = self.frames.len();
+        let index = self.position as usize % count;
+        let t = self.position.fract();
+        self.colors.clear();
+        if t == 0.0 {
+            self.colors.extend_from_slice(&self.frames[index]);
+        } else {
+            let next = &self.frames[(index + 1) % count];
+            self.colors.extend(
+                self.frames[index]
+                    .iter()
+                    .zip(next)
+                    .map(|(&a, &b)| mix(a, b, t)),
+            );
+        }
+        self.position = (self.position + self.step) % count as f32;
+        &self.colors
+    }
+}
+
+/// The rate a sequence was made at, from its directory's manifest.
+fn recorded_fps(path: &Path) -> Result<Option<f32>, Box<dyn Error>> {
+    let file = match path.file_name() {
+        Some(file) => file.to_string_lossy(),
+        None => return Ok(None),
+    };
+    Ok(manifest::load(&manifest::manifest_path(path))?
+        .into_iter()
+        .find(|entry| entry.file == file && entry.seconds > 0.0)
+        .map(|entry| entry.frames as f32 / entry.seconds))
+}
+
+/// Which of the sequence's LEDs each of the tree's LEDs shows.
+fn remap(
+    path: &Path,
+    led_count: usize,
+    source_coords: Option<&Path>,
+    opt: &Opt,
+    coords: &[Coord],
+) -> Result<Vec<usize>, Box<dyn Error>> {
+    let metadata = SequenceMetadata::load(path)?;
+    let tree_hash = metadata::checksum(&fs::read(&opt.coords_path)?);
+    let source_path = match source_coords {
+        Some(source_path) => source_path,
+        None if led_count == coords.len() => {
+            let made_for = metadata.and_then(|metadata| metadata.coords_hash);
+            if made_for.is_some_and(|hash| hash != tree_hash) {
+                warn!(
+                    "{} was made for other coordinates, give coords= in the calendar to remap it",
+                    path.display()
+                );
+            }
+            return Ok((0..led_count).collect());
+        }
+        None => {
+            // Without the sequence's layout, the best guess is that both strings run the same way
+            warn!(
+                "{} has {} LEDs but the tree has {}, so it is stretched along the string \
+                 (give coords= in the calendar to remap it by position)",
+                path.display(),
+                led_count,
+                coords.len()
+            );
+            return Ok((0..coords.len())
+                .map(|i| i * led_count / coords.len())
+                .collect());
+        }
+    };
+
+    let source_hash = metadata::checksum(&fs::read(source_path)?);
+    if let Some(made_for) = metadata.and_then(|metadata| metadata.coords_hash) {
+        if made_for != source_hash {
+            warn!(
+                "{} was made for other coordinates than {}",
+                path.display(),
+                source_path.display()
+            );
+        }
+    }
+    let source = load_coords_from(opt, source_path)?;
+    if source.len() != led_count {
+        return Err(format!(
+            "{} has {} LEDs, but {} has {}",
+            path.display(),
+            led_count,
+            source_path.display(),
+            source.len()
+        )
+        .into());
+    }
+    if source_hash == tree_hash {
+        return Ok((0..led_count).collect());
+    }
+    info!(
+        "Remapping {} from {} LEDs to the tree's {} by position",
+        path.display(),
+        led_count,
+        coords.len()
+    );
+    Ok(nearest(&source, coords))
+}
+
+/// For each of `to`, the nearest of `from`, once both layouts are scaled to the same height and
+/// stood on the same point.
+fn nearest(from: &[Coord], to: &[Coord]) -> Vec<usize> {
+    let from = normalize(from);
+    normalize(to)
+        .into_iter()
+        .map(|(x, y, z)| {
+            let distance = |&(fx, fy, fz): &Coord| {
+                (fx - x) * (fx - x) + (fy - y) * (fy - y) + (fz - z) * (fz - z)
+            };
+            (0..from.len())
+                .min_by(|&a, &b| distance(&from[a]).total_cmp(&distance(&from[b])))
+                .unwrap_or(0)
+        })
+        .collect()
+}
+
+/// Centres a layout on its base and scales it to a height of 1.
+fn normalize(coords: &[Coord]) -> Vec<Coord> {
+    let bounds = Bounds::of(coords);
+    let height = (bounds.max.2 - bounds.min.2).max(f32::EPSILON);
+    let centre = (
+        (bounds.min.0 + bounds.max.0) / 2.0,
+        (bounds.min.1 + bounds.max.1) / 2.0,
+    );
+    coords
+        .iter()
+        .map(|&(x, y, z)| {
+            (
+                (x - centre.0) / height,
+                (y - centre.1) / height,
+                bounds.height_fraction((x, y, z)),
+            )
+        })
+        .collect()
+}
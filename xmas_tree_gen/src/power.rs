@@ -0,0 +1,219 @@
+//! Stretches a battery through the night for a tree off the grid, e.g. on a porch with a solar
+//! panel. The charge left is read every so often on a thread of its own, and as it falls from
+//! `--battery-full` towards `--battery-low` `live` turns the tree down and makes its effects
+//! sparser, until below `--battery-low` it shows nothing but a dim twinkle on a few LEDs. Once
+//! the panel has charged the battery back up a little above `--battery-low` the show returns.
+
+use std::{
+    error::Error,
+    fs,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use structopt::StructOpt;
+use tracing::{debug, info, warn};
+
+use crate::{effects::Color, light_sensor, safe};
+
+/// How often the battery is read. Its charge changes slowly, so there's no need to ask often.
+const READ_INTERVAL: Duration = Duration::from_secs(10);
+/// Percentage points above `--battery-low` the charge must climb to before the show comes back,
+/// so a reading which wobbles around the threshold doesn't keep switching it on and off.
+const RECOVER_MARGIN: f32 = 5.0;
+/// One LED in this many is lit by the sparse twinkle.
+const SPARSE_EVERY: usize = 8;
+
+/// Where readings come from: `http://HOST[:PORT]/PATH` or a file.
+#[derive(Debug, Clone)]
+pub enum BatterySource {
+    /// A URL giving the charge as a number, or JSON with a `percent` field.
+    Http { host: String, path: String },
+    /// A file holding the charge, kept up to date by another program.
+    File(PathBuf),
+}
+
+impl FromStr for BatterySource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("http://") {
+            Some(rest) => {
+                let (host, path) = match rest.find('/') {
+                    Some(slash) => rest.split_at(slash),
+                    None => (rest, "/"),
+                };
+                if host.is_empty() {
+                    return Err(format!("No host in battery URL: {}", s));
+                }
+                Ok(Self::Http {
+                    host: host.into(),
+                    path: path.into(),
+                })
+            }
+            None => Ok(Self::File(s.into())),
+        }
+    }
+}
+
+impl BatterySource {
+    fn read_percent(&self) -> Result<f32, Box<dyn Error>> {
+        match self {
+            Self::Http { host, path } => parse_percent(&light_sensor::get(host, path)?),
+            Self::File(path) => parse_percent(
+                &fs::read_to_string(path)
+                    .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?,
+            ),
+        }
+    }
+}
+
+/// Reads a charge in percent, written either as a plain number (with or without a `%`) or as
+/// JSON with a `percent`, `battery`, `soc` or `state_of_charge` field, as battery monitors and
+/// solar charge controllers variously call it. When the text as a whole isn't one its last line
+/// is tried, so a file an MQTT client appends each message to can be read as it grows.
+fn parse_percent(text: &str) -> Result<f32, Box<dyn Error>> {
+    let parse = |text: &str| -> Option<f32> {
+        let text = text.trim();
+        if let Ok(percent) = text.trim_end_matches('%').trim_end().parse() {
+            return Some(percent);
+        }
+        let value: serde_json::Value = serde_json::from_str(text).ok()?;
+        ["percent", "battery", "soc", "state_of_charge"]
+            .iter()
+            .find_map(|field| value.get(field)?.as_f64())
+            .map(|percent| percent as f32)
+    };
+    let last_line = text.lines().rev().find(|line| !line.trim().is_empty());
+    parse(text)
+        .or_else(|| parse(last_line?))
+        .filter(|percent| percent.is_finite())
+        .map(|percent| percent.clamp(0.0, 100.0))
+        .ok_or_else(|| format!("Expected a battery percentage, got {:?}", text.trim()).into())
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct PowerOpt {
+    /// Turn the tree down as its battery runs low: `http://HOST/PATH` giving the charge in
+    /// percent as a number (or JSON with a `percent` or `soc` field), or a file another program
+    /// keeps it in, such as an MQTT topic with `mosquitto_sub -t TOPIC > FILE` (the last line is
+    /// read).
+    #[structopt(long)]
+    battery: Option<BatterySource>,
+    /// Charge, in percent, at and above which the tree runs at full power.
+    #[structopt(long, default_value = "60")]
+    battery_full: f32,
+    /// Charge, in percent, below which only a sparse twinkle is shown.
+    #[structopt(long, default_value = "20")]
+    battery_low: f32,
+    /// Brightness and effect detail just above --battery-low, from 0 to 1.
+    #[structopt(long, default_value = "0.3")]
+    battery_min: f32,
+}
+
+impl PowerOpt {
+    /// Checks the options make sense, and that the battery can be read now.
+    pub fn start(&self) -> Result<Option<Battery>, Box<dyn Error>> {
+        let source = match &self.battery {
+            Some(source) => source.clone(),
+            None => return Ok(None),
+        };
+        if !(0.0..100.0).contains(&self.battery_low) || self.battery_low >= self.battery_full {
+            return Err("--battery-low must be at least 0 and below --battery-full".into());
+        }
+        if !(0.0..=1.0).contains(&self.battery_min) {
+            return Err("--battery-min must be between 0 and 1".into());
+        }
+        let first = source
+            .read_percent()
+            .map_err(|e| format!("Cannot read the battery: {}", e))?;
+        info!("Battery is at {:.0}%", first);
+        let reading = Arc::new(Mutex::new(first));
+        let shared = reading.clone();
+        thread::spawn(move || {
+            let mut failing = false;
+            loop {
+                thread::sleep(READ_INTERVAL);
+                match source.read_percent() {
+                    Ok(percent) => {
+                        if failing {
+                            info!("Battery can be read again, at {:.0}%", percent);
+                            failing = false;
+                        }
+                        debug!("Battery is at {:.1}%", percent);
+                        *shared.lock().unwrap() = percent;
+                    }
+                    // The power mode holds at the last reading until the battery comes back
+                    Err(e) if !failing => {
+                        warn!("Cannot read the battery: {}", e);
+                        failing = true;
+                    }
+                    Err(e) => debug!("Cannot read the battery: {}", e),
+                }
+            }
+        });
+        Ok(Some(Battery {
+            opt: self.clone(),
+            reading,
+            sparse: false,
+        }))
+    }
+}
+
+/// How hard the tree can run on what's left in the battery.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PowerMode {
+    /// Brightness and effect detail scaled by this, from `--battery-min` to 1.
+    Level(f32),
+    /// Only the sparse twinkle.
+    Sparse,
+}
+
+pub struct Battery {
+    opt: PowerOpt,
+    reading: Arc<Mutex<f32>>,
+    sparse: bool,
+}
+
+impl Battery {
+    /// What the latest reading allows.
+    pub fn mode(&mut self) -> PowerMode {
+        let percent = *self.reading.lock().unwrap();
+        let PowerOpt {
+            battery_full: full,
+            battery_low: low,
+            battery_min: min,
+            ..
+        } = self.opt;
+        if !self.sparse && percent < low {
+            info!(
+                "Battery is down to {:.0}%, switching to a sparse twinkle",
+                percent
+            );
+            self.sparse = true;
+        } else if self.sparse && percent >= (low + RECOVER_MARGIN).min(full) {
+            info!("Battery is back up to {:.0}%, resuming the show", percent);
+            self.sparse = false;
+        }
+        if self.sparse {
+            return PowerMode::Sparse;
+        }
+        let t = ((percent - low) / (full - low)).clamp(0.0, 1.0);
+        PowerMode::Level(min + (1.0 - min) * t)
+    }
+}
+
+/// Frame `frame` of the twinkle shown on a low battery: the safe mode twinkle on one LED in
+/// every `SPARSE_EVERY`, with the rest off.
+pub fn sparse_twinkle(frame: usize, fps: f32, led_count: usize) -> Vec<Color> {
+    let mut colors = safe::twinkle(frame, fps, led_count);
+    for (led, color) in colors.iter_mut().enumerate() {
+        if led % SPARSE_EVERY != 0 {
+            *color = (0.0, 0.0, 0.0);
+        }
+    }
+    colors
+}
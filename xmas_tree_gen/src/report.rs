@@ -0,0 +1,43 @@
+use std::{
+    error::Error,
+    io::{self, Write},
+    str::FromStr,
+};
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            other => Err(format!("Unknown output format: {}", other)),
+        }
+    }
+}
+
+/// The result of an analysis command, printable for humans or as JSON for scripts.
+pub trait Report: Serialize {
+    fn write_text(&self, out: &mut dyn io::Write) -> io::Result<()>;
+}
+
+pub fn emit<R: Report>(report: &R, format: OutputFormat) -> Result<(), Box<dyn Error>> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match format {
+        OutputFormat::Text => report.write_text(&mut out)?,
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, report)?;
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,67 @@
+//! Lists the review notes made on a sequence in the player, with the time into the show of each,
+//! to go through afterwards or share with whoever is fixing the effects.
+
+use std::{error::Error, io, path::Path};
+
+use serde::Serialize;
+use xmas_tree_common::notes;
+
+use crate::report::Report;
+
+#[derive(Debug, Serialize)]
+pub struct NoteEntry {
+    pub frame: usize,
+    pub time_secs: f32,
+    pub author: Option<String>,
+    pub written: String,
+    pub text: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NotesReport {
+    pub notes: Vec<NoteEntry>,
+}
+
+/// Reads the notes on the sequence at `path`, timed at `fps`.
+pub fn notes(path: &Path, fps: f32) -> Result<NotesReport, Box<dyn Error>> {
+    let mut notes = notes::load(path)?;
+    notes.sort_by_key(|note| note.frame);
+    let notes = notes
+        .into_iter()
+        .map(|note| NoteEntry {
+            frame: note.frame,
+            time_secs: note.frame as f32 / fps,
+            author: note.author,
+            written: note.written,
+            text: note.text,
+        })
+        .collect();
+    Ok(NotesReport { notes })
+}
+
+/// Formats a time into the show as `M:SS.S`.
+fn timestamp(secs: f32) -> String {
+    let minutes = (secs / 60.0).floor();
+    format!("{}:{:04.1}", minutes, secs - minutes * 60.0)
+}
+
+impl Report for NotesReport {
+    fn write_text(&self, out: &mut dyn io::Write) -> io::Result<()> {
+        writeln!(out, "Notes:             {}", self.notes.len())?;
+        for note in &self.notes {
+            let by = match &note.author {
+                Some(author) => format!(", {}", author),
+                None => String::new(),
+            };
+            writeln!(
+                out,
+                "{:<19}{} (frame {}{})",
+                timestamp(note.time_secs),
+                note.text,
+                note.frame,
+                by
+            )?;
+        }
+        Ok(())
+    }
+}
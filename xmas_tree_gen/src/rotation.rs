@@ -0,0 +1,197 @@
+use std::str::FromStr;
+
+use crate::effects::Coord;
+
+pub type Matrix = [[f32; 3]; 3];
+
+pub const IDENTITY: Matrix = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+#[derive(Debug, Clone, Copy)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl FromStr for Easing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Self::Linear),
+            "ease-in" => Ok(Self::EaseIn),
+            "ease-out" => Ok(Self::EaseOut),
+            "ease-in-out" => Ok(Self::EaseInOut),
+            other => Err(format!("Unknown easing: {}", other)),
+        }
+    }
+}
+
+impl Easing {
+    fn apply(self, t: f32) -> f32 {
+        match self {
+            Self::Linear => t,
+            Self::EaseIn => t * t,
+            Self::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Self::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Step {
+    Rotate {
+        axis: Axis,
+        /// In radians.
+        angle: f32,
+        frames: f32,
+        easing: Easing,
+    },
+    Hold {
+        frames: f32,
+    },
+}
+
+impl Step {
+    fn frames(&self) -> f32 {
+        match *self {
+            Self::Rotate { frames, .. } | Self::Hold { frames } => frames,
+        }
+    }
+}
+
+/// A looping sequence of rotations, written as steps separated by `;` or newlines. Each step is
+/// `AXIS:DEGREES:FRAMES[:EASING]` to rotate about the x, y or z axis, or `hold:FRAMES` to pause.
+/// Blank lines and lines starting with `#` are ignored.
+#[derive(Debug, Clone)]
+pub struct RotationScript {
+    pub steps: Vec<Step>,
+}
+
+fn parse_frames(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(frames) if frames > 0.0 => Ok(frames),
+        _ => Err(format!("Invalid number of frames: {}", s)),
+    }
+}
+
+impl FromStr for Step {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<_> = s.split(':').map(str::trim).collect();
+        let (axis, angle, frames, easing) = match parts[..] {
+            ["hold", frames] => {
+                return Ok(Self::Hold {
+                    frames: parse_frames(frames)?,
+                })
+            }
+            [axis, angle, frames] => (axis, angle, frames, Easing::Linear),
+            [axis, angle, frames, easing] => (axis, angle, frames, easing.parse()?),
+            _ => return Err(format!("Invalid rotation step: {}", s)),
+        };
+        let axis = match axis {
+            "x" => Axis::X,
+            "y" => Axis::Y,
+            "z" => Axis::Z,
+            other => return Err(format!("Unknown rotation axis: {}", other)),
+        };
+        let angle: f32 = angle
+            .parse()
+            .map_err(|_| format!("Invalid rotation angle: {}", angle))?;
+        Ok(Self::Rotate {
+            axis,
+            angle: angle.to_radians(),
+            frames: parse_frames(frames)?,
+            easing,
+        })
+    }
+}
+
+impl FromStr for RotationScript {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let steps = s
+            .split([';', '\n'])
+            .map(str::trim)
+            .filter(|step| !step.is_empty() && !step.starts_with('#'))
+            .map(str::parse)
+            .collect::<Result<Vec<Step>, _>>()?;
+        if steps.is_empty() {
+            return Err("Rotation script has no steps".into());
+        }
+        Ok(Self { steps })
+    }
+}
+
+pub fn validate(s: &str) -> Result<(), String> {
+    s.parse::<RotationScript>().map(|_| ())
+}
+
+pub fn rotation(axis: Axis, angle: f32) -> Matrix {
+    let (s, c) = angle.sin_cos();
+    match axis {
+        Axis::X => [[1.0, 0.0, 0.0], [0.0, c, -s], [0.0, s, c]],
+        Axis::Y => [[c, 0.0, s], [0.0, 1.0, 0.0], [-s, 0.0, c]],
+        Axis::Z => [[c, -s, 0.0], [s, c, 0.0], [0.0, 0.0, 1.0]],
+    }
+}
+
+/// The matrix which applies `b` and then `a`.
+pub fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut result = [[0.0; 3]; 3];
+    for (i, row) in result.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    result
+}
+
+pub fn transform(m: &Matrix, c: Coord) -> Coord {
+    (
+        m[0][0] * c.0 + m[0][1] * c.1 + m[0][2] * c.2,
+        m[1][0] * c.0 + m[1][1] * c.1 + m[1][2] * c.2,
+        m[2][0] * c.0 + m[2][1] * c.1 + m[2][2] * c.2,
+    )
+}
+
+impl RotationScript {
+    pub fn frames(&self) -> f32 {
+        self.steps.iter().map(Step::frames).sum()
+    }
+
+    /// The orientation `frame` frames into the script. The script loops, starting again from
+    /// no rotation, so scripts whose rotations add up to a whole turn loop seamlessly.
+    pub fn orientation(&self, frame: f32) -> Matrix {
+        let mut remaining = frame.rem_euclid(self.frames());
+        let mut result = IDENTITY;
+        for step in &self.steps {
+            let progress = (remaining / step.frames()).min(1.0);
+            if let Step::Rotate {
+                axis,
+                angle,
+                easing,
+                ..
+            } = *step
+            {
+                let step_rotation = rotation(axis, angle * easing.apply(progress));
+                result = multiply(&step_rotation, &result);
+            }
+            remaining -= step.frames();
+            if remaining < 0.0 {
+                break;
+            }
+        }
+        result
+    }
+}
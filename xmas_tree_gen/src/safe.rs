@@ -0,0 +1,36 @@
+//! The gentle twinkle `live --safe-mode` falls back to when what it was asked to show can't be
+//! loaded, so a corrupt file or a typo leaves the tree looking deliberate rather than dark. It
+//! uses neither the coordinates nor the effects, so it still works when either is what broke.
+
+use std::f32::consts::TAU;
+
+use crate::effects::Color;
+
+/// A warm white, kept dim.
+const WARM: Color = (1.0, 0.7, 0.35);
+const MIN_LEVEL: f32 = 0.04;
+const MAX_LEVEL: f32 = 0.3;
+
+/// Scrambles an LED's index into bits that look random, but are the same every frame.
+fn hash(led: usize) -> u32 {
+    let mut x = led as u32 ^ 0x9e37_79b9;
+    x = x.wrapping_mul(0x85eb_ca6b);
+    x ^= x >> 13;
+    x = x.wrapping_mul(0xc2b2_ae35);
+    x ^ (x >> 16)
+}
+
+/// Frame `frame` of the twinkle, each LED slowly glowing brighter and fading at its own pace.
+pub fn twinkle(frame: usize, fps: f32, led_count: usize) -> Vec<Color> {
+    let seconds = frame as f32 / fps;
+    (0..led_count)
+        .map(|led| {
+            let bits = hash(led);
+            let period = 3.0 + 5.0 * (bits & 0xffff) as f32 / 65535.0;
+            let phase = (bits >> 16) as f32 / 65535.0;
+            let wave = 0.5 - 0.5 * (TAU * (seconds / period + phase)).cos();
+            let level = MIN_LEVEL + (MAX_LEVEL - MIN_LEVEL) * wave.powi(3);
+            (WARM.0 * level, WARM.1 * level, WARM.2 * level)
+        })
+        .collect()
+}
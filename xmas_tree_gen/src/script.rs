@@ -0,0 +1,384 @@
+//! Effects written as Rhai scripts, so trying out a new effect doesn't need a rebuild. A script
+//! defines a `render` function which is given the LED coordinates as `[x, y, z]` arrays, the
+//! frame number and the length of the sequence, and returns an `[r, g, b]` color for every LED,
+//! with channels from 0 to 1:
+//!
+//! ```rhai
+//! fn render(coords, frame, total_frames) {
+//!     coords.map(|c| saturated_color(c[2] / 4.0 + frame / 100.0))
+//! }
+//! ```
+//!
+//! Scripts can also call `lerp`, `mix`, `smoothstep` and `noise`, which work as in the built in
+//! effects. A `render` function taking a fourth argument is also given how visible each LED is
+//! from outside the tree, from 0 to 1, for keeping details to the LEDs on the outside.
+//!
+//! Comments at the top of a script such as `//! author: NAME` and `//! version: 1.2` are recorded
+//! in the credits of sequences made with it.
+//!
+//! Scripts shared online are run without trusting them: they can't touch files or the network,
+//! only `import` modules from their own directory when allowed to with `--script-imports`, each
+//! frame has a budget of operations, and each string, array or map a script makes has a size
+//! limit (see [`SandboxOpt`]). These bound how long a frame takes and how big any one value gets,
+//! not the memory a script uses overall. A script which goes over is stopped with an error saying
+//! which limit it hit. `print` and `debug` go to the log.
+
+use std::{
+    error::Error,
+    fs, mem,
+    path::{Component, Path},
+};
+
+use rhai::{
+    module_resolvers::{DummyModuleResolver, FileModuleResolver},
+    Array, Dynamic, Engine, EvalAltResult, Module, ModuleResolver, Position, Scope, Shared, AST,
+    FLOAT, INT,
+};
+use structopt::StructOpt;
+use tracing::{debug, error, info};
+
+use crate::effects::{self, Authorship, Color, Effect, EffectContext, EffectInfo};
+
+/// Describes scripts for parameter and length handling. They render through `ScriptEffect`
+/// rather than `render`.
+pub static INFO: EffectInfo = EffectInfo {
+    name: "script",
+    description: "An effect written as a Rhai script.",
+    params: &[],
+    render: |_, out| out.fill((0.0, 0.0, 0.0)),
+    cycle: None,
+    uses_previous: false,
+    detail: None,
+};
+
+/// The script an effect name refers to, if it is one.
+pub fn path(effect: &str) -> Option<&Path> {
+    Some(Path::new(effect)).filter(|path| path.extension().is_some_and(|ext| ext == "rhai"))
+}
+
+/// Reads the `author` and `version` lines from the comments at the top of a script.
+fn authorship(source: &str) -> Authorship {
+    let mut authorship = Authorship::default();
+    let header = source
+        .lines()
+        .map(str::trim)
+        .take_while(|line| line.is_empty() || line.starts_with("//"));
+    for line in header {
+        let line = line.trim_start_matches('/').trim_start_matches('!');
+        if let Some((key, value)) = line.split_once(':') {
+            let value = Some(value.trim().to_string()).filter(|value| !value.is_empty());
+            match key.trim() {
+                "author" => authorship.author = value,
+                "version" => authorship.version = value,
+                _ => {}
+            }
+        }
+    }
+    authorship
+}
+
+/// The name an effect is credited by: a script's file name without `.rhai`, otherwise the
+/// effect name itself.
+pub fn credit_name(effect: &str) -> &str {
+    path(effect)
+        .and_then(|path| path.file_stem())
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(effect)
+}
+
+pub fn to_array(color: Color) -> Array {
+    vec![
+        Dynamic::from_float(color.0 as FLOAT),
+        Dynamic::from_float(color.1 as FLOAT),
+        Dynamic::from_float(color.2 as FLOAT),
+    ]
+}
+
+pub fn to_color(value: Dynamic) -> Result<Color, String> {
+    let channel = |value: &Dynamic| {
+        value
+            .as_float()
+            .or_else(|_| value.as_int().map(|i| i as FLOAT))
+            .map(|v| v as f32)
+    };
+    match value.try_cast::<Array>().as_deref() {
+        Some([r, g, b]) => match (channel(r), channel(g), channel(b)) {
+            (Ok(r), Ok(g), Ok(b)) => Ok((r, g, b)),
+            _ => Err("color channels must be numbers".into()),
+        },
+        _ => Err("colors must be [r, g, b] arrays".into()),
+    }
+}
+
+/// Smooth random values from 0 to 1, which change over about one unit in each direction.
+fn noise(x: FLOAT, y: FLOAT, z: FLOAT) -> FLOAT {
+    let corner = |i: i64, j: i64, k: i64| {
+        let mut h = (i as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            ^ (j as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+            ^ (k as u64).wrapping_mul(0x1656_67B1_9E37_79F9);
+        h ^= h >> 29;
+        h = h.wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        h ^= h >> 32;
+        (h >> 11) as FLOAT / (1u64 << 53) as FLOAT
+    };
+    let fade = |t: FLOAT| t * t * (3.0 - 2.0 * t);
+    let lerp = |a: FLOAT, b: FLOAT, t: FLOAT| a + (b - a) * t;
+    let (i, j, k) = (x.floor() as i64, y.floor() as i64, z.floor() as i64);
+    let (u, v, w) = (
+        fade(x - x.floor()),
+        fade(y - y.floor()),
+        fade(z - z.floor()),
+    );
+    let along_x = |j, k| lerp(corner(i, j, k), corner(i + 1, j, k), u);
+    let along_y = |k| lerp(along_x(j, k), along_x(j + 1, k), v);
+    lerp(along_y(k), along_y(k + 1), w)
+}
+
+// What scripts are allowed to do. Not a doc comment, which would replace the app's description in
+// `--help` when flattened into its options.
+#[derive(Debug, Clone, StructOpt)]
+pub struct SandboxOpt {
+    /// Most operations a script may take to render one frame, which stops one stuck in a loop.
+    #[structopt(long = "script-fuel", default_value = "10000000", global = true)]
+    pub fuel: u64,
+    /// Most memory, in megabytes, any one string, array or map made by a script may take up. This
+    /// limits each value on its own, not the memory of all of them together.
+    #[structopt(long = "script-memory", default_value = "16", global = true)]
+    pub memory_mb: usize,
+    /// Let scripts import `.rhai` modules from their own directory.
+    #[structopt(long = "script-imports", global = true)]
+    pub imports: bool,
+}
+
+impl SandboxOpt {
+    /// Explains an error from a script, saying which limit it went over if that's what it was.
+    pub fn describe(&self, e: &EvalAltResult) -> String {
+        match e.unwrap_inner() {
+            EvalAltResult::ErrorTooManyOperations(_) => format!(
+                "script took more than {} operations for one frame (see --script-fuel)",
+                self.fuel
+            ),
+            EvalAltResult::ErrorDataTooLarge(what, _) => format!(
+                "{} went over the script memory limit of {} MB (see --script-memory)",
+                what, self.memory_mb
+            ),
+            EvalAltResult::ErrorModuleNotFound(path, _) if !self.imports => format!(
+                "script tried to import {}, which needs --script-imports",
+                path
+            ),
+            EvalAltResult::ErrorModuleNotFound(path, _) if !is_local(path) => format!(
+                "script tried to import {}, but only modules beside it can be imported",
+                path
+            ),
+            _ => e.to_string(),
+        }
+    }
+}
+
+/// Whether an import stays within the importing script's directory.
+fn is_local(path: &str) -> bool {
+    Path::new(path)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// Lets scripts import modules from their own directory, and nowhere else.
+struct LocalModules(FileModuleResolver);
+
+impl ModuleResolver for LocalModules {
+    fn resolve(
+        &self,
+        engine: &Engine,
+        source: Option<&str>,
+        path: &str,
+        pos: Position,
+    ) -> Result<Shared<Module>, Box<EvalAltResult>> {
+        if !is_local(path) {
+            return Err(EvalAltResult::ErrorModuleNotFound(path.into(), pos).into());
+        }
+        self.0.resolve(engine, source, path, pos)
+    }
+}
+
+/// An engine limited by `sandbox`, importing modules from `dir` if allowed to, with the
+/// functions scripts share with the built in effects.
+pub fn engine(sandbox: &SandboxOpt, dir: &Path) -> Engine {
+    let mut engine = Engine::new();
+    // Debug builds default to very shallow limits, which reject ordinary one-liners
+    engine.set_max_expr_depths(64, 64);
+    let memory = sandbox.memory_mb << 20;
+    engine
+        .set_max_operations(sandbox.fuel)
+        .set_max_string_size(memory)
+        .set_max_array_size(memory / mem::size_of::<Dynamic>())
+        // Maps also hold a key for every value
+        .set_max_map_size(memory / (2 * mem::size_of::<Dynamic>()));
+    if sandbox.imports {
+        engine.set_module_resolver(LocalModules(FileModuleResolver::new_with_path(dir)));
+    } else {
+        engine.set_module_resolver(DummyModuleResolver::new());
+    }
+    // Sequences are often written to stdout, so scripts mustn't write there
+    engine
+        .on_print(|text| info!("Script: {}", text))
+        .on_debug(|text, _, pos| debug!("Script ({}): {}", pos, text));
+    engine
+        .register_fn("saturated_color", |hue: FLOAT| {
+            to_array(effects::saturated_color(hue as f32))
+        })
+        .register_fn("lerp", |a: FLOAT, b: FLOAT, t: FLOAT| a + (b - a) * t)
+        .register_fn(
+            "mix",
+            |a: Array, b: Array, t: FLOAT| -> Result<_, Box<EvalAltResult>> {
+                let (a, b) = (to_color(a.into())?, to_color(b.into())?);
+                Ok(to_array(effects::mix(a, b, t as f32)))
+            },
+        )
+        .register_fn("smoothstep", |edge0: FLOAT, edge1: FLOAT, x: FLOAT| {
+            effects::smoothstep(edge0 as f32, edge1 as f32, x as f32) as FLOAT
+        })
+        .register_fn("noise", noise);
+    engine
+}
+
+pub struct ScriptEffect {
+    pub authorship: Authorship,
+    engine: Engine,
+    sandbox: SandboxOpt,
+    ast: AST,
+    /// Whether `render` takes each LED's visibility as well.
+    takes_visibility: bool,
+    /// Only the first error is reported, rather than one for every frame.
+    failed: bool,
+}
+
+impl ScriptEffect {
+    pub fn load(path: &Path, sandbox: &SandboxOpt) -> Result<Self, Box<dyn Error>> {
+        let source = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read script {}: {}", path.display(), e))?;
+        let engine = engine(sandbox, path.parent().unwrap_or_else(|| Path::new("")));
+        let ast = engine
+            .compile(&source)
+            .map_err(|e| format!("Error in script {}: {}", path.display(), e))?;
+        let takes_visibility = match ast
+            .iter_functions()
+            .find(|f| f.name == "render" && (3..=4).contains(&f.params.len()))
+        {
+            Some(render) => render.params.len() == 4,
+            None => {
+                return Err(format!(
+                    "Script {} has no render(coords, frame, total_frames) function",
+                    path.display()
+                )
+                .into())
+            }
+        };
+        Ok(Self {
+            authorship: authorship(&source),
+            engine,
+            sandbox: sandbox.clone(),
+            ast,
+            takes_visibility,
+            failed: false,
+        })
+    }
+
+    fn try_render(&self, ctx: &EffectContext, out: &mut [Color]) -> Result<(), String> {
+        let coords: Array = ctx
+            .coords
+            .iter()
+            .map(|&coord| to_array(coord).into())
+            .collect();
+        let (frame, total_frames) = (ctx.frame as INT, ctx.total_frames as INT);
+        let colors: Array = if self.takes_visibility {
+            let visibility: Array = ctx
+                .visibility
+                .iter()
+                .map(|&v| Dynamic::from_float(v as FLOAT))
+                .collect();
+            self.engine.call_fn(
+                &mut Scope::new(),
+                &self.ast,
+                "render",
+                (coords, frame, total_frames, visibility),
+            )
+        } else {
+            self.engine.call_fn(
+                &mut Scope::new(),
+                &self.ast,
+                "render",
+                (coords, frame, total_frames),
+            )
+        }
+        .map_err(|e| self.sandbox.describe(&e))?;
+        if colors.len() != out.len() {
+            return Err(format!(
+                "render returned {} colors for {} LEDs",
+                colors.len(),
+                out.len()
+            ));
+        }
+        for (out, color) in out.iter_mut().zip(colors) {
+            *out = to_color(color)?;
+        }
+        Ok(())
+    }
+}
+
+impl Effect for ScriptEffect {
+    /// Renders black frames if the script fails.
+    fn render(&mut self, ctx: &EffectContext, out: &mut [Color]) {
+        if let Err(e) = self.try_render(ctx, out) {
+            if !self.failed {
+                error!("Script failed at frame {}: {}", ctx.frame, e);
+                self.failed = true;
+            }
+            out.fill((0.0, 0.0, 0.0));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SANDBOX: SandboxOpt = SandboxOpt {
+        fuel: 10_000,
+        memory_mb: 1,
+        imports: false,
+    };
+
+    /// The error a script's `render` stops with, as a sequence would report it.
+    fn failure(sandbox: &SandboxOpt, body: &str) -> String {
+        let engine = engine(sandbox, Path::new("."));
+        let ast = engine
+            .compile(format!("fn render() {{ {} }}", body))
+            .unwrap();
+        let e = engine
+            .call_fn::<Dynamic>(&mut Scope::new(), &ast, "render", ())
+            .unwrap_err();
+        sandbox.describe(&e)
+    }
+
+    #[test]
+    fn describes_limits() {
+        assert_eq!(
+            failure(&SANDBOX, "loop {}"),
+            "script took more than 10000 operations for one frame (see --script-fuel)"
+        );
+        assert!(failure(&SANDBOX, r#"let s = "x"; loop { s += s; }"#)
+            .ends_with("went over the script memory limit of 1 MB (see --script-memory)"),);
+        assert_eq!(
+            failure(&SANDBOX, r#"import "colors" as colors;"#),
+            "script tried to import colors, which needs --script-imports"
+        );
+        let imports = SandboxOpt {
+            imports: true,
+            ..SANDBOX
+        };
+        assert_eq!(
+            failure(&imports, r#"import "../colors" as colors;"#),
+            "script tried to import ../colors, but only modules beside it can be imported"
+        );
+    }
+}
@@ -0,0 +1,92 @@
+//! A quick check of the lights, run by `live --self-test` before the show starts. The whole tree
+//! goes red, green then blue, showing every LED's channels work and are in the right order, then
+//! each port (or, without a hardware profile, each universe's worth of LEDs) lights alone, so an
+//! unplugged extension or swapped cable is obvious to whoever's watching. Outputs which fail to
+//! send are reported in the log.
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use tracing::{debug, info, warn};
+use xmas_tree_common::{
+    hardware::HardwareProfile,
+    output::{OutputGroup, OutputSink},
+    sequence::Rgb,
+};
+
+const COLOR_SECONDS: f32 = 0.6;
+const SEGMENT_SECONDS: f32 = 0.5;
+/// LEDs in a universe of 510 channels.
+const UNIVERSE_LEDS: usize = 170;
+/// Half brightness, which is plenty to see and keeps a fully lit tree within its power supply.
+const LEVEL: u8 = 128;
+
+/// Sends `frame` for `seconds`, at the show's frame rate so realtime outputs don't time out.
+fn hold(outputs: &mut OutputGroup, frame: &[Rgb], seconds: f32, fps: f32) {
+    let frame_time = Duration::from_secs_f32(1.0 / fps);
+    let end = Instant::now() + Duration::from_secs_f32(seconds);
+    while Instant::now() < end {
+        if let Err(e) = outputs.send_frame(frame) {
+            debug!("Self-test frame failed: {}", e);
+        }
+        thread::sleep(frame_time);
+    }
+}
+
+pub fn run(
+    outputs: &mut OutputGroup,
+    led_count: usize,
+    fps: f32,
+    hardware: Option<&HardwareProfile>,
+) {
+    info!("Running the self-test");
+    let errors_before: Vec<u64> = outputs
+        .stats_by_sink()
+        .map(|(_, stats)| stats.errors)
+        .collect();
+
+    for (name, color) in [
+        ("red", [LEVEL, 0, 0]),
+        ("green", [0, LEVEL, 0]),
+        ("blue", [0, 0, LEVEL]),
+    ] {
+        info!("Self-test: every LED {}", name);
+        hold(outputs, &vec![color; led_count], COLOR_SECONDS, fps);
+    }
+
+    let (kind, size) = match hardware {
+        Some(hardware) => ("port", hardware.pixels_per_port),
+        None => ("universe", UNIVERSE_LEDS),
+    };
+    let mut frame = vec![[0; 3]; led_count];
+    for (index, start) in (0..led_count).step_by(size.max(1)).enumerate() {
+        let end = (start + size).min(led_count);
+        info!(
+            "Self-test: {} {} (LEDs {}-{})",
+            kind,
+            index + 1,
+            start,
+            end - 1
+        );
+        frame.fill([0; 3]);
+        frame[start..end].fill([LEVEL; 3]);
+        hold(outputs, &frame, SEGMENT_SECONDS, fps);
+    }
+    if let Err(e) = outputs.blank(led_count) {
+        debug!("Self-test blanking failed: {}", e);
+    }
+
+    let mut passed = true;
+    for ((name, stats), before) in outputs.stats_by_sink().zip(errors_before) {
+        let failed = stats.errors - before;
+        if failed > 0 {
+            warn!("Self-test: {} failed to send {} frames", name, failed);
+            passed = false;
+        }
+    }
+    if passed {
+        info!("Self-test passed");
+    }
+}
@@ -0,0 +1,540 @@
+//! Shares what `live` is showing over HTTP, so a viewer somewhere else can draw the tree as it
+//! happens. `GET /coords` gives the LED positions as CSV (z up, in GIFT units, one LED per row),
+//! `GET /status` describes the show as JSON, and `GET /frames` is a stream of server-sent events,
+//! one per frame, each holding every LED's color as six hex digits. Frames are streamed at a
+//! lower rate than the tree shows them, to keep the stream light enough for a home connection.
+//!
+//! Viewers watching together can see where each other are looking. Each posts its camera's focus
+//! to `POST /cursor?viewer=NAME&x=X&y=Y&z=Z` (optionally with `&led=INDEX` when pointing at a
+//! bulb) and reads everyone's from `GET /viewers`; a viewer that stops posting is forgotten after
+//! [`VIEWER_TIMEOUT`]. With a token set, one viewer at a time can hold the transport controls:
+//! `POST /control?viewer=NAME&action=take`, sent with an `Authorization: Bearer TOKEN` header,
+//! takes them over from anyone else holding them, and the holder can then `pause`, `play` and
+//! `release`. The token is never accepted in the query string, where it would end up in logs and
+//! browser history.
+//!
+//! `GET /` is a viewer doing all of this in the browser: it draws the tree, marks where everyone
+//! else is looking, and has the transport controls.
+//!
+//! With votes kept (see [`crate::votes`]), `POST /like` likes whatever's showing and
+//! `GET /votes` gives the season's scores so far.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Write as _,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use subtle::ConstantTimeEq;
+use tracing::{debug, info, warn};
+use xmas_tree_common::sequence::Rgb;
+
+use crate::{effects::Coord, votes::Votes};
+
+static VIEWER: &str = include_str!("../web/viewer.html");
+
+/// Most viewers streaming frames at once, since each has a thread of its own.
+const MAX_VIEWERS: usize = 16;
+/// Most connections handled at once, streams included. Others are closed straight away.
+const MAX_CONNECTIONS: usize = 64;
+/// How long a viewer can take to send its request, or to take each frame sent to it.
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+/// Longest request line or header, in bytes.
+const MAX_LINE: u64 = 8 * 1024;
+/// Most headers in a request.
+const MAX_HEADERS: usize = 64;
+/// How long a viewer is remembered after it last posted its cursor. Viewers post it at least
+/// every few seconds, even when it hasn't moved.
+const VIEWER_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Serialize)]
+struct Status {
+    /// What's running, such as an effect or theme.
+    show: String,
+    led_count: usize,
+    fps: f32,
+    /// The rate `/frames` sends at.
+    stream_fps: f32,
+    paused: bool,
+    /// The viewer holding the transport controls.
+    controller: Option<String>,
+    /// Why `live --safe-mode` is showing its fallback instead of the show.
+    failure: Option<String>,
+    /// Why the script being run couldn't be reloaded, while its last good version keeps running.
+    script_error: Option<String>,
+}
+
+/// Where a viewer is looking.
+#[derive(Debug, Clone, Copy, Serialize)]
+struct Cursor {
+    /// The point the camera orbits, in the same coordinates as `/coords`.
+    focus: [f32; 3],
+    /// The bulb being pointed at.
+    led: Option<usize>,
+    #[serde(skip)]
+    seen: Instant,
+}
+
+struct State {
+    coords: String,
+    token: Option<String>,
+    status: Mutex<Status>,
+    /// The latest frame, and how many frames have been shown, so viewers can skip repeats.
+    frame: Mutex<(Vec<Rgb>, u64)>,
+    cursors: Mutex<HashMap<String, Cursor>>,
+    streams: AtomicUsize,
+    connections: AtomicUsize,
+    votes: Option<Mutex<Votes>>,
+}
+
+impl State {
+    /// Forgets viewers that have gone away, handing back the controls (and resuming the show) if
+    /// one of them held them.
+    fn prune(&self) {
+        let mut cursors = self.cursors.lock().unwrap();
+        cursors.retain(|_, cursor| cursor.seen.elapsed() < VIEWER_TIMEOUT);
+        let mut status = self.status.lock().unwrap();
+        if let Some(controller) = &status.controller {
+            if !cursors.contains_key(controller) {
+                info!("{} left, releasing the controls", controller);
+                status.controller = None;
+                status.paused = false;
+            }
+        }
+    }
+}
+
+pub struct ShareServer {
+    state: Arc<State>,
+}
+
+impl ShareServer {
+    /// Starts serving on `addr`, e.g. `0.0.0.0:8080`. Without a `token`, nobody can take the
+    /// transport controls, and without `votes` nobody can like the show.
+    pub fn start(
+        addr: &str,
+        coords: &[Coord],
+        fps: f32,
+        stream_fps: f32,
+        token: Option<String>,
+        votes: Option<Votes>,
+    ) -> Result<Self, Box<dyn Error>> {
+        if stream_fps.is_nan() || stream_fps <= 0.0 {
+            return Err(format!("Invalid stream rate: {} fps", stream_fps).into());
+        }
+        let listener =
+            TcpListener::bind(addr).map_err(|e| format!("Cannot serve on {}: {}", addr, e))?;
+        let mut csv = String::from("x,y,z\n");
+        for (x, y, z) in coords {
+            writeln!(csv, "{},{},{}", x, y, z).unwrap();
+        }
+        let state = Arc::new(State {
+            coords: csv,
+            token,
+            status: Mutex::new(Status {
+                show: String::new(),
+                led_count: coords.len(),
+                fps,
+                stream_fps: stream_fps.min(fps),
+                paused: false,
+                controller: None,
+                failure: None,
+                script_error: None,
+            }),
+            frame: Mutex::new((Vec::new(), 0)),
+            cursors: Mutex::new(HashMap::new()),
+            streams: AtomicUsize::new(0),
+            connections: AtomicUsize::new(0),
+            votes: votes.map(Mutex::new),
+        });
+        let shared = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if shared.connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                    shared.connections.fetch_sub(1, Ordering::SeqCst);
+                    debug!("Too many connections, closing a new one");
+                    continue;
+                }
+                let state = shared.clone();
+                thread::spawn(move || {
+                    if let Err(e) = serve(&state, stream) {
+                        debug!("Viewer disconnected: {}", e);
+                    }
+                    state.connections.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        info!("Sharing the tree at http://{}/", addr);
+        Ok(Self { state })
+    }
+
+    pub fn set_show(&self, show: &str) {
+        let mut status = self.state.status.lock().unwrap();
+        if status.show != show {
+            status.show = show.into();
+        }
+    }
+
+    pub fn set_failure(&self, failure: Option<&str>) {
+        let mut status = self.state.status.lock().unwrap();
+        if status.failure.as_deref() != failure {
+            status.failure = failure.map(Into::into);
+        }
+    }
+
+    pub fn set_script_error(&self, error: Option<&str>) {
+        let mut status = self.state.status.lock().unwrap();
+        if status.script_error.as_deref() != error {
+            status.script_error = error.map(Into::into);
+        }
+    }
+
+    /// Whether a viewer has paused the show.
+    pub fn paused(&self) -> bool {
+        self.state.prune();
+        self.state.status.lock().unwrap().paused
+    }
+
+    pub fn send_frame(&self, rgb: &[Rgb]) {
+        let mut frame = self.state.frame.lock().unwrap();
+        frame.0.clear();
+        frame.0.extend_from_slice(rgb);
+        frame.1 += 1;
+        if let Some(votes) = &self.state.votes {
+            let status = self.state.status.lock().unwrap();
+            if let Err(e) = votes.lock().unwrap().shown(&status.show, 1.0 / status.fps) {
+                warn!("Cannot save votes: {}", e);
+            }
+        }
+    }
+}
+
+fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+         Access-Control-Allow-Origin: *\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+/// Reads one line of a request into `line`, giving up on lines longer than [`MAX_LINE`] rather
+/// than buffering them.
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> Result<(), Box<dyn Error>> {
+    line.clear();
+    reader.take(MAX_LINE).read_line(line)?;
+    if !line.ends_with('\n') {
+        return Err(if line.len() as u64 >= MAX_LINE {
+            "Request line too long".into()
+        } else {
+            "Request ended early".into()
+        });
+    }
+    Ok(())
+}
+
+fn serve(state: &State, mut stream: TcpStream) -> Result<(), Box<dyn Error>> {
+    stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    read_line(&mut reader, &mut request)?;
+    // Only the token is read from the headers, but they all have to be read before responding
+    let mut token = None;
+    let mut header = String::new();
+    for i in 0.. {
+        read_line(&mut reader, &mut header)?;
+        if header.trim_end().is_empty() {
+            break;
+        }
+        if i == MAX_HEADERS {
+            return Err("Too many request headers".into());
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("authorization") {
+                token = value
+                    .trim()
+                    .strip_prefix("Bearer ")
+                    .map(|t| t.trim().to_string());
+            }
+        }
+    }
+    let mut parts = request.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = parse_query(query);
+    state.prune();
+    match (method, path) {
+        ("GET", "/coords") => respond(&mut stream, "200 OK", "text/csv", &state.coords)?,
+        ("GET", "/status") => {
+            let status = state.status.lock().unwrap().clone();
+            respond(
+                &mut stream,
+                "200 OK",
+                "application/json",
+                &serde_json::to_string(&status)?,
+            )?
+        }
+        ("GET", "/frames") => stream_frames(state, stream)?,
+        ("GET", "/viewers") => {
+            let cursors = state.cursors.lock().unwrap().clone();
+            respond(
+                &mut stream,
+                "200 OK",
+                "application/json",
+                &serde_json::to_string(&cursors)?,
+            )?
+        }
+        ("POST", "/cursor") => match post_cursor(state, &query) {
+            Ok(()) => respond(&mut stream, "204 No Content", "text/plain", "")?,
+            Err(e) => respond(&mut stream, "400 Bad Request", "text/plain", &e)?,
+        },
+        ("POST", "/control") => match control(state, &query, token.as_deref()) {
+            Ok(()) => respond(&mut stream, "204 No Content", "text/plain", "")?,
+            Err(e) => respond(&mut stream, "403 Forbidden", "text/plain", &e)?,
+        },
+        ("POST", "/like") => like(state, &mut stream)?,
+        ("GET", "/votes") => match &state.votes {
+            Some(votes) => {
+                let json = serde_json::to_string(votes.lock().unwrap().scores())?;
+                respond(&mut stream, "200 OK", "application/json", &json)?
+            }
+            None => respond(
+                &mut stream,
+                "404 Not Found",
+                "text/plain",
+                "Voting is off\n",
+            )?,
+        },
+        ("GET", "/") => respond(&mut stream, "200 OK", "text/html", VIEWER)?,
+        // Lets viewers served from elsewhere send the token
+        ("OPTIONS", _) => write!(
+            stream,
+            "HTTP/1.1 204 No Content\r\nAccess-Control-Allow-Origin: *\r\n\
+             Access-Control-Allow-Methods: GET, POST\r\n\
+             Access-Control-Allow-Headers: Authorization\r\nConnection: close\r\n\r\n"
+        )?,
+        _ => respond(&mut stream, "404 Not Found", "text/plain", "Not found\n")?,
+    }
+    Ok(())
+}
+
+/// Likes the show that's on.
+fn like(state: &State, stream: &mut TcpStream) -> Result<(), Box<dyn Error>> {
+    let votes = match &state.votes {
+        Some(votes) => votes,
+        None => {
+            return Ok(respond(
+                stream,
+                "404 Not Found",
+                "text/plain",
+                "Voting is off\n",
+            )?)
+        }
+    };
+    let show = state.status.lock().unwrap().show.clone();
+    let counted = votes
+        .lock()
+        .unwrap()
+        .like(&show, stream.peer_addr()?.ip())?;
+    if counted {
+        info!("Someone liked {}", show);
+        respond(stream, "200 OK", "text/plain", &format!("Liked {}\n", show))?;
+    } else {
+        respond(
+            stream,
+            "429 Too Many Requests",
+            "text/plain",
+            "You liked something a moment ago\n",
+        )?;
+    }
+    Ok(())
+}
+
+/// Splits a query string into its parameters, decoding `+` and `%XX` escapes.
+fn parse_query(query: &str) -> HashMap<String, String> {
+    let decode = |s: &str| {
+        let mut bytes = Vec::with_capacity(s.len());
+        let mut rest = s.as_bytes();
+        while let Some((&byte, tail)) = rest.split_first() {
+            let escaped = tail
+                .get(..2)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            match (byte, escaped) {
+                (b'%', Some(escaped)) => {
+                    bytes.push(escaped);
+                    rest = &tail[2..];
+                    continue;
+                }
+                (b'+', _) => bytes.push(b' '),
+                _ => bytes.push(byte),
+            }
+            rest = tail;
+        }
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(name), decode(value))
+        })
+        .collect()
+}
+
+fn viewer(query: &HashMap<String, String>) -> Result<&str, String> {
+    match query.get("viewer").map(String::as_str) {
+        Some(name) if !name.trim().is_empty() => Ok(name),
+        _ => Err("Missing viewer name\n".into()),
+    }
+}
+
+fn post_cursor(state: &State, query: &HashMap<String, String>) -> Result<(), String> {
+    let name = viewer(query)?;
+    let axis = |axis: &str| -> Result<f32, String> {
+        query
+            .get(axis)
+            .and_then(|value| value.parse().ok())
+            .filter(|value: &f32| value.is_finite())
+            .ok_or_else(|| format!("Missing or invalid {}\n", axis))
+    };
+    let focus = [axis("x")?, axis("y")?, axis("z")?];
+    let led_count = state.status.lock().unwrap().led_count;
+    let led = match query.get("led") {
+        Some(led) => Some(
+            led.parse()
+                .ok()
+                .filter(|&led| led < led_count)
+                .ok_or_else(|| format!("Invalid LED index: {}\n", led))?,
+        ),
+        None => None,
+    };
+    let mut cursors = state.cursors.lock().unwrap();
+    if !cursors.contains_key(name) {
+        info!("{} is watching", name);
+    }
+    cursors.insert(
+        name.into(),
+        Cursor {
+            focus,
+            led,
+            seen: Instant::now(),
+        },
+    );
+    Ok(())
+}
+
+/// Passes the transport controls between viewers, and lets the one holding them use them.
+fn control(
+    state: &State,
+    query: &HashMap<String, String>,
+    token: Option<&str>,
+) -> Result<(), String> {
+    let name = viewer(query)?;
+    match &state.token {
+        // Compared in constant time, so how long a wrong guess takes doesn't give the token away
+        Some(expected) if token.is_some_and(|token| token_matches(token, expected)) => {}
+        Some(_) if token.is_none() => {
+            return Err("Send the token as an `Authorization: Bearer TOKEN` header\n".into())
+        }
+        Some(_) => return Err("Wrong token\n".into()),
+        None => return Err("Remote control is disabled\n".into()),
+    }
+    // Holding the controls needs a cursor, so a viewer that goes away gives them back
+    if !state.cursors.lock().unwrap().contains_key(name) {
+        return Err("Post a cursor before taking the controls\n".into());
+    }
+    let mut status = state.status.lock().unwrap();
+    let action = query.get("action").map(String::as_str).unwrap_or("");
+    if action == "take" {
+        if status.controller.as_deref() != Some(name) {
+            info!("{} took the controls", name);
+            status.controller = Some(name.into());
+        }
+        return Ok(());
+    }
+    if status.controller.as_deref() != Some(name) {
+        return Err("Take the controls first\n".into());
+    }
+    match action {
+        "pause" => status.paused = true,
+        "play" => status.paused = false,
+        "release" => {
+            info!("{} released the controls", name);
+            status.controller = None;
+            status.paused = false;
+        }
+        _ => return Err(format!("Unknown action: {}\n", action)),
+    }
+    Ok(())
+}
+
+/// Whether `token` is `expected`, taking as long whichever of their bytes differ. Only the
+/// length can be told from the time taken.
+fn token_matches(token: &str, expected: &str) -> bool {
+    token.as_bytes().ct_eq(expected.as_bytes()).into()
+}
+
+fn stream_frames(state: &State, mut stream: TcpStream) -> Result<(), Box<dyn Error>> {
+    if state.streams.fetch_add(1, Ordering::SeqCst) >= MAX_VIEWERS {
+        state.streams.fetch_sub(1, Ordering::SeqCst);
+        respond(
+            &mut stream,
+            "503 Service Unavailable",
+            "text/plain",
+            "Too many viewers\n",
+        )?;
+        return Ok(());
+    }
+    let result = send_events(state, &mut stream);
+    state.streams.fetch_sub(1, Ordering::SeqCst);
+    result
+}
+
+fn send_events(state: &State, stream: &mut TcpStream) -> Result<(), Box<dyn Error>> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+         Access-Control-Allow-Origin: *\r\nCache-Control: no-cache\r\n\r\n"
+    )?;
+    let interval = Duration::from_secs_f32(1.0 / state.status.lock().unwrap().stream_fps);
+    let mut sent = None;
+    let mut event = String::new();
+    loop {
+        event.clear();
+        {
+            let frame = state.frame.lock().unwrap();
+            if sent != Some(frame.1) && !frame.0.is_empty() {
+                sent = Some(frame.1);
+                event.push_str("data: ");
+                for [r, g, b] in &frame.0 {
+                    write!(event, "{:02x}{:02x}{:02x}", r, g, b).unwrap();
+                }
+                event.push_str("\n\n");
+            }
+        }
+        if !event.is_empty() {
+            stream.write_all(event.as_bytes())?;
+        }
+        thread::sleep(interval);
+    }
+}
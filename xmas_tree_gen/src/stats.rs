@@ -0,0 +1,263 @@
+//! The summary printed after a sequence is rendered, so every file can be traced back to how it
+//! was made and checked for problems before it goes anywhere near a tree.
+
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use serde::Serialize;
+use structopt::StructOpt;
+
+use xmas_tree_common::sequence::Rgb;
+
+use crate::{correction, effects::Color, report::Report};
+
+#[derive(Debug, StructOpt)]
+pub struct StatsOpt {
+    /// Also write the summary printed after rendering to this file, as JSON.
+    #[structopt(long = "stats", parse(from_os_str))]
+    path: Option<PathBuf>,
+}
+
+/// A change in the whole tree's brightness this big from one frame to the next counts as half
+/// a flash.
+const FLASH_CHANGE: f32 = 0.2;
+/// Photosensitivity guidelines allow no more than three flashes in any one second.
+const MAX_FLASHES_PER_SECOND: usize = 3;
+
+#[derive(Debug, Serialize)]
+pub struct EffectStats {
+    pub name: String,
+    pub start_frame: usize,
+    pub frames: usize,
+    pub duration_secs: f32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GenerationReport {
+    pub effects: Vec<EffectStats>,
+    /// Frames in the file, including any lead in and lead out.
+    pub total_frames: usize,
+    pub fps: f32,
+    pub duration_secs: f32,
+    pub seed: u64,
+    pub file_size: Option<u64>,
+    /// Estimated current drawn by the brightest frame, at 20 mA per channel.
+    pub peak_amps: f32,
+    pub peak_amps_frame: usize,
+    /// Estimated current averaged over every frame, including any lead in and lead out.
+    pub mean_amps: f32,
+    /// Frames dimmed to stay within `--max-amps`.
+    pub limited_frames: usize,
+    pub render_secs: f32,
+    pub warnings: Vec<String>,
+}
+
+/// Finds where the whole tree first flashes faster than a given rate, from its overall
+/// brightness frame by frame.
+pub struct FlashDetector {
+    /// Frames in one second.
+    window: usize,
+    /// Most jumps in brightness allowed within a second, two to a flash.
+    max_changes: usize,
+    previous_level: Option<f32>,
+    /// Frames where the brightness jumped up or down, within the last second.
+    changes: VecDeque<usize>,
+    last_change_up: Option<bool>,
+    strobe_frame: Option<usize>,
+}
+
+impl FlashDetector {
+    pub fn new(fps: f32, max_per_second: f32) -> Self {
+        Self {
+            window: fps.ceil() as usize,
+            max_changes: (max_per_second * 2.0) as usize,
+            previous_level: None,
+            changes: VecDeque::new(),
+            last_change_up: None,
+            strobe_frame: None,
+        }
+    }
+
+    /// Records the brightness of frame `frame`, from 0 to 1.
+    pub fn add(&mut self, frame: usize, level: f32) {
+        if let Some(previous) = self.previous_level.replace(level) {
+            let up = level > previous;
+            // Only a change in the opposite direction to the last one makes a new flash
+            if (level - previous).abs() >= FLASH_CHANGE && self.last_change_up != Some(up) {
+                self.last_change_up = Some(up);
+                self.changes.push_back(frame);
+            }
+        }
+        while matches!(self.changes.front(), Some(&first) if first + self.window <= frame) {
+            self.changes.pop_front();
+        }
+        if self.strobe_frame.is_none() && self.changes.len() > self.max_changes {
+            self.strobe_frame = self.changes.front().copied();
+        }
+    }
+
+    /// The frame where flashing first went over the limit.
+    pub fn strobe_frame(&self) -> Option<usize> {
+        self.strobe_frame
+    }
+}
+
+/// Watches frames as they are rendered, for the report.
+pub struct StatsCollector {
+    fps: f32,
+    started: Instant,
+    clipped: usize,
+    peak_amps: f32,
+    peak_amps_frame: usize,
+    total_amps: f32,
+    limited_frames: usize,
+    flashes: FlashDetector,
+}
+
+impl StatsCollector {
+    pub fn new(fps: f32) -> Self {
+        Self {
+            fps,
+            started: Instant::now(),
+            clipped: 0,
+            peak_amps: 0.0,
+            peak_amps_frame: 0,
+            total_amps: 0.0,
+            limited_frames: 0,
+            flashes: FlashDetector::new(fps, MAX_FLASHES_PER_SECOND as f32),
+        }
+    }
+
+    /// Records frame `frame` of the file, before its colors are clamped.
+    pub fn add_frame(&mut self, frame: usize, colors: &[Color]) {
+        let mut total = 0.0;
+        for &(r, g, b) in colors {
+            for channel in [r, g, b] {
+                if !(0.0..=1.0).contains(&channel) {
+                    self.clipped += 1;
+                }
+                total += channel.clamp(0.0, 1.0);
+            }
+        }
+
+        let level = total / (colors.len() * 3).max(1) as f32;
+        self.flashes.add(frame, level);
+    }
+
+    /// Records the values written for frame `frame`, and whether they were dimmed to stay
+    /// within `--max-amps`.
+    pub fn add_output(&mut self, frame: usize, rgb: &[Rgb], limited: bool) {
+        let amps = correction::amps(rgb);
+        if amps > self.peak_amps {
+            self.peak_amps = amps;
+            self.peak_amps_frame = frame;
+        }
+        self.total_amps += amps;
+        if limited {
+            self.limited_frames += 1;
+        }
+    }
+
+    pub fn finish(
+        self,
+        effects: Vec<EffectStats>,
+        total_frames: usize,
+        seed: u64,
+        output: Option<&Path>,
+    ) -> GenerationReport {
+        let mut warnings = Vec::new();
+        if self.clipped > 0 {
+            warnings.push(format!(
+                "{} color values were outside 0-1 and were clipped",
+                self.clipped
+            ));
+        }
+        if self.limited_frames > 0 {
+            warnings.push(format!(
+                "{} frames were dimmed to stay within --max-amps",
+                self.limited_frames
+            ));
+        }
+        if let Some(frame) = self.flashes.strobe_frame() {
+            warnings.push(format!(
+                "Strobe risk: more than {} flashes a second from frame {} ({:.1}s)",
+                MAX_FLASHES_PER_SECOND,
+                frame,
+                frame as f32 / self.fps
+            ));
+        }
+        GenerationReport {
+            effects,
+            total_frames,
+            fps: self.fps,
+            duration_secs: total_frames as f32 / self.fps,
+            seed,
+            file_size: output
+                .and_then(|path| fs::metadata(path).ok())
+                .map(|m| m.len()),
+            peak_amps: self.peak_amps,
+            peak_amps_frame: self.peak_amps_frame,
+            // Lead in and lead out frames are black, so add nothing
+            mean_amps: self.total_amps / total_frames.max(1) as f32,
+            limited_frames: self.limited_frames,
+            render_secs: self.started.elapsed().as_secs_f32(),
+            warnings,
+        }
+    }
+}
+
+impl StatsOpt {
+    /// Prints the report, unless `quiet`, and writes it as JSON if asked to.
+    pub fn emit(&self, report: &GenerationReport, quiet: bool) -> Result<(), Box<dyn Error>> {
+        if !quiet {
+            report.write_text(&mut io::stderr())?;
+        }
+        if let Some(path) = &self.path {
+            let mut file = File::create(path)?;
+            serde_json::to_writer_pretty(&mut file, report)?;
+            writeln!(file)?;
+        }
+        Ok(())
+    }
+}
+
+impl Report for GenerationReport {
+    fn write_text(&self, out: &mut dyn io::Write) -> io::Result<()> {
+        for effect in &self.effects {
+            writeln!(
+                out,
+                "Effect:            {} (frames {}..{}, {:.1}s)",
+                effect.name,
+                effect.start_frame,
+                effect.start_frame + effect.frames,
+                effect.duration_secs
+            )?;
+        }
+        writeln!(
+            out,
+            "Frames:            {} ({:.1}s at {} fps)",
+            self.total_frames, self.duration_secs, self.fps
+        )?;
+        writeln!(out, "Seed:              {}", self.seed)?;
+        if let Some(size) = self.file_size {
+            writeln!(out, "File size:         {} bytes", size)?;
+        }
+        writeln!(
+            out,
+            "Peak current:      {:.2}A (frame {})",
+            self.peak_amps, self.peak_amps_frame
+        )?;
+        writeln!(out, "Mean current:      {:.2}A", self.mean_amps)?;
+        writeln!(out, "Render time:       {:.1}s", self.render_secs)?;
+        for warning in &self.warnings {
+            writeln!(out, "Warning:           {}", warning)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+//! Standard workloads for the benchmarks in `benches/`: the stress-test layouts `coords make`
+//! writes, read back as every other command reads coordinates, and effects rendering frame after
+//! frame on them as `generate` would.
+
+use std::{collections::HashMap, error::Error, mem};
+
+use xmas_tree_common::coords::{self as common_coords, Handedness, LoadOptions, Units, UpAxis};
+
+pub use crate::coords::Layout;
+use crate::{
+    coords::{make_layout, write_coords},
+    effects::{self, Bounds, Color, Coord, EffectContext, EffectInfo, EFFECTS},
+    generate::Length,
+    neighbours::{NeighbourGraph, NEIGHBOUR_COUNT},
+    params::Params,
+    visibility,
+};
+
+/// Seed the layouts are made with, so every run measures the same trees.
+const LAYOUT_SEED: u64 = 1;
+/// The defaults of `--fps` and `--seed`.
+const FPS: f32 = 34.7;
+const SEED: u64 = 42;
+/// Frames effects with no natural cycle loop over.
+const LOOP_FRAMES: usize = 1000;
+
+/// A layout as `coords make` writes it, at its default size.
+pub fn layout_csv(layout: Layout) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut csv = Vec::new();
+    write_coords(
+        &mut csv,
+        &make_layout(layout, layout.default_count(), LAYOUT_SEED),
+    )?;
+    Ok(csv)
+}
+
+/// Reads a layout's coordinates, repairing outliers and placing missing LEDs.
+pub fn load(csv: &[u8]) -> Result<Vec<Coord>, Box<dyn Error>> {
+    let options = LoadOptions {
+        up_axis: UpAxis::Auto,
+        handedness: Handedness::Right,
+        units: Units::Auto,
+        repair_outliers: true,
+        fill_missing: true,
+    };
+    Ok(common_coords::parse(csv, &options)?.coords)
+}
+
+/// The neighbour graph effects and filters are given, which is built once per tree.
+pub fn neighbour_graph(coords: &[Coord]) -> NeighbourGraph {
+    NeighbourGraph::knn(coords, NEIGHBOUR_COUNT)
+}
+
+/// Names of all the built in effects.
+pub fn effect_names() -> impl Iterator<Item = &'static str> {
+    EFFECTS.iter().map(|info| info.name)
+}
+
+/// A loaded layout, with everything worked out from it before rendering starts.
+pub struct Workload {
+    coords: Vec<Coord>,
+    bounds: Bounds,
+    neighbours: NeighbourGraph,
+    visibility: Vec<f32>,
+}
+
+impl Workload {
+    pub fn new(layout: Layout) -> Result<Self, Box<dyn Error>> {
+        let coords = load(&layout_csv(layout)?)?;
+        Ok(Self {
+            bounds: Bounds::of(&coords),
+            neighbours: neighbour_graph(&coords),
+            visibility: visibility::estimate(&coords),
+            coords,
+        })
+    }
+
+    pub fn coords(&self) -> &[Coord] {
+        &self.coords
+    }
+
+    /// Starts rendering `effect` with its default parameters.
+    pub fn effect(&self, effect: &str) -> Result<EffectRun<'_>, Box<dyn Error>> {
+        let info = effects::lookup(effect).ok_or_else(|| format!("Unknown effect: {}", effect))?;
+        let params = Params::resolve(info, &[], &HashMap::new(), SEED, 0)?;
+        let total_frames = Length::Auto { cycles: 1 }
+            .resolve(info, &self.coords, &params)
+            .unwrap_or(LOOP_FRAMES)
+            .max(1);
+        Ok(EffectRun {
+            workload: self,
+            info,
+            params,
+            total_frames,
+            frame: 0,
+            previous: None,
+            colors: vec![(0.0, 0.0, 0.0); self.coords.len()],
+        })
+    }
+}
+
+/// An effect rendering one frame after another, looping over its natural length if it has one.
+pub struct EffectRun<'a> {
+    workload: &'a Workload,
+    info: &'static EffectInfo,
+    params: Params,
+    total_frames: usize,
+    frame: usize,
+    previous: Option<Vec<Color>>,
+    colors: Vec<Color>,
+}
+
+impl EffectRun<'_> {
+    /// Renders the next frame.
+    pub fn render_frame(&mut self) -> &[Color] {
+        let workload = self.workload;
+        let ctx = EffectContext {
+            coords: &workload.coords,
+            bounds: workload.bounds,
+            frame: self.frame,
+            total_frames: self.total_frames,
+            fps: FPS,
+            seed: SEED,
+            previous: self.previous.as_deref(),
+            neighbours: &workload.neighbours,
+            visibility: &workload.visibility,
+            params: &self.params,
+            audio: None,
+        };
+        (self.info.render)(&ctx, &mut self.colors);
+        match &mut self.previous {
+            Some(previous) => mem::swap(previous, &mut self.colors),
+            None => self.previous = Some(self.colors.clone()),
+        }
+        self.frame = (self.frame + 1) % self.total_frames;
+        self.previous.as_deref().unwrap()
+    }
+}
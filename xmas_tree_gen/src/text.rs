@@ -0,0 +1,350 @@
+//! Text drawn into a strip of pixels, for effects which write messages around the tree.
+//!
+//! Glyphs come from DejaVu Sans Bold, which is built in and covers accented Latin, Greek,
+//! Cyrillic, Hebrew and Arabic, so greetings such as "Wesołych Świąt" and "С Новым годом" come
+//! out as written. Text is normalized first, so accents typed as separate combining marks still
+//! find their glyphs. Arabic letters take the forms which join them to their neighbours, and
+//! right-to-left runs are put in display order. DejaVu has no CJK glyphs, so Chinese, Japanese
+//! and Korean characters are drawn from GNU Unifont's 16 by 16 pixel glyphs, which are built in
+//! as well (see `CJK_RANGES`), so text comes out the same wherever it's rendered. Text which
+//! neither can draw is rejected rather than shown with gaps.
+
+use std::{io::Read, sync::OnceLock};
+
+use ab_glyph::{point, Font, FontArc, GlyphId, PxScale, ScaleFont};
+use flate2::read::ZlibDecoder;
+use unicode_bidi::BidiInfo;
+use unicode_normalization::UnicodeNormalization;
+
+static FONT: &[u8] = include_bytes!("../fonts/DejaVuSans-Bold.ttf");
+/// Unifont's bitmaps for `CJK_RANGES`, zlib compressed: `CJK_GLYPH_BYTES` for each code point in
+/// turn, a row of two bytes at a time from the top, with the leftmost pixel in the top bit.
+static CJK_FONT: &[u8] = include_bytes!("../fonts/unifont-cjk.z");
+
+/// The code points `CJK_FONT` has glyphs for, inclusive: CJK punctuation, kana, Hangul jamo, the
+/// CJK Unified Ideographs, Hangul syllables and fullwidth ASCII.
+const CJK_RANGES: &[(u32, u32)] = &[
+    (0x3000, 0x30FF),
+    (0x3130, 0x318F),
+    (0x4E00, 0x9FFF),
+    (0xAC00, 0xD7A3),
+    (0xFF01, 0xFF60),
+];
+const CJK_SIZE: usize = 16;
+const CJK_GLYPH_BYTES: usize = CJK_SIZE * CJK_SIZE / 8;
+/// Samples taken across and down each pixel when scaling a bitmap glyph.
+const CJK_SAMPLES: usize = 4;
+
+fn builtin() -> &'static FontArc {
+    static BUILTIN: OnceLock<FontArc> = OnceLock::new();
+    BUILTIN.get_or_init(|| FontArc::try_from_slice(FONT).expect("Built in font is invalid"))
+}
+
+fn cjk_glyphs() -> &'static [u8] {
+    static GLYPHS: OnceLock<Vec<u8>> = OnceLock::new();
+    GLYPHS.get_or_init(|| {
+        let mut glyphs = Vec::new();
+        ZlibDecoder::new(CJK_FONT)
+            .read_to_end(&mut glyphs)
+            .expect("Built in CJK font is invalid");
+        glyphs
+    })
+}
+
+/// How a character is drawn.
+#[derive(Clone, Copy)]
+enum Glyph {
+    Outline(GlyphId),
+    /// One of the CJK bitmaps.
+    Bitmap(&'static [u8]),
+}
+
+impl Glyph {
+    /// Whether pixel (`x`, `y`) of a bitmap glyph is set.
+    fn bit(bitmap: &[u8], x: usize, y: usize) -> bool {
+        bitmap[y * 2 + x / 8] & (0x80 >> (x % 8)) != 0
+    }
+}
+
+/// The glyph for `c`, from the built in font if it has one and otherwise from the CJK bitmaps.
+fn glyph(c: char) -> Option<Glyph> {
+    let id = builtin().glyph_id(c);
+    if id.0 != 0 {
+        return Some(Glyph::Outline(id));
+    }
+    let mut index = 0;
+    for &(first, last) in CJK_RANGES {
+        if (first..=last).contains(&(c as u32)) {
+            let start = (index + (c as u32 - first) as usize) * CJK_GLYPH_BYTES;
+            return Some(Glyph::Bitmap(&cjk_glyphs()[start..start + CJK_GLYPH_BYTES]));
+        }
+        index += (last - first + 1) as usize;
+    }
+    None
+}
+
+/// The first Arabic letter with contextual forms, hamza.
+const ARABIC_START: u32 = 0x621;
+/// How many contextual forms each letter from U+0621 has in the Arabic Presentation Forms-B
+/// block, which lists them in the same order from U+FE80: 1 for letters which never join, 2 for
+/// those which only join the letter before them (isolated and final forms), and 4 for those which
+/// join on both sides (isolated, final, initial and medial). U+063B to U+0640 have none.
+const ARABIC_FORMS: [u8; 42] = [
+    1, 2, 2, 2, 2, 4, 2, 4, 2, 4, 4, 4, 4, 4, 2, 2, 2, 2, 4, 4, 4, 4, 4, 4, 4, 4, 0, 0, 0, 0, 0, 0,
+    4, 4, 4, 4, 4, 4, 4, 2, 2, 4,
+];
+const PRESENTATION_FORMS: u32 = 0xFE80;
+const TATWEEL: char = '\u{640}';
+const LAM: char = '\u{644}';
+/// The alefs which join with a lam before them into a ligature, in the order the ligatures are
+/// listed from U+FEF5.
+const LAM_ALEFS: [char; 4] = ['\u{622}', '\u{623}', '\u{625}', '\u{627}'];
+const LAM_ALEF_FORMS: u32 = 0xFEF5;
+
+fn arabic_forms(c: char) -> u8 {
+    (c as u32)
+        .checked_sub(ARABIC_START)
+        .and_then(|i| ARABIC_FORMS.get(i as usize))
+        .copied()
+        .unwrap_or(0)
+}
+
+/// Whether `c` connects to the letter after it.
+fn joins_next(c: char) -> bool {
+    arabic_forms(c) == 4 || c == TATWEEL
+}
+
+/// Whether `c` connects to the letter before it.
+fn joins_previous(c: char) -> bool {
+    arabic_forms(c) >= 2 || c == TATWEEL
+}
+
+/// Vowel marks, which sit over letters without affecting how they join.
+fn is_transparent(c: char) -> bool {
+    matches!(c, '\u{64B}'..='\u{65F}' | '\u{670}')
+}
+
+/// Replaces each Arabic letter with the form which joins it to its neighbours, since the font has
+/// no shaping tables of its own to do this.
+fn join_arabic(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let letter_before = |i: usize| chars[..i].iter().rev().find(|&&c| !is_transparent(c));
+    let letter_after = |i: usize| chars[i + 1..].iter().find(|&&c| !is_transparent(c));
+    let mut joined = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let forms = arabic_forms(c);
+        if forms == 0 {
+            joined.push(c);
+            i += 1;
+            continue;
+        }
+        let after_join = letter_before(i).is_some_and(|&before| joins_next(before));
+        let form = if c == LAM && chars.get(i + 1).is_some_and(|c| LAM_ALEFS.contains(c)) {
+            let alef = LAM_ALEFS.iter().position(|a| *a == chars[i + 1]).unwrap();
+            i += 1;
+            LAM_ALEF_FORMS + alef as u32 * 2 + after_join as u32
+        } else {
+            let before_join =
+                forms == 4 && letter_after(i).is_some_and(|&after| joins_previous(after));
+            let offset: u32 = ARABIC_FORMS[..(c as u32 - ARABIC_START) as usize]
+                .iter()
+                .map(|&forms| forms as u32)
+                .sum();
+            let form = match (after_join && forms >= 2, before_join) {
+                (false, false) => 0,
+                (true, false) => 1,
+                (false, true) => 2,
+                (true, true) => 3,
+            };
+            PRESENTATION_FORMS + offset + form
+        };
+        joined.push(char::from_u32(form).unwrap());
+        i += 1;
+    }
+    joined
+}
+
+/// Puts `text` in the order it's displayed in, left to right, returning whether it reads right
+/// to left overall.
+fn display_order(text: &str) -> (String, bool) {
+    let bidi = BidiInfo::new(text, None);
+    match bidi.paragraphs.first() {
+        Some(paragraph) => (
+            bidi.reorder_line(paragraph, paragraph.range.clone())
+                .into_owned(),
+            paragraph.level.is_rtl(),
+        ),
+        None => (String::new(), false),
+    }
+}
+
+/// The characters drawn for `text`, in display order, and whether it reads right to left.
+fn prepare(text: &str) -> (String, bool) {
+    // Line breaks would start a new paragraph, but a strip has only one line
+    let text: String = text
+        .nfc()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    display_order(&join_arabic(text.trim()))
+}
+
+/// Checks there's a font which can draw each character of `text`.
+pub fn validate(text: &str) -> Result<(), String> {
+    let (display, _) = prepare(text);
+    match display
+        .chars()
+        .find(|&c| !c.is_whitespace() && glyph(c).is_none())
+    {
+        Some(c) => Err(format!(
+            "No font has a glyph for '{}' (U+{:04X})",
+            c, c as u32
+        )),
+        None => Ok(()),
+    }
+}
+
+/// A line of text drawn in white on black, as one row of coverage values after another.
+pub struct TextStrip {
+    pub width: usize,
+    pub height: usize,
+    coverage: Vec<f32>,
+    /// Which character, counting in display order, each column belongs to.
+    columns: Vec<usize>,
+    /// Whether the text reads right to left, so should scroll from left to right.
+    pub rtl: bool,
+}
+
+impl TextStrip {
+    /// Draws `text` with letters `height` pixels tall from the top of the tallest to the bottom of
+    /// the lowest.
+    pub fn render(text: &str, height: usize) -> Self {
+        let (display, rtl) = prepare(text);
+        let builtin = builtin();
+        let scale = PxScale::from(height as f32);
+        let scaled = builtin.as_scaled(scale);
+        let baseline = scaled.ascent();
+        // Bitmap glyphs fill the strip's height, as the built in font's letters do
+        let pixel = height as f32 / CJK_SIZE as f32;
+
+        let mut glyphs = Vec::new();
+        let mut x = 0.0;
+        let mut previous = None;
+        for c in display.chars() {
+            let glyph = glyph(c).unwrap_or(Glyph::Outline(GlyphId(0)));
+            match glyph {
+                Glyph::Outline(id) => {
+                    if let Some(previous) = previous {
+                        x += scaled.kern(previous, id);
+                    }
+                    glyphs.push((glyph, x));
+                    x += scaled.h_advance(id);
+                    previous = Some(id);
+                }
+                Glyph::Bitmap(_) => {
+                    glyphs.push((glyph, x));
+                    x += CJK_SIZE as f32 * pixel;
+                    previous = None;
+                }
+            }
+        }
+
+        let width = x.ceil() as usize;
+        let mut strip = Self {
+            width,
+            height,
+            coverage: vec![0.0; width * height],
+            columns: vec![0; width],
+            rtl,
+        };
+        for (i, &(glyph, x)) in glyphs.iter().enumerate() {
+            let end = glyphs.get(i + 1).map_or(width, |&(_, next)| next as usize);
+            for column in &mut strip.columns[(x as usize).min(width)..end.min(width)] {
+                *column = i;
+            }
+            match glyph {
+                Glyph::Outline(id) => {
+                    let glyph = id.with_scale_and_position(scale, point(x, baseline));
+                    if let Some(outline) = builtin.outline_glyph(glyph) {
+                        let bounds = outline.px_bounds();
+                        outline.draw(|gx, gy, coverage| {
+                            let px = bounds.min.x as i32 + gx as i32;
+                            let py = bounds.min.y as i32 + gy as i32;
+                            strip.cover(px, py, coverage);
+                        });
+                    }
+                }
+                Glyph::Bitmap(bitmap) => strip.draw_bitmap(bitmap, x, 0.0, pixel),
+            }
+        }
+        strip
+    }
+
+    /// Draws a CJK bitmap with its top left corner at (`x`, `top`), each of its pixels `pixel`
+    /// strip pixels across, covering each strip pixel as much as the bitmap's pixels do.
+    fn draw_bitmap(&mut self, bitmap: &[u8], x: f32, top: f32, pixel: f32) {
+        let (left, row) = (x.floor() as i32, top.floor() as i32);
+        let size = (CJK_SIZE as f32 * pixel).ceil() as i32 + 1;
+        let sample = |p: i32, s: usize| p as f32 + (s as f32 + 0.5) / CJK_SAMPLES as f32;
+        for py in row..row + size {
+            for px in left..left + size {
+                let mut hits = 0;
+                for sy in 0..CJK_SAMPLES {
+                    for sx in 0..CJK_SAMPLES {
+                        let bx = (sample(px, sx) - x) / pixel;
+                        let by = (sample(py, sy) - top) / pixel;
+                        let inside = (0.0..CJK_SIZE as f32).contains(&bx)
+                            && (0.0..CJK_SIZE as f32).contains(&by);
+                        if inside && Glyph::bit(bitmap, bx as usize, by as usize) {
+                            hits += 1;
+                        }
+                    }
+                }
+                self.cover(px, py, hits as f32 / (CJK_SAMPLES * CJK_SAMPLES) as f32);
+            }
+        }
+    }
+
+    /// Covers pixel (`x`, `y`) at least as much as `coverage`, if it's inside the strip.
+    fn cover(&mut self, x: i32, y: i32, coverage: f32) {
+        if (0..self.width as i32).contains(&x) && (0..self.height as i32).contains(&y) {
+            let pixel = &mut self.coverage[y as usize * self.width + x as usize];
+            *pixel = pixel.max(coverage.min(1.0));
+        }
+    }
+
+    /// How much of the pixel at (`x`, `y`) is covered, and the character it belongs to, or `None`
+    /// outside the strip.
+    pub fn sample(&self, x: f32, y: f32) -> Option<(f32, usize)> {
+        if x < 0.0 || y < 0.0 {
+            return None;
+        }
+        let (column, row) = (x as usize, y as usize);
+        if column >= self.width || row >= self.height {
+            return None;
+        }
+        Some((
+            self.coverage[row * self.width + column],
+            self.columns[column],
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_cjk_from_builtin_bitmaps() {
+        for text in ["メリークリスマス", "圣诞快乐", "메리 크리스마스"] {
+            assert_eq!(validate(text), Ok(()));
+            let strip = TextStrip::render(text, 8);
+            let lit = (0..strip.width)
+                .filter(|&x| (0..8).any(|y| strip.sample(x as f32, y as f32).unwrap().0 > 0.0))
+                .count();
+            assert!(lit > strip.width / 2, "{} barely drawn", text);
+        }
+        assert!(validate("\u{0F40}").is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! Writes a small picture of a sequence next to it, and its line in the directory's manifest, so
+//! folders of sequences can be browsed in a file manager. The picture is the tree seen from the
+//! front, lit by whichever of a sample of frames has the most of the tree lit in the most
+//! colors, so a sequence that starts dark or on a single color still gets a representative one.
+
+use std::{error::Error, fs, path::Path};
+
+use image::{Rgb as Pixel, RgbImage};
+use tracing::debug;
+use xmas_tree_common::{
+    color,
+    manifest::{self, ManifestEntry},
+    metadata::{self, SequenceMetadata},
+    sequence::Rgb,
+};
+
+use crate::effects::Coord;
+
+const WIDTH: u32 = 96;
+const HEIGHT: u32 = 128;
+/// Frames considered for the picture, spread evenly through the sequence.
+const SAMPLES: usize = 48;
+/// Share of the picture left empty around the tree.
+const MARGIN: f32 = 0.06;
+const BACKGROUND: [u8; 3] = [6, 8, 14];
+/// How far each bulb's glow reaches, in pixels.
+const GLOW_RADIUS: i32 = 2;
+/// Hue buckets counted when judging how colorful a frame is.
+const HUE_BUCKETS: usize = 12;
+/// Channel value below which an LED counts as off.
+const LIT: u8 = 24;
+
+/// Keeps the most representative of the frames it's given.
+pub struct Thumbnail {
+    /// Every this many frames is considered.
+    every: usize,
+    frame: usize,
+    best: Option<(f32, Vec<Rgb>)>,
+}
+
+impl Thumbnail {
+    pub fn new(total_frames: usize) -> Self {
+        Self {
+            every: (total_frames / SAMPLES).max(1),
+            frame: 0,
+            best: None,
+        }
+    }
+
+    pub fn add(&mut self, frame: &[Rgb]) {
+        let considered = self.frame.is_multiple_of(self.every);
+        self.frame += 1;
+        if !considered {
+            return;
+        }
+        let score = score(frame);
+        if self.best.as_ref().is_none_or(|(best, _)| score > *best) {
+            self.best = Some((score, frame.to_vec()));
+        }
+    }
+
+    /// Writes the picture, unless `write_picture` is false (e.g. for encrypted sequences), and
+    /// records the sequence in its directory's manifest.
+    pub fn finish(
+        &self,
+        sequence_path: &Path,
+        coords: &[Coord],
+        metadata: &SequenceMetadata,
+        fps: f32,
+        write_picture: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let picture_path = metadata::thumbnail_path(sequence_path);
+        let written = match &self.best {
+            Some((_, frame)) if write_picture => {
+                draw(coords, frame)
+                    .save(&picture_path)
+                    .map_err(|e| format!("Cannot write {}: {}", picture_path.display(), e))?;
+                debug!("Wrote thumbnail {}", picture_path.display());
+                Some(picture_path.as_path())
+            }
+            // Don't leave an older sequence's picture looking like this one's
+            _ => {
+                if picture_path.exists() {
+                    fs::remove_file(&picture_path)?;
+                }
+                None
+            }
+        };
+        manifest::record(
+            sequence_path,
+            ManifestEntry::new(sequence_path, metadata, fps, written),
+        )
+    }
+}
+
+/// How well a frame shows off the sequence: how much of the tree is lit, weighted by how many
+/// different hues it's lit in.
+fn score(frame: &[Rgb]) -> f32 {
+    let mut hues = [false; HUE_BUCKETS];
+    let mut lit = 0.0;
+    for &[r, g, b] in frame {
+        let max = r.max(g).max(b);
+        if max < LIT {
+            continue;
+        }
+        lit += max as f32 / 255.0;
+        let (hue, saturation, _) = color::rgb_to_hsv(to_color([r, g, b]));
+        if saturation > 0.3 {
+            hues[(hue.rem_euclid(1.0) * HUE_BUCKETS as f32) as usize % HUE_BUCKETS] = true;
+        }
+    }
+    let colorful = hues.iter().filter(|&&hue| hue).count();
+    lit * (1.0 + colorful as f32)
+}
+
+fn to_color([r, g, b]: Rgb) -> color::Color {
+    (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0)
+}
+
+/// The tree from the front, with each bulb as a soft dot of light.
+fn draw(coords: &[Coord], frame: &[Rgb]) -> RgbImage {
+    let (mut min, mut max) = (
+        (f32::INFINITY, f32::INFINITY),
+        (f32::NEG_INFINITY, f32::NEG_INFINITY),
+    );
+    for &(x, _, z) in coords {
+        min = (min.0.min(x), min.1.min(z));
+        max = (max.0.max(x), max.1.max(z));
+    }
+    // Fit the taller of the two sides, keeping the tree's proportions
+    let usable = |size: u32| size as f32 * (1.0 - 2.0 * MARGIN);
+    let scale = (usable(WIDTH) / (max.0 - min.0).max(f32::EPSILON))
+        .min(usable(HEIGHT) / (max.1 - min.1).max(f32::EPSILON));
+    let centre = ((min.0 + max.0) / 2.0, (min.1 + max.1) / 2.0);
+
+    let mut light = vec![[0.0f32; 3]; (WIDTH * HEIGHT) as usize];
+    for (&(x, _, z), &rgb) in coords.iter().zip(frame) {
+        let px = WIDTH as f32 / 2.0 + (x - centre.0) * scale;
+        // Images count rows down from the top
+        let py = HEIGHT as f32 / 2.0 - (z - centre.1) * scale;
+        for dy in -GLOW_RADIUS..=GLOW_RADIUS {
+            for dx in -GLOW_RADIUS..=GLOW_RADIUS {
+                let (ix, iy) = (px as i32 + dx, py as i32 + dy);
+                if ix < 0 || iy < 0 || ix >= WIDTH as i32 || iy >= HEIGHT as i32 {
+                    continue;
+                }
+                let distance = ((dx * dx + dy * dy) as f32).sqrt();
+                let weight = (-distance * distance / 2.0).exp();
+                let pixel = &mut light[(iy as u32 * WIDTH + ix as u32) as usize];
+                for (channel, &value) in pixel.iter_mut().zip(&rgb) {
+                    *channel += value as f32 * weight;
+                }
+            }
+        }
+    }
+    RgbImage::from_fn(WIDTH, HEIGHT, |x, y| {
+        let pixel = light[(y * WIDTH + x) as usize];
+        let mut out = [0; 3];
+        for ((out, background), value) in out.iter_mut().zip(BACKGROUND).zip(pixel) {
+            *out = (background as f32 + value).min(255.0) as u8;
+        }
+        Pixel(out)
+    })
+}
@@ -0,0 +1,148 @@
+//! An effect rendering on demand with parameters which can be changed between frames, for the
+//! player's parameter panel. Changing a parameter takes effect on the next frame, and the values
+//! can be turned back into the `generate` command line which renders the same thing.
+
+use std::{collections::HashMap, error::Error, mem, path::PathBuf};
+
+use xmas_tree_common::sequence::Rgb;
+
+pub use crate::params::{ParamArg, ParamInfo, ParamKind};
+use crate::{
+    effects::{self, Bounds, Color, Coord, EffectContext, EffectInfo},
+    generate::{self, Length},
+    neighbours::{NeighbourGraph, NEIGHBOUR_COUNT},
+    params::Params,
+    visibility,
+};
+
+/// Frames effects with no natural cycle loop over, as in `generate`'s default length.
+const LOOP_FRAMES: usize = 1000;
+
+/// An effect with its parameters, on one set of coordinates.
+pub struct TweakableEffect {
+    info: &'static EffectInfo,
+    params: Params,
+    coords: Vec<Coord>,
+    bounds: Bounds,
+    neighbours: NeighbourGraph,
+    visibility: Vec<f32>,
+    fps: f32,
+    seed: u64,
+    total_frames: usize,
+    /// The last frame rendered, which effects reading `ctx.previous` carry on from.
+    rendered: Option<usize>,
+    previous: Vec<Color>,
+    colors: Vec<Color>,
+    rgb: Vec<Rgb>,
+}
+
+impl TweakableEffect {
+    /// Starts `effect` on `coords` with `args` applied over its defaults. `palettes` are the
+    /// project's palette files, by name.
+    pub fn new(
+        effect: &str,
+        coords: Vec<Coord>,
+        args: &[ParamArg],
+        palettes: &HashMap<String, PathBuf>,
+        fps: f32,
+        seed: u64,
+    ) -> Result<Self, Box<dyn Error>> {
+        let info = effects::lookup(effect).ok_or_else(|| format!("Unknown effect: {}", effect))?;
+        let params = Params::resolve(info, args, palettes, seed, 0)?;
+        let total_frames = Length::Auto { cycles: 1 }
+            .resolve(info, &coords, &params)
+            .unwrap_or(LOOP_FRAMES)
+            .max(1);
+        Ok(Self {
+            info,
+            params,
+            bounds: Bounds::of(&coords),
+            neighbours: NeighbourGraph::knn(&coords, NEIGHBOUR_COUNT),
+            visibility: visibility::estimate(&coords),
+            fps,
+            seed,
+            total_frames,
+            rendered: None,
+            previous: Vec::new(),
+            colors: vec![(0.0, 0.0, 0.0); coords.len()],
+            rgb: Vec::new(),
+            coords,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.info.name
+    }
+
+    /// The parameters the effect declares, in the order it declares them.
+    pub fn params(&self) -> &'static [ParamInfo] {
+        self.info.params
+    }
+
+    /// How many frames the effect loops over: its natural cycle if it has one, as the parameters
+    /// were when it started.
+    pub fn frame_count(&self) -> usize {
+        self.total_frames
+    }
+
+    pub fn value(&self, name: &str) -> &str {
+        self.params.raw(name)
+    }
+
+    /// Changes a parameter from the next frame on, checking it like `--param` does.
+    pub fn set(&mut self, name: &str, value: String) -> Result<(), String> {
+        self.params.set(self.info, name, value)
+    }
+
+    /// The `generate` command line which renders the effect as it's set up now.
+    pub fn command_line(&self) -> String {
+        let mut command = format!(
+            "xmas_tree_gen --seed {} generate {}",
+            self.seed, self.info.name
+        );
+        if self.info.cycle.is_some() {
+            command += " --len auto";
+        }
+        let flags = self.params.cli_flags(self.info);
+        if !flags.is_empty() {
+            command += " ";
+            command += &flags;
+        }
+        command
+    }
+
+    /// Renders frame `frame`, which is wrapped around the effect's loop, as `generate` would write
+    /// it. Frames are rendered in order while playing, and asking for the same frame again renders
+    /// it with the parameters as they are now.
+    pub fn render(&mut self, frame: usize) -> &[Rgb] {
+        let frame = frame % self.total_frames;
+        match self.rendered {
+            Some(rendered) if rendered == frame => {}
+            Some(rendered) if (rendered + 1) % self.total_frames == frame => {
+                mem::swap(&mut self.previous, &mut self.colors)
+            }
+            // Effects which carry on from the previous frame start again after a jump
+            _ => self.previous.clear(),
+        }
+        let ctx = EffectContext {
+            coords: &self.coords,
+            bounds: self.bounds,
+            frame,
+            total_frames: self.total_frames,
+            fps: self.fps,
+            seed: self.seed,
+            previous: Some(&self.previous[..]).filter(|previous| !previous.is_empty()),
+            neighbours: &self.neighbours,
+            visibility: &self.visibility,
+            params: &self.params,
+            audio: None,
+        };
+        self.colors.resize(self.coords.len(), (0.0, 0.0, 0.0));
+        (self.info.render)(&ctx, &mut self.colors);
+        self.rendered = Some(frame);
+        self.rgb.clear();
+        self.rgb
+            .extend(self.colors.iter().map(|&color| generate::to_rgb(color)));
+        &self.rgb
+    }
+}
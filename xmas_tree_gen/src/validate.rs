@@ -0,0 +1,267 @@
+//! Checks a sequence against the rules for GIFT submissions, which are stricter than what the
+//! rest of the tools accept: a `FRAME_ID,R_0,G_0,B_0,...` header naming every LED of the tree in
+//! order, one row per frame numbered from 0 without gaps, and whole values from 0 to 255.
+//! Reading is done here rather than with the common parser, since that one quietly accepts (and
+//! so hides) most of the mistakes worth catching.
+
+use std::{error::Error, fs, io, path::Path};
+
+use serde::Serialize;
+use xmas_tree_common::seal;
+
+use crate::{report::Report, stats::FlashDetector};
+
+/// Problems of each kind listed before the rest are only counted, so one systematic mistake
+/// doesn't bury the others.
+const MAX_LISTED: usize = 10;
+
+#[derive(Debug, Serialize)]
+pub struct ValidationReport {
+    pub frames: usize,
+    pub leds: usize,
+    pub fps: f32,
+    pub duration_secs: f32,
+    pub mean_brightness: f32,
+    pub peak_brightness: f32,
+    pub peak_frame: usize,
+    /// Problems which would get the sequence rejected.
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// Problems of one kind, listing the first few.
+struct Problems {
+    kind: &'static str,
+    listed: Vec<String>,
+    count: usize,
+}
+
+impl Problems {
+    fn new(kind: &'static str) -> Self {
+        Self {
+            kind,
+            listed: Vec::new(),
+            count: 0,
+        }
+    }
+
+    fn push(&mut self, message: impl FnOnce() -> String) {
+        if self.count < MAX_LISTED {
+            self.listed.push(message());
+        }
+        self.count += 1;
+    }
+
+    fn finish(self, out: &mut Vec<String>) {
+        out.extend(self.listed);
+        if self.count > MAX_LISTED {
+            out.push(format!(
+                "...and {} more {}",
+                self.count - MAX_LISTED,
+                self.kind
+            ));
+        }
+    }
+}
+
+fn expected_header(led_count: usize) -> Vec<String> {
+    let mut header = vec!["FRAME_ID".to_string()];
+    for led in 0..led_count {
+        header.extend(["R", "G", "B"].iter().map(|c| format!("{}_{}", c, led)));
+    }
+    header
+}
+
+/// Checks the sequence at `path` against a tree of `led_count` LEDs, warning when the whole tree
+/// flashes more than `max_flash_hz` times a second.
+pub fn validate(
+    path: &Path,
+    led_count: usize,
+    fps: f32,
+    max_flash_hz: f32,
+    max_frames: Option<usize>,
+) -> Result<ValidationReport, Box<dyn Error>> {
+    let data = fs::read(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    let data = seal::unseal(&data).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    let text = match std::str::from_utf8(&data) {
+        Ok(text) => text,
+        Err(e) => return Err(format!("{} isn't a text file: {}", path.display(), e).into()),
+    };
+    let text = match text.strip_prefix('\u{feff}') {
+        Some(rest) => {
+            warnings.push("File starts with a byte order mark".into());
+            rest
+        }
+        None => text,
+    };
+    if text.contains('\r') {
+        warnings.push("File has Windows (CRLF) line endings".into());
+    }
+
+    let mut lines = text.lines().enumerate();
+    let header: Vec<&str> = match lines.next() {
+        Some((_, line)) => line.split(',').collect(),
+        None => Vec::new(),
+    };
+    let expected = expected_header(led_count);
+    if header.len() != expected.len() {
+        errors.push(format!(
+            "Header has {} columns, but {} LEDs need {}",
+            header.len(),
+            led_count,
+            expected.len()
+        ));
+    }
+    if let Some((column, (found, wanted))) = header
+        .iter()
+        .zip(&expected)
+        .enumerate()
+        .find(|(_, (found, wanted))| found != wanted)
+    {
+        errors.push(format!(
+            "Header column {} is {:?}, expected {:?}",
+            column + 1,
+            found,
+            wanted
+        ));
+    }
+
+    let mut bad_rows = Problems::new("rows with the wrong number of values");
+    let mut bad_ids = Problems::new("frame ID problems");
+    let mut bad_values = Problems::new("invalid values");
+    let mut flashes = FlashDetector::new(fps, max_flash_hz);
+    let mut frames = 0;
+    let mut next_id = 0;
+    let mut total_brightness = 0.0;
+    let mut peak_brightness = 0.0;
+    let mut peak_frame = 0;
+    for (index, line) in lines {
+        let line_number = index + 1;
+        if line.is_empty() {
+            bad_rows.push(|| format!("Line {} is empty", line_number));
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').collect();
+        if fields.len() != expected.len() {
+            bad_rows.push(|| {
+                format!(
+                    "Line {} has {} values, expected {}",
+                    line_number,
+                    fields.len(),
+                    expected.len()
+                )
+            });
+        }
+        match fields[0].parse::<usize>() {
+            Ok(id) if id == next_id => next_id += 1,
+            Ok(id) => {
+                bad_ids.push(|| {
+                    format!(
+                        "Line {} has frame ID {}, expected {}",
+                        line_number, id, next_id
+                    )
+                });
+                // Carry on from here, so one gap is one problem
+                next_id = id + 1;
+            }
+            Err(_) => bad_ids
+                .push(|| format!("Line {} has invalid frame ID {:?}", line_number, fields[0])),
+        }
+        let mut total = 0;
+        for (column, field) in fields.iter().enumerate().skip(1) {
+            match field.parse::<u32>() {
+                Ok(value) if value <= 255 => total += value,
+                _ => bad_values.push(|| {
+                    let name = expected
+                        .get(column)
+                        .map_or("an extra column", |s| s.as_str());
+                    format!(
+                        "Line {} has {:?} for {}, expected a whole number from 0 to 255",
+                        line_number, field, name
+                    )
+                }),
+            }
+        }
+        let brightness = total as f32 / (led_count * 3 * 255).max(1) as f32;
+        total_brightness += brightness;
+        if brightness > peak_brightness {
+            peak_brightness = brightness;
+            peak_frame = frames;
+        }
+        flashes.add(frames, brightness);
+        frames += 1;
+    }
+    bad_rows.finish(&mut errors);
+    bad_ids.finish(&mut errors);
+    bad_values.finish(&mut errors);
+
+    if frames == 0 {
+        errors.push("Sequence has no frames".into());
+    }
+    if let Some(max_frames) = max_frames {
+        if frames > max_frames {
+            errors.push(format!(
+                "Sequence has {} frames, more than the limit of {}",
+                frames, max_frames
+            ));
+        }
+    }
+    if let Some(frame) = flashes.strobe_frame() {
+        warnings.push(format!(
+            "The whole tree flashes more than {} times a second from frame {} ({:.1}s)",
+            max_flash_hz,
+            frame,
+            frame as f32 / fps
+        ));
+    }
+
+    Ok(ValidationReport {
+        frames,
+        leds: led_count,
+        fps,
+        duration_secs: frames as f32 / fps,
+        mean_brightness: total_brightness / frames.max(1) as f32,
+        peak_brightness,
+        peak_frame,
+        errors,
+        warnings,
+    })
+}
+
+impl Report for ValidationReport {
+    fn write_text(&self, out: &mut dyn io::Write) -> io::Result<()> {
+        writeln!(out, "Frames:            {}", self.frames)?;
+        writeln!(out, "LEDs:              {}", self.leds)?;
+        writeln!(
+            out,
+            "Duration:          {:.1}s at {} fps",
+            self.duration_secs, self.fps
+        )?;
+        writeln!(out, "Mean brightness:   {:.3}", self.mean_brightness)?;
+        writeln!(
+            out,
+            "Peak brightness:   {:.3} (frame {})",
+            self.peak_brightness, self.peak_frame
+        )?;
+        for error in &self.errors {
+            writeln!(out, "Error:             {}", error)?;
+        }
+        for warning in &self.warnings {
+            writeln!(out, "Warning:           {}", warning)?;
+        }
+        writeln!(
+            out,
+            "Result:            {}",
+            if self.is_valid() { "valid" } else { "invalid" }
+        )?;
+        Ok(())
+    }
+}
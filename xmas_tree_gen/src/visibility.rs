@@ -0,0 +1,88 @@
+//! Estimates how much of each LED can be seen from outside the tree, from the coordinates
+//! alone. LEDs deep in the branches, near the trunk, are mostly hidden by the branches and LEDs
+//! in front of them, so fine details such as letters read better when they're kept to the LEDs
+//! on the outside.
+//!
+//! Two things count against an LED: how far in from the outline of the tree it sits, at its
+//! height, and how many other LEDs lie between it and the outside, looking straight out from
+//! the trunk.
+
+use rayon::prelude::*;
+
+use crate::effects::Coord;
+
+/// The fraction of the tree's height either side of an LED used to find the outline of the
+/// tree at its height.
+const OUTLINE_BAND: f32 = 0.05;
+/// The radius the outline is taken at, as a percentile of the radii in the band, so a few stray
+/// LEDs don't set it.
+const OUTLINE_PERCENTILE: f32 = 0.9;
+/// How far out of the straight line out from the trunk another LED can be and still hide one,
+/// as the cosine of the angle.
+const OCCLUDER_COS: f32 = 0.85;
+/// How many LEDs in front halve an LED's visibility.
+const OCCLUDERS_HALF: f32 = 6.0;
+/// Visibility is scaled so LEDs at this percentile and above count as fully visible.
+const VISIBLE_PERCENTILE: f32 = 0.9;
+
+fn percentile(values: &mut [f32], p: f32) -> f32 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_by(f32::total_cmp);
+    values[((values.len() - 1) as f32 * p).round() as usize]
+}
+
+/// How visible each LED is from outside the tree, from 0 (hidden in the middle) to 1 (on the
+/// outside).
+pub fn estimate(coords: &[Coord]) -> Vec<f32> {
+    if coords.is_empty() {
+        return Vec::new();
+    }
+    let count = coords.len() as f32;
+    let centre = coords
+        .iter()
+        .fold((0.0, 0.0), |(x, y), c| (x + c.0 / count, y + c.1 / count));
+    let radius = |&(x, y, _): &Coord| ((x - centre.0).powi(2) + (y - centre.1).powi(2)).sqrt();
+    let bottom = coords.iter().map(|c| c.2).fold(f32::INFINITY, f32::min);
+    let top = coords.iter().map(|c| c.2).fold(f32::NEG_INFINITY, f32::max);
+    let band = (top - bottom) * OUTLINE_BAND;
+
+    let mut visibility: Vec<f32> = coords
+        .par_iter()
+        .map(|coord| {
+            let mut nearby: Vec<f32> = coords
+                .iter()
+                .filter(|other| (other.2 - coord.2).abs() <= band)
+                .map(radius)
+                .collect();
+            let outline = percentile(&mut nearby, OUTLINE_PERCENTILE).max(f32::EPSILON);
+            let r = radius(coord);
+            let depth = (r / outline).min(1.0);
+
+            // Looking out from the trunk through this LED, at the same height
+            let out = if r > f32::EPSILON {
+                ((coord.0 - centre.0) / r, (coord.1 - centre.1) / r)
+            } else {
+                (0.0, 0.0)
+            };
+            let occluders = coords
+                .iter()
+                .filter(|other| {
+                    let v = (other.0 - coord.0, other.1 - coord.1, other.2 - coord.2);
+                    let length = (v.0 * v.0 + v.1 * v.1 + v.2 * v.2).sqrt();
+                    length > f32::EPSILON
+                        && length <= outline
+                        && v.0 * out.0 + v.1 * out.1 >= OCCLUDER_COS * length
+                })
+                .count() as f32;
+            depth * OCCLUDERS_HALF / (OCCLUDERS_HALF + occluders)
+        })
+        .collect();
+
+    let full = percentile(&mut visibility.clone(), VISIBLE_PERCENTILE).max(f32::EPSILON);
+    for v in &mut visibility {
+        *v = (*v / full).min(1.0);
+    }
+    visibility
+}
@@ -0,0 +1,100 @@
+//! Likes for whatever `live` is showing, sent with `POST /like` from a viewer or a phone shortcut
+//! and kept for the whole season, so next year's playlist can favour what people liked. Each show
+//! also keeps how long it has been on, since one running all evening collects more likes than
+//! one on for a few minutes just by being seen more; `likes_per_hour` accounts for that.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fs, io,
+    net::IpAddr,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// How often time shown is saved, between likes.
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+/// Shortest time between likes from one address, so holding down a button doesn't count.
+const LIKE_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Score {
+    pub likes: u32,
+    pub seconds_shown: f64,
+    /// Worked out when saved, for reading the file.
+    #[serde(default)]
+    pub likes_per_hour: f64,
+}
+
+pub struct Votes {
+    path: PathBuf,
+    scores: BTreeMap<String, Score>,
+    last_like: HashMap<IpAddr, Instant>,
+    last_saved: Instant,
+}
+
+impl Votes {
+    /// Carries on with the scores already in `path`, if there are any.
+    pub fn load(path: PathBuf) -> Result<Self, Box<dyn Error>> {
+        let scores = match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json)
+                .map_err(|e| format!("Cannot read votes from {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(format!("Cannot read {}: {}", path.display(), e).into()),
+        };
+        Ok(Self {
+            path,
+            scores,
+            last_like: HashMap::new(),
+            last_saved: Instant::now(),
+        })
+    }
+
+    pub fn scores(&self) -> &BTreeMap<String, Score> {
+        &self.scores
+    }
+
+    /// Counts a like from `from` for `show`, unless they liked something very recently.
+    pub fn like(&mut self, show: &str, from: IpAddr) -> Result<bool, Box<dyn Error>> {
+        let now = Instant::now();
+        self.last_like
+            .retain(|_, at| now.duration_since(*at) < LIKE_INTERVAL);
+        if self.last_like.contains_key(&from) {
+            return Ok(false);
+        }
+        self.last_like.insert(from, now);
+        self.scores.entry(show.into()).or_default().likes += 1;
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Adds to the time `show` has been on, saving every so often.
+    pub fn shown(&mut self, show: &str, seconds: f32) -> Result<(), Box<dyn Error>> {
+        self.scores.entry(show.into()).or_default().seconds_shown += seconds as f64;
+        if self.last_saved.elapsed() >= SAVE_INTERVAL {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&mut self) -> Result<(), Box<dyn Error>> {
+        self.last_saved = Instant::now();
+        for score in self.scores.values_mut() {
+            let hours = score.seconds_shown / 3600.0;
+            score.likes_per_hour = if hours > 0.0 {
+                score.likes as f64 / hours
+            } else {
+                0.0
+            };
+        }
+        // Write to a temporary file first so a crash mid-save leaves the old scores intact
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(&self.scores)?)
+            .map_err(|e| format!("Cannot write {}: {}", tmp_path.display(), e))?;
+        fs::rename(&tmp_path, &self.path)
+            .map_err(|e| format!("Cannot write {}: {}", self.path.display(), e))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,315 @@
+//! An editor for the channel map, which says which universe or controller port drives each LED,
+//! so a tree wired to many outputs doesn't need its map written out by hand. While it's open the
+//! tree shows each bulb in its output's color, with unassigned bulbs dim and selected ones white.
+//!
+//! Shift-drag a box to select every bulb inside it (including ones behind), holding Ctrl as well
+//! to add to the selection, and Escape clears it. [ and ] pick the output, A assigns the selected
+//! bulbs to it and Delete unassigns them. Tab switches the tree between the map and the sequence,
+//! and Return saves. Every change can be undone.
+
+use std::{error::Error, path::PathBuf};
+
+use bevy::{
+    prelude::*,
+    render::{camera::Camera, render_graph::base::camera::CAMERA_3D},
+};
+use xmas_tree_common::channel_map::ChannelMap;
+
+use crate::{
+    inspect::FONT,
+    undo::{self, Edit, History, Step},
+    BulbLocations, TIMELINE_HEIGHT,
+};
+
+const PANEL_LEFT: f32 = 10.0;
+const PANEL_BOTTOM: f32 = TIMELINE_HEIGHT + 10.0;
+/// Brightness of bulbs which aren't on any output yet.
+const UNASSIGNED_LEVEL: f32 = 0.08;
+/// Turns each output's hue by the golden angle from the one before, so neighbouring outputs get
+/// clearly different colors however many there are.
+const HUE_STEP: f32 = 137.5;
+
+pub struct ChannelMapper {
+    path: PathBuf,
+    map: ChannelMap,
+    selected: Vec<bool>,
+    /// The output A assigns the selection to.
+    output: u16,
+    /// Where a box selection started, in window coordinates, and whether it adds to the
+    /// selection.
+    dragging: Option<(Vec2, bool)>,
+    /// The corner of the box following the cursor.
+    cursor: Vec2,
+    history: History<ChannelMap>,
+    /// Whether the tree shows the map rather than the sequence.
+    pub previewing: bool,
+    /// Set when the panel needs updating.
+    dirty: bool,
+}
+
+impl ChannelMapper {
+    /// Edits the channel map at `path`, which is created on saving if it doesn't exist yet.
+    pub fn open(path: PathBuf, bulb_count: usize) -> Result<Self, Box<dyn Error>> {
+        let map = if path.exists() {
+            ChannelMap::load(&path, bulb_count)?
+        } else {
+            ChannelMap::empty(bulb_count)
+        };
+        let output = map.outputs().keys().next().copied().unwrap_or(1);
+        Ok(Self {
+            path,
+            map,
+            selected: vec![false; bulb_count],
+            output,
+            dragging: None,
+            cursor: Vec2::ZERO,
+            history: History::default(),
+            previewing: true,
+            dirty: true,
+        })
+    }
+
+    /// The colors to show on the tree while previewing.
+    pub fn tree_colors(&self) -> impl Iterator<Item = Color> + '_ {
+        self.map
+            .assignments
+            .iter()
+            .zip(&self.selected)
+            .map(|(output, &selected)| match output {
+                _ if selected => Color::WHITE,
+                Some(output) => output_color(*output),
+                None => Color::rgb(UNASSIGNED_LEVEL, UNASSIGNED_LEVEL, UNASSIGNED_LEVEL),
+            })
+    }
+
+    /// Puts the selected bulbs on `output`, or takes them off their outputs.
+    fn assign(&mut self, output: Option<u16>) {
+        let leds: Vec<usize> = (0..self.selected.len())
+            .filter(|&i| self.selected[i] && self.map.assignments[i] != output)
+            .collect();
+        if leds.is_empty() {
+            return;
+        }
+        let from = leds.iter().map(|&i| self.map.assignments[i]).collect();
+        let edit = Assign {
+            leds,
+            from,
+            to: output,
+        };
+        self.history.apply(edit, &mut self.map);
+        self.dirty = true;
+    }
+
+    /// The corners of the box being dragged out, bottom left first.
+    fn selection_box(&self) -> Option<(Vec2, Vec2)> {
+        let (start, _) = self.dragging?;
+        Some((start.min(self.cursor), start.max(self.cursor)))
+    }
+}
+
+fn output_color(output: u16) -> Color {
+    Color::hsl((output as f32 * HUE_STEP) % 360.0, 1.0, 0.5)
+}
+
+/// Whether a left drag should select bulbs rather than turn the camera.
+pub fn selecting(keys: &Input<KeyCode>) -> bool {
+    keys.pressed(KeyCode::LShift) || keys.pressed(KeyCode::RShift)
+}
+
+struct Assign {
+    leds: Vec<usize>,
+    from: Vec<Option<u16>>,
+    to: Option<u16>,
+}
+
+impl Edit<ChannelMap> for Assign {
+    fn apply(&self, map: &mut ChannelMap) {
+        for &index in &self.leds {
+            map.assignments[index] = self.to;
+        }
+    }
+
+    fn revert(&self, map: &mut ChannelMap) {
+        for (&index, &from) in self.leds.iter().zip(&self.from) {
+            map.assignments[index] = from;
+        }
+    }
+}
+
+struct SelectionBox;
+
+struct MapperText;
+
+/// Adds the editor's systems, when a channel map is being edited.
+pub fn add_systems(app: &mut AppBuilder) {
+    app.add_startup_system(setup.system())
+        .add_system(keys.system())
+        .add_system(select.system())
+        .add_system(panel.system());
+}
+
+fn setup(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut fonts: ResMut<Assets<Font>>,
+) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                ..Default::default()
+            },
+            material: materials.add(Color::NONE.into()),
+            ..Default::default()
+        })
+        .insert(SelectionBox);
+    let font = fonts.add(Font::try_from_bytes(FONT.to_vec()).expect("Built in font is invalid"));
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(PANEL_LEFT),
+                    bottom: Val::Px(PANEL_BOTTOM),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font,
+                    font_size: 18.0,
+                    color: Color::WHITE,
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(MapperText);
+}
+
+fn keys(mut mapper: ResMut<ChannelMapper>, keys: Res<Input<KeyCode>>) {
+    let mapper = &mut *mapper;
+    if keys.just_pressed(KeyCode::Tab) {
+        mapper.previewing = !mapper.previewing;
+    }
+    if keys.just_pressed(KeyCode::Return) {
+        match mapper.map.save(&mapper.path) {
+            Ok(()) => eprintln!("Saved channel map to {}", mapper.path.display()),
+            Err(e) => eprintln!("Failed to save channel map: {}", e),
+        }
+    }
+    if let Some(step) = undo::step(&keys).filter(|_| mapper.dragging.is_none()) {
+        let changed = match step {
+            Step::Undo => mapper.history.undo(&mut mapper.map),
+            Step::Redo => mapper.history.redo(&mut mapper.map),
+        };
+        mapper.dirty |= changed;
+    }
+    if keys.just_pressed(KeyCode::LBracket) {
+        mapper.output = mapper.output.saturating_sub(1);
+        mapper.dirty = true;
+    }
+    if keys.just_pressed(KeyCode::RBracket) {
+        mapper.output = mapper.output.saturating_add(1);
+        mapper.dirty = true;
+    }
+    if keys.just_pressed(KeyCode::Escape) {
+        mapper.selected.fill(false);
+        mapper.dirty = true;
+    }
+    if keys.just_pressed(KeyCode::A) {
+        mapper.assign(Some(mapper.output));
+    }
+    if keys.just_pressed(KeyCode::Delete) {
+        mapper.assign(None);
+    }
+}
+
+/// Drags out a box with the left button while Shift is held, and selects the bulbs in it when
+/// the button is let go.
+fn select(
+    mut mapper: ResMut<ChannelMapper>,
+    buttons: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    windows: Res<Windows>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    bulb_locations: Res<BulbLocations>,
+) {
+    let cursor = match windows.get_primary().and_then(|w| w.cursor_position()) {
+        Some(cursor) => cursor,
+        None => return,
+    };
+    if buttons.just_pressed(MouseButton::Left) && selecting(&keys) {
+        let adding = keys.pressed(KeyCode::LControl) || keys.pressed(KeyCode::RControl);
+        mapper.dragging = Some((cursor, adding));
+    }
+    if mapper.dragging.is_none() {
+        return;
+    }
+    mapper.cursor = cursor;
+    mapper.dirty = true;
+    if buttons.pressed(MouseButton::Left) {
+        return;
+    }
+    let (min, max) = mapper.selection_box().unwrap();
+    let (_, adding) = mapper.dragging.take().unwrap();
+    let (camera, transform) = match cameras
+        .iter()
+        .find(|(camera, _)| camera.name.as_deref() == Some(CAMERA_3D))
+    {
+        Some(camera) => camera,
+        None => return,
+    };
+    let mapper = &mut *mapper;
+    for (selected, &(x, y, z)) in mapper.selected.iter_mut().zip(&bulb_locations.0) {
+        let inside = camera
+            .world_to_screen(&windows, transform, Vec3::new(x, z, y))
+            .is_some_and(|position| {
+                (min.x..=max.x).contains(&position.x) && (min.y..=max.y).contains(&position.y)
+            });
+        *selected = inside || (adding && *selected);
+    }
+}
+
+/// Shows the box being dragged out, and which output is being assigned with how many bulbs on
+/// each.
+fn panel(
+    mut mapper: ResMut<ChannelMapper>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut boxes: Query<(&mut Style, &Handle<ColorMaterial>), With<SelectionBox>>,
+    mut texts: Query<&mut Text, With<MapperText>>,
+) {
+    if !mapper.dirty {
+        return;
+    }
+    mapper.dirty = false;
+    let selection_box = mapper.selection_box();
+    for (mut style, handle) in boxes.iter_mut() {
+        let color = match selection_box {
+            Some((min, max)) => {
+                style.position.left = Val::Px(min.x);
+                style.position.bottom = Val::Px(min.y);
+                style.size = Size::new(Val::Px(max.x - min.x), Val::Px(max.y - min.y));
+                Color::rgba(1.0, 1.0, 1.0, 0.15)
+            }
+            None => Color::NONE,
+        };
+        if let Some(material) = materials.get_mut(handle) {
+            material.color = color;
+        }
+    }
+    let selected = mapper.selected.iter().filter(|&&selected| selected).count();
+    let mut text = format!(
+        "Output {} ({} selected, A assigns)",
+        mapper.output, selected
+    );
+    for (output, leds) in mapper.map.outputs() {
+        text += &format!("\n{}: {} LEDs", output, leds.len());
+    }
+    text += &format!("\nUnassigned: {}", mapper.map.unassigned());
+    for mut shown in texts.iter_mut() {
+        shown.sections[0].value = text.clone();
+    }
+}
@@ -0,0 +1,331 @@
+//! Tools for working out which LED is which when an effect looks wrong. Clicking a bulb (without
+//! dragging the camera) shows its index, position and current value in the corner, `--highlight`
+//! rings chosen bulbs, and L shows the wiring: a line through the bulbs in the order they're
+//! wired, which makes mistakes in captured coordinates stand out as stray zigzags.
+
+use std::{error::Error, str::FromStr};
+
+use bevy::{
+    input::mouse::MouseMotion,
+    pbr::render_graph::PBR_PIPELINE_HANDLE,
+    prelude::*,
+    render::{
+        camera::Camera,
+        pipeline::{PrimitiveTopology, RenderPipeline},
+        render_graph::base::camera::CAMERA_3D,
+    },
+};
+use xmas_tree_common::channel_map;
+
+use crate::{
+    aot_plugin::AlwaysOnTopPass, palette_editor::PaletteEditor, param_panel::ParamPanel,
+    BulbLocations, MouseButtonState, Preview,
+};
+
+pub static FONT: &[u8] = include_bytes!("../../xmas_tree_gen/fonts/DejaVuSans-Bold.ttf");
+
+/// How far the mouse can move between pressing and releasing the button for it to still count
+/// as a click, in pixels.
+const CLICK_SLOP: f32 = 4.0;
+/// How close to a bulb on screen a click has to be to pick it, in pixels.
+const PICK_RADIUS: f32 = 12.0;
+const MARKER_RADIUS: f32 = 0.045;
+
+/// LED indices, written as a comma separated list of indices and inclusive ranges, e.g.
+/// `0,49-51`.
+#[derive(Debug, Clone, Default)]
+pub struct IndexList(pub Vec<usize>);
+
+impl FromStr for IndexList {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        channel_map::parse_indices(s).map(Self)
+    }
+}
+
+pub struct Inspector {
+    highlight: Vec<usize>,
+    /// Whether the wiring line is shown.
+    wiring: bool,
+    picked: Option<usize>,
+    /// Set when the overlay needs updating for a newly picked bulb.
+    dirty: bool,
+}
+
+impl Inspector {
+    /// Checks every highlighted LED is on the tree.
+    pub fn new(
+        highlight: IndexList,
+        wiring: bool,
+        bulb_count: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        if let Some(&index) = highlight.0.iter().find(|&&index| index >= bulb_count) {
+            return Err(format!(
+                "Can't highlight LED {}, the tree only has {}",
+                index, bulb_count
+            )
+            .into());
+        }
+        Ok(Self {
+            highlight: highlight.0,
+            wiring,
+            picked: None,
+            dirty: false,
+        })
+    }
+}
+
+/// Drawn over the tree, like the bulbs, but not lit by the sequence.
+#[derive(Bundle)]
+struct OverlayBundle {
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+    aot_pass: AlwaysOnTopPass,
+    draw: Draw,
+    visible: Visible,
+    render_pipelines: RenderPipelines,
+    transform: Transform,
+    global_transform: GlobalTransform,
+}
+
+impl Default for OverlayBundle {
+    fn default() -> Self {
+        Self {
+            render_pipelines: RenderPipelines::from_pipelines(vec![RenderPipeline::new(
+                PBR_PIPELINE_HANDLE.typed(),
+            )]),
+            mesh: Default::default(),
+            material: Default::default(),
+            aot_pass: Default::default(),
+            draw: Default::default(),
+            visible: Visible {
+                is_transparent: true,
+                ..Default::default()
+            },
+            transform: Default::default(),
+            global_transform: Default::default(),
+        }
+    }
+}
+
+struct WiringLine;
+
+/// Rings the picked bulb.
+struct PickMarker;
+
+struct InspectText;
+
+pub fn add_systems(app: &mut AppBuilder) {
+    app.add_startup_system(setup.system())
+        .add_system(keys.system())
+        .add_system(pick.system())
+        .add_system(overlay.system());
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut fonts: ResMut<Assets<Font>>,
+    inspector: Res<Inspector>,
+    bulb_locations: Res<BulbLocations>,
+) {
+    let positions: Vec<[f32; 3]> = bulb_locations
+        .0
+        .iter()
+        .map(|&(x, y, z)| [x, z, y])
+        .collect();
+    let mut line = Mesh::new(PrimitiveTopology::LineStrip);
+    // The PBR pipeline needs normals and UVs, although an unlit line doesn't use them
+    line.set_attribute(
+        Mesh::ATTRIBUTE_NORMAL,
+        vec![[0.0, 1.0, 0.0]; positions.len()],
+    );
+    line.set_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.0, 0.0]; positions.len()]);
+    line.set_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    commands
+        .spawn_bundle(OverlayBundle {
+            mesh: meshes.add(line),
+            material: materials.add(StandardMaterial {
+                base_color: Color::rgba(0.2, 0.8, 1.0, 0.8),
+                unlit: true,
+                ..Default::default()
+            }),
+            visible: Visible {
+                is_visible: inspector.wiring,
+                is_transparent: true,
+            },
+            ..Default::default()
+        })
+        .insert(WiringLine);
+
+    let marker = meshes.add(Mesh::from(shape::Icosphere {
+        radius: MARKER_RADIUS,
+        subdivisions: 2,
+    }));
+    let highlight = materials.add(StandardMaterial {
+        base_color: Color::rgba(1.0, 0.2, 1.0, 0.5),
+        unlit: true,
+        ..Default::default()
+    });
+    for &index in &inspector.highlight {
+        let (x, y, z) = bulb_locations.0[index];
+        commands.spawn_bundle(OverlayBundle {
+            mesh: marker.clone(),
+            material: highlight.clone(),
+            transform: Transform::from_xyz(x, z, y),
+            ..Default::default()
+        });
+    }
+    commands
+        .spawn_bundle(OverlayBundle {
+            mesh: marker,
+            material: materials.add(StandardMaterial {
+                base_color: Color::rgba(1.0, 1.0, 0.2, 0.5),
+                unlit: true,
+                ..Default::default()
+            }),
+            visible: Visible {
+                is_visible: false,
+                is_transparent: true,
+            },
+            ..Default::default()
+        })
+        .insert(PickMarker);
+
+    let font = fonts.add(Font::try_from_bytes(FONT.to_vec()).expect("Built in font is invalid"));
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(10.0),
+                    top: Val::Px(10.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font,
+                    font_size: 18.0,
+                    color: Color::WHITE,
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(InspectText);
+}
+
+/// L shows and hides the wiring, and Escape clears the picked bulb.
+fn keys(
+    mut inspector: ResMut<Inspector>,
+    keys: Res<Input<KeyCode>>,
+    mut lines: Query<&mut Visible, With<WiringLine>>,
+) {
+    if keys.just_pressed(KeyCode::L) {
+        inspector.wiring = !inspector.wiring;
+        for mut visible in lines.iter_mut() {
+            visible.is_visible = inspector.wiring;
+        }
+    }
+    if keys.just_pressed(KeyCode::Escape) && inspector.picked.is_some() {
+        inspector.picked = None;
+        inspector.dirty = true;
+    }
+}
+
+/// Picks the bulb nearest a click, on screen, when the left button is let go without having
+/// turned the camera.
+#[allow(clippy::too_many_arguments)]
+fn pick(
+    mut inspector: ResMut<Inspector>,
+    buttons: Res<Input<MouseButton>>,
+    mouse_button_state: Res<MouseButtonState>,
+    mut motion: EventReader<MouseMotion>,
+    mut dragged: Local<f32>,
+    windows: Res<Windows>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    bulb_locations: Res<BulbLocations>,
+    editor: Option<Res<PaletteEditor>>,
+    params: Option<Res<ParamPanel>>,
+) {
+    let moved: f32 = motion.iter().map(|e| e.delta.length()).sum();
+    if buttons.just_pressed(MouseButton::Left) {
+        *dragged = 0.0;
+    }
+    *dragged += moved;
+    if !buttons.just_released(MouseButton::Left) || *dragged > CLICK_SLOP {
+        return;
+    }
+    // The cursor stays where it was pressed while the button is held
+    let cursor = mouse_button_state.locked_position;
+    let width = windows.get_primary().map_or(0.0, |window| window.width());
+    if editor.is_some_and(|editor| editor.over_panel(cursor))
+        || params.is_some_and(|params| params.over_panel(cursor, width))
+    {
+        return;
+    }
+    let (camera, transform) = match cameras
+        .iter()
+        .find(|(camera, _)| camera.name.as_deref() == Some(CAMERA_3D))
+    {
+        Some(camera) => camera,
+        None => return,
+    };
+    let nearest = bulb_locations
+        .0
+        .iter()
+        .enumerate()
+        .filter_map(|(index, &(x, y, z))| {
+            let position = camera.world_to_screen(&windows, transform, Vec3::new(x, z, y))?;
+            Some((index, position.distance(cursor)))
+        })
+        .filter(|&(_, distance)| distance <= PICK_RADIUS)
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(index, _)| index);
+    if nearest != inspector.picked {
+        inspector.picked = nearest;
+        inspector.dirty = true;
+    }
+}
+
+/// Shows the picked bulb's index, position (in the coordinate file's z-up orientation) and the
+/// value it's being sent.
+fn overlay(
+    mut inspector: ResMut<Inspector>,
+    preview: Res<Preview>,
+    bulb_locations: Res<BulbLocations>,
+    mut texts: Query<&mut Text, With<InspectText>>,
+    mut markers: Query<(&mut Transform, &mut Visible), With<PickMarker>>,
+) {
+    if !inspector.dirty && !preview.is_changed() {
+        return;
+    }
+    inspector.dirty = false;
+    let picked = inspector.picked;
+    for (mut transform, mut visible) in markers.iter_mut() {
+        visible.is_visible = picked.is_some();
+        if let Some(index) = picked {
+            let (x, y, z) = bulb_locations.0[index];
+            *transform = Transform::from_xyz(x, z, y);
+        }
+    }
+    let text = match picked {
+        Some(index) => {
+            let (x, y, z) = bulb_locations.0[index];
+            let mut text = format!("LED {}\nx {:.3}  y {:.3}  z {:.3}", index, x, y, z);
+            if let Some([r, g, b]) = preview.rgb.get(index) {
+                text += &format!("\nRGB {}, {}, {}", r, g, b);
+            }
+            text
+        }
+        None => String::new(),
+    };
+    for mut shown in texts.iter_mut() {
+        shown.sections[0].value = text.clone();
+    }
+}
@@ -1,7 +1,13 @@
 use std::error::Error;
 use std::f32::consts::PI;
+use std::fs;
 use std::path::PathBuf;
-use std::{collections::HashSet, ops::Add};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Add,
+};
 
 use aot_plugin::{AlwaysOnTopPass, AlwaysOnTopPlugin};
 use bevy::pbr::render_graph::PBR_PIPELINE_HANDLE;
@@ -14,12 +20,39 @@ use bevy::{
     prelude::*,
     render::camera::Camera,
 };
+use channel_mapper::ChannelMapper;
 use cone::Cone;
-use itertools::Itertools;
-use structopt::StructOpt;
+use inspect::{IndexList, Inspector};
+use notes::{Notes, Timeline};
+use palette_editor::PaletteEditor;
+use param_panel::ParamPanel;
+use render::RenderOpt;
+use source::{EffectSource, FrameSource};
+use structopt::{clap::ArgMatches, StructOpt};
+use xmas_tree_common::{
+    coords::{self, Handedness, LoadOptions, Units, UpAxis},
+    failures::{self, FailureMode},
+    geometry,
+    hardware::HardwareProfile,
+    metadata::{Credit, Marker, SequenceMetadata},
+    output::{self, OutputGroup, OutputSink, Watchdog},
+    pipeline::{BrightnessCap, Schedule},
+    project::Project,
+    seal,
+    sequence::Rgb,
+};
+use xmas_tree_gen::tweak::{ParamArg, TweakableEffect};
 
 mod aot_plugin;
+mod channel_mapper;
 mod cone;
+mod inspect;
+mod notes;
+mod palette_editor;
+mod param_panel;
+mod render;
+mod source;
+mod undo;
 
 #[derive(Default, Debug)]
 struct MouseButtonState {
@@ -33,14 +66,81 @@ struct Bulb {
     inner: bool,
 }
 
-struct Frame {
-    colors: Vec<Color>,
-}
-
 struct Sequence {
-    frames: Vec<Frame>,
+    source: Box<dyn FrameSource>,
+    /// LEDs broken by `--simulate-failures`, applied to each frame as it's read.
+    failures: Vec<Option<FailureMode>>,
+    /// The playhead, in seconds into the sequence.
     time: f32,
+    paused: bool,
+    /// How many seconds of the sequence play per second of wall-clock time.
+    speed: f32,
     fps: f32,
+    /// Refresh rate of the simulated hardware, if frame drops are being simulated.
+    hardware_fps: Option<f32>,
+    markers: Vec<Marker>,
+    /// Who wrote the effects the sequence was made from.
+    credits: Vec<Credit>,
+}
+
+impl Sequence {
+    /// Wraps a playback time around the end of the sequence. Streams never wrap.
+    fn wrap(&self, time: f32) -> f32 {
+        match self.source.frame_count() {
+            Some(count) => time.rem_euclid(count as f32 / self.fps),
+            None => time,
+        }
+    }
+
+    /// Moves the playhead on by `delta` seconds of wall-clock time, unless paused.
+    fn advance(&mut self, delta: f32) {
+        if !self.paused {
+            self.time = self.wrap(self.time + delta * self.speed);
+        }
+    }
+
+    /// Moves the playhead to a frame. Aims for the middle of the frame so rounding can't land on
+    /// the one before.
+    fn seek_frame(&mut self, frame: usize) {
+        self.time = (frame as f32 + 0.5) / self.fps;
+    }
+
+    fn frame_at(&self, time: f32) -> usize {
+        let index = (time * self.fps) as usize;
+        match self.source.frame_count() {
+            Some(count) => index.min(count - 1),
+            None => index,
+        }
+    }
+
+    fn frame_index(&self) -> usize {
+        self.frame_at(self.time)
+    }
+
+    /// Reads a frame into `rgb`, with any simulated failures. Returns false if a stream has no
+    /// frame yet.
+    fn read_frame(&mut self, index: usize, rgb: &mut Vec<Rgb>) -> Result<bool, Box<dyn Error>> {
+        let frame = match self.source.frame_at(index)? {
+            Some(frame) => frame,
+            None => return Ok(false),
+        };
+        rgb.clear();
+        rgb.extend_from_slice(frame);
+        for (rgb, failure) in rgb.iter_mut().zip(&self.failures) {
+            if let Some(mode) = failure {
+                *rgb = mode.apply(*rgb, 255, 0);
+            }
+        }
+        Ok(true)
+    }
+
+    /// The marker the sequence is currently in the section after, if any.
+    fn current_marker(&self) -> Option<usize> {
+        let frame = self.frame_index();
+        self.markers
+            .iter()
+            .rposition(|marker| marker.frame <= frame)
+    }
 }
 
 struct BulbLocations(Vec<(f32, f32, f32)>);
@@ -51,54 +151,335 @@ struct BulbLocations(Vec<(f32, f32, f32)>);
     about = "Plays a christmas tree light sequence."
 )]
 struct Opt {
+    /// Sequence file to play (CSV, delta or fseq), or `-` to play frames piped in as they arrive, e.g. from
+    /// `xmas_tree_gen generate twinkle --preview`, or `ddp://[ADDRESS]` to show frames sent over
+    /// the network, e.g. by `xmas_tree_gen live --output 'ddp://tree?mirror=desktop'`, or
+    /// `effect://NAME` to run one of xmas_tree_gen's effects live, with a panel to tune its
+    /// parameters. Large files are indexed in the background, and CSV files start playing from
+    /// the beginning while they are.
     #[structopt(parse(from_os_str))]
     sequence_path: PathBuf,
     #[structopt(parse(from_os_str), default_value = "coords/coords_2021.csv")]
     coords_path: PathBuf,
+    /// Sets a parameter of the effect run with `effect://NAME`, as NAME=VALUE. The flags the
+    /// parameter panel copies can be given back here to carry on from where they left off.
+    #[structopt(long = "param", number_of_values = 1)]
+    params: Vec<ParamArg>,
+    /// Seed for the effect run with `effect://NAME`.
+    #[structopt(long, default_value = "42")]
+    seed: u64,
+    /// Project file (`.xtree`) with the coordinates, hardware profile and outputs to use when
+    /// they aren't given as options.
+    #[structopt(long = "project", parse(from_os_str))]
+    project_path: Option<PathBuf>,
+    /// Which axis of the coordinates file points up the tree: z, y or auto.
+    #[structopt(long, alias = "up", default_value = "auto")]
+    up_axis: UpAxis,
+    /// Handedness of the coordinates file: right or left.
+    #[structopt(long, default_value = "right")]
+    handedness: Handedness,
+    /// Units of the coordinates file: gift, m, cm or auto. Scans are rescaled to GIFT units.
+    #[structopt(long, default_value = "auto")]
+    units: Units,
+    /// Move LEDs whose captured position is clearly out of line with their neighbours on the
+    /// string back between them.
+    #[structopt(long)]
+    repair_outliers: bool,
+    /// Place LEDs the scan didn't find (left at 0,0,0) along the string, between the nearest
+    /// found LEDs either side.
+    #[structopt(long)]
+    fill_missing: bool,
+    /// Frame rate, for sequence files which don't record one. fseq files play at their own.
     #[structopt(long, default_value = "34.7")]
     fps: f32,
+    /// Play the sequence even if it was generated for different coordinates.
+    #[structopt(long)]
+    force: bool,
+    /// JSON hardware profile, used to warn when the sequence plays faster than the LEDs can refresh.
+    #[structopt(long, parse(from_os_str))]
+    hardware: Option<PathBuf>,
+    /// Only show the frames the hardware would manage to display.
+    #[structopt(long, requires = "hardware")]
+    simulate_drops: bool,
+    /// Also send frames to real lights, e.g. `wled://192.168.1.50`, `ddp://192.168.1.50`,
+    /// `e131://?universe=1&order=grb`, `artnet://192.168.1.255`, `opc://localhost`,
+    /// `serial:///dev/ttyUSB0` or `gpio://` (in a build with the `gpio` feature), or record them
+    /// with `file://PATH`. Repeat to mirror the show to several outputs [default: the project's
+    /// outputs].
+    #[structopt(long = "output", number_of_values = 1)]
+    outputs: Vec<String>,
+    /// Lag of the hardware output in milliseconds. Frames are sent this far ahead of the preview
+    /// so that the real tree lines up with it (and any audio).
+    #[structopt(long, default_value = "0")]
+    output_latency: f32,
+    /// Maximum brightness of the hardware output, from 0 to 1.
+    #[structopt(long, default_value = "1")]
+    output_brightness: f32,
+    /// Hardware output brightness by time of day, e.g. `17:00=1.0,22:00=0.6`.
+    #[structopt(long)]
+    output_schedule: Option<Schedule>,
+    /// Simulate faulty LEDs: this fraction of them are stuck on, stuck off or have their red and
+    /// green swapped.
+    #[structopt(long, parse(try_from_str = failures::parse_rate))]
+    simulate_failures: Option<f32>,
+    /// Seed choosing which LEDs `--simulate-failures` breaks.
+    #[structopt(long, default_value = "1")]
+    failure_seed: u64,
+    /// Fade the hardware output to black if no frame has been sent for this many milliseconds.
+    #[structopt(long, default_value = "2000")]
+    output_timeout: u64,
+    /// Move each bulb in the preview up to this far (in GIFT units) from its measured position,
+    /// and droop outer bulbs, to check an effect doesn't depend on exact coordinates.
+    #[structopt(long, default_value = "0")]
+    jitter: f32,
+    /// Seed choosing how `--jitter` moves each bulb.
+    #[structopt(long, default_value = "1")]
+    jitter_seed: u64,
+    /// Open a palette editor, which saves to this palette file for use as `--param
+    /// palette=@PATH` with xmas_tree_gen.
+    #[structopt(long, parse(from_os_str))]
+    palette_editor: Option<PathBuf>,
+    /// Open a channel map editor, which saves which universe or controller port drives each LED
+    /// to this JSON file. Shift-drag selects bulbs, [ and ] pick an output and A assigns them to
+    /// it.
+    #[structopt(long, parse(from_os_str), conflicts_with = "palette-editor")]
+    channel_map: Option<PathBuf>,
+    /// Ring these LEDs in the preview, e.g. `0,49-51`. Clicking a bulb shows its index, position
+    /// and value.
+    #[structopt(long)]
+    highlight: Option<IndexList>,
+    /// Start with the wiring shown, as a line through the bulbs in the order they're wired. L
+    /// shows and hides it.
+    #[structopt(long)]
+    wiring: bool,
+    /// Name recorded with the review notes written, so notes from several people can be told
+    /// apart. N writes a note on the current frame.
+    #[structopt(long)]
+    reviewer: Option<String>,
+    #[structopt(flatten)]
+    render: RenderOpt,
+}
+
+/// How long the hardware output takes to fade out once frames stop arriving.
+const WATCHDOG_FADE: Duration = Duration::from_secs(1);
+
+struct HardwareOutput {
+    sink: OutputGroup,
+    latency: f32,
+    last_frame: Option<usize>,
+    rgb: Vec<Rgb>,
+}
+
+/// How far the left and right arrow keys move through the sequence while it's playing.
+const SEEK_STEP: f32 = 5.0;
+/// Slowest and fastest playback speeds the + and - keys reach.
+const SPEED_RANGE: (f32, f32) = (0.125, 8.0);
+
+/// Takes the options which weren't given on the command line from the project.
+fn apply_project(opt: &mut Opt, matches: &ArgMatches, project: Project) {
+    let unset = |name| matches.occurrences_of(name) == 0;
+    if let Some(key) = &project.key {
+        seal::set_key(key);
+    }
+    if let Some(path) = project.coords.filter(|_| unset("coords_path")) {
+        opt.coords_path = path;
+    }
+    if let Some(up_axis) = project.up_axis.filter(|_| unset("up_axis")) {
+        opt.up_axis = up_axis;
+    }
+    if let Some(handedness) = project.handedness.filter(|_| unset("handedness")) {
+        opt.handedness = handedness;
+    }
+    if let Some(units) = project.units.filter(|_| unset("units")) {
+        opt.units = units;
+    }
+    opt.repair_outliers |= project.repair_outliers;
+    opt.fill_missing |= project.fill_missing;
+    if let Some(fps) = project.fps.filter(|_| unset("fps")) {
+        opt.fps = fps;
+    }
+    if opt.hardware.is_none() {
+        opt.hardware = project.hardware;
+    }
+    if opt.outputs.is_empty() {
+        opt.outputs = project.outputs;
+    }
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let opt = Opt::from_args();
-    let mut led_coords_csv = csv::ReaderBuilder::new()
-        .has_headers(false)
-        .from_path(opt.coords_path)?;
-    let bulb_locations = BulbLocations(led_coords_csv.deserialize().collect::<Result<_, _>>()?);
-    let mut sequence_csv = csv::ReaderBuilder::new()
-        .has_headers(true)
-        .from_path(opt.sequence_path)?;
-    let sequence = Sequence {
-        frames: sequence_csv
-            .records()
-            .map(|record| {
-                Ok(Frame {
-                    colors: record?
-                        .into_iter()
-                        .skip(1)
-                        .map(|f| f.parse::<f32>().expect("Expected number") / 255.0)
-                        .tuples()
-                        .map(|(r, g, b)| Color::rgb(r, g, b))
-                        .collect(),
-                })
-            })
-            .collect::<Result<_, csv::Error>>()?,
+    let matches = Opt::clap().get_matches();
+    let mut opt = Opt::from_clap(&matches);
+    let mut palettes = HashMap::new();
+    if let Some(path) = &opt.project_path {
+        let project = Project::load(path)?;
+        palettes = project.palettes.clone();
+        apply_project(&mut opt, &matches, project);
+    }
+    if !opt.params.is_empty() && source::effect_name(&opt.sequence_path).is_none() {
+        return Err("--param only applies to an effect run with effect://NAME".into());
+    }
+    let coords_data = fs::read(&opt.coords_path)?;
+    let metadata = if source::is_stream(&opt.sequence_path) {
+        None
+    } else {
+        SequenceMetadata::load(&opt.sequence_path)?
+    };
+    if let Some(metadata) = &metadata {
+        if let Err(e) = metadata.verify_coords(&opt.coords_path, &coords_data) {
+            if !opt.force {
+                return Err(format!("{} (use --force to play it anyway)", e).into());
+            }
+            eprintln!("Warning: {}", e);
+        }
+    }
+    let hardware = opt
+        .hardware
+        .as_deref()
+        .map(HardwareProfile::load)
+        .transpose()?;
+    if let Some(warning) = hardware.as_ref().and_then(|h| h.check_fps(opt.fps)) {
+        eprintln!("Warning: {}", warning);
+    }
+    let layout = coords::parse(
+        &coords_data,
+        &LoadOptions {
+            up_axis: opt.up_axis,
+            handedness: opt.handedness,
+            units: opt.units,
+            repair_outliers: opt.repair_outliers,
+            fill_missing: opt.fill_missing,
+        },
+    )
+    .map_err(|e| format!("{}: {}", opt.coords_path.display(), e))?;
+    if opt.up_axis == UpAxis::Auto && layout.up_axis != UpAxis::Z {
+        eprintln!(
+            "Warning: coordinates look {}-up, use --up-axis to override",
+            layout.up_axis
+        );
+    }
+    if !layout.repaired.is_empty() {
+        eprintln!(
+            "Warning: repaired the positions of LEDs {:?}",
+            layout.repaired
+        );
+    }
+    if layout.filled.is_empty() && !layout.missing.is_empty() {
+        eprintln!(
+            "Warning: LEDs {:?} weren't found, use --fill-missing to place them between their \
+             neighbours",
+            layout.missing
+        );
+    }
+    let mut bulb_locations = BulbLocations(layout.coords);
+    // Effects are run on the measured positions, as xmas_tree_gen would render them
+    let effect = match source::effect_name(&opt.sequence_path) {
+        Some(name) => Some(Arc::new(Mutex::new(TweakableEffect::new(
+            name,
+            bulb_locations.0.clone(),
+            &opt.params,
+            &palettes,
+            opt.fps,
+            opt.seed,
+        )?))),
+        None => None,
+    };
+    if opt.jitter > 0.0 {
+        coords::jitter(&mut bulb_locations.0, opt.jitter, opt.jitter_seed);
+    }
+    let (markers, credits) = metadata
+        .map(|m| (m.markers, m.credits))
+        .unwrap_or_default();
+    let source: Box<dyn FrameSource> = match &effect {
+        Some(effect) => Box::new(EffectSource::new(effect.clone())),
+        None => source::open(&opt.sequence_path)?,
+    };
+    let mut sequence = Sequence {
+        fps: source.fps().unwrap_or(opt.fps),
+        source,
+        failures: opt
+            .simulate_failures
+            .map(|rate| failures::pick(bulb_locations.0.len(), rate, opt.failure_seed))
+            .unwrap_or_default(),
         time: 0.0,
-        fps: opt.fps,
+        paused: false,
+        speed: 1.0,
+        hardware_fps: hardware.filter(|_| opt.simulate_drops).map(|h| h.max_fps()),
+        markers,
+        credits,
     };
+    if opt.render.enabled() {
+        return render::render(&opt.render, &mut sequence, &bulb_locations.0);
+    }
 
-    App::build()
-        .insert_resource(Msaa { samples: 4 })
+    let mut app = App::build();
+    if !opt.outputs.is_empty() {
+        let brightness = BrightnessCap {
+            cap: opt.output_brightness.clamp(0.0, 1.0),
+            schedule: opt.output_schedule.clone().unwrap_or_default(),
+        };
+        let brightness = Some(brightness).filter(|b| b.cap < 1.0 || opt.output_schedule.is_some());
+        let mut group = OutputGroup::default();
+        for url in &opt.outputs {
+            let sink = output::open_with(url, brightness.clone())?;
+            // Recordings shouldn't end with a fade out
+            let sink = if url.starts_with("file://") {
+                sink
+            } else {
+                Box::new(Watchdog::new(
+                    sink,
+                    Duration::from_millis(opt.output_timeout),
+                    WATCHDOG_FADE,
+                ))
+            };
+            group.add(url.clone(), sink);
+        }
+        app.insert_resource(HardwareOutput {
+            sink: group,
+            latency: opt.output_latency / 1000.0,
+            last_frame: None,
+            rgb: Vec::new(),
+        })
+        .add_system(hardware_output.system());
+    }
+    if let Some(path) = &opt.palette_editor {
+        app.insert_resource(PaletteEditor::open(path.clone(), &bulb_locations.0)?);
+        palette_editor::add_systems(&mut app);
+    }
+    if let Some(path) = &opt.channel_map {
+        app.insert_resource(ChannelMapper::open(path.clone(), bulb_locations.0.len())?);
+        channel_mapper::add_systems(&mut app);
+    }
+    if let Some(effect) = effect {
+        app.insert_resource(ParamPanel::new(effect));
+        param_panel::add_systems(&mut app);
+    }
+    let notes_path = Some(opt.sequence_path.clone()).filter(|path| !source::is_stream(path));
+    app.insert_resource(Notes::load(notes_path, opt.reviewer.clone())?);
+    let inspector = Inspector::new(
+        opt.highlight.unwrap_or_default(),
+        opt.wiring,
+        bulb_locations.0.len(),
+    )?;
+    app.insert_resource(Msaa { samples: 4 })
         .insert_resource(bulb_locations)
         .insert_resource(sequence)
+        .insert_resource(inspector)
         .init_resource::<MouseButtonState>()
+        .init_resource::<Preview>()
         .add_plugins(DefaultPlugins)
         .add_plugin(AlwaysOnTopPlugin)
         .add_startup_system(setup.system())
+        .add_startup_system(setup_timeline.system())
         .add_system(mouse_button_input.system())
         .add_system(camera_control.system())
         .add_system(sequence_animation.system())
-        .run();
+        .add_system(marker_navigation.system())
+        .add_system(playback_keys.system())
+        .add_system(timeline.system())
+        .add_system(window_title.system());
+    inspect::add_systems(&mut app);
+    notes::add_systems(&mut app);
+    app.run();
     Ok(())
 }
 
@@ -179,11 +560,15 @@ fn setup(
         });
     }
 
-    // cone
+    // cone, fitted to the outside of the bulbs
+    let fitted = geometry::Cone::fit(&bulb_locations.0);
+    let height = fitted.apex_height - fitted.axis.bottom;
+    let (cx, cy, cz) = fitted.axis.point_at(fitted.axis.bottom + height / 2.0);
+    let (dx, dy, dz) = fitted.axis.direction;
     commands.spawn_bundle(PbrBundle {
         mesh: meshes.add(Mesh::from(Cone {
-            height: 2.5,
-            radius: 0.9,
+            height,
+            radius: fitted.base_radius(),
             ..Default::default()
         })),
         visible: Visible {
@@ -195,7 +580,11 @@ fn setup(
             roughness: 0.9,
             ..Default::default()
         }),
-        transform: Transform::from_xyz(0.0, 1.75, 0.0),
+        transform: Transform {
+            translation: Vec3::new(cx, cz, cy),
+            rotation: Quat::from_rotation_arc(Vec3::Y, Vec3::new(dx, dz, dy)),
+            ..Default::default()
+        },
         ..Default::default()
     });
     // plane
@@ -220,11 +609,26 @@ fn mouse_button_input(
     mut mouse_button_state: ResMut<MouseButtonState>,
     mut windows: ResMut<Windows>,
     mut mouse_button_input_events: EventReader<MouseButtonInput>,
+    editor: Option<Res<PaletteEditor>>,
+    mapper: Option<Res<ChannelMapper>>,
+    params: Option<Res<ParamPanel>>,
+    keys: Res<Input<KeyCode>>,
 ) {
     let window = windows.get_primary_mut().unwrap();
     let was_locked = !mouse_button_state.pressed.is_empty();
+    // Clicks on the palette editor and parameter panel, and box selections for the channel
+    // mapper, are left for them to handle
+    let width = window.width();
+    let over_editor = match (&editor, window.cursor_position()) {
+        (Some(editor), Some(cursor)) => !was_locked && editor.over_panel(cursor),
+        _ => false,
+    } || match (&params, window.cursor_position()) {
+        (Some(params), Some(cursor)) => !was_locked && params.over_panel(cursor, width),
+        _ => false,
+    } || (mapper.is_some() && !was_locked && channel_mapper::selecting(&keys));
     for event in mouse_button_input_events.iter() {
         match event.state {
+            ElementState::Pressed if over_editor => {}
             ElementState::Pressed => {
                 mouse_button_state.pressed.insert(event.button);
             }
@@ -262,21 +666,73 @@ fn camera_control(
     }
 }
 
+/// The frame the preview is showing.
+#[derive(Default)]
+struct Preview {
+    frame: Option<usize>,
+    rgb: Vec<Rgb>,
+    colors: Vec<Color>,
+}
+
 fn sequence_animation(
     mut sequence: ResMut<Sequence>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     time: Res<Time>,
+    mut preview: ResMut<Preview>,
     query: Query<(&Handle<StandardMaterial>, &Bulb)>,
+    editor: Option<Res<PaletteEditor>>,
+    mapper: Option<Res<ChannelMapper>>,
+) {
+    sequence.advance(time.delta_seconds());
+    let editor_colors: Option<Vec<Color>> = match (editor, mapper) {
+        (Some(editor), _) if editor.previewing => Some(editor.tree_colors().collect()),
+        (_, Some(mapper)) if mapper.previewing => Some(mapper.tree_colors().collect()),
+        _ => None,
+    };
+    if let Some(colors) = editor_colors {
+        preview.colors = colors;
+        // Read the sequence again once the editor is no longer shown
+        preview.frame = None;
+        show_colors(&mut materials, &query, &preview.colors);
+        return;
+    }
+    let mut time = sequence.time;
+    if let Some(hardware_fps) = sequence.hardware_fps {
+        // The hardware only picks up a new frame each time it finishes a refresh
+        time = (time * hardware_fps).floor() / hardware_fps;
+    }
+    let frame_index = sequence.frame_at(time);
+    // Streams are read every time, since their frames don't follow the playback time
+    let is_stream = sequence.source.frame_count().is_none();
+    if preview.frame == Some(frame_index) && !is_stream {
+        return;
+    }
+    let Preview { rgb, colors, .. } = &mut *preview;
+    match sequence.read_frame(frame_index, rgb) {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(e) => {
+            eprintln!("Failed to read frame {}: {}", frame_index, e);
+            return;
+        }
+    }
+    colors.clear();
+    colors.extend(rgb.iter().map(|&[r, g, b]| Color::rgb_u8(r, g, b)));
+    preview.frame = Some(frame_index);
+    show_colors(&mut materials, &query, &preview.colors);
+}
+
+/// Lights each bulb and its glow in the preview.
+fn show_colors(
+    materials: &mut Assets<StandardMaterial>,
+    query: &Query<(&Handle<StandardMaterial>, &Bulb)>,
+    colors: &[Color],
 ) {
-    sequence.time =
-        (sequence.time + time.delta_seconds()) % (sequence.frames.len() as f32 / sequence.fps);
-    let frame_index = (sequence.time * sequence.fps) as usize;
-    let current_frame = &sequence.frames[frame_index];
     for (mat_handle, bulb) in query.iter() {
         let mat = materials.get_mut(mat_handle).unwrap();
-        let mut color = current_frame.colors[bulb.index].as_hlsa_f32();
+        let mut color = colors[bulb.index].as_hlsa_f32();
         if bulb.inner {
-            let light_color = current_frame.colors[bulb.index] + Color::rgb(0.25, 0.25, 0.25);
+            let light_color = colors[bulb.index] + Color::rgb(0.25, 0.25, 0.25);
             color = light_color.as_hlsa_f32();
             color[2] = (color[2] + 0.25).min(1.0);
         } else {
@@ -286,3 +742,178 @@ fn sequence_animation(
         mat.base_color = Color::hsla(color[0], color[1], color[2], color[3]);
     }
 }
+
+fn hardware_output(mut sequence: ResMut<Sequence>, mut output: ResMut<HardwareOutput>) {
+    let time = sequence.wrap(sequence.time + output.latency);
+    let frame_index = sequence.frame_at(time);
+    let is_stream = sequence.source.frame_count().is_none();
+    if output.last_frame == Some(frame_index) && !is_stream {
+        return;
+    }
+    output.last_frame = Some(frame_index);
+    let HardwareOutput { sink, rgb, .. } = &mut *output;
+    let result = match sequence.read_frame(frame_index, rgb) {
+        Ok(true) => sink.send_frame(rgb).map_err(Into::into),
+        other => other.map(|_| ()),
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to send frame to output: {}", e);
+    }
+}
+
+/// Space pauses and resumes. While playing the left and right arrow keys skip back and forward
+/// through the sequence, and while paused they step a frame at a time. + and - double and halve
+/// the playback speed.
+fn playback_keys(keys: Res<Input<KeyCode>>, mut sequence: ResMut<Sequence>) {
+    if keys.just_pressed(KeyCode::Space) {
+        sequence.paused = !sequence.paused;
+    }
+    if keys.just_pressed(KeyCode::Equals) || keys.just_pressed(KeyCode::NumpadAdd) {
+        sequence.speed = (sequence.speed * 2.0).min(SPEED_RANGE.1);
+    }
+    if keys.just_pressed(KeyCode::Minus) || keys.just_pressed(KeyCode::NumpadSubtract) {
+        sequence.speed = (sequence.speed / 2.0).max(SPEED_RANGE.0);
+    }
+    let forward = if keys.just_pressed(KeyCode::Right) {
+        true
+    } else if keys.just_pressed(KeyCode::Left) {
+        false
+    } else {
+        return;
+    };
+    if !sequence.paused {
+        let step = if forward { SEEK_STEP } else { -SEEK_STEP };
+        sequence.time = sequence.wrap((sequence.time + step).max(0.0));
+        return;
+    }
+    let frame = sequence.frame_index();
+    let frame = match (sequence.source.frame_count(), forward) {
+        // Streams only ever show their newest frame
+        (None, _) => return,
+        (Some(count), true) => (frame + 1) % count,
+        (Some(count), false) => (frame + count - 1) % count,
+    };
+    sequence.seek_frame(frame);
+}
+
+/// Page Up and Page Down jump between markers.
+fn marker_navigation(keys: Res<Input<KeyCode>>, mut sequence: ResMut<Sequence>) {
+    if sequence.markers.is_empty() {
+        return;
+    }
+    let current = sequence.current_marker();
+    let target = if keys.just_pressed(KeyCode::PageDown) {
+        Some(current.map_or(0, |i| i + 1) % sequence.markers.len())
+    } else if keys.just_pressed(KeyCode::PageUp) {
+        // Go back to the start of the current section, unless it has only just started
+        let frame = sequence.frame_index();
+        let one_second = sequence.fps as usize;
+        match current {
+            Some(i) if frame >= sequence.markers[i].frame + one_second => Some(i),
+            Some(0) | None => Some(sequence.markers.len() - 1),
+            Some(i) => Some(i - 1),
+        }
+    } else {
+        None
+    };
+    if let Some(target) = target {
+        let frame = sequence.markers[target].frame;
+        sequence.seek_frame(frame);
+    }
+}
+
+/// Marks the bar along the bottom of the window which shows how far through the sequence the
+/// playhead is.
+struct TimelineFill;
+
+/// Height of the timeline bar, in pixels.
+pub const TIMELINE_HEIGHT: f32 = 6.0;
+
+fn setup_timeline(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    sequence: Res<Sequence>,
+) {
+    commands.spawn_bundle(UiCameraBundle::default());
+    let bar = |left: f32, width: Val| Style {
+        size: Size::new(width, Val::Px(TIMELINE_HEIGHT)),
+        position_type: PositionType::Absolute,
+        position: Rect {
+            left: Val::Percent(left),
+            bottom: Val::Px(0.0),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let fill = materials.add(Color::rgb(0.9, 0.9, 0.9).into());
+    let tick = materials.add(Color::rgb(1.0, 0.8, 0.1).into());
+    commands
+        .spawn_bundle(NodeBundle {
+            style: bar(0.0, Val::Percent(100.0)),
+            material: materials.add(Color::rgba(0.0, 0.0, 0.0, 0.5).into()),
+            ..Default::default()
+        })
+        .insert(Timeline)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: bar(0.0, Val::Percent(0.0)),
+                    material: fill,
+                    ..Default::default()
+                })
+                .insert(TimelineFill);
+            // A tick for each marker
+            if let Some(count) = sequence.source.frame_count() {
+                for marker in &sequence.markers {
+                    parent.spawn_bundle(NodeBundle {
+                        style: bar(marker.frame as f32 * 100.0 / count as f32, Val::Px(2.0)),
+                        material: tick.clone(),
+                        ..Default::default()
+                    });
+                }
+            }
+        });
+}
+
+fn timeline(sequence: Res<Sequence>, mut query: Query<&mut Style, With<TimelineFill>>) {
+    let progress = match sequence.source.frame_count() {
+        Some(count) => (sequence.frame_index() + 1) as f32 / count as f32,
+        None => 0.0,
+    };
+    for mut style in query.iter_mut() {
+        style.size.width = Val::Percent(progress * 100.0);
+    }
+}
+
+/// The window title shows the frame counter, playback state and current marker.
+fn window_title(sequence: Res<Sequence>, mut windows: ResMut<Windows>, mut shown: Local<String>) {
+    let mut title = format!("xmas_tree_player - frame {}", sequence.frame_index() + 1);
+    if let Some(count) = sequence.source.frame_count() {
+        title += &format!("/{}", count);
+    }
+    if sequence.speed != 1.0 {
+        title += &format!(" x{}", sequence.speed);
+    }
+    if sequence.paused {
+        title += " (paused)";
+    }
+    if let Some(i) = sequence.current_marker() {
+        title += &format!(" - {}", sequence.markers[i].name);
+    }
+    let frame = sequence.frame_index();
+    let credits: Vec<String> = sequence
+        .credits
+        .iter()
+        .filter(|credit| credit.contains(frame))
+        .map(|credit| credit.to_string())
+        .collect();
+    if !credits.is_empty() {
+        title += &format!(" [{}]", credits.join(", "));
+    }
+    if title != *shown {
+        if let Some(window) = windows.get_primary_mut() {
+            window.set_title(title.clone());
+        }
+        *shown = title;
+    }
+}
@@ -0,0 +1,224 @@
+//! Review notes on frames of the sequence, for going through a show before it goes on the real
+//! tree. N pauses and starts a note on the current frame (or edits the one already there), Return
+//! saves it and Escape abandons it; saving a note empty deletes it. Notes are ticked on the
+//! timeline, shown in the corner for a couple of seconds from their frame, and saved in a sidecar
+//! next to the sequence which `xmas_tree_gen notes` lists.
+
+use std::{error::Error, path::PathBuf};
+
+use bevy::{input::InputSystem, prelude::*, window::ReceivedCharacter};
+use xmas_tree_common::notes::{self, Note};
+
+use crate::{inspect::FONT, Sequence, TIMELINE_HEIGHT};
+
+/// How long a note stays in the corner after its frame, in seconds.
+const SHOW_SECS: f32 = 2.0;
+
+pub struct Notes {
+    /// The sequence the notes are on. Streams can't have notes.
+    path: Option<PathBuf>,
+    notes: Vec<Note>,
+    /// Recorded with new notes, from `--reviewer`.
+    author: Option<String>,
+    /// The frame a note is being typed on, and the text so far.
+    editing: Option<(usize, String)>,
+    /// Set when the ticks on the timeline need updating.
+    changed: bool,
+}
+
+impl Notes {
+    pub fn load(path: Option<PathBuf>, author: Option<String>) -> Result<Self, Box<dyn Error>> {
+        let notes = match &path {
+            Some(path) => notes::load(path)?,
+            None => Vec::new(),
+        };
+        Ok(Self {
+            path,
+            notes,
+            author,
+            editing: None,
+            changed: true,
+        })
+    }
+
+    /// Replaces the note on `frame` with `text`, or removes it if `text` is empty, and saves.
+    fn set(&mut self, frame: usize, text: String) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        self.notes.retain(|note| note.frame != frame);
+        if !text.trim().is_empty() {
+            self.notes
+                .push(Note::new(frame, text.trim().into(), self.author.clone()));
+        }
+        if let Err(e) = notes::save(path, &mut self.notes) {
+            eprintln!("Failed to save notes: {}", e);
+        }
+        self.changed = true;
+    }
+}
+
+struct NoteText;
+
+struct NoteTick;
+
+/// Marks the timeline bar, which note ticks are added to.
+pub struct Timeline;
+
+pub fn add_systems(app: &mut AppBuilder) {
+    // Typing runs straight after the keyboard is read, so it can hide the keys from the other
+    // shortcuts
+    app.add_startup_system(setup.system())
+        .add_system_to_stage(CoreStage::PreUpdate, typing.system().after(InputSystem))
+        .add_system(ticks.system())
+        .add_system(overlay.system());
+}
+
+fn setup(mut commands: Commands, mut fonts: ResMut<Assets<Font>>) {
+    let font = fonts.add(Font::try_from_bytes(FONT.to_vec()).expect("Built in font is invalid"));
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    right: Val::Px(10.0),
+                    bottom: Val::Px(TIMELINE_HEIGHT + 10.0),
+                    ..Default::default()
+                },
+                max_size: Size::new(Val::Px(500.0), Val::Undefined),
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font,
+                    font_size: 18.0,
+                    color: Color::rgb(1.0, 1.0, 0.6),
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(NoteText);
+}
+
+fn typing(
+    mut notes: ResMut<Notes>,
+    mut keys: ResMut<Input<KeyCode>>,
+    mut chars: EventReader<ReceivedCharacter>,
+    mut sequence: ResMut<Sequence>,
+) {
+    let (frame, mut text) = match notes.editing.take() {
+        Some(editing) => editing,
+        None => {
+            if keys.just_pressed(KeyCode::N)
+                && notes.path.is_some()
+                && sequence.source.frame_count().is_some()
+            {
+                let frame = sequence.frame_index();
+                let text = notes
+                    .notes
+                    .iter()
+                    .find(|note| note.frame == frame)
+                    .map_or_else(String::new, |note| note.text.clone());
+                sequence.paused = true;
+                notes.editing = Some((frame, text));
+                // The N itself isn't part of the note
+                chars.iter().for_each(drop);
+            }
+            return;
+        }
+    };
+    text.extend(chars.iter().map(|c| c.char).filter(|c| !c.is_control()));
+    if keys.just_pressed(KeyCode::Back) {
+        text.pop();
+    }
+    if keys.just_pressed(KeyCode::Return) {
+        notes.set(frame, text);
+    } else if !keys.just_pressed(KeyCode::Escape) {
+        notes.editing = Some((frame, text));
+    }
+    // Keys typed into the note mustn't also work as shortcuts
+    let typed: Vec<KeyCode> = keys.get_pressed().copied().collect();
+    for key in typed {
+        keys.reset(key);
+    }
+}
+
+/// Ticks each note on the timeline, below the marker ticks.
+fn ticks(
+    mut commands: Commands,
+    mut notes: ResMut<Notes>,
+    sequence: Res<Sequence>,
+    timeline: Query<Entity, With<Timeline>>,
+    old: Query<Entity, With<NoteTick>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut material: Local<Option<Handle<ColorMaterial>>>,
+) {
+    let count = match sequence.source.frame_count() {
+        Some(count) if notes.changed => count,
+        _ => return,
+    };
+    let timeline = match timeline.iter().next() {
+        Some(timeline) => timeline,
+        None => return,
+    };
+    notes.changed = false;
+    for tick in old.iter() {
+        commands.entity(tick).despawn_recursive();
+    }
+    let material = material
+        .get_or_insert_with(|| materials.add(Color::rgb(0.3, 0.8, 1.0).into()))
+        .clone();
+    commands.entity(timeline).with_children(|parent| {
+        for note in &notes.notes {
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(2.0), Val::Px(TIMELINE_HEIGHT / 2.0)),
+                        position_type: PositionType::Absolute,
+                        position: Rect {
+                            left: Val::Percent(note.frame as f32 * 100.0 / count as f32),
+                            bottom: Val::Px(0.0),
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    },
+                    material: material.clone(),
+                    ..Default::default()
+                })
+                .insert(NoteTick);
+        }
+    });
+}
+
+/// Shows the note being typed, or otherwise the notes on the last couple of seconds.
+fn overlay(
+    notes: Res<Notes>,
+    sequence: Res<Sequence>,
+    mut texts: Query<&mut Text, With<NoteText>>,
+) {
+    let text = match &notes.editing {
+        Some((frame, text)) => format!("Note on frame {}: {}_", frame + 1, text),
+        None => {
+            let frame = sequence.frame_index();
+            let shown = (SHOW_SECS * sequence.fps) as usize;
+            notes
+                .notes
+                .iter()
+                .filter(|note| note.frame <= frame && frame <= note.frame + shown)
+                .map(|note| match &note.author {
+                    Some(author) => format!("{}: {}", author, note.text),
+                    None => note.text.clone(),
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+    };
+    for mut shown in texts.iter_mut() {
+        if shown.sections[0].value != text {
+            shown.sections[0].value = text.clone();
+        }
+    }
+}
@@ -0,0 +1,379 @@
+//! A panel for designing a palette against the tree itself. The tree shows the palette as a
+//! gradient from the bottom to the top while it's edited, and it's saved in the palette file
+//! format, for use as e.g. `xmas_tree_gen generate barber-pole --param palette=@PATH`.
+//!
+//! Click a color stop to select it, and drag it to move it along the palette. Insert adds a copy
+//! of the selected stop and Delete removes it. Q/A, W/S and E/D raise and lower its hue,
+//! saturation and brightness. Tab switches the tree between the palette and the sequence, and
+//! Return saves. Every change can be undone.
+
+use std::{error::Error, path::PathBuf};
+
+use bevy::prelude::*;
+use xmas_tree_common::{
+    color::{hsv_to_rgb, rgb_to_hsv, Color as Rgb},
+    palette::Palette,
+};
+
+use crate::{
+    undo::{self, Edit, History, Step},
+    TIMELINE_HEIGHT,
+};
+
+/// The palette a new file starts with.
+const DEFAULT_PALETTE: &str = "christmas";
+const MAX_STOPS: usize = 12;
+const SWATCH_SIZE: f32 = 40.0;
+const SWATCH_GAP: f32 = 6.0;
+const SWATCH_BORDER: f32 = 3.0;
+const PANEL_LEFT: f32 = 10.0;
+const PANEL_BOTTOM: f32 = TIMELINE_HEIGHT + 10.0;
+const GRADIENT_HEIGHT: f32 = 16.0;
+const GRADIENT_SLICES: usize = 96;
+/// How fast holding Q or A turns the hue, in turns per second.
+const HUE_RATE: f32 = 0.25;
+/// How fast holding W, S, E or D changes saturation and brightness, per second.
+const LEVEL_RATE: f32 = 0.5;
+
+pub struct PaletteEditor {
+    path: PathBuf,
+    palette: Palette,
+    /// How far up the tree each bulb is, from 0 to 1.
+    heights: Vec<f32>,
+    selected: usize,
+    /// Where the stop being dragged along the palette started, and where it is now.
+    dragging: Option<(usize, usize)>,
+    /// The stop being recolored with the keys, and its color before, until the keys are let go.
+    adjusting: Option<(usize, Rgb)>,
+    history: History<Palette>,
+    /// Whether the tree shows the palette rather than the sequence.
+    pub previewing: bool,
+    /// Set when the panel needs updating.
+    dirty: bool,
+}
+
+impl PaletteEditor {
+    /// Edits the palette file at `path`, which is created on saving if it doesn't exist yet.
+    pub fn open(path: PathBuf, bulbs: &[(f32, f32, f32)]) -> Result<Self, Box<dyn Error>> {
+        let palette = if path.exists() {
+            Palette::load(&path)?
+        } else {
+            DEFAULT_PALETTE.parse()?
+        };
+        if palette.colors.len() > MAX_STOPS {
+            return Err(format!(
+                "{} has {} colors, but the editor only has room for {}",
+                path.display(),
+                palette.colors.len(),
+                MAX_STOPS
+            )
+            .into());
+        }
+        let (min, max) = bulbs
+            .iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), b| {
+                (min.min(b.2), max.max(b.2))
+            });
+        let range = (max - min).max(f32::EPSILON);
+        Ok(Self {
+            path,
+            palette,
+            heights: bulbs.iter().map(|b| (b.2 - min) / range).collect(),
+            selected: 0,
+            dragging: None,
+            adjusting: None,
+            history: History::default(),
+            previewing: true,
+            dirty: true,
+        })
+    }
+
+    /// Records a recoloring once its keys have been let go, or before anything else is edited.
+    fn finish_adjusting(&mut self) {
+        if let Some((index, from)) = self.adjusting.take() {
+            let to = self.palette.colors[index];
+            self.history
+                .record(PaletteEdit::Recolor { index, from, to });
+        }
+    }
+
+    /// The colors to show on the tree while previewing.
+    pub fn tree_colors(&self) -> impl Iterator<Item = Color> + '_ {
+        self.heights
+            .iter()
+            .map(move |&height| to_color(self.palette.gradient(height)))
+    }
+
+    /// The slot along the row of stops at a window position, even if it's past the last stop.
+    fn slot_at(&self, cursor: Vec2) -> Option<usize> {
+        let (x, y) = (cursor.x - PANEL_LEFT, cursor.y - PANEL_BOTTOM);
+        if x < 0.0 || !(0.0..=SWATCH_SIZE).contains(&y) {
+            return None;
+        }
+        Some((x / (SWATCH_SIZE + SWATCH_GAP)) as usize)
+    }
+
+    /// Whether a window position is on one of the stops, so clicks there don't turn the camera.
+    pub fn over_panel(&self, cursor: Vec2) -> bool {
+        self.slot_at(cursor)
+            .is_some_and(|slot| slot < self.palette.colors.len())
+    }
+}
+
+fn to_color((r, g, b): Rgb) -> Color {
+    Color::rgb(r, g, b)
+}
+
+enum PaletteEdit {
+    Insert { index: usize, color: Rgb },
+    Remove { index: usize, color: Rgb },
+    Move { from: usize, to: usize },
+    Recolor { index: usize, from: Rgb, to: Rgb },
+}
+
+impl Edit<Palette> for PaletteEdit {
+    fn apply(&self, palette: &mut Palette) {
+        let colors = &mut palette.colors;
+        match *self {
+            Self::Insert { index, color } => colors.insert(index, color),
+            Self::Remove { index, .. } => {
+                colors.remove(index);
+            }
+            Self::Move { from, to } => {
+                let color = colors.remove(from);
+                colors.insert(to, color);
+            }
+            Self::Recolor { index, to, .. } => colors[index] = to,
+        }
+    }
+
+    fn revert(&self, palette: &mut Palette) {
+        let colors = &mut palette.colors;
+        match *self {
+            Self::Insert { index, .. } => {
+                colors.remove(index);
+            }
+            Self::Remove { index, color } => colors.insert(index, color),
+            Self::Move { from, to } => {
+                let color = colors.remove(to);
+                colors.insert(from, color);
+            }
+            Self::Recolor { index, from, .. } => colors[index] = from,
+        }
+    }
+}
+
+/// One of the color stops, or the border around it which shows the selection.
+struct Swatch {
+    index: usize,
+    border: bool,
+}
+
+struct GradientSlice(usize);
+
+/// Adds the panel's systems, when a palette is being edited.
+pub fn add_systems(app: &mut AppBuilder) {
+    app.add_startup_system(setup.system())
+        .add_system(keys.system())
+        .add_system(mouse.system())
+        .add_system(panel.system());
+}
+
+fn setup(mut commands: Commands, mut materials: ResMut<Assets<ColorMaterial>>) {
+    let node = |left: f32, bottom: f32, width: f32, height: f32| Style {
+        size: Size::new(Val::Px(width), Val::Px(height)),
+        position_type: PositionType::Absolute,
+        position: Rect {
+            left: Val::Px(left),
+            bottom: Val::Px(bottom),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    // Every stop has a node of its own, and unused ones are left transparent
+    for index in 0..MAX_STOPS {
+        let left = PANEL_LEFT + index as f32 * (SWATCH_SIZE + SWATCH_GAP);
+        let inner = SWATCH_SIZE - SWATCH_BORDER * 2.0;
+        commands
+            .spawn_bundle(NodeBundle {
+                style: node(left, PANEL_BOTTOM, SWATCH_SIZE, SWATCH_SIZE),
+                material: materials.add(Color::NONE.into()),
+                ..Default::default()
+            })
+            .insert(Swatch {
+                index,
+                border: true,
+            })
+            .with_children(|parent| {
+                parent
+                    .spawn_bundle(NodeBundle {
+                        style: node(SWATCH_BORDER, SWATCH_BORDER, inner, inner),
+                        material: materials.add(Color::NONE.into()),
+                        ..Default::default()
+                    })
+                    .insert(Swatch {
+                        index,
+                        border: false,
+                    });
+            });
+    }
+    let width = MAX_STOPS as f32 * (SWATCH_SIZE + SWATCH_GAP) - SWATCH_GAP;
+    let slice = width / GRADIENT_SLICES as f32;
+    for i in 0..GRADIENT_SLICES {
+        commands
+            .spawn_bundle(NodeBundle {
+                style: node(
+                    PANEL_LEFT + i as f32 * slice,
+                    PANEL_BOTTOM + SWATCH_SIZE + SWATCH_GAP,
+                    // Overlap the next slice so no gaps show between them
+                    slice + 1.0,
+                    GRADIENT_HEIGHT,
+                ),
+                material: materials.add(Color::NONE.into()),
+                ..Default::default()
+            })
+            .insert(GradientSlice(i));
+    }
+}
+
+fn keys(mut editor: ResMut<PaletteEditor>, keys: Res<Input<KeyCode>>, time: Res<Time>) {
+    let editor = &mut *editor;
+    if keys.just_pressed(KeyCode::Tab) {
+        editor.previewing = !editor.previewing;
+    }
+    if keys.just_pressed(KeyCode::Return) {
+        match editor.palette.save(&editor.path) {
+            Ok(()) => eprintln!("Saved palette to {}", editor.path.display()),
+            Err(e) => eprintln!("Failed to save palette: {}", e),
+        }
+    }
+    if let Some(step) = undo::step(&keys).filter(|_| editor.dragging.is_none()) {
+        editor.finish_adjusting();
+        let changed = match step {
+            Step::Undo => editor.history.undo(&mut editor.palette),
+            Step::Redo => editor.history.redo(&mut editor.palette),
+        };
+        if changed {
+            editor.selected = editor.selected.min(editor.palette.colors.len() - 1);
+            editor.dirty = true;
+        }
+    }
+    if keys.just_pressed(KeyCode::Insert) && editor.palette.colors.len() < MAX_STOPS {
+        editor.finish_adjusting();
+        let edit = PaletteEdit::Insert {
+            index: editor.selected + 1,
+            color: editor.palette.colors[editor.selected],
+        };
+        editor.history.apply(edit, &mut editor.palette);
+        editor.selected += 1;
+        editor.dirty = true;
+    }
+    if keys.just_pressed(KeyCode::Delete) && editor.palette.colors.len() > 1 {
+        editor.finish_adjusting();
+        let edit = PaletteEdit::Remove {
+            index: editor.selected,
+            color: editor.palette.colors[editor.selected],
+        };
+        editor.history.apply(edit, &mut editor.palette);
+        editor.selected = editor.selected.min(editor.palette.colors.len() - 1);
+        editor.dirty = true;
+    }
+
+    let colors = &mut editor.palette.colors;
+    let (hue, saturation, value) = rgb_to_hsv(colors[editor.selected]);
+    let mut hsv = [hue, saturation, value];
+    let step = time.delta_seconds();
+    let adjustments = [
+        (KeyCode::Q, KeyCode::A, HUE_RATE),
+        (KeyCode::W, KeyCode::S, LEVEL_RATE),
+        (KeyCode::E, KeyCode::D, LEVEL_RATE),
+    ];
+    let mut adjusted = false;
+    for (channel, &(up, down, rate)) in hsv.iter_mut().zip(&adjustments) {
+        if keys.pressed(up) {
+            *channel += rate * step;
+            adjusted = true;
+        }
+        if keys.pressed(down) {
+            *channel -= rate * step;
+            adjusted = true;
+        }
+    }
+    if adjusted {
+        let [hue, saturation, value] = hsv;
+        let color = hsv_to_rgb(hue, saturation.clamp(0.0, 1.0), value.clamp(0.0, 1.0));
+        if editor.adjusting.is_none() {
+            editor.adjusting = Some((editor.selected, colors[editor.selected]));
+        }
+        colors[editor.selected] = color;
+        editor.dirty = true;
+    } else {
+        editor.finish_adjusting();
+    }
+}
+
+fn mouse(
+    mut editor: ResMut<PaletteEditor>,
+    buttons: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+) {
+    let cursor = match windows.get_primary().and_then(|w| w.cursor_position()) {
+        Some(cursor) => cursor,
+        None => return,
+    };
+    if buttons.just_pressed(MouseButton::Left) && editor.over_panel(cursor) {
+        editor.finish_adjusting();
+        let index = editor.slot_at(cursor).unwrap();
+        editor.selected = index;
+        editor.dragging = Some((index, index));
+        editor.dirty = true;
+    }
+    if !buttons.pressed(MouseButton::Left) {
+        // The whole drag is undone in one go
+        if let Some((from, to)) = editor.dragging.take().filter(|(from, to)| from != to) {
+            editor.history.record(PaletteEdit::Move { from, to });
+        }
+        return;
+    }
+    if let Some((start, from)) = editor.dragging {
+        // Dragging past either end moves the stop to that end
+        let slot = ((cursor.x - PANEL_LEFT) / (SWATCH_SIZE + SWATCH_GAP)).max(0.0) as usize;
+        let to = slot.min(editor.palette.colors.len() - 1);
+        if to != from {
+            PaletteEdit::Move { from, to }.apply(&mut editor.palette);
+            editor.selected = to;
+            editor.dragging = Some((start, to));
+            editor.dirty = true;
+        }
+    }
+}
+
+fn panel(
+    mut editor: ResMut<PaletteEditor>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    swatches: Query<(&Swatch, &Handle<ColorMaterial>)>,
+    slices: Query<(&GradientSlice, &Handle<ColorMaterial>)>,
+) {
+    if !editor.dirty {
+        return;
+    }
+    editor.dirty = false;
+    let colors = &editor.palette.colors;
+    for (swatch, handle) in swatches.iter() {
+        let color = match colors.get(swatch.index) {
+            None => Color::NONE,
+            Some(_) if swatch.border && swatch.index == editor.selected => Color::WHITE,
+            Some(_) if swatch.border => Color::rgb(0.2, 0.2, 0.2),
+            Some(&color) => to_color(color),
+        };
+        if let Some(material) = materials.get_mut(handle) {
+            material.color = color;
+        }
+    }
+    for (slice, handle) in slices.iter() {
+        let t = (slice.0 as f32 + 0.5) / GRADIENT_SLICES as f32;
+        if let Some(material) = materials.get_mut(handle) {
+            material.color = to_color(editor.palette.gradient(t));
+        }
+    }
+}
@@ -0,0 +1,390 @@
+//! A panel for tuning an effect while it runs live, with `effect://NAME`. Every parameter the
+//! effect declares has a row: numbers get a slider over the range `--variation` keeps them
+//! within, choices switch to the next one when clicked, and the rest (palettes, scripts and
+//! paths) show their value, which `--param` sets. Changes show on the tree from the next frame.
+//!
+//! Copy CLI flags puts the `xmas_tree_gen generate` command which renders the effect as it's set
+//! up now on the clipboard, and prints it as well.
+
+use std::sync::{Arc, Mutex};
+
+use arboard::Clipboard;
+use bevy::prelude::*;
+use xmas_tree_gen::tweak::{ParamInfo, ParamKind, TweakableEffect};
+
+use crate::{inspect::FONT, HardwareOutput, Preview, TIMELINE_HEIGHT};
+
+const PANEL_RIGHT: f32 = 10.0;
+const PANEL_BOTTOM: f32 = TIMELINE_HEIGHT + 10.0;
+const PANEL_WIDTH: f32 = 260.0;
+const ROW_HEIGHT: f32 = 40.0;
+const LABEL_HEIGHT: f32 = 20.0;
+const TRACK_HEIGHT: f32 = 10.0;
+const BUTTON_HEIGHT: f32 = 26.0;
+/// Where the rows start, leaving a gap above the button.
+const ROWS_BOTTOM: f32 = PANEL_BOTTOM + BUTTON_HEIGHT + 8.0;
+const FONT_SIZE: f32 = 16.0;
+/// Longest value shown for parameters without a slider, in characters.
+const MAX_VALUE_CHARS: usize = 24;
+
+/// How a row changes its parameter.
+#[derive(Clone, Copy)]
+enum Control {
+    Slider {
+        min: f32,
+        max: f32,
+        integer: bool,
+    },
+    /// Clicking moves on to the next choice.
+    Choice(&'static [&'static str]),
+    /// Set with `--param` only.
+    Fixed,
+}
+
+impl Control {
+    fn of(param: &ParamInfo, value: &str) -> Self {
+        let integer = match param.kind {
+            ParamKind::Float => false,
+            ParamKind::Int => true,
+            ParamKind::Choice(choices) => return Self::Choice(choices),
+            _ => return Self::Fixed,
+        };
+        // Parameters without a safe range get one from zero to twice their default, stretched
+        // to take in the value they started with
+        let value: f32 = value.parse().unwrap_or(0.0);
+        let (min, max) = param.range.unwrap_or_else(|| {
+            let default: f32 = param.default.parse().unwrap_or(0.0);
+            (0.0, (default * 2.0).max(1.0))
+        });
+        Self::Slider {
+            min: min.min(value),
+            max: max.max(value),
+            integer,
+        }
+    }
+}
+
+pub struct ParamPanel {
+    effect: Arc<Mutex<TweakableEffect>>,
+    controls: Vec<Control>,
+    /// The row whose slider is being dragged.
+    dragging: Option<usize>,
+    /// Kept for as long as the player runs, since on some systems what's copied goes when the
+    /// clipboard that copied it does.
+    clipboard: Option<Clipboard>,
+    /// Set when the panel needs updating.
+    dirty: bool,
+}
+
+impl ParamPanel {
+    pub fn new(effect: Arc<Mutex<TweakableEffect>>) -> Self {
+        let controls = {
+            let effect = effect.lock().unwrap();
+            effect
+                .params()
+                .iter()
+                .map(|param| Control::of(param, effect.value(param.name)))
+                .collect()
+        };
+        Self {
+            effect,
+            controls,
+            dragging: None,
+            clipboard: None,
+            dirty: true,
+        }
+    }
+
+    /// Where row `row` starts from the bottom of the window. The button is below the last row.
+    fn row_bottom(&self, row: usize) -> f32 {
+        ROWS_BOTTOM + (self.controls.len() - 1 - row) as f32 * ROW_HEIGHT
+    }
+
+    fn panel_left(window_width: f32) -> f32 {
+        window_width - PANEL_RIGHT - PANEL_WIDTH
+    }
+
+    /// The row at a window position, if any, or `None` for the button.
+    fn row_at(&self, cursor: Vec2, window_width: f32) -> Option<Option<usize>> {
+        let x = cursor.x - Self::panel_left(window_width);
+        if !(0.0..=PANEL_WIDTH).contains(&x) || cursor.y < PANEL_BOTTOM {
+            return None;
+        }
+        if cursor.y <= PANEL_BOTTOM + BUTTON_HEIGHT {
+            return Some(None);
+        }
+        if cursor.y < ROWS_BOTTOM {
+            return None;
+        }
+        let from_bottom = ((cursor.y - ROWS_BOTTOM) / ROW_HEIGHT) as usize;
+        (from_bottom < self.controls.len()).then(|| Some(self.controls.len() - 1 - from_bottom))
+    }
+
+    /// Whether a window position is on the panel, so clicks there don't turn the camera.
+    pub fn over_panel(&self, cursor: Vec2, window_width: f32) -> bool {
+        self.row_at(cursor, window_width).is_some()
+    }
+
+    /// Moves row `row`'s slider to where the cursor is along it.
+    fn slide(&mut self, row: usize, cursor_x: f32, window_width: f32) {
+        let (min, max, integer) = match self.controls[row] {
+            Control::Slider { min, max, integer } => (min, max, integer),
+            _ => return,
+        };
+        let t = ((cursor_x - Self::panel_left(window_width)) / PANEL_WIDTH).clamp(0.0, 1.0);
+        let value = min + (max - min) * t;
+        let value = if integer {
+            (value.round() as usize).to_string()
+        } else {
+            // Three significant figures are plenty to tune by eye, and keep the flags short
+            let digits = (2 - (max - min).max(0.001).log10().floor() as i32).max(0) as usize;
+            format!("{:.*}", digits, value)
+        };
+        // Written as the default is when it lands on it, so it's left out of the flags
+        let default = self.effect.lock().unwrap().params()[row].default;
+        let value = match (value.parse::<f32>(), default.parse::<f32>()) {
+            (Ok(value), Ok(default_value)) if value == default_value => default.to_string(),
+            _ => value,
+        };
+        self.set(row, value);
+    }
+
+    fn set(&mut self, row: usize, value: String) {
+        let mut effect = self.effect.lock().unwrap();
+        let name = effect.params()[row].name;
+        if effect.value(name) == value {
+            return;
+        }
+        match effect.set(name, value) {
+            Ok(()) => self.dirty = true,
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    /// Moves a choice on to the next one, going back to the first after the last.
+    fn next_choice(&mut self, row: usize) {
+        let choices = match self.controls[row] {
+            Control::Choice(choices) => choices,
+            _ => return,
+        };
+        let current = {
+            let effect = self.effect.lock().unwrap();
+            effect.value(effect.params()[row].name).to_string()
+        };
+        let index = choices.iter().position(|&choice| choice == current);
+        let next = index.map_or(0, |index| (index + 1) % choices.len());
+        self.set(row, choices[next].to_string());
+    }
+
+    fn copy_command(&mut self) {
+        let command = self.effect.lock().unwrap().command_line();
+        eprintln!("{}", command);
+        if self.clipboard.is_none() {
+            match Clipboard::new() {
+                Ok(clipboard) => self.clipboard = Some(clipboard),
+                Err(e) => {
+                    eprintln!("Failed to copy the command: {}", e);
+                    return;
+                }
+            }
+        }
+        if let Err(e) = self.clipboard.as_mut().unwrap().set_text(command) {
+            eprintln!("Failed to copy the command: {}", e);
+        }
+    }
+}
+
+/// One of the rows' text, the whole of the track or the part of it up to the value.
+enum RowPart {
+    Label,
+    Track,
+    Fill,
+}
+
+struct Row {
+    index: usize,
+    part: RowPart,
+}
+
+/// Adds the panel's systems, when an effect is running live.
+pub fn add_systems(app: &mut AppBuilder) {
+    app.add_startup_system(setup.system())
+        .add_system(mouse.system())
+        .add_system(panel.system());
+}
+
+fn setup(
+    mut commands: Commands,
+    panel: Res<ParamPanel>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut fonts: ResMut<Assets<Font>>,
+) {
+    let node = |bottom: f32, width: f32, height: f32| Style {
+        size: Size::new(Val::Px(width), Val::Px(height)),
+        position_type: PositionType::Absolute,
+        position: Rect {
+            right: Val::Px(PANEL_RIGHT),
+            bottom: Val::Px(bottom),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let font = fonts.add(Font::try_from_bytes(FONT.to_vec()).expect("Built in font is invalid"));
+    let text = |value: &str| {
+        Text::with_section(
+            value,
+            TextStyle {
+                font: font.clone(),
+                font_size: FONT_SIZE,
+                color: Color::WHITE,
+            },
+            Default::default(),
+        )
+    };
+    for index in 0..panel.controls.len() {
+        let bottom = panel.row_bottom(index);
+        commands
+            .spawn_bundle(TextBundle {
+                style: node(bottom + TRACK_HEIGHT + 4.0, PANEL_WIDTH, LABEL_HEIGHT),
+                text: text(""),
+                ..Default::default()
+            })
+            .insert(Row {
+                index,
+                part: RowPart::Label,
+            });
+        commands
+            .spawn_bundle(NodeBundle {
+                style: node(bottom, PANEL_WIDTH, TRACK_HEIGHT),
+                material: materials.add(Color::NONE.into()),
+                ..Default::default()
+            })
+            .insert(Row {
+                index,
+                part: RowPart::Track,
+            })
+            .with_children(|parent| {
+                parent
+                    .spawn_bundle(NodeBundle {
+                        style: Style {
+                            size: Size::new(Val::Percent(0.0), Val::Percent(100.0)),
+                            ..Default::default()
+                        },
+                        material: materials.add(Color::rgb(0.9, 0.9, 0.9).into()),
+                        ..Default::default()
+                    })
+                    .insert(Row {
+                        index,
+                        part: RowPart::Fill,
+                    });
+            });
+    }
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                padding: Rect::all(Val::Px(4.0)),
+                ..node(PANEL_BOTTOM, PANEL_WIDTH, BUTTON_HEIGHT)
+            },
+            material: materials.add(Color::rgb(0.2, 0.2, 0.2).into()),
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: text("Copy CLI flags"),
+                ..Default::default()
+            });
+        });
+}
+
+fn mouse(
+    mut panel: ResMut<ParamPanel>,
+    buttons: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    mut preview: ResMut<Preview>,
+    output: Option<ResMut<HardwareOutput>>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let (cursor, width) = match window.cursor_position() {
+        Some(cursor) => (cursor, window.width()),
+        None => return,
+    };
+    if buttons.just_pressed(MouseButton::Left) {
+        match panel.row_at(cursor, width) {
+            Some(None) => panel.copy_command(),
+            Some(Some(row)) => {
+                let control = panel.controls[row];
+                match control {
+                    Control::Slider { .. } => panel.dragging = Some(row),
+                    Control::Choice(_) => panel.next_choice(row),
+                    Control::Fixed => {}
+                }
+            }
+            None => {}
+        }
+    }
+    if !buttons.pressed(MouseButton::Left) {
+        panel.dragging = None;
+    }
+    if let Some(row) = panel.dragging {
+        panel.slide(row, cursor.x, width);
+    }
+    if panel.dirty {
+        // Show the change even while paused, when the frame wouldn't otherwise be read again
+        preview.frame = None;
+        if let Some(mut output) = output {
+            output.last_frame = None;
+        }
+    }
+}
+
+/// Shows each parameter's value, and how far along its range each slider is.
+fn panel(
+    mut panel: ResMut<ParamPanel>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut texts: Query<(&Row, &mut Text)>,
+    mut nodes: Query<(&Row, &mut Style, &Handle<ColorMaterial>)>,
+) {
+    if !panel.dirty {
+        return;
+    }
+    panel.dirty = false;
+    let effect = panel.effect.lock().unwrap();
+    let params = effect.params();
+    for (row, mut text) in texts.iter_mut() {
+        let param = &params[row.index];
+        let value = effect.value(param.name);
+        let value = match panel.controls[row.index] {
+            Control::Fixed if value.chars().count() > MAX_VALUE_CHARS => {
+                format!(
+                    "{}...",
+                    value.chars().take(MAX_VALUE_CHARS).collect::<String>()
+                )
+            }
+            Control::Fixed if value.is_empty() => "(none)".to_string(),
+            _ => value.to_string(),
+        };
+        text.sections[0].value = format!("{}: {}", param.name, value);
+    }
+    for (row, mut style, handle) in nodes.iter_mut() {
+        let (min, max) = match panel.controls[row.index] {
+            Control::Slider { min, max, .. } => (min, max),
+            _ => continue,
+        };
+        match row.part {
+            RowPart::Track => {
+                if let Some(material) = materials.get_mut(handle) {
+                    material.color = Color::rgba(1.0, 1.0, 1.0, 0.2);
+                }
+            }
+            RowPart::Fill => {
+                let value: f32 = effect.value(params[row.index].name).parse().unwrap_or(min);
+                let t = ((value - min) / (max - min).max(f32::EPSILON)).clamp(0.0, 1.0);
+                style.size.width = Val::Percent(t * 100.0);
+            }
+            RowPart::Label => {}
+        }
+    }
+}
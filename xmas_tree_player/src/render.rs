@@ -0,0 +1,433 @@
+//! Renders a sequence to PNG frames or a video without opening a window, so previews can be
+//! shared with people who don't have the player. The bulbs are drawn in software from the same
+//! viewpoint as the player's camera, one image per frame of the sequence.
+//!
+//! With `--render-stereo` each frame is drawn once for each eye, to judge how volumetric effects
+//! look in 3D: as a red/cyan anaglyph for cheap glasses, or side by side for a phone in a VR
+//! holder.
+
+use std::{
+    error::Error,
+    f32::consts::PI,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, Command, Stdio},
+    str::FromStr,
+};
+
+use bevy::math::Vec3;
+use structopt::StructOpt;
+use xmas_tree_common::sequence::Rgb;
+
+use crate::Sequence;
+
+#[derive(Debug, StructOpt)]
+pub struct RenderOpt {
+    /// Render the sequence to this video file with ffmpeg, instead of playing it.
+    #[structopt(long = "render", parse(from_os_str), conflicts_with = "frames_dir")]
+    video: Option<PathBuf>,
+    /// Render the sequence to numbered PNGs in this directory, instead of playing it.
+    #[structopt(long = "render-frames", parse(from_os_str))]
+    frames_dir: Option<PathBuf>,
+    /// Size of the rendered images, as WIDTHxHEIGHT.
+    #[structopt(long = "render-size", default_value = "1280x720")]
+    size: Size,
+    /// How far the camera circles the tree over the whole render, in degrees.
+    #[structopt(long = "render-orbit", default_value = "0")]
+    orbit: f32,
+    /// Audio to put in the `--render` video, such as the song a music show was made for. It's
+    /// cut or padded with silence to the length of the sequence.
+    #[structopt(long = "render-audio", parse(from_os_str), requires = "video")]
+    audio: Option<PathBuf>,
+    /// Milliseconds to delay the audio by against the frames, or to start it early if
+    /// negative, e.g. to match the `--output-latency` a show was tuned with.
+    #[structopt(
+        long = "render-audio-offset",
+        default_value = "0",
+        allow_hyphen_values = true
+    )]
+    audio_offset: f32,
+    /// Render for both eyes: `anaglyph` (red/cyan glasses) or `side-by-side` (a phone VR
+    /// holder).
+    #[structopt(long = "render-stereo")]
+    stereo: Option<Stereo>,
+    /// Distance between the eyes for `--render-stereo`, in coordinate units. Larger exaggerates
+    /// the depth.
+    #[structopt(long = "render-eye-separation", default_value = "0.15")]
+    eye_separation: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stereo {
+    Anaglyph,
+    SideBySide,
+}
+
+impl FromStr for Stereo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "anaglyph" => Ok(Self::Anaglyph),
+            "side-by-side" => Ok(Self::SideBySide),
+            other => Err(format!("Unknown stereo mode: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Size {
+    width: u32,
+    height: u32,
+}
+
+impl FromStr for Size {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Expected WIDTHxHEIGHT, got {}", s);
+        let (width, height) = s.split_once('x').ok_or_else(invalid)?;
+        let size = Self {
+            width: width.trim().parse().map_err(|_| invalid())?,
+            height: height.trim().parse().map_err(|_| invalid())?,
+        };
+        if size.width == 0 || size.height == 0 {
+            return Err(invalid());
+        }
+        Ok(size)
+    }
+}
+
+/// The player's starting camera, in its y-up world.
+const EYE: [f32; 3] = [-2.0, 2.5, 5.0];
+const TARGET: [f32; 3] = [0.0, 1.5, 0.0];
+/// Vertical field of view, as in the player.
+const FOV: f32 = PI / 4.0;
+/// Radius of the bright centre of a bulb and of its glow, as in the player.
+const BULB_RADIUS: f32 = 0.01;
+const GLOW_RADIUS: f32 = 0.03;
+/// Keeps distant bulbs visible in small renders.
+const MIN_BULB_PIXELS: f32 = 1.0;
+const BACKGROUND: [f32; 3] = [0.02, 0.02, 0.03];
+
+/// Draws bulbs as glowing discs, blended additively so they need no depth sorting.
+struct Canvas {
+    size: Size,
+    pixels: Vec<[f32; 3]>,
+}
+
+impl Canvas {
+    fn new(size: Size) -> Self {
+        Self {
+            size,
+            pixels: vec![BACKGROUND; (size.width * size.height) as usize],
+        }
+    }
+
+    fn clear(&mut self) {
+        self.pixels.fill(BACKGROUND);
+    }
+
+    /// Adds `core_color` within `core` pixels of `(cx, cy)`, and `glow_color` fading out from
+    /// there to `glow` pixels.
+    fn splat(
+        &mut self,
+        (cx, cy): (f32, f32),
+        core: f32,
+        glow: f32,
+        core_color: [f32; 3],
+        glow_color: [f32; 3],
+    ) {
+        let (width, height) = (self.size.width as i32, self.size.height as i32);
+        let reach = glow.max(core).ceil() as i32 + 1;
+        let (x0, y0) = (cx as i32 - reach, cy as i32 - reach);
+        for y in y0.max(0)..(y0 + reach * 2 + 1).min(height) {
+            for x in x0.max(0)..(x0 + reach * 2 + 1).min(width) {
+                let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+                let distance = (dx * dx + dy * dy).sqrt();
+                let (color, strength) = if distance <= core {
+                    (core_color, 1.0)
+                } else if distance <= glow {
+                    let t = (distance - core) / (glow - core).max(f32::EPSILON);
+                    (glow_color, (1.0 - t) * (1.0 - t))
+                } else {
+                    continue;
+                };
+                let pixel = &mut self.pixels[(y * width + x) as usize];
+                for (pixel, channel) in pixel.iter_mut().zip(color) {
+                    *pixel += channel * strength;
+                }
+            }
+        }
+    }
+
+    /// Draws the bulbs at `bulbs` (in the coordinate file's z-up orientation) lit with `rgb`,
+    /// as seen from `view`.
+    fn draw(&mut self, view: &View, bulbs: &[(f32, f32, f32)], rgb: &[Rgb]) {
+        self.clear();
+        for (&(x, y, z), &[r, g, b]) in bulbs.iter().zip(rgb) {
+            let relative = Vec3::new(x, z, y) - view.eye;
+            let depth = relative.dot(view.forward);
+            if depth <= 0.0 {
+                continue;
+            }
+            let scale = view.focal / depth;
+            let center = (
+                self.size.width as f32 / 2.0 + relative.dot(view.right) * scale + view.shift,
+                self.size.height as f32 / 2.0 - relative.dot(view.up) * scale,
+            );
+            let color = [r, g, b].map(|channel| channel as f32 / 255.0);
+            let core = color.map(|channel| (channel + 0.25).min(1.0));
+            let glow = color.map(|channel| channel * 0.5);
+            self.splat(
+                center,
+                (BULB_RADIUS * scale).max(MIN_BULB_PIXELS),
+                (GLOW_RADIUS * scale).max(MIN_BULB_PIXELS * 3.0),
+                core,
+                glow,
+            );
+        }
+    }
+
+    fn to_rgb8(&self, out: &mut Vec<u8>) {
+        out.clear();
+        out.extend(
+            self.pixels
+                .iter()
+                .flatten()
+                .map(|&channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8),
+        );
+    }
+}
+
+/// A camera looking at the tree, possibly from one eye of a pair.
+struct View {
+    eye: Vec3,
+    forward: Vec3,
+    right: Vec3,
+    up: Vec3,
+    focal: f32,
+    /// How far the image is moved to the right, in pixels. Each eye of a pair looks straight
+    /// ahead, and their images are moved so that they agree about the target, which then looks
+    /// to be at the depth of the screen.
+    shift: f32,
+}
+
+impl View {
+    /// Looks from `eye` at `target`, with the eye moved `offset` to the right.
+    fn new(eye: Vec3, target: Vec3, offset: f32, focal: f32) -> Self {
+        let forward = (target - eye).normalize();
+        let right = forward.cross(Vec3::Y).normalize();
+        let up = right.cross(forward);
+        Self {
+            eye: eye + right * offset,
+            forward,
+            right,
+            up,
+            focal,
+            shift: offset * focal / (target - eye).length(),
+        }
+    }
+}
+
+/// Shows the left eye's image in red and the right eye's in green and blue. The red is the left
+/// image's brightness rather than its red channel, so bulbs of every color are seen by both eyes.
+fn anaglyph(left: &Canvas, right: &Canvas, out: &mut Vec<u8>) {
+    out.clear();
+    for (&[lr, lg, lb], &[_, rg, rb]) in left.pixels.iter().zip(&right.pixels) {
+        let luma = 0.299 * lr + 0.587 * lg + 0.114 * lb;
+        out.extend([luma, rg, rb].map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8));
+    }
+}
+
+/// Puts the left eye's image on the left and the right eye's on the right.
+fn side_by_side(left: &Canvas, right: &Canvas, out: &mut Vec<u8>) {
+    out.clear();
+    let (left_width, right_width) = (left.size.width as usize, right.size.width as usize);
+    let rows = left
+        .pixels
+        .chunks(left_width)
+        .zip(right.pixels.chunks(right_width));
+    for (left_row, right_row) in rows {
+        out.extend(
+            left_row
+                .iter()
+                .chain(right_row)
+                .flatten()
+                .map(|&channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8),
+        );
+    }
+}
+
+/// Where rendered images go.
+enum Sink {
+    Frames(PathBuf),
+    Video { ffmpeg: Child, stdin: ChildStdin },
+}
+
+/// Moves audio `offset_ms` later, or earlier if negative, and pads it with silence so it doesn't
+/// end before the video.
+fn audio_filter(offset_ms: f32) -> String {
+    let shift = if offset_ms >= 0.0 {
+        format!("adelay=delays={}:all=1", offset_ms.round())
+    } else {
+        format!("atrim=start={},asetpts=PTS-STARTPTS", -offset_ms / 1000.0)
+    };
+    shift + ",apad"
+}
+
+impl Sink {
+    /// Opens the destination for `count` frames at `fps`.
+    fn open(opt: &RenderOpt, fps: f32, count: usize) -> Result<Self, Box<dyn Error>> {
+        if let Some(dir) = &opt.frames_dir {
+            fs::create_dir_all(dir)?;
+            return Ok(Self::Frames(dir.clone()));
+        }
+        let path = opt.video.as_ref().unwrap();
+        let mut command = Command::new("ffmpeg");
+        command
+            .args(["-y", "-loglevel", "error", "-f", "rawvideo", "-pix_fmt", "rgb24"])
+            .arg("-s")
+            .arg(format!("{}x{}", opt.size.width, opt.size.height))
+            .arg("-r")
+            .arg(fps.to_string())
+            .args(["-i", "-"]);
+        if let Some(audio) = &opt.audio {
+            // The frames are timed by their count alone, so cutting the audio to exactly their
+            // length keeps the two in step all the way through
+            command
+                .arg("-i")
+                .arg(audio)
+                .args(["-map", "0:v", "-map", "1:a", "-af"])
+                .arg(audio_filter(opt.audio_offset))
+                .arg("-t")
+                .arg(format!("{:.6}", count as f64 / fps as f64));
+        }
+        let mut ffmpeg = command
+            .args(["-pix_fmt", "yuv420p"])
+            .arg(path)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                format!(
+                    "Cannot run ffmpeg to encode {} ({}), use --render-frames to write PNGs instead",
+                    path.display(),
+                    e
+                )
+            })?;
+        let stdin = ffmpeg.stdin.take().unwrap();
+        Ok(Self::Video { ffmpeg, stdin })
+    }
+
+    fn write(&mut self, index: usize, size: Size, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        match self {
+            Self::Frames(dir) => image::save_buffer(
+                dir.join(format!("{:05}.png", index)),
+                data,
+                size.width,
+                size.height,
+                image::ColorType::Rgb8,
+            )?,
+            Self::Video { stdin, .. } => stdin.write_all(data)?,
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), Box<dyn Error>> {
+        if let Self::Video { mut ffmpeg, stdin } = self {
+            drop(stdin);
+            let status = ffmpeg.wait()?;
+            if !status.success() {
+                return Err(format!("ffmpeg failed ({})", status).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RenderOpt {
+    pub fn enabled(&self) -> bool {
+        self.video.is_some() || self.frames_dir.is_some()
+    }
+
+    fn destination(&self) -> &Path {
+        self.video.as_deref().or(self.frames_dir.as_deref()).unwrap()
+    }
+}
+
+/// Renders every frame of the sequence at its own frame rate, with bulbs at `bulbs` (in the
+/// coordinate file's z-up orientation).
+pub fn render(
+    opt: &RenderOpt,
+    sequence: &mut Sequence,
+    bulbs: &[(f32, f32, f32)],
+) -> Result<(), Box<dyn Error>> {
+    sequence.source.wait_loaded()?;
+    let count = sequence
+        .source
+        .frame_count()
+        .ok_or("Rendering needs a sequence file rather than a stream")?;
+    let size = opt.size;
+    if opt.stereo == Some(Stereo::SideBySide) && size.width < 2 {
+        return Err("Side by side stereo needs a render at least 2 pixels wide".into());
+    }
+    let focal = size.height as f32 / 2.0 / (FOV / 2.0).tan();
+    // Side by side, each eye gets half the width
+    let (left_size, right_size) = match opt.stereo {
+        Some(Stereo::SideBySide) => (
+            Size {
+                width: size.width / 2,
+                ..size
+            },
+            Size {
+                width: size.width - size.width / 2,
+                ..size
+            },
+        ),
+        _ => (size, size),
+    };
+    let mut left = Canvas::new(left_size);
+    let mut right = Canvas::new(right_size);
+    let mut sink = Sink::open(opt, sequence.fps, count)?;
+    let (eye, target) = (Vec3::from(EYE), Vec3::from(TARGET));
+    let mut rgb = Vec::new();
+    let mut data = Vec::new();
+    for index in 0..count {
+        if !sequence.read_frame(index, &mut rgb)? {
+            continue;
+        }
+        // Circle the camera around the tree's axis, above the target
+        let angle = (opt.orbit * index as f32 / count as f32).to_radians();
+        let offset = eye - target;
+        let eye = target
+            + Vec3::new(
+                offset.x * angle.cos() + offset.z * angle.sin(),
+                offset.y,
+                offset.z * angle.cos() - offset.x * angle.sin(),
+            );
+        match opt.stereo {
+            None => {
+                left.draw(&View::new(eye, target, 0.0, focal), bulbs, &rgb);
+                left.to_rgb8(&mut data);
+            }
+            Some(stereo) => {
+                let half = opt.eye_separation / 2.0;
+                left.draw(&View::new(eye, target, -half, focal), bulbs, &rgb);
+                right.draw(&View::new(eye, target, half, focal), bulbs, &rgb);
+                match stereo {
+                    Stereo::Anaglyph => anaglyph(&left, &right, &mut data),
+                    Stereo::SideBySide => side_by_side(&left, &right, &mut data),
+                }
+            }
+        }
+        sink.write(index, size, &data)?;
+    }
+    sink.finish()?;
+    eprintln!(
+        "Rendered {} frames to {}",
+        count,
+        opt.destination().display()
+    );
+    Ok(())
+}
@@ -0,0 +1,361 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+use xmas_tree_common::{
+    csv_format::CsvStreamReader,
+    protocols::{DdpReceiver, DDP_PORT},
+    seal,
+    sequence::{Cursor, Rgb, SequenceFormat, SequenceIndex},
+};
+use xmas_tree_gen::tweak::TweakableEffect;
+
+/// Where the player's frames come from. New kinds of input implement this, so playback and the
+/// hardware output don't need to know about them.
+pub trait FrameSource: Send + Sync {
+    /// How many frames there are, or `None` for a stream which plays frames as they arrive.
+    fn frame_count(&self) -> Option<usize>;
+
+    /// The rate the frames should play at, if the source records one. Otherwise `--fps` is
+    /// used.
+    fn fps(&self) -> Option<f32> {
+        None
+    }
+
+    /// The frame at `index`, which is less than the frame count. Streams ignore the index and
+    /// return their newest frame, or `None` before the first one arrives.
+    fn frame_at(&mut self, index: usize) -> Result<Option<&[Rgb]>, Box<dyn Error>>;
+
+    /// Waits until the frame count is known, for uses which need the whole sequence rather than
+    /// playing it as it loads.
+    fn wait_loaded(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+/// Opens a sequence file, reads frames from stdin if the path is `-`, or listens for frames sent
+/// over the network if it's `ddp://[ADDRESS]`.
+pub fn open(path: &Path) -> Result<Box<dyn FrameSource>, Box<dyn Error>> {
+    if path == Path::new("-") {
+        return Ok(Box::new(StdinSource::spawn()?));
+    }
+    if let Some(address) = path.to_str().and_then(|path| path.strip_prefix("ddp://")) {
+        return Ok(Box::new(NetworkSource::listen(address)?));
+    }
+    Ok(Box::new(FileSource::open(path)?))
+}
+
+/// Whether frames come from somewhere other than a sequence file, so there's no metadata or
+/// notes to go with them.
+pub fn is_stream(path: &Path) -> bool {
+    path == Path::new("-")
+        || path.to_str().is_some_and(|path| path.starts_with("ddp://"))
+        || effect_name(path).is_some()
+}
+
+/// The effect to run live if the path is `effect://NAME`. Those aren't opened by [`open`], since
+/// the effect needs the coordinates, and its parameters are shared with the parameter panel.
+pub fn effect_name(path: &Path) -> Option<&str> {
+    path.to_str()?.strip_prefix("effect://")
+}
+
+/// How many places in a file are read from at once: the preview, and the hardware output which
+/// runs ahead of it.
+const FILE_CURSORS: usize = 2;
+
+/// A sequence file in any supported format, read through its index. Indexing reads the whole
+/// file, so apart from fseq files, which list their frames in a header, it happens in the
+/// background and playback starts straight away. Until the index is ready CSV files play from
+/// the start as they're read, like a stream, and other formats show nothing.
+pub struct FileSource {
+    state: FileState,
+    /// Each cursor with the frame it last read, so reading forward from there is cheap.
+    cursors: Vec<(Option<usize>, Cursor)>,
+}
+
+enum FileState {
+    Indexing {
+        thread: JoinHandle<Result<SequenceIndex, String>>,
+        early: Option<EarlyFrames>,
+    },
+    Indexed(SequenceIndex),
+    Failed(String),
+}
+
+/// Frames of a CSV file read in order while it's being indexed.
+struct EarlyFrames {
+    reader: CsvStreamReader<BufReader<File>>,
+    /// How many frames have been read, the last of which is in `frame`.
+    read: usize,
+    frame: Vec<Rgb>,
+}
+
+impl EarlyFrames {
+    /// Reads on to frame `index`, or as close as the file goes. Frames before the last one read
+    /// are gone, so asking for one of them gives the last one again.
+    fn frame_at(&mut self, index: usize) -> Result<Option<&[Rgb]>, Box<dyn Error>> {
+        while self.read <= index && self.reader.read_frame(&mut self.frame)? {
+            self.read += 1;
+        }
+        if self.frame.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(&self.frame))
+    }
+}
+
+impl FileSource {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut file = BufReader::new(File::open(path)?);
+        // Sealed files are unsealed whole before they can be read, and it's better to find out
+        // straight away if the key is missing or wrong
+        let sealed = seal::is_sealed(file.fill_buf()?);
+        let state = match SequenceFormat::sniff(&mut file)? {
+            _ if sealed => FileState::Indexed(check_frames(SequenceIndex::open(path)?)?),
+            SequenceFormat::Fseq => FileState::Indexed(check_frames(SequenceIndex::open(path)?)?),
+            format => {
+                let early = if format == SequenceFormat::Csv {
+                    Some(EarlyFrames {
+                        reader: CsvStreamReader::new(file)?,
+                        read: 0,
+                        frame: Vec::new(),
+                    })
+                } else {
+                    None
+                };
+                let path = path.to_owned();
+                let thread = thread::spawn(move || {
+                    SequenceIndex::open(&path)
+                        .and_then(check_frames)
+                        .map_err(|e| e.to_string())
+                });
+                FileState::Indexing { thread, early }
+            }
+        };
+        Ok(Self {
+            state,
+            cursors: (0..FILE_CURSORS)
+                .map(|_| (None, Cursor::default()))
+                .collect(),
+        })
+    }
+
+    /// Picks up the index once it's been built, waiting for it if `wait` is set.
+    fn poll_index(&mut self, wait: bool) {
+        match &self.state {
+            FileState::Indexing { thread, .. } if wait || thread.is_finished() => {}
+            _ => return,
+        }
+        let thread = match std::mem::replace(&mut self.state, FileState::Failed(String::new())) {
+            FileState::Indexing { thread, .. } => thread,
+            _ => unreachable!(),
+        };
+        self.state = match thread.join() {
+            Ok(Ok(index)) => FileState::Indexed(index),
+            Ok(Err(e)) => FileState::Failed(e),
+            Err(_) => FileState::Failed("Indexing the sequence panicked".into()),
+        };
+    }
+}
+
+fn check_frames(index: SequenceIndex) -> Result<SequenceIndex, Box<dyn Error>> {
+    if index.frame_count() == 0 {
+        return Err("Sequence has no frames".into());
+    }
+    Ok(index)
+}
+
+impl FrameSource for FileSource {
+    fn frame_count(&self) -> Option<usize> {
+        match &self.state {
+            FileState::Indexed(index) => Some(index.frame_count()),
+            _ => None,
+        }
+    }
+
+    fn fps(&self) -> Option<f32> {
+        match &self.state {
+            FileState::Indexed(index) => index.fps(),
+            _ => None,
+        }
+    }
+
+    fn wait_loaded(&mut self) -> Result<(), Box<dyn Error>> {
+        self.poll_index(true);
+        match &self.state {
+            FileState::Failed(e) => Err(e.clone().into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn frame_at(&mut self, index: usize) -> Result<Option<&[Rgb]>, Box<dyn Error>> {
+        self.poll_index(false);
+        let sequence = match &mut self.state {
+            FileState::Indexed(sequence) => sequence,
+            FileState::Indexing { early, .. } => {
+                return match early {
+                    Some(early) => early.frame_at(index),
+                    None => Ok(None),
+                }
+            }
+            FileState::Failed(e) => return Err(e.clone().into()),
+        };
+        // Continue from the cursor closest behind the frame, or else start an unused one, so
+        // separate readers each keep their own cursor
+        let nearest = self
+            .cursors
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, (last, _))| match *last {
+                Some(last) if last <= index => (2, last),
+                None => (1, 0),
+                Some(_) => (0, 0),
+            })
+            .map(|(i, _)| i)
+            .unwrap();
+        let (last, cursor) = &mut self.cursors[nearest];
+        *last = Some(index);
+        Ok(Some(sequence.frame(cursor, index)?))
+    }
+}
+
+/// CSV frames piped into stdin, such as from `xmas_tree_gen generate --preview`. The sender sets
+/// the pace, and the player always shows the newest frame.
+pub struct StdinSource {
+    latest: Arc<Mutex<Option<Vec<Rgb>>>>,
+    frame: Vec<Rgb>,
+}
+
+impl StdinSource {
+    /// Reads the header, then keeps reading frames in the background until stdin closes.
+    pub fn spawn() -> Result<Self, Box<dyn Error>> {
+        let mut reader = CsvStreamReader::new(BufReader::new(io::stdin()))
+            .map_err(|e| format!("Cannot read frames from stdin: {}", e))?;
+        let latest = Arc::new(Mutex::new(None));
+        let shared = latest.clone();
+        thread::spawn(move || {
+            let mut frame = Vec::new();
+            loop {
+                match reader.read_frame(&mut frame) {
+                    Ok(true) => *shared.lock().unwrap() = Some(frame.clone()),
+                    Ok(false) => break,
+                    Err(e) => {
+                        eprintln!("Stopped reading frames from stdin: {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(Self {
+            latest,
+            frame: Vec::new(),
+        })
+    }
+}
+
+impl FrameSource for StdinSource {
+    fn frame_count(&self) -> Option<usize> {
+        None
+    }
+
+    fn frame_at(&mut self, _index: usize) -> Result<Option<&[Rgb]>, Box<dyn Error>> {
+        if let Some(frame) = self.latest.lock().unwrap().take() {
+            self.frame = frame;
+        }
+        if self.frame.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(&self.frame))
+    }
+}
+
+/// Frames sent over DDP, e.g. by an output with `mirror=` on the machine driving the tree, so
+/// the preview shows exactly what the tree is being sent. Like stdin, the sender sets the pace.
+pub struct NetworkSource {
+    latest: Arc<Mutex<Option<Vec<Rgb>>>>,
+    frame: Vec<Rgb>,
+}
+
+impl NetworkSource {
+    /// Listens on `address`, which may leave out the host or the port to listen on every
+    /// interface or the standard DDP port.
+    pub fn listen(address: &str) -> Result<Self, Box<dyn Error>> {
+        let address = match address.split_once(':') {
+            Some(("", port)) => format!("0.0.0.0:{}", port),
+            Some(_) => address.to_string(),
+            None if address.is_empty() => format!("0.0.0.0:{}", DDP_PORT),
+            None => format!("{}:{}", address, DDP_PORT),
+        };
+        let mut receiver = DdpReceiver::bind(&address)
+            .map_err(|e| format!("Cannot listen for frames on {}: {}", address, e))?;
+        eprintln!("Listening for frames on {}", address);
+        let latest = Arc::new(Mutex::new(None));
+        let shared = latest.clone();
+        thread::spawn(move || {
+            let mut frame = Vec::new();
+            loop {
+                match receiver.receive(&mut frame) {
+                    Ok(()) => *shared.lock().unwrap() = Some(frame.clone()),
+                    Err(e) => {
+                        eprintln!("Stopped receiving frames: {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(Self {
+            latest,
+            frame: Vec::new(),
+        })
+    }
+}
+
+impl FrameSource for NetworkSource {
+    fn frame_count(&self) -> Option<usize> {
+        None
+    }
+
+    fn frame_at(&mut self, _index: usize) -> Result<Option<&[Rgb]>, Box<dyn Error>> {
+        if let Some(frame) = self.latest.lock().unwrap().take() {
+            self.frame = frame;
+        }
+        if self.frame.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(&self.frame))
+    }
+}
+
+/// An effect rendered as it plays, looping over its natural cycle. The parameter panel changes
+/// its parameters while it runs.
+pub struct EffectSource {
+    effect: Arc<Mutex<TweakableEffect>>,
+    frame: Vec<Rgb>,
+}
+
+impl EffectSource {
+    pub fn new(effect: Arc<Mutex<TweakableEffect>>) -> Self {
+        Self {
+            effect,
+            frame: Vec::new(),
+        }
+    }
+}
+
+impl FrameSource for EffectSource {
+    fn frame_count(&self) -> Option<usize> {
+        Some(self.effect.lock().unwrap().frame_count())
+    }
+
+    fn frame_at(&mut self, index: usize) -> Result<Option<&[Rgb]>, Box<dyn Error>> {
+        self.frame.clear();
+        self.frame
+            .extend_from_slice(self.effect.lock().unwrap().render(index));
+        Ok(Some(&self.frame))
+    }
+}
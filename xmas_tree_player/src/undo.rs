@@ -0,0 +1,91 @@
+//! Undo and redo for the editor modes. Each editor makes its changes through `Edit`s, which know
+//! how to take themselves back, and keeps them in a `History`. Every editor uses the same keys:
+//! Ctrl+Z undoes, and Ctrl+Y or Ctrl+Shift+Z redoes.
+
+use bevy::prelude::*;
+
+/// Edits older than this are forgotten.
+const MAX_EDITS: usize = 1000;
+
+/// A change to the document of type `T` being edited.
+pub trait Edit<T>: Send + Sync + 'static {
+    fn apply(&self, target: &mut T);
+    fn revert(&self, target: &mut T);
+}
+
+pub struct History<T> {
+    done: Vec<Box<dyn Edit<T>>>,
+    undone: Vec<Box<dyn Edit<T>>>,
+}
+
+impl<T> Default for History<T> {
+    fn default() -> Self {
+        Self {
+            done: Vec::new(),
+            undone: Vec::new(),
+        }
+    }
+}
+
+impl<T: 'static> History<T> {
+    /// Makes an edit, so it can be undone.
+    pub fn apply(&mut self, edit: impl Edit<T>, target: &mut T) {
+        edit.apply(target);
+        self.record(edit);
+    }
+
+    /// Remembers an edit which has already been made, e.g. bit by bit while a key was held.
+    pub fn record(&mut self, edit: impl Edit<T>) {
+        if self.done.len() == MAX_EDITS {
+            self.done.remove(0);
+        }
+        self.done.push(Box::new(edit));
+        self.undone.clear();
+    }
+
+    /// Takes back the last edit, returning whether there was one.
+    pub fn undo(&mut self, target: &mut T) -> bool {
+        match self.done.pop() {
+            Some(edit) => {
+                edit.revert(target);
+                self.undone.push(edit);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Makes the last undone edit again, returning whether there was one.
+    pub fn redo(&mut self, target: &mut T) -> bool {
+        match self.undone.pop() {
+            Some(edit) => {
+                edit.apply(target);
+                self.done.push(edit);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Undo,
+    Redo,
+}
+
+/// Whether undo or redo was just asked for.
+pub fn step(keys: &Input<KeyCode>) -> Option<Step> {
+    let held = |a, b| keys.pressed(a) || keys.pressed(b);
+    if !held(KeyCode::LControl, KeyCode::RControl) {
+        return None;
+    }
+    let shift = held(KeyCode::LShift, KeyCode::RShift);
+    if keys.just_pressed(KeyCode::Y) || (shift && keys.just_pressed(KeyCode::Z)) {
+        Some(Step::Redo)
+    } else if keys.just_pressed(KeyCode::Z) {
+        Some(Step::Undo)
+    } else {
+        None
+    }
+}